@@ -1863,13 +1863,17 @@ class TranslateASTVisitor final
 
                 // Encode attribute names and relevant info if supported
                 CborEncoder attr_info;
-                bool has_attrs = def ? def->hasAttrs() : FD->hasAttrs();
+                // Without a definition, the latest declaration has the
+                // attributes of all of them, like those of `#pragma weak`
+                const Decl *attr_decl = def ? static_cast<const Decl *>(def)
+                                            : FD->getMostRecentDecl();
+                bool has_attrs = attr_decl->hasAttrs();
 
                 cbor_encoder_create_array(array, &attr_info,
                                           CborIndefiniteLength);
 
                 if (has_attrs) {
-                    auto attrs = def ? def->getAttrs() : FD->getAttrs();
+                    auto attrs = attr_decl->getAttrs();
 
                     for (auto attr : attrs) {
                         cbor_encode_text_stringz(&attr_info,
@@ -1983,12 +1987,15 @@ class TranslateASTVisitor final
                 cbor_encoder_create_array(array, &attr_info,
                                           CborIndefiniteLength);
 
-                bool has_attrs = def ? def->hasAttrs() : VD->hasAttrs();
+                // As for functions
+                const Decl *attr_decl = def ? static_cast<const Decl *>(def)
+                                            : VD->getMostRecentDecl();
+                bool has_attrs = attr_decl->hasAttrs();
 
                 if (has_attrs) {
-                    auto attrs = def ? def->getAttrs() : VD->getAttrs();
+                    auto attrs = attr_decl->getAttrs();
 
-                    for (auto attr : def->attrs()) {
+                    for (auto attr : attrs) {
                         cbor_encode_text_stringz(&attr_info,
                                                  attr->getSpelling());
 
@@ -4,6 +4,7 @@ use std::ffi::{CStr, CString};
 use std::io::{Error, ErrorKind};
 use std::path::Path;
 use std::slice;
+use std::sync::Mutex;
 
 pub mod clang_ast;
 
@@ -18,24 +19,45 @@ pub fn get_clang_major_version() -> Option<u32> {
         .ok()
 }
 
+/// Identifies the exporter build, for keying caches of exported ASTs.
+pub fn exporter_version() -> String {
+    let clang = unsafe { CStr::from_ptr(clang_version()) };
+    format!(
+        "{}+clang-{}",
+        env!("CARGO_PKG_VERSION"),
+        clang.to_string_lossy()
+    )
+}
+
 pub fn get_untyped_ast(
     file_path: &Path,
     cc_db: &Path,
     extra_args: &[&str],
     debug: bool,
 ) -> Result<clang_ast::AstContext, Error> {
+    let buffer = get_ast_cbor(file_path, cc_db, extra_args, debug)?;
+    untyped_ast_from_cbor(&buffer)
+}
+
+/// Run Clang on `file_path` and return the exported AST in its serialized
+/// CBOR form, e.g. for caching.
+pub fn get_ast_cbor(
+    file_path: &Path,
+    cc_db: &Path,
+    extra_args: &[&str],
+    debug: bool,
+) -> Result<Vec<u8>, Error> {
     let cbors = get_ast_cbors(file_path, cc_db, extra_args, debug);
-    let buffer = cbors
-        .values()
+    cbors
+        .into_values()
         .next()
-        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Could not parse input file"))?;
-
-    // let cbor_path = file_path.with_extension("cbor");
-    // let mut cbor_file = File::create(&cbor_path)?;
-    // cbor_file.write_all(&buffer[..])?;
-    // eprintln!("Dumped CBOR to {}", cbor_path.to_string_lossy());
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Could not parse input file"))
+}
 
-    let items: Value = from_slice(&buffer[..]).unwrap();
+/// Decode an AST previously produced by [`get_ast_cbor`].
+pub fn untyped_ast_from_cbor(buffer: &[u8]) -> Result<clang_ast::AstContext, Error> {
+    let items: Value = from_slice(buffer)
+        .map_err(|e| Error::new(ErrorKind::InvalidData, format!("{:}", e)))?;
 
     match clang_ast::process(items) {
         Ok(cxt) => Ok(cxt),
@@ -43,6 +65,10 @@ pub fn get_untyped_ast(
     }
 }
 
+/// The exporter parses its arguments with LLVM's `cl` machinery, whose option
+/// state is process-global, so only one export may run at a time.
+static EXPORTER_LOCK: Mutex<()> = Mutex::new(());
+
 fn get_ast_cbors(
     file_path: &Path,
     cc_db: &Path,
@@ -63,6 +89,7 @@ fn get_ast_cbors(
     let args_ptrs: Vec<*const libc::c_char> = args_owned.iter().map(|x| x.as_ptr()).collect();
 
     let hashmap;
+    let _guard = EXPORTER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
     unsafe {
         let ptr = ast_exporter(
             args_ptrs.len() as libc::c_int,
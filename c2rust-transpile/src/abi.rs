@@ -0,0 +1,187 @@
+//! The C prototypes of the functions a translation unit defines, reduced to
+//! what matters for calling them: the class and size of each parameter and
+//! of the return value. Sizes are those of an LP64 target; see [`is_lp64`].
+
+use std::fmt;
+use std::path::PathBuf;
+
+use serde_derive::Serialize;
+
+use crate::c_ast::{CDeclKind, CQualTypeId, CTypeKind, TypedAstContext};
+
+/// How a value of a type is passed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AbiType {
+    Void,
+    /// An integer, `bool` or enum type of this many bytes
+    Integer(u8),
+    /// A floating point type of this many bytes
+    Float(u8),
+    /// A data or function pointer
+    Pointer,
+    /// A struct or union passed by value
+    Record,
+    /// A type that is not compared, like `long double` or a vector
+    Other,
+}
+
+impl AbiType {
+    /// Whether a value of type `self` is passed like one of type `other`.
+    /// Types that are not compared match any type.
+    pub fn matches(self, other: AbiType) -> bool {
+        self == other || self == AbiType::Other || other == AbiType::Other
+    }
+}
+
+impl fmt::Display for AbiType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AbiType::Void => write!(f, "void"),
+            AbiType::Integer(size) => write!(f, "int{}", size * 8),
+            AbiType::Float(size) => write!(f, "float{}", size * 8),
+            AbiType::Pointer => write!(f, "pointer"),
+            AbiType::Record => write!(f, "record"),
+            AbiType::Other => write!(f, "?"),
+        }
+    }
+}
+
+/// The prototype of a function defined by a translation unit
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Prototype {
+    pub name: String,
+    /// The translation unit that defines it
+    pub input: PathBuf,
+    pub ret: AbiType,
+    pub params: Vec<AbiType>,
+    pub variadic: bool,
+}
+
+impl fmt::Display for Prototype {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "fn(")?;
+        for (i, param) in self.params.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", param)?;
+        }
+        if self.variadic {
+            let sep = if self.params.is_empty() { "" } else { ", " };
+            write!(f, "{}...", sep)?;
+        }
+        write!(f, ") -> {}", self.ret)
+    }
+}
+
+/// Whether `target`, a target triple, has 32-bit `int`s and 64-bit `long`s
+/// and pointers, as [`abi_type`] assumes. An unknown target is assumed to.
+pub fn is_lp64(target: &str) -> bool {
+    let mut parts = target.split('-');
+    let arch = parts.next().unwrap_or_default();
+    let lp64_arch = arch.starts_with("riscv64")
+        || matches!(
+            arch,
+            "x86_64"
+                | "aarch64"
+                | "arm64"
+                | "powerpc64"
+                | "powerpc64le"
+                | "ppc64"
+                | "ppc64le"
+                | "s390x"
+                | "mips64"
+                | "mips64el"
+                | "sparcv9"
+                | "sparc64"
+                | "loongarch64"
+        );
+    // Windows is LLP64, and the x32 ABI has 32-bit pointers
+    let other_model = parts.any(|part| {
+        part.starts_with("windows") || part.starts_with("mingw") || part.ends_with("x32")
+    });
+    target.is_empty() || (lp64_arch && !other_model)
+}
+
+fn abi_type(ast: &TypedAstContext, typ: CQualTypeId) -> AbiType {
+    use CTypeKind::*;
+    match ast.resolve_type(typ.ctype).kind {
+        Void => AbiType::Void,
+        Bool | Char | SChar | UChar => AbiType::Integer(1),
+        Short | UShort => AbiType::Integer(2),
+        Int | UInt => AbiType::Integer(4),
+        Long | ULong | LongLong | ULongLong => AbiType::Integer(8),
+        Int128 | UInt128 => AbiType::Integer(16),
+        Float => AbiType::Float(4),
+        Double => AbiType::Float(8),
+        Pointer(_) | BlockPointer(_) | Reference(_) => AbiType::Pointer,
+        // Array parameters are passed as pointers
+        ConstantArray(..) | IncompleteArray(_) | VariableArray(..) => AbiType::Pointer,
+        Struct(_) | Union(_) => AbiType::Record,
+        Enum(decl) => match ast[decl].kind {
+            CDeclKind::Enum {
+                integral_type: Some(typ),
+                ..
+            } => abi_type(ast, typ),
+            _ => AbiType::Integer(4),
+        },
+        _ => AbiType::Other,
+    }
+}
+
+/// The prototypes of the functions with external linkage that `ast`, the
+/// AST of the translation unit `input`, defines.
+pub fn prototypes(ast: &TypedAstContext, input: PathBuf) -> Vec<Prototype> {
+    let mut prototypes = ast
+        .iter_decls()
+        .filter_map(|(_, decl)| match decl.kind {
+            CDeclKind::Function {
+                is_global: true,
+                body: Some(_),
+                is_inline,
+                is_inline_externally_visible,
+                typ,
+                ref name,
+                ..
+            } if !is_inline || is_inline_externally_visible => {
+                match ast.resolve_type(typ).kind {
+                    CTypeKind::Function(ret, ref params, variadic, _, _) => Some(Prototype {
+                        name: name.clone(),
+                        input: input.clone(),
+                        ret: abi_type(ast, ret),
+                        params: params
+                            .iter()
+                            .map(|&param| abi_type(ast, param))
+                            // `f(void)` has a `void` parameter
+                            .filter(|&param| param != AbiType::Void)
+                            .collect(),
+                        variadic,
+                    }),
+                    _ => None,
+                }
+            }
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    prototypes.sort_by(|a, b| a.name.cmp(&b.name));
+    prototypes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lp64_targets() {
+        assert!(is_lp64("x86_64-unknown-linux-gnu"));
+        assert!(is_lp64("aarch64-apple-darwin"));
+        assert!(is_lp64("riscv64gc-unknown-linux-gnu"));
+        assert!(is_lp64(""));
+        assert!(!is_lp64("i686-unknown-linux-gnu"));
+        assert!(!is_lp64("armv7-unknown-linux-gnueabihf"));
+        assert!(!is_lp64("x86_64-pc-windows-msvc"));
+        assert!(!is_lp64("x86_64-w64-mingw32"));
+        assert!(!is_lp64("x86_64-unknown-linux-gnux32"));
+    }
+}
@@ -0,0 +1,72 @@
+//! Assembly sources (`.s` and `.S`) are not translated, but compiled as they
+//! are in the generated `build.rs`. To take part in the dependency graph,
+//! their symbols are read from an object file assembled with the flags of
+//! the original build.
+
+use std::path::Path;
+use std::process::Command;
+
+use deps_builder::DependencyInfo;
+use failure::{format_err, Error};
+
+use crate::compile_cmds::CompileCmd;
+use crate::objects::ObjectSymbols;
+
+/// Whether `file` is likely an assembly source, by its extension
+pub fn is_assembly(file: &Path) -> bool {
+    matches!(
+        file.extension().and_then(|ext| ext.to_str()),
+        Some("s" | "S")
+    )
+}
+
+/// Run `command`, returning its standard output if it succeeds
+fn run(command: &mut Command) -> Result<Vec<u8>, Error> {
+    let output = command.output()?;
+    if !output.status.success() {
+        return Err(format_err!(
+            "{:?} failed: {}",
+            command,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(output.stdout)
+}
+
+/// The global symbols that the assembly source of `cmd` defines and those it
+/// uses from other files. `.S` files go through the preprocessor with the
+/// flags of the original build.
+pub fn export_assembly(cmd: &CompileCmd) -> Result<DependencyInfo, Error> {
+    let input_path = cmd.abs_file();
+    let args = cmd.args();
+    let compiler = args
+        .first()
+        .ok_or_else(|| format_err!("{} has no compile command", input_path.display()))?;
+    // Removed when dropped
+    let object = tempfile::Builder::new()
+        .prefix("c2rust-asm-")
+        .suffix(".o")
+        .tempfile()?;
+    run(Command::new(compiler)
+        .current_dir(cmd.directory())
+        .args(cmd.native_flags())
+        .arg("-c")
+        .arg(&input_path)
+        .arg("-o")
+        .arg(object.path()))?;
+    let symbols = ObjectSymbols::read(object.path())?;
+
+    let path = input_path.to_str().unwrap().to_string();
+    let (defined, undefined) = symbols.into_dependency_symbols(&path);
+    let info = DependencyInfo {
+        input_path: path,
+        // Nothing is translated from assembly
+        output_path: String::new(),
+        object_path: cmd
+            .abs_output_file()
+            .map(|path| path.to_str().unwrap().to_string()),
+        undefined,
+        defined,
+    };
+    Ok(info)
+}
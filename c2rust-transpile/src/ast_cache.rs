@@ -0,0 +1,184 @@
+//! Exported Clang ASTs, kept around so that each translation unit only has
+//! to go through Clang once.
+//!
+//! `transpile` first runs `export` over every file to build the dependency
+//! graph and then translates each file; both phases need the same AST. The
+//! in-memory cache hands the CBOR from the first phase to the second. The
+//! optional on-disk cache persists the CBOR between runs, keyed by the input
+//! file's contents, its compile flags and the exporter version. It also
+//! records every header the AST was built from, so that a changed header
+//! invalidates the entry.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use c2rust_ast_exporter as ast_exporter;
+use c2rust_ast_exporter::clang_ast::AstContext;
+use log::{info, warn};
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::compile_cmds::CompileCmd;
+
+/// Hex-encoded SHA-256 of a file's contents.
+pub(crate) fn hash_file(path: &Path) -> io::Result<String> {
+    Ok(format!("{:x}", Sha256::digest(fs::read(path)?)))
+}
+
+/// A source file that an exported AST was built from.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SourceHash {
+    pub path: PathBuf,
+    pub hash: String,
+}
+
+impl SourceHash {
+    pub fn new(path: &Path) -> io::Result<Self> {
+        Ok(Self {
+            path: path.to_owned(),
+            hash: hash_file(path)?,
+        })
+    }
+
+    /// Whether the file still has the contents it had when hashed.
+    pub fn is_current(&self) -> bool {
+        hash_file(&self.path).map_or(false, |hash| hash == self.hash)
+    }
+}
+
+/// Hashes of all the existing files (the main file and its headers) that
+/// went into `ast`.
+pub(crate) fn ast_sources(ast: &AstContext) -> Vec<SourceHash> {
+    let mut sources = ast
+        .files
+        .iter()
+        .filter_map(|file| file.path.as_deref())
+        .filter(|path| path.is_file())
+        .filter_map(|path| SourceHash::new(path).ok())
+        .collect::<Vec<_>>();
+    sources.sort_by(|a, b| a.path.cmp(&b.path));
+    sources.dedup();
+    sources
+}
+
+pub(crate) struct AstCache {
+    /// CBOR exported earlier in this run, by cache key
+    memory: Option<Mutex<HashMap<String, Vec<u8>>>>,
+    /// Directory of the persistent cache, if enabled
+    dir: Option<PathBuf>,
+    /// Use the persistent cache without adding entries to it
+    read_only: bool,
+}
+
+impl AstCache {
+    /// Create a cache. With `keep_in_memory`, exported ASTs are held until
+    /// they are requested a second time; this is skipped when a cache
+    /// directory is given, since reading an entry back from disk is cheap
+    /// and keeps memory use bounded on large projects.
+    pub fn new(dir: Option<PathBuf>, keep_in_memory: bool, read_only: bool) -> Self {
+        let memory = (keep_in_memory && dir.is_none()).then(Default::default);
+        Self {
+            memory,
+            dir,
+            read_only,
+        }
+    }
+
+    /// Cache key for a translation unit: its contents, how it is compiled,
+    /// and which exporter produced the AST.
+    fn key(&self, cmd: &CompileCmd, input_path: &Path, extra_args: &[&str]) -> io::Result<String> {
+        let mut hasher = Sha256::new();
+        let mut add = |field: &[u8]| {
+            hasher.update((field.len() as u64).to_le_bytes());
+            hasher.update(field);
+        };
+        add(ast_exporter::exporter_version().as_bytes());
+        add(input_path.to_string_lossy().as_bytes());
+        add(cmd.directory().to_string_lossy().as_bytes());
+        for arg in cmd.args() {
+            add(arg.as_bytes());
+        }
+        for arg in extra_args {
+            add(arg.as_bytes());
+        }
+        add(&fs::read(input_path)?);
+        Ok(format!("{:x}", hasher.finalize()))
+    }
+
+    /// Get the untyped AST of `cmd`'s input, running Clang only if neither
+    /// this run nor a previous one has exported it already.
+    pub fn get_untyped_ast(
+        &self,
+        cmd: &CompileCmd,
+        cc_db: &Path,
+        extra_args: &[&str],
+        debug: bool,
+    ) -> io::Result<AstContext> {
+        let input_path = cmd.abs_file();
+        let key = self.key(cmd, &input_path, extra_args)?;
+
+        let cached = match &self.memory {
+            Some(memory) => memory.lock().unwrap().remove(&key),
+            None => None,
+        };
+        if let Some(cbor) = cached {
+            return ast_exporter::untyped_ast_from_cbor(&cbor);
+        }
+
+        if let Some(ast) = self.load(&key) {
+            info!("Using cached AST for {}", input_path.display());
+            return Ok(ast);
+        }
+
+        let cbor = ast_exporter::get_ast_cbor(&input_path, cc_db, extra_args, debug)?;
+        let ast = ast_exporter::untyped_ast_from_cbor(&cbor)?;
+        if let Err(e) = self.store(&key, &cbor, &ast) {
+            warn!("Could not cache AST for {}: {}", input_path.display(), e);
+        }
+        if let Some(memory) = &self.memory {
+            memory.lock().unwrap().insert(key, cbor);
+        }
+        Ok(ast)
+    }
+
+    fn entry_paths(&self, key: &str) -> Option<(PathBuf, PathBuf)> {
+        let dir = self.dir.as_ref()?;
+        Some((
+            dir.join(format!("{}.cbor", key)),
+            dir.join(format!("{}.json", key)),
+        ))
+    }
+
+    fn load(&self, key: &str) -> Option<AstContext> {
+        let (cbor_path, sources_path) = self.entry_paths(key)?;
+        let sources: Vec<SourceHash> =
+            serde_json::from_slice(&fs::read(sources_path).ok()?).ok()?;
+        if !sources.iter().all(SourceHash::is_current) {
+            return None;
+        }
+        ast_exporter::untyped_ast_from_cbor(&fs::read(cbor_path).ok()?).ok()
+    }
+
+    fn store(&self, key: &str, cbor: &[u8], ast: &AstContext) -> io::Result<()> {
+        let (cbor_path, sources_path) = match self.entry_paths(key) {
+            Some(paths) if !self.read_only => paths,
+            _ => return Ok(()),
+        };
+        fs::create_dir_all(cbor_path.parent().unwrap())?;
+        let sources = serde_json::to_vec(&ast_sources(ast))?;
+        // Write the CBOR first, so a complete sources list implies a complete entry
+        write_atomic(&cbor_path, cbor)?;
+        write_atomic(&sources_path, &sources)
+    }
+}
+
+/// Write via a temporary file so that concurrent readers never see a
+/// partially written cache entry.
+fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let tmp_path = path.with_extension(format!("tmp{}", std::process::id()));
+    fs::write(&tmp_path, contents)?;
+    fs::rename(&tmp_path, path)
+}
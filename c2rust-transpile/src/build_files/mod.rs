@@ -1,21 +1,30 @@
 use std::collections::BTreeMap;
 use std::fs::{self, File};
-use std::io::Write;
+use std::io::{self, Write};
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::Arc;
 
 use deps_builder::DependencyGraph;
 use handlebars::Handlebars;
+use log::warn;
 use pathdiff::diff_paths;
 use serde_derive::Serialize;
 use serde_json::json;
 
-use super::compile_cmds::LinkCmd;
+use super::compile_cmds::{CompileCmd, LinkCmd, LinkType};
 use super::TranspilerConfig;
 use crate::get_module_name;
+use crate::plan::{FileAction, Plan, PlannedCrate};
 use crate::CrateSet;
 use crate::ExternCrateDetails;
 use crate::PragmaSet;
+use crate::TranspileError;
+
+/// The dispatcher of a multi-call binary
+const MULTICALL_RS_FILE: &str = "multicall.rs";
 
 #[derive(Debug, Copy, Clone)]
 pub enum BuildDirectoryContents {
@@ -37,32 +46,13 @@ impl FromStr for BuildDirectoryContents {
     }
 }
 
-/// Create the build directory
+/// Get the build directory. It is created when the first file is written
+/// to it.
 pub fn get_build_dir(tcfg: &TranspilerConfig, cc_db: &Path) -> PathBuf {
     let cc_db_dir = cc_db
         .parent() // get directory of `compile_commands.json`
         .unwrap();
 
-    match &tcfg.output_dir {
-        Some(dir) => {
-            let output_dir = dir.clone();
-            if !output_dir.exists() {
-                fs::create_dir(&output_dir).unwrap_or_else(|_| {
-                    panic!("couldn't create build directory: {}", output_dir.display())
-                });
-            }
-            output_dir
-        }
-        None => cc_db_dir.into(),
-    }
-}
-
-/// Get the build directory without creating it
-pub fn get_build_dir_raw(tcfg: &TranspilerConfig, cc_db: &Path) -> PathBuf {
-    let cc_db_dir = cc_db
-        .parent() // get directory of `compile_commands.json`
-        .unwrap();
-
     match &tcfg.output_dir {
         Some(dir) => {
             let output_dir = dir.clone();
@@ -78,18 +68,21 @@ pub struct CrateConfig<'lcmd> {
     pub pragmas: PragmaSet,
     pub crates: CrateSet,
     pub link_cmd: &'lcmd LinkCmd,
+    /// The C files to compile with the `cc` crate instead of translating
+    pub native_cmds: Vec<Arc<CompileCmd>>,
 }
 
 /// Emit `Cargo.toml` and `lib.rs` for a library or `main.rs` for a binary.
 /// Returns the path to `lib.rs` or `main.rs` (or `None` if the output file
-/// existed already).
+/// existed already). With a `plan`, the files are only added to it.
 pub fn emit_build_files<'lcmd>(
     tcfg: &TranspilerConfig,
     build_dir: &Path,
     crate_cfg: Option<CrateConfig<'lcmd>>,
     workspace_members: Option<Vec<String>>,
     dependency_graph: &DependencyGraph,
-) -> Option<PathBuf> {
+    plan: Option<&Plan>,
+) -> Result<Option<PathBuf>, TranspileError> {
     let mut reg = Handlebars::new();
 
     reg.register_template_string("Cargo.toml", include_str!("Cargo.toml.hbs"))
@@ -98,10 +91,25 @@ pub fn emit_build_files<'lcmd>(
         .unwrap();
     reg.register_template_string("build.rs", include_str!("build.rs.hbs"))
         .unwrap();
+    reg.register_template_string("multicall.rs", include_str!("multicall.rs.hbs"))
+        .unwrap();
+    reg.register_template_string("install-links.sh", include_str!("install-links.sh.hbs"))
+        .unwrap();
 
-    if !build_dir.exists() {
-        fs::create_dir_all(&build_dir)
-            .unwrap_or_else(|_| panic!("couldn't create build directory: {}", build_dir.display()));
+    if let Some(plan) = plan {
+        if let Some(ccfg) = &crate_cfg {
+            plan.add_crate(PlannedCrate {
+                name: ccfg.crate_name.clone(),
+                build_dir: build_dir.to_owned(),
+                is_library: ccfg.link_cmd.r#type.is_library(),
+                modules: ccfg.modules.clone(),
+            });
+        }
+        if let Some(members) = &workspace_members {
+            plan.add_workspace_members(members);
+        }
+    } else if !build_dir.exists() {
+        fs::create_dir_all(&build_dir).map_err(TranspileError::io(build_dir))?;
     }
 
     emit_cargo_toml(
@@ -111,15 +119,18 @@ pub fn emit_build_files<'lcmd>(
         &crate_cfg,
         workspace_members,
         &dependency_graph,
-    );
+        plan,
+    )?;
     if tcfg.translate_valist {
-        emit_rust_toolchain(tcfg, build_dir);
+        emit_rust_toolchain(tcfg, build_dir, plan)?;
     }
-    crate_cfg.and_then(|ccfg| {
-        if tcfg.emit_no_lib {
-            emit_build_rs(tcfg, &reg, build_dir, ccfg.link_cmd)
-        } else {
-            emit_build_rs(tcfg, &reg, build_dir, ccfg.link_cmd);
+    match crate_cfg {
+        Some(ccfg) if tcfg.emit_no_lib => emit_build_rs(tcfg, &reg, build_dir, &ccfg, plan),
+        Some(ccfg) => {
+            emit_build_rs(tcfg, &reg, build_dir, &ccfg, plan)?;
+            if tcfg.multicall {
+                emit_multicall(tcfg, &reg, build_dir, &ccfg, dependency_graph, plan)?;
+            }
             emit_lib_rs(
                 tcfg,
                 &reg,
@@ -128,9 +139,26 @@ pub fn emit_build_files<'lcmd>(
                 ccfg.pragmas,
                 &ccfg.crates,
                 &dependency_graph,
+                plan,
             )
         }
-    })
+        None => Ok(None),
+    }
+}
+
+#[derive(Serialize)]
+struct NativeGroup {
+    /// The name of the static library the files are compiled into
+    name: String,
+    files: Vec<String>,
+    flags: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct Utility {
+    name: String,
+    /// The path of the `<name>_main` function
+    main: String,
 }
 
 #[derive(Serialize)]
@@ -186,6 +214,82 @@ enum ModuleSubset {
     //Both,
 }
 
+fn is_binary_module(
+    tcfg: &TranspilerConfig,
+    module: &Path,
+    dependency_graph: &DependencyGraph,
+) -> bool {
+    tcfg.is_binary(
+        dependency_graph
+            .nodes
+            .iter()
+            .find(|n| n.output_path == module.to_str().unwrap())
+            .unwrap(),
+    )
+}
+
+/// The utilities of a multi-call binary, i.e., the translated modules with a
+/// `main` function, and the paths of their entry points.
+fn multicall_utilities(
+    tcfg: &TranspilerConfig,
+    build_dir: &Path,
+    crate_name: &str,
+    modules: &[PathBuf],
+    dependency_graph: &DependencyGraph,
+) -> Vec<Utility> {
+    let mut utilities = vec![];
+    let mut definers = BTreeMap::<&str, String>::new();
+    for m in modules {
+        let node = dependency_graph
+            .nodes
+            .iter()
+            .find(|n| n.output_path == m.to_str().unwrap())
+            .unwrap();
+        if !tcfg.is_multicall_util(node) {
+            continue;
+        }
+        let name = TranspilerConfig::binary_name_from_path(m);
+        // The utilities are linked together, so their exported symbols must
+        // not clash
+        for symbol in &node.defined {
+            if let Some(other) = definers.insert(&symbol.name, name.clone()) {
+                warn!(
+                    "`{}` is defined by both the {} and {} utilities; the multi-call binary will not link",
+                    symbol.name, other, name
+                );
+            }
+        }
+        let mut main = vec![crate_name.replace('-', "_")];
+        match m.strip_prefix(build_dir) {
+            Ok(relpath) => main.extend(
+                relpath
+                    .iter()
+                    .map(|sm| get_module_name(Path::new(sm), true, false, false).unwrap()),
+            ),
+            Err(_) => main.push(get_module_name(m, true, false, false).unwrap()),
+        }
+        main.push(format!("{}_main", name));
+        utilities.push(Utility {
+            name,
+            main: main.join("::"),
+        });
+    }
+    utilities
+}
+
+/// The translated modules that are the roots of binaries.
+pub fn binary_modules(
+    tcfg: &TranspilerConfig,
+    modules: &[PathBuf],
+    dependency_graph: &DependencyGraph,
+) -> Vec<PathBuf> {
+    modules
+        .iter()
+        .filter(|m| is_binary_module(tcfg, m, dependency_graph))
+        .cloned()
+        .collect()
+}
+
 fn convert_module_list(
     tcfg: &TranspilerConfig,
     build_dir: &Path,
@@ -194,13 +298,7 @@ fn convert_module_list(
     dependency_graph: &DependencyGraph,
 ) -> Vec<Module> {
     modules.retain(|m| {
-        let is_binary = tcfg.is_binary(
-            &(dependency_graph
-                .nodes
-                .iter()
-                .find(|n| n.output_path == m.to_str().unwrap())
-                .unwrap()),
-        );
+        let is_binary = is_binary_module(tcfg, m, dependency_graph);
         let is_binary_subset = module_subset == ModuleSubset::Binaries;
         // Don't add binary modules to lib.rs, these are emitted to
         // standalone, separate binary modules.
@@ -211,15 +309,7 @@ fn convert_module_list(
     let mut module_tree = ModuleTree(BTreeMap::new());
     for m in &modules {
         match m.strip_prefix(build_dir) {
-            Ok(relpath)
-                if !tcfg.is_binary(
-                    &(dependency_graph
-                        .nodes
-                        .iter()
-                        .find(|n| n.output_path == m.to_str().unwrap())
-                        .unwrap()),
-                ) =>
-            {
+            Ok(relpath) if !is_binary_module(tcfg, m, dependency_graph) => {
                 // The module is inside the build directory, use nested modules
                 let mut cur = &mut module_tree;
                 for sm in relpath.iter() {
@@ -257,19 +347,42 @@ fn get_lib_rs_file_name(tcfg: &TranspilerConfig) -> &str {
     }
 }
 
+/// Group the C files that are compiled natively by their flags, since one
+/// `cc::Build` compiles all files with the same flags. The paths and flags
+/// are quoted as Rust string literals.
+fn native_groups(crate_name: &str, native_cmds: &[Arc<CompileCmd>]) -> Vec<NativeGroup> {
+    let mut groups = BTreeMap::<Vec<String>, Vec<String>>::new();
+    for cmd in native_cmds {
+        let file = cmd.abs_file().to_string_lossy().into_owned();
+        groups.entry(cmd.native_flags()).or_default().push(file);
+    }
+    let quote = |s: &String| format!("{:?}", s);
+    groups
+        .into_iter()
+        .enumerate()
+        .map(|(i, (flags, files))| NativeGroup {
+            name: format!("{}_native_{}", crate_name.replace('-', "_"), i),
+            files: files.iter().map(quote).collect(),
+            flags: flags.iter().map(quote).collect(),
+        })
+        .collect()
+}
+
 /// Emit `build.rs` to make it easier to link in native libraries
 fn emit_build_rs(
     tcfg: &TranspilerConfig,
     reg: &Handlebars,
     build_dir: &Path,
-    link_cmd: &LinkCmd,
-) -> Option<PathBuf> {
+    ccfg: &CrateConfig,
+    plan: Option<&Plan>,
+) -> Result<Option<PathBuf>, TranspileError> {
     let json = json!({
-        "libraries": link_cmd.libs,
+        "libraries": ccfg.link_cmd.libs,
+        "native": native_groups(&ccfg.crate_name, &ccfg.native_cmds),
     });
-    let output = reg.render("build.rs", &json).unwrap();
     let output_path = build_dir.join("build.rs");
-    maybe_write_to_file(&output_path, output, tcfg.overwrite_existing)
+    let output = render(reg, "build.rs", &json).map_err(TranspileError::io(&output_path))?;
+    maybe_write_to_file(&output_path, output, tcfg.overwrite_build_files(), plan)
 }
 
 /// Emit lib.rs (main.rs) for a library (binary). Returns `Some(path)`
@@ -282,7 +395,8 @@ fn emit_lib_rs(
     pragmas: PragmaSet,
     crates: &CrateSet,
     dependency_graph: &DependencyGraph,
-) -> Option<PathBuf> {
+    plan: Option<&Plan>,
+) -> Result<Option<PathBuf>, TranspileError> {
     let modules = convert_module_list(
         tcfg,
         build_dir,
@@ -302,9 +416,9 @@ fn emit_lib_rs(
     });
 
     let output_path = build_dir.join(file_name);
-    let output = reg.render("lib.rs", &json).unwrap();
+    let output = render(reg, "lib.rs", &json).map_err(TranspileError::io(&output_path))?;
 
-    maybe_write_to_file(&output_path, output, tcfg.overwrite_existing)
+    maybe_write_to_file(&output_path, output, tcfg.overwrite_build_files(), plan)
 }
 
 /// Get lib for binary. Returns `String`
@@ -316,7 +430,7 @@ pub fn get_lib(
     pragmas: PragmaSet,
     crates: &CrateSet,
     dependency_graph: &DependencyGraph,
-) -> String {
+) -> io::Result<String> {
     let mut reg = Handlebars::new();
     reg.register_template_string("lib.rs", include_str!("lib.rs.hbs"))
         .unwrap();
@@ -339,15 +453,110 @@ pub fn get_lib(
         "crates": crates,
     });
 
-    reg.render("lib.rs", &json).unwrap()
+    render(&reg, "lib.rs", &json)
+}
+
+/// Get the preamble of a binary that links against the library crate
+/// `lib_crate` instead of declaring the library modules itself.
+pub fn get_bin_preamble(
+    pragmas: PragmaSet,
+    crates: &CrateSet,
+    lib_crate: &str,
+) -> io::Result<String> {
+    let mut reg = Handlebars::new();
+    reg.register_template_string("lib.rs", include_str!("lib.rs.hbs"))
+        .unwrap();
+
+    let crates = convert_dependencies_list(crates.clone());
+    let json = json!({
+        "modules": [],
+        "pragmas": pragmas,
+        "crates": crates,
+    });
+
+    let mut preamble = render(&reg, "lib.rs", &json)?;
+    preamble.push_str(&format!("extern crate {};\n", lib_crate));
+    Ok(preamble)
+}
+
+/// Render `template`, with errors as I/O errors of the file being generated.
+fn render(reg: &Handlebars, template: &str, json: &serde_json::Value) -> io::Result<String> {
+    reg.render(template, json)
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+}
+
+/// Emit the dispatcher of a multi-call binary and a script that installs a
+/// symlink to it for each of its utilities.
+fn emit_multicall(
+    tcfg: &TranspilerConfig,
+    reg: &Handlebars,
+    build_dir: &Path,
+    ccfg: &CrateConfig,
+    dependency_graph: &DependencyGraph,
+    plan: Option<&Plan>,
+) -> Result<(), TranspileError> {
+    let utilities = multicall_utilities(
+        tcfg,
+        build_dir,
+        &ccfg.crate_name,
+        &ccfg.modules,
+        dependency_graph,
+    );
+    if utilities.is_empty() {
+        warn!(
+            "No binaries found for the multi-call binary {}",
+            ccfg.crate_name
+        );
+    }
+    let json = json!({
+        "crate_name": ccfg.crate_name,
+        "crate_rust_name": ccfg.crate_name.replace('-', "_"),
+        "utilities": utilities,
+    });
+
+    let output_path = build_dir.join(MULTICALL_RS_FILE);
+    let output = render(reg, "multicall.rs", &json).map_err(TranspileError::io(&output_path))?;
+    maybe_write_to_file(&output_path, output, tcfg.overwrite_build_files(), plan)?;
+
+    let output_path = build_dir.join("install-links.sh");
+    let output =
+        render(reg, "install-links.sh", &json).map_err(TranspileError::io(&output_path))?;
+    let written = maybe_write_to_file(&output_path, output, tcfg.overwrite_build_files(), plan)?;
+    #[cfg(unix)]
+    if let (Some(path), None) = (written, plan) {
+        let mut permissions = fs::metadata(&path)
+            .map_err(TranspileError::io(&path))?
+            .permissions();
+        permissions.set_mode(0o755);
+        fs::set_permissions(&path, permissions).map_err(TranspileError::io(&path))?;
+    }
+    Ok(())
 }
 
 /// If we translate variadic functions, the output will only compile
 /// on a nightly toolchain until the `c_variadics` feature is stable.
-fn emit_rust_toolchain(tcfg: &TranspilerConfig, build_dir: &Path) {
+fn emit_rust_toolchain(
+    tcfg: &TranspilerConfig,
+    build_dir: &Path,
+    plan: Option<&Plan>,
+) -> Result<(), TranspileError> {
     let output_path = build_dir.join("rust-toolchain.toml");
     let output = include_str!("../../rust-toolchain.toml").to_string();
-    maybe_write_to_file(&output_path, output, tcfg.overwrite_existing);
+    maybe_write_to_file(&output_path, output, tcfg.overwrite_build_files(), plan)?;
+    Ok(())
+}
+
+/// Whether the binaries of a crate link against its library crate
+fn binaries_use_lib_crate(tcfg: &TranspilerConfig, has_binaries: bool) -> bool {
+    tcfg.binaries_use_lib() && has_binaries || tcfg.multicall
+}
+
+/// Whether other crates can depend on the library of a crate linked as
+/// `link_type` with the binary modules `binaries`, i.e., whether the library
+/// is built as an rlib.
+pub fn has_rlib(tcfg: &TranspilerConfig, link_type: &LinkType, binaries: &[PathBuf]) -> bool {
+    let use_lib = binaries_use_lib_crate(tcfg, !binaries.is_empty());
+    !tcfg.emit_no_lib && (!matches!(link_type, LinkType::Shared) || use_lib)
 }
 
 fn emit_cargo_toml<'lcmd>(
@@ -357,7 +566,8 @@ fn emit_cargo_toml<'lcmd>(
     crate_cfg: &Option<CrateConfig<'lcmd>>,
     workspace_members: Option<Vec<String>>,
     dependency_graph: &DependencyGraph,
-) {
+    plan: Option<&Plan>,
+) -> Result<(), TranspileError> {
     // rust_checks_path is gone because we don't want to refer to the source
     // path but instead want the cross-check libs to be installed via cargo.
     let mut json = json!({
@@ -374,6 +584,15 @@ fn emit_cargo_toml<'lcmd>(
             dependency_graph,
         );
         let dependencies = convert_dependencies_list(ccfg.crates.clone());
+        let mut binaries = binaries;
+        if tcfg.multicall {
+            binaries.push(Module {
+                path: Some(MULTICALL_RS_FILE.to_owned()),
+                name: ccfg.crate_name.clone(),
+                open: false,
+                close: false,
+            });
+        }
         let crate_json = if tcfg.emit_no_lib {
             json!({
                 "crate_name": ccfg.crate_name,
@@ -382,16 +601,25 @@ fn emit_cargo_toml<'lcmd>(
                 "is_library": false,
                 "binaries": binaries,
                 "dependencies": dependencies,
+                "native": !ccfg.native_cmds.is_empty(),
             })
         } else {
+            // Binaries that link against the library need it as an rlib
+            let link_type = &ccfg.link_cmd.r#type;
+            let use_lib = binaries_use_lib_crate(tcfg, !binaries.is_empty());
+            let crate_types = match link_type {
+                LinkType::Shared if use_lib => "\"cdylib\", \"rlib\"",
+                _ => link_type.as_cargo_types(),
+            };
             json!({
                 "crate_name": ccfg.crate_name,
                 "crate_rust_name": ccfg.crate_name.replace('-', "_"),
-                "crate_types": ccfg.link_cmd.r#type.as_cargo_types(),
-                "is_library": ccfg.link_cmd.r#type.is_library(),
+                "crate_types": crate_types,
+                "is_library": link_type.is_library() || use_lib,
                 "lib_rs_file": get_lib_rs_file_name(tcfg),
                 "binaries": binaries,
                 "dependencies": dependencies,
+                "native": !ccfg.native_cmds.is_empty(),
             })
         };
         json.as_object_mut().unwrap().extend(
@@ -405,24 +633,75 @@ fn emit_cargo_toml<'lcmd>(
 
     let file_name = "Cargo.toml";
     let output_path = build_dir.join(file_name);
-    let output = reg.render(file_name, &json).unwrap();
-    maybe_write_to_file(&output_path, output, tcfg.overwrite_existing);
+    let output = render(reg, file_name, &json).map_err(TranspileError::io(&output_path))?;
+    maybe_write_to_file(&output_path, output, tcfg.overwrite_build_files(), plan)?;
+    Ok(())
 }
 
-fn maybe_write_to_file(output_path: &Path, output: String, overwrite: bool) -> Option<PathBuf> {
+pub(crate) fn maybe_write_to_file(
+    output_path: &Path,
+    output: String,
+    overwrite: bool,
+    plan: Option<&Plan>,
+) -> Result<Option<PathBuf>, TranspileError> {
     if output_path.exists() && !overwrite {
-        eprintln!("Skipping existing file {}", output_path.display());
-        return None;
+        match plan {
+            Some(plan) => plan.add_build_file(output_path, FileAction::SkipExisting),
+            None => eprintln!("Skipping existing file {}", output_path.display()),
+        }
+        return Ok(None);
+    }
+    if let Some(plan) = plan {
+        plan.add_build_file(output_path, FileAction::write(output_path));
+        return Ok(Some(PathBuf::from(output_path)));
     }
 
-    let mut file = match File::create(&output_path) {
-        Ok(file) => file,
-        Err(e) => panic!("Unable to open file for writing: {}", e),
-    };
-    match file.write_all(output.as_bytes()) {
-        Ok(()) => (),
-        Err(e) => panic!("Unable to write translation to file: {}", e),
-    };
+    let mut file = File::create(&output_path).map_err(TranspileError::io(output_path))?;
+    file.write_all(output.as_bytes())
+        .map_err(TranspileError::io(output_path))?;
 
-    Some(PathBuf::from(output_path))
+    Ok(Some(PathBuf::from(output_path)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ccmd(file: &str, flags: &[&str]) -> Arc<CompileCmd> {
+        let mut arguments = vec!["cc", "-c"];
+        arguments.extend(flags);
+        arguments.push(file);
+        let cmd = json!({
+            "directory": "/build",
+            "file": file,
+            "arguments": arguments,
+        });
+        Arc::new(serde_json::from_value(cmd).unwrap())
+    }
+
+    #[test]
+    fn native_groups_by_flags() {
+        let cmds = [
+            ccmd("/src/a.c", &["-O2", "-I", "inc"]),
+            ccmd("/src/b.S", &["-DASM"]),
+            ccmd("/src/c.c", &["-O2", "-I", "inc"]),
+        ];
+        let groups = native_groups("my-crate", &cmds);
+        assert_eq!(groups.len(), 2);
+
+        let asm = &groups[0];
+        assert_eq!(asm.name, "my_crate_native_0");
+        assert_eq!(asm.files, ["\"/src/b.S\""]);
+        assert_eq!(asm.flags, ["\"-DASM\""]);
+
+        let c = &groups[1];
+        assert_eq!(c.name, "my_crate_native_1");
+        assert_eq!(c.files, ["\"/src/a.c\"", "\"/src/c.c\""]);
+        assert_eq!(c.flags, ["\"-O2\"", "\"-I\"", "\"/build/inc\""]);
+    }
+
+    #[test]
+    fn no_native_groups() {
+        assert!(native_groups("my-crate", &[]).is_empty());
+    }
 }
@@ -193,6 +193,9 @@ fn parse_attributes(attributes: Vec<Value>) -> IndexSet<Attribute> {
                 attrs.insert(Attribute::Used);
             }
             "visibility" => expect_visibility_value = true,
+            "weak" => {
+                attrs.insert(Attribute::Weak);
+            }
             "section" => expect_section_value = true,
             s if expect_section_value => {
                 attrs.insert(Attribute::Section(s.into()));
@@ -231,6 +234,9 @@ pub struct ConversionContext {
     pub typed_context: TypedAstContext,
 
     pub invalid_clang_ast: bool,
+
+    /// Why the Clang AST is invalid, for the failure report
+    pub invalid_clang_ast_errors: Vec<TranslationError>,
 }
 
 fn display_loc(ctx: &AstContext, loc: &Option<SrcSpan>) -> Option<DisplaySrcSpan> {
@@ -250,7 +256,7 @@ fn has_packed_attribute(attrs: Vec<Value>) -> bool {
 impl ConversionContext {
     /// Create a new 'ConversionContext' seeded with top-level nodes from an 'AstContext'.
     pub fn new(untyped_context: &AstContext) -> ConversionContext {
-        let mut invalid_clang_ast = false;
+        let mut invalid_clang_ast_errors = vec![];
 
         // This starts out as all of the top-level nodes, which we expect to be 'DECL's
         let mut visit_as: Vec<(ClangId, NodeType)> = Vec::new();
@@ -258,55 +264,51 @@ impl ConversionContext {
             if untyped_context.ast_nodes.contains_key(top_node) {
                 visit_as.push((*top_node, node_types::DECL));
             } else {
-                diag!(
-                    Diagnostic::ClangAst,
-                    "{}",
-                    TranslationError::new(
-                        None,
-                        err_msg(format!("Missing top-level node with id: {}", top_node)).context(
-                            TranslationErrorKind::InvalidClangAst(
-                                ClangAstParseErrorKind::MissingNode,
-                            )
-                        )
+                let error = TranslationError::new(
+                    None,
+                    err_msg(format!("Missing top-level node with id: {}", top_node)).context(
+                        TranslationErrorKind::InvalidClangAst(ClangAstParseErrorKind::MissingNode),
                     ),
                 );
-                invalid_clang_ast = true;
+                diag!(Diagnostic::ClangAst, "{}", error);
+                invalid_clang_ast_errors.push(error);
             }
         }
 
-        for node in untyped_context.ast_nodes.values() {
+        // Report the invalid nodes in source order rather than that of the
+        // hash map; the node ID orders nodes of the same span, like the
+        // implicit nodes of a macro expansion
+        let mut nodes = untyped_context.ast_nodes.iter().collect::<Vec<_>>();
+        nodes.sort_by_key(|&(&id, node)| (node.loc, id));
+        for (_, node) in nodes {
             for child in node.children.iter().flatten() {
                 if !untyped_context.ast_nodes.contains_key(child) {
-                    diag!(
-                        Diagnostic::ClangAst,
-                        "{}",
-                        TranslationError::new(
-                            display_loc(untyped_context, &Some(node.loc)),
-                            err_msg(format!("Missing child {} of node {:?}", child, node,))
-                                .context(TranslationErrorKind::InvalidClangAst(
-                                    ClangAstParseErrorKind::MissingChild,
-                                )),
+                    let error = TranslationError::new(
+                        display_loc(untyped_context, &Some(node.loc)),
+                        err_msg(format!("Missing child {} of node {:?}", child, node,)).context(
+                            TranslationErrorKind::InvalidClangAst(
+                                ClangAstParseErrorKind::MissingChild,
+                            ),
                         ),
                     );
-                    invalid_clang_ast = true;
+                    diag!(Diagnostic::ClangAst, "{}", error);
+                    invalid_clang_ast_errors.push(error);
                 }
             }
 
             if let Some(type_id) = &node.type_id {
                 let type_ptr = type_id & TypeNode::ID_MASK;
                 if !untyped_context.type_nodes.contains_key(&type_ptr) {
-                    diag!(
-                        Diagnostic::ClangAst,
-                        "{}",
-                        TranslationError::new(
-                            display_loc(untyped_context, &Some(node.loc)),
-                            err_msg(format!("Missing type {} for node: {:?}", type_id, node,))
-                                .context(TranslationErrorKind::InvalidClangAst(
-                                    ClangAstParseErrorKind::MissingType,
-                                )),
+                    let error = TranslationError::new(
+                        display_loc(untyped_context, &Some(node.loc)),
+                        err_msg(format!("Missing type {} for node: {:?}", type_id, node,)).context(
+                            TranslationErrorKind::InvalidClangAst(
+                                ClangAstParseErrorKind::MissingType,
+                            ),
                         ),
                     );
-                    invalid_clang_ast = true;
+                    diag!(Diagnostic::ClangAst, "{}", error);
+                    invalid_clang_ast_errors.push(error);
                 }
             }
         }
@@ -316,7 +318,8 @@ impl ConversionContext {
             processed_nodes: HashMap::new(),
             visit_as,
             typed_context: TypedAstContext::new(&untyped_context.files),
-            invalid_clang_ast,
+            invalid_clang_ast: !invalid_clang_ast_errors.is_empty(),
+            invalid_clang_ast_errors,
         };
 
         ctx.convert(untyped_context);
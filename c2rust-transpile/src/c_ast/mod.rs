@@ -45,9 +45,11 @@ use iterators::{DFNodes, SomeId};
 /// AST context containing all of the nodes in the Clang AST
 #[derive(Debug, Clone)]
 pub struct TypedAstContext {
-    c_types: HashMap<CTypeId, CType>,
-    c_exprs: HashMap<CExprId, CExpr>,
-    c_stmts: HashMap<CStmtId, CStmt>,
+    // Nodes are kept in the order they were converted in, so that iterating
+    // over or dumping them does not vary between runs.
+    c_types: IndexMap<CTypeId, CType>,
+    c_exprs: IndexMap<CExprId, CExpr>,
+    c_stmts: IndexMap<CStmtId, CStmt>,
 
     // Decls require a stable iteration order as this map will be
     // iterated over export all defined types during translation.
@@ -55,7 +57,7 @@ pub struct TypedAstContext {
 
     pub c_decls_top: Vec<CDeclId>,
     pub c_main: Option<CDeclId>,
-    pub parents: HashMap<CDeclId, CDeclId>, // record fields and enum constants
+    pub parents: IndexMap<CDeclId, CDeclId>, // record fields and enum constants
 
     // Mapping from FileId to SrcFile. Deduplicated by file path.
     files: Vec<SrcFile>,
@@ -71,14 +73,14 @@ pub struct TypedAstContext {
     pub label_names: IndexMap<CLabelId, Rc<str>>,
 
     // map expressions to the stack of macros they were expanded from
-    pub macro_invocations: HashMap<CExprId, Vec<CDeclId>>,
+    pub macro_invocations: IndexMap<CExprId, Vec<CDeclId>>,
 
     // map macro decls to the expressions they expand to
-    pub macro_expansions: HashMap<CDeclId, Vec<CExprId>>,
+    pub macro_expansions: IndexMap<CDeclId, Vec<CExprId>>,
 
     // map expressions to the text of the macro invocation they expanded from,
     // if any
-    pub macro_expansion_text: HashMap<CExprId, String>,
+    pub macro_expansion_text: IndexMap<CExprId, String>,
 
     pub comments: Vec<Located<String>>,
 
@@ -102,6 +104,16 @@ pub struct DisplaySrcSpan {
     loc: SrcSpan,
 }
 
+impl DisplaySrcSpan {
+    pub fn file(&self) -> Option<&Path> {
+        self.file.as_deref()
+    }
+
+    pub fn span(&self) -> SrcSpan {
+        self.loc
+    }
+}
+
 impl Display for DisplaySrcSpan {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         if let Some(ref file) = self.file {
@@ -167,20 +179,20 @@ impl TypedAstContext {
         }
 
         TypedAstContext {
-            c_types: HashMap::new(),
-            c_exprs: HashMap::new(),
+            c_types: IndexMap::new(),
+            c_exprs: IndexMap::new(),
             c_decls: IndexMap::new(),
-            c_stmts: HashMap::new(),
+            c_stmts: IndexMap::new(),
 
             c_decls_top: Vec::new(),
             c_main: None,
             files,
             file_map,
             include_map,
-            parents: HashMap::new(),
-            macro_invocations: HashMap::new(),
-            macro_expansions: HashMap::new(),
-            macro_expansion_text: HashMap::new(),
+            parents: IndexMap::new(),
+            macro_invocations: IndexMap::new(),
+            macro_expansions: IndexMap::new(),
+            macro_expansion_text: IndexMap::new(),
             label_names: Default::default(),
 
             comments: Vec::new(),
@@ -669,9 +681,11 @@ impl TypedAstContext {
     }
 
     pub fn sort_top_decls(&mut self) {
-        // Group and sort declarations by file and by position
+        // Group and sort declarations by file and by position. The sort is
+        // stable, so that declarations at the same position, e.g., from one
+        // macro expansion, keep the order of the AST.
         let mut decls_top = mem::take(&mut self.c_decls_top);
-        decls_top.sort_unstable_by(|a, b| {
+        decls_top.sort_by(|a, b| {
             let a = self.index(*a);
             let b = self.index(*b);
             use Ordering::*;
@@ -829,6 +843,14 @@ impl CommentContext {
     }
 }
 
+#[cfg(test)]
+impl TypedAstContext {
+    /// Add a type, for tests without a Clang AST
+    pub(crate) fn add_type(&mut self, id: CTypeId, kind: CTypeKind) {
+        self.c_types.insert(id, Located { loc: None, kind });
+    }
+}
+
 impl Index<CTypeId> for TypedAstContext {
     type Output = CType;
 
@@ -1738,6 +1760,8 @@ pub enum Attribute {
     Used,
     /// __attribute((visibility("hidden")))
     Visibility(String),
+    /// __attribute__((weak, __weak__))
+    Weak,
     /// __attribute__((fallthrough, __fallthrough__))
     Fallthrough,
 }
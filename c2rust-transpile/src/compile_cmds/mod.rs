@@ -1,13 +1,15 @@
 use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::path::{Path, PathBuf};
-use std::rc::Rc;
+use std::sync::Arc;
 
 use failure::Error;
-use log::warn;
+use log::{info, warn};
 use regex::Regex;
 use serde_derive::Deserialize;
 
+use crate::assembly::is_assembly;
+
 #[derive(Deserialize, Debug, Default, Clone)]
 pub struct CompileCmd {
     /// The working directory of the compilation. All paths specified in the command
@@ -22,26 +24,77 @@ pub struct CompileCmd {
     /// to rerun the exact compilation step for the translation unit in the environment
     /// the build system uses. Parameters use shell quoting and shell escaping of quotes,
     /// with ‘"’ and ‘\’ being the only special characters. Shell expansion is not supported.
-    #[serde(skip_deserializing)]
-    _command: Option<String>,
+    #[serde(default)]
+    command: Option<String>,
     /// The compile command executed as list of strings. Either arguments or command is required.
-    #[serde(default, skip_deserializing)]
-    _arguments: Vec<String>,
+    #[serde(default)]
+    arguments: Vec<String>,
     /// The name of the output created by this compilation step. This field is optional. It can
     /// be used to distinguish different processing modes of the same input file.
     output: Option<String>,
 }
 
 impl CompileCmd {
+    /// The absolute path of the input file. If the file doesn't exist, this
+    /// is not canonicalized, and it is up to the caller to report it missing.
     pub fn abs_file(&self) -> PathBuf {
         match self.file.is_absolute() {
             true => self.file.clone(),
             false => {
                 let path = self.directory.join(&self.file);
-                let e = format!("could not canonicalize {}", path.display());
-                path.canonicalize().expect(&e)
+                path.canonicalize().unwrap_or(path)
+            }
+        }
+    }
+
+    /// The compiler invocation as a list of arguments, whichever of
+    /// `arguments` or `command` the database provided.
+    pub fn args(&self) -> Vec<String> {
+        if !self.arguments.is_empty() {
+            return self.arguments.clone();
+        }
+        self.command
+            .as_deref()
+            .and_then(shlex::split)
+            .unwrap_or_default()
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    /// The flags to compile the input file with again, outside of its
+    /// original build: the compiler, input, output and dependency file
+    /// arguments are dropped, and relative include paths are made absolute.
+    pub fn native_flags(&self) -> Vec<String> {
+        let mut args = self.args().into_iter().skip(1);
+        let input = self.file.to_str();
+        let mut flags = vec![];
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-c" | "-MD" | "-MMD" | "-MP" | "-M" | "-MM" => {}
+                "-o" | "-MF" | "-MT" | "-MQ" => {
+                    args.next();
+                }
+                "-I" | "-isystem" | "-iquote" | "-include" => {
+                    if let Some(path) = args.next() {
+                        flags.push(arg);
+                        flags.push(self.abs_flag_path(&path));
+                    }
+                }
+                _ if Some(arg.as_str()) == input => {}
+                _ if arg.starts_with("-o") || arg.starts_with("-MF") => {}
+                _ if arg.starts_with("-I") => {
+                    flags.push(format!("-I{}", self.abs_flag_path(&arg[2..])));
+                }
+                _ => flags.push(arg),
             }
         }
+        flags
+    }
+
+    fn abs_flag_path(&self, path: &str) -> String {
+        self.directory.join(path).to_string_lossy().into_owned()
     }
 
     pub fn abs_output_file(&self) -> Option<PathBuf> {
@@ -52,8 +105,7 @@ impl CompileCmd {
                     true => Some(output_file),
                     false => {
                         let path = self.directory.join(&output_file);
-                        let e = format!("could not canonicalize {}", path.display());
-                        Some(path.canonicalize().expect(&e))
+                        Some(path.canonicalize().unwrap_or(path))
                     }
                 }
             }
@@ -92,7 +144,7 @@ impl LinkType {
 pub struct LinkCmd {
     /// All input files going into this link
     pub inputs: Vec<String>,
-    /// The output file; this is taken from the `CompileCmd`
+    /// The absolute path of the output file; this is taken from the `CompileCmd`
     #[serde(default)]
     pub output: Option<String>,
     /// List of libraries to link in (without `-l` prefix)
@@ -103,13 +155,21 @@ pub struct LinkCmd {
     pub r#type: LinkType,
     /// Input files in `CompileCmd` form
     #[serde(default)]
-    pub cmd_inputs: Vec<Rc<CompileCmd>>,
+    pub cmd_inputs: Vec<Arc<CompileCmd>>,
+    /// Input files that are not translated, i.e., filtered out or assembly
+    /// files, in `CompileCmd` form
+    #[serde(default)]
+    pub native_inputs: Vec<Arc<CompileCmd>>,
     #[serde(default)]
     pub top_level: bool,
+    /// The working directory of the link, which relative `inputs` and
+    /// `lib_dirs` are relative to; this is taken from the `CompileCmd`
+    #[serde(default)]
+    pub directory: PathBuf,
 }
 
 /// Convert a linear vector of `CompileCmd`s into a DAG of `LinkCmd`s and `CompileCmd`s
-fn build_link_commands(mut v: Vec<Rc<CompileCmd>>) -> Result<Vec<LinkCmd>, Error> {
+fn build_link_commands(mut v: Vec<Arc<CompileCmd>>) -> Result<Vec<LinkCmd>, Error> {
     let mut output_map = HashMap::new();
     for (idx, ccmd) in v.iter().enumerate() {
         if let Some(ref output) = ccmd.output {
@@ -126,10 +186,13 @@ fn build_link_commands(mut v: Vec<Rc<CompileCmd>>) -> Result<Vec<LinkCmd>, Error
         };
         let mut lcmd: LinkCmd = serde_bencode::from_str(lcmd)?;
 
-        lcmd.output = ccmd.output.clone();
+        lcmd.output = ccmd
+            .abs_output_file()
+            .map(|path| path.to_string_lossy().into_owned());
+        lcmd.directory = ccmd.directory.clone();
         for inp in &lcmd.inputs {
             if let Some(ccmd_idx) = output_map.get(&inp) {
-                let inp_ccmd = Rc::clone(&v[*ccmd_idx]);
+                let inp_ccmd = Arc::clone(&v[*ccmd_idx]);
                 lcmd.cmd_inputs.push(inp_ccmd);
                 seen_ccmds.insert(*ccmd_idx);
             }
@@ -157,7 +220,9 @@ fn build_link_commands(mut v: Vec<Rc<CompileCmd>>) -> Result<Vec<LinkCmd>, Error
             lib_dirs: vec![],
             r#type: LinkType::Static,
             cmd_inputs: v,
+            native_inputs: vec![],
             top_level: true,
+            directory: PathBuf::new(),
         };
         res.push(lcmd);
     }
@@ -168,7 +233,7 @@ fn build_link_commands(mut v: Vec<Rc<CompileCmd>>) -> Result<Vec<LinkCmd>, Error
 /// some build scripts repeatedly compile the same input file with different
 /// command line flags thus creating multiple outputs. We remove any duplicates
 /// in the order we see them and warn the user.
-fn filter_duplicate_cmds(v: Vec<Rc<CompileCmd>>) -> Vec<Rc<CompileCmd>> {
+fn filter_duplicate_cmds(v: Vec<Arc<CompileCmd>>) -> Vec<Arc<CompileCmd>> {
     let mut seen = HashSet::new();
     let mut cmds = vec![];
 
@@ -194,37 +259,56 @@ fn filter_duplicate_cmds(v: Vec<Rc<CompileCmd>>) -> Vec<Rc<CompileCmd>> {
     cmds
 }
 
+/// Add the commands of files that are not translated to the link commands
+/// that use their outputs, or else to the top-level one.
+fn add_native_inputs(lcmds: &mut [LinkCmd], native: Vec<Arc<CompileCmd>>) {
+    for ccmd in native {
+        let lcmd = lcmds
+            .iter()
+            .position(|lcmd| {
+                ccmd.output
+                    .as_ref()
+                    .map_or(false, |output| lcmd.inputs.contains(output))
+            })
+            .or_else(|| lcmds.iter().position(|lcmd| lcmd.top_level));
+        match lcmd {
+            Some(idx) => lcmds[idx].native_inputs.push(ccmd),
+            None => info!(
+                "{} is not linked into any translated crate",
+                ccmd.abs_file().display()
+            ),
+        }
+    }
+}
+
 /// Read `compile_commands` file, optionally ignore any entries not matching
-/// `filter`, and filter out any .S files since they're likely assembly files.
+/// `filter` or whose input file `include` rejects, and filter out any .S files
+/// since they're likely assembly files. The ignored files are kept in the
+/// `native_inputs` of their link commands.
 pub fn get_compile_commands(
     compile_commands: &Path,
     filter: &Option<Regex>,
+    include: impl Fn(&Path) -> bool,
 ) -> Result<Vec<LinkCmd>, Error> {
     let f = std::io::BufReader::new(File::open(compile_commands)?); // open read-only
 
     // Read the JSON contents of the file as an instance of `Value`
-    let v: Vec<Rc<CompileCmd>> = serde_json::from_reader(f)?;
-
-    // apply the filter argument, if any
-    let v = if let &Some(ref re) = filter {
-        v.into_iter()
-            .filter(|c| re.is_match(c.file.to_str().unwrap()))
-            .collect::<Vec<Rc<CompileCmd>>>()
-    } else {
-        v
-    };
-
-    // Filter out any assembly files
-    let v = v
+    let v: Vec<Arc<CompileCmd>> = serde_json::from_reader(f)?;
+
+    // apply the filter argument, if any, and filter out any assembly files
+    let (v, ignored): (Vec<_>, Vec<_>) = v.into_iter().partition(|c| {
+        let file = c.file.to_str().unwrap();
+        filter.as_ref().map_or(true, |re| re.is_match(file))
+            && include(&c.abs_file())
+            && !is_assembly(&c.file)
+    });
+    let native = ignored
         .into_iter()
-        .filter(|c| {
-            let file = c.file.to_str().unwrap();
-            let likely_asm = file.ends_with(".S") || file.ends_with(".s");
-            !likely_asm
-        })
-        .collect::<Vec<Rc<CompileCmd>>>();
+        .filter(|c| !c.file.starts_with("/c2rust/link/"))
+        .collect::<Vec<_>>();
 
     let mut lcmds = build_link_commands(v)?;
+    add_native_inputs(&mut lcmds, native);
 
     for lcmd in &mut lcmds {
         let inputs = std::mem::take(&mut lcmd.cmd_inputs);
@@ -234,3 +318,111 @@ pub fn get_compile_commands(
 
     Ok(lcmds)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ccmd(arguments: &[&str], output: Option<&str>) -> Arc<CompileCmd> {
+        let file = arguments.last().unwrap();
+        let cmd = serde_json::json!({
+            "directory": "/build",
+            "file": file,
+            "arguments": arguments,
+            "output": output,
+        });
+        Arc::new(serde_json::from_value(cmd).unwrap())
+    }
+
+    fn lcmd(inputs: &[&str], top_level: bool) -> LinkCmd {
+        LinkCmd {
+            inputs: inputs.iter().map(|s| s.to_string()).collect(),
+            output: None,
+            libs: vec![],
+            lib_dirs: vec![],
+            r#type: LinkType::Static,
+            cmd_inputs: vec![],
+            native_inputs: vec![],
+            top_level,
+            directory: PathBuf::from("/build"),
+        }
+    }
+
+    #[test]
+    fn native_flags_drop_outputs_and_input() {
+        let cmd = ccmd(
+            &[
+                "cc", "-c", "-o", "foo.o", "-MD", "-MF", "foo.d", "-MT", "foo.o", "-DX=1", "-O2",
+                "-ofoo.o", "-MFfoo.d", "foo.c",
+            ],
+            Some("foo.o"),
+        );
+        assert_eq!(cmd.native_flags(), ["-DX=1", "-O2"]);
+    }
+
+    #[test]
+    fn native_flags_make_includes_absolute() {
+        let cmd = ccmd(
+            &[
+                "cc",
+                "-I",
+                "inc",
+                "-Isrc/inc",
+                "-include",
+                "config.h",
+                "-I/usr/include/foo",
+                "-isystem",
+                "sys",
+                "foo.c",
+            ],
+            None,
+        );
+        assert_eq!(
+            cmd.native_flags(),
+            [
+                "-I",
+                "/build/inc",
+                "-I/build/src/inc",
+                "-include",
+                "/build/config.h",
+                "-I/usr/include/foo",
+                "-isystem",
+                "/build/sys",
+            ]
+        );
+    }
+
+    #[test]
+    fn native_inputs_go_to_their_link_command() {
+        let mut lcmds = vec![lcmd(&["main.o", "asm.o"], false), lcmd(&[], true)];
+        let native = vec![
+            ccmd(&["cc", "-c", "asm.S"], Some("asm.o")),
+            ccmd(&["cc", "-c", "other.c"], Some("other.o")),
+            ccmd(&["cc", "-c", "none.c"], None),
+        ];
+        add_native_inputs(&mut lcmds, native);
+
+        let files = |lcmd: &LinkCmd| {
+            lcmd.native_inputs
+                .iter()
+                .map(|cmd| cmd.file.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(files(&lcmds[0]), [Path::new("asm.S")]);
+        // Files whose output no link command consumes go to the top level
+        assert_eq!(
+            files(&lcmds[1]),
+            [Path::new("other.c"), Path::new("none.c")]
+        );
+    }
+
+    #[test]
+    fn native_inputs_without_top_level() {
+        let mut lcmds = vec![lcmd(&["main.o"], false)];
+        add_native_inputs(
+            &mut lcmds,
+            vec![ccmd(&["cc", "-c", "other.c"], Some("other.o"))],
+        );
+        assert!(lcmds[0].native_inputs.is_empty());
+    }
+}
@@ -4,7 +4,8 @@ use crate::diagnostics::TranslationResult;
 use crate::renamer::*;
 use c2rust_ast_builder::{mk, properties::*};
 use failure::format_err;
-use std::collections::{HashMap, HashSet};
+use indexmap::IndexSet;
+use std::collections::HashMap;
 use std::ops::Index;
 use syn::*;
 
@@ -19,7 +20,7 @@ pub struct TypeConverter {
     renamer: Renamer<CDeclId>,
     fields: HashMap<CDeclId, Renamer<FieldKey>>,
     suffix_names: HashMap<(CDeclId, &'static str), String>,
-    features: HashSet<&'static str>,
+    features: IndexSet<&'static str>,
 }
 
 pub const RESERVED_NAMES: [&str; 103] = [
@@ -145,11 +146,11 @@ impl TypeConverter {
             renamer: Renamer::new(&RESERVED_NAMES),
             fields: HashMap::new(),
             suffix_names: HashMap::new(),
-            features: HashSet::new(),
+            features: IndexSet::new(),
         }
     }
 
-    pub fn features_used(&self) -> &HashSet<&'static str> {
+    pub fn features_used(&self) -> &IndexSet<&'static str> {
         &self.features
     }
 
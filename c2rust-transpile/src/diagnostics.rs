@@ -2,11 +2,12 @@ use colored::Colorize;
 use failure::{err_msg, Backtrace, Context, Error, Fail};
 use fern::colors::ColoredLevelConfig;
 use log::{Level, SetLoggerError};
+use std::cell::RefCell;
 use std::collections::HashSet;
 use std::fmt::{self, Display};
-use std::io;
+use std::io::{self, Write};
 use std::str::FromStr;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use strum_macros::{Display, EnumString};
 
 use crate::c_ast::{ClangAstParseErrorKind, DisplaySrcSpan};
@@ -28,6 +29,74 @@ macro_rules! diag {
 
 pub(crate) use diag;
 
+/// Print a progress message to stdout. Unlike `println!`, messages printed
+/// from a worker thread are buffered with the rest of that worker's output
+/// (see [`capture`]) so that translation units never interleave.
+macro_rules! progress {
+    ($($arg:tt)*) => ($crate::diagnostics::emit($crate::diagnostics::Stream::Stdout, format!($($arg)*)))
+}
+
+pub(crate) use progress;
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Stream {
+    Stdout,
+    Stderr,
+}
+
+/// Output collected by [`capture`], in the order it was emitted.
+#[derive(Debug, Default)]
+pub(crate) struct CapturedOutput(Vec<(Stream, String)>);
+
+/// Serializes writes to stdout/stderr so that lines (and replayed buffers)
+/// from different threads are never interleaved.
+static OUTPUT_LOCK: Mutex<()> = Mutex::new(());
+
+thread_local! {
+    static CAPTURED: RefCell<Option<CapturedOutput>> = RefCell::new(None);
+}
+
+/// Emit a line of output, buffering it if the current thread is capturing.
+pub(crate) fn emit(stream: Stream, line: String) {
+    let line = CAPTURED.with(|captured| match *captured.borrow_mut() {
+        Some(ref mut output) => {
+            output.0.push((stream, line));
+            None
+        }
+        None => Some(line),
+    });
+    if let Some(line) = line {
+        CapturedOutput(vec![(stream, line)]).replay();
+    }
+}
+
+/// Run `f`, collecting everything it logs or prints through [`progress!`]
+/// instead of writing it out immediately.
+pub(crate) fn capture<T>(f: impl FnOnce() -> T) -> (T, CapturedOutput) {
+    let outer = CAPTURED.with(|captured| captured.replace(Some(CapturedOutput::default())));
+    let res = f();
+    let output = CAPTURED.with(|captured| captured.replace(outer));
+    (res, output.unwrap_or_default())
+}
+
+impl CapturedOutput {
+    /// Write out the captured output in one go.
+    pub(crate) fn replay(self) {
+        let _guard = OUTPUT_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let stdout = io::stdout();
+        let stderr = io::stderr();
+        let mut stdout = stdout.lock();
+        let mut stderr = stderr.lock();
+        for (stream, line) in self.0 {
+            // Keep the relative order of stdout and stderr lines intact.
+            let _ = match stream {
+                Stream::Stdout => writeln!(stdout, "{}", line).and_then(|()| stdout.flush()),
+                Stream::Stderr => writeln!(stderr, "{}", line).and_then(|()| stderr.flush()),
+            };
+        }
+    }
+}
+
 pub fn init(mut enabled_warnings: HashSet<Diagnostic>, log_level: log::LevelFilter) {
     enabled_warnings.extend(DEFAULT_WARNINGS.iter().cloned());
 
@@ -62,7 +131,9 @@ pub fn init(mut enabled_warnings: HashSet<Diagnostic>, log_level: log::LevelFilt
                 .map(|d| enabled_warnings.contains(&d))
                 .unwrap_or(true)
         })
-        .chain(io::stderr())
+        .chain(fern::Output::call(|record| {
+            emit(Stream::Stderr, record.args().to_string())
+        }))
         .into_log();
     // Ignore the [`SetLoggerError`] b/c we just want to make sure it's set at least once.
     let _: Result<(), SetLoggerError> = log_reroute::init();
@@ -90,6 +161,9 @@ pub enum TranslationErrorKind {
 
     // Clang AST exported by AST-exporter was not valid
     InvalidClangAst(ClangAstParseErrorKind),
+
+    // The translator itself panicked
+    Panic,
 }
 
 /// Constructs a `TranslationError` using the standard string interpolation syntax.
@@ -125,6 +199,13 @@ impl Display for TranslationErrorKind {
             InvalidClangAst(_) => {
                 return write!(f, "Exported Clang AST was invalid. Check warnings above for unimplemented features.");
             }
+
+            Panic => {
+                return write!(
+                    f,
+                    "The translator panicked. This is a bug in C2Rust; please report it."
+                );
+            }
         }
         Ok(())
     }
@@ -169,6 +250,16 @@ impl TranslationError {
         msg.into()
     }
 
+    /// An error standing in for a panic caught while translating
+    pub fn panic(msg: String) -> Self {
+        err_msg(msg).context(TranslationErrorKind::Panic).into()
+    }
+
+    /// Source locations of the error, innermost first
+    pub fn locations(&self) -> &[DisplaySrcSpan] {
+        &self.loc
+    }
+
     pub fn add_loc(mut self, loc: Option<DisplaySrcSpan>) -> Self {
         if let Some(loc) = loc {
             self.loc.push(loc);
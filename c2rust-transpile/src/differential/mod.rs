@@ -0,0 +1,541 @@
+//! Differential test harnesses, which compare the translated functions of a
+//! library with those of the original C object on inputs generated by
+//! proptest.
+//!
+//! Each translation unit gets a crate of its own in `differential/<module>`
+//! of the build directory. It depends on the translated library and links
+//! the C object, after renaming every symbol the object defines so that the
+//! C and the Rust definitions can be linked side by side.
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use deps_builder::DependencyInfo;
+use handlebars::Handlebars;
+use log::warn;
+use pathdiff::diff_paths;
+use serde_json::json;
+
+use crate::build_files::maybe_write_to_file;
+use crate::c_ast::{CDeclKind, CQualTypeId, CTypeKind, TypedAstContext};
+use crate::diagnostics::progress;
+use crate::plan::Plan;
+use crate::{get_module_name, TranspileError, TranspilerConfig};
+
+/// The prefix of the renamed symbols of the C object
+const C_PREFIX: &str = "c2rust_c_";
+
+/// A parameter or return type that the harness can generate or compare
+#[derive(Debug, Clone, Copy)]
+enum HarnessType {
+    /// An integer type or `bool`, by its Rust name
+    Integer(&'static str),
+    /// A floating point type, compared by its bits so that NaNs are equal
+    Float(&'static str),
+    /// A pointer to a C string that is only read
+    String(&'static str),
+    /// A pointer to a writable buffer holding a C string
+    Buffer(&'static str),
+}
+
+impl HarnessType {
+    fn of(ast: &TypedAstContext, typ: CQualTypeId) -> Option<Self> {
+        use CTypeKind::*;
+        let char_type = |kind: &CTypeKind| match kind {
+            Char => Some("libc::c_char"),
+            SChar => Some("libc::c_schar"),
+            UChar => Some("libc::c_uchar"),
+            _ => None,
+        };
+        let integer = match ast.resolve_type(typ.ctype).kind {
+            Pointer(pointee) => {
+                let ty = char_type(&ast.resolve_type(pointee.ctype).kind)?;
+                return Some(if pointee.qualifiers.is_const {
+                    HarnessType::String(ty)
+                } else {
+                    HarnessType::Buffer(ty)
+                });
+            }
+            Float => return Some(HarnessType::Float("libc::c_float")),
+            Double => return Some(HarnessType::Float("libc::c_double")),
+            ref kind @ (Char | SChar | UChar) => char_type(kind)?,
+            Bool => "bool",
+            Short => "libc::c_short",
+            UShort => "libc::c_ushort",
+            Int => "libc::c_int",
+            UInt => "libc::c_uint",
+            Long => "libc::c_long",
+            ULong => "libc::c_ulong",
+            LongLong => "libc::c_longlong",
+            ULongLong => "libc::c_ulonglong",
+            Int128 => "i128",
+            UInt128 => "u128",
+            _ => return None,
+        };
+        Some(HarnessType::Integer(integer))
+    }
+
+    fn rust_type(self) -> String {
+        match self {
+            HarnessType::Integer(ty) | HarnessType::Float(ty) => ty.to_owned(),
+            HarnessType::String(ty) => format!("*const {}", ty),
+            HarnessType::Buffer(ty) => format!("*mut {}", ty),
+        }
+    }
+
+    fn strategy(self) -> String {
+        match self {
+            HarnessType::Integer(ty) | HarnessType::Float(ty) => format!("any::<{}>()", ty),
+            HarnessType::String(_) => "c_string()".to_owned(),
+            HarnessType::Buffer(_) => "buffer()".to_owned(),
+        }
+    }
+
+    /// The expression comparing the value `call` returns
+    fn returned(self, call: String) -> String {
+        match self {
+            HarnessType::Integer(_) => call,
+            HarnessType::Float(_) => format!("{}.to_bits()", call),
+            HarnessType::String(_) | HarnessType::Buffer(_) => format!("string_at({} as _)", call),
+        }
+    }
+}
+
+/// An exported function of a translation unit that the harness calls
+#[derive(Debug)]
+struct Function {
+    name: String,
+    /// The name of the translated function, which differs with
+    /// `--prefix-function-names`
+    rust_name: String,
+    params: Vec<HarnessType>,
+    /// `None` for `void`
+    ret: Option<HarnessType>,
+}
+
+impl Function {
+    /// The signature of `name`, if the harness can call it. `prefix` is
+    /// that of `--prefix-function-names`.
+    fn new(ast: &TypedAstContext, prefix: &str, name: &str, typ: &CTypeKind) -> Option<Self> {
+        let (ret, params) = match typ {
+            CTypeKind::Function(ret, params, false, _, true) => (ret, params),
+            _ => return None,
+        };
+        let ret = match ast.resolve_type(ret.ctype).kind {
+            CTypeKind::Void => None,
+            _ => Some(HarnessType::of(ast, *ret)?),
+        };
+        let params = params
+            .iter()
+            .filter(|param| !matches!(ast.resolve_type(param.ctype).kind, CTypeKind::Void))
+            .map(|&param| HarnessType::of(ast, param))
+            .collect::<Option<Vec<_>>>()?;
+        Some(Function {
+            name: name.to_owned(),
+            rust_name: format!("{}{}", prefix, name),
+            params,
+            ret,
+        })
+    }
+
+    fn declaration(&self) -> serde_json::Value {
+        let params = self
+            .params
+            .iter()
+            .enumerate()
+            .map(|(i, param)| format!("a{}: {}", i, param.rust_type()))
+            .collect::<Vec<_>>();
+        let ret = self
+            .ret
+            .map(|ret| format!(" -> {}", ret.rust_type()))
+            .unwrap_or_default();
+        json!({
+            "name": self.name,
+            "c_symbol": format!("{}{}", C_PREFIX, self.name),
+            "rust_symbol": self.rust_name,
+            "params": params.join(", "),
+            "ret": ret,
+        })
+    }
+
+    /// The call of the C (`side` is `c`) or the translated function (`rust`)
+    fn call(&self, side: &str) -> String {
+        let args = self
+            .params
+            .iter()
+            .enumerate()
+            .map(|(i, param)| match param {
+                HarnessType::Integer(_) | HarnessType::Float(_) => format!("a{}", i),
+                HarnessType::String(_) => format!("a{}.as_ptr() as _", i),
+                HarnessType::Buffer(_) => format!("{}_a{}.as_mut_ptr() as _", side, i),
+            })
+            .collect::<Vec<_>>();
+        let call = format!("{}_{}({})", side, self.name, args.join(", "));
+        match self.ret {
+            Some(ret) => ret.returned(call),
+            None => call,
+        }
+    }
+
+    fn test(&self) -> serde_json::Value {
+        let args = self
+            .params
+            .iter()
+            .enumerate()
+            .map(|(i, param)| format!("a{} in {}", i, param.strategy()))
+            .collect::<Vec<_>>();
+        let buffers = self
+            .params
+            .iter()
+            .enumerate()
+            .filter(|(_, param)| matches!(param, HarnessType::Buffer(_)))
+            .map(|(i, _)| format!("a{}", i))
+            .collect::<Vec<_>>();
+        json!({
+            "name": self.name,
+            "args": args.join(", "),
+            "buffers": buffers,
+            "c_call": self.call("c"),
+            "rust_call": self.call("rust"),
+        })
+    }
+}
+
+/// The functions of one translation unit, and what is needed to link its
+/// C object.
+#[derive(Debug)]
+struct Harness {
+    input: PathBuf,
+    /// The translated module
+    module: PathBuf,
+    object: Option<PathBuf>,
+    /// Every symbol the C object defines
+    symbols: Vec<String>,
+    functions: Vec<Function>,
+    /// The exported functions with other parameter or return types
+    untested: Vec<String>,
+}
+
+impl Harness {
+    /// The data of the templates of the harness `name` in `harness_dir`,
+    /// which links `object` and the crate `crate_name` in `build_dir`
+    fn template_data(
+        &self,
+        name: &str,
+        object: &Path,
+        crate_name: &str,
+        build_dir: &Path,
+        harness_dir: &Path,
+    ) -> serde_json::Value {
+        let quote = |s: &str| format!("{:?}", s);
+        let (prop_tests, plain_tests) = self
+            .functions
+            .iter()
+            .partition::<Vec<_>, _>(|function| !function.params.is_empty());
+        json!({
+            "name": name,
+            "input": self.input.display().to_string(),
+            "object": quote(&object.to_string_lossy()),
+            "prefix": C_PREFIX,
+            "symbols": self.symbols.iter().map(|s| quote(s)).collect::<Vec<_>>(),
+            "crate_name": crate_name,
+            "crate_rust_name": crate_name.replace('-', "_"),
+            "crate_path": diff_paths(build_dir, harness_dir).unwrap(),
+            "functions": self.functions.iter().map(Function::declaration).collect::<Vec<_>>(),
+            "prop_tests": prop_tests.iter().map(|f| f.test()).collect::<Vec<_>>(),
+            "plain_tests": plain_tests.iter().map(|f| f.test()).collect::<Vec<_>>(),
+            "untested": self.untested.join(", "),
+        })
+    }
+}
+
+fn templates() -> Handlebars<'static> {
+    let mut reg = Handlebars::new();
+    reg.register_template_string("Cargo.toml", include_str!("Cargo.toml.hbs"))
+        .unwrap();
+    reg.register_template_string("build.rs", include_str!("build.rs.hbs"))
+        .unwrap();
+    reg.register_template_string("differential.rs", include_str!("differential.rs.hbs"))
+        .unwrap();
+    reg
+}
+
+/// The harnesses of the translation units of a run.
+#[derive(Debug, Default)]
+pub struct DifferentialTests {
+    harnesses: Mutex<Vec<Harness>>,
+}
+
+impl DifferentialTests {
+    /// Collect the exported functions of the translation unit described by
+    /// `dependency_info`, which is translated into `module`.
+    pub fn add(
+        &self,
+        tcfg: &TranspilerConfig,
+        ast: &TypedAstContext,
+        dependency_info: &DependencyInfo,
+        module: &Path,
+    ) {
+        let symbols = dependency_info
+            .defined
+            .iter()
+            .map(|symbol| symbol.name.clone())
+            .collect::<BTreeSet<_>>();
+        let prefix = tcfg.prefix_function_names.as_deref().unwrap_or_default();
+        let mut functions = vec![];
+        let mut untested = vec![];
+        for (_, decl) in ast.iter_decls() {
+            match decl.kind {
+                CDeclKind::Function {
+                    is_global: true,
+                    body: Some(_),
+                    is_inline,
+                    is_inline_externally_visible,
+                    typ,
+                    ref name,
+                    ..
+                } if (!is_inline || is_inline_externally_visible)
+                    && symbols.contains(name)
+                    && name != "main" =>
+                {
+                    let typ = &ast.resolve_type(typ).kind;
+                    match Function::new(ast, prefix, name, typ) {
+                        Some(function) => functions.push(function),
+                        None => untested.push(name.clone()),
+                    }
+                }
+                _ => {}
+            }
+        }
+        functions.sort_by(|a, b| a.name.cmp(&b.name));
+        untested.sort();
+        self.harnesses.lock().unwrap().push(Harness {
+            input: PathBuf::from(&dependency_info.input_path),
+            module: module.to_owned(),
+            object: dependency_info.object_path.as_ref().map(PathBuf::from),
+            symbols: symbols.into_iter().collect(),
+            functions,
+            untested,
+        });
+    }
+
+    /// Emit the harnesses of the `modules` of the crate `crate_name` in
+    /// `build_dir`. `has_rlib` tells whether other crates can link against
+    /// its library.
+    pub fn emit(
+        &self,
+        tcfg: &TranspilerConfig,
+        build_dir: &Path,
+        crate_name: &str,
+        modules: &[PathBuf],
+        has_rlib: bool,
+        plan: Option<&Plan>,
+    ) -> Result<(), TranspileError> {
+        let harnesses = self.harnesses.lock().unwrap();
+        let harnesses = harnesses
+            .iter()
+            .filter(|harness| modules.contains(&harness.module))
+            .filter(|harness| !harness.functions.is_empty())
+            .collect::<Vec<_>>();
+        if harnesses.is_empty() {
+            return Ok(());
+        }
+        if !has_rlib {
+            warn!(
+                "The library of {} is not built as an rlib; no differential tests emitted for it",
+                crate_name
+            );
+            return Ok(());
+        }
+
+        let reg = templates();
+        let src_dir = build_dir.join("src");
+        for harness in harnesses {
+            let object = match &harness.object {
+                Some(object) => object,
+                None => {
+                    warn!(
+                        "No object file is known for {}; no differential tests emitted for it",
+                        harness.input.display()
+                    );
+                    continue;
+                }
+            };
+            // Name the harness after the module path, e.g., `lib_quotearg`
+            // for `src/lib/quotearg.rs`
+            let relpath = harness
+                .module
+                .strip_prefix(&src_dir)
+                .or_else(|_| harness.module.strip_prefix(build_dir))
+                .unwrap_or(&harness.module)
+                .with_extension("");
+            let name = relpath
+                .iter()
+                .map(|sm| get_module_name(Path::new(sm), true, false, false).unwrap())
+                .collect::<Vec<_>>()
+                .join("_");
+            let harness_dir = build_dir.join("differential").join(&name);
+            let json = harness.template_data(&name, object, crate_name, build_dir, &harness_dir);
+
+            if plan.is_none() {
+                fs::create_dir_all(&harness_dir).map_err(TranspileError::io(&harness_dir))?;
+            }
+            let overwrite = tcfg.overwrite_build_files();
+            for file_name in ["Cargo.toml", "build.rs", "differential.rs"] {
+                let output = reg.render(file_name, &json).unwrap();
+                let output_path = harness_dir.join(file_name);
+                maybe_write_to_file(&output_path, output, overwrite, plan)?;
+            }
+            if plan.is_none() {
+                progress!(
+                    "Emitted differential tests of {} function(s) in {}",
+                    harness.functions.len(),
+                    harness_dir.display()
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::c_ast::{CTypeId, Qualifiers};
+
+    const INT: CTypeId = CTypeId(1);
+    const DOUBLE: CTypeId = CTypeId(2);
+    const CHAR: CTypeId = CTypeId(3);
+    const CONST_CHAR_PTR: CTypeId = CTypeId(4);
+    const CHAR_PTR: CTypeId = CTypeId(5);
+    const INT_PTR: CTypeId = CTypeId(6);
+    const VOID: CTypeId = CTypeId(7);
+
+    fn ast() -> TypedAstContext {
+        let mut ast = TypedAstContext::new(&[]);
+        let const_char = CQualTypeId {
+            qualifiers: Qualifiers {
+                is_const: true,
+                ..Default::default()
+            },
+            ctype: CHAR,
+        };
+        let types = [
+            (INT, CTypeKind::Int),
+            (DOUBLE, CTypeKind::Double),
+            (CHAR, CTypeKind::Char),
+            (CONST_CHAR_PTR, CTypeKind::Pointer(const_char)),
+            (CHAR_PTR, CTypeKind::Pointer(CQualTypeId::new(CHAR))),
+            (INT_PTR, CTypeKind::Pointer(CQualTypeId::new(INT))),
+            (VOID, CTypeKind::Void),
+        ];
+        for (id, kind) in types {
+            ast.add_type(id, kind);
+        }
+        ast
+    }
+
+    fn prototype(ret: CTypeId, params: &[CTypeId]) -> CTypeKind {
+        let params = params.iter().copied().map(CQualTypeId::new).collect();
+        CTypeKind::Function(CQualTypeId::new(ret), params, false, false, true)
+    }
+
+    /// The harness of `double scale(int, double)`, `int len(const char *)`,
+    /// `void upcase(char *)`, `int count(void)` and `int sum(int *)`
+    fn harness() -> Harness {
+        let ast = ast();
+        let prototypes = [
+            ("scale", prototype(DOUBLE, &[INT, DOUBLE])),
+            ("len", prototype(INT, &[CONST_CHAR_PTR])),
+            ("upcase", prototype(VOID, &[CHAR_PTR])),
+            ("count", prototype(INT, &[VOID])),
+            ("sum", prototype(INT, &[INT_PTR])),
+        ];
+        let mut functions = vec![];
+        let mut untested = vec![];
+        for (name, typ) in &prototypes {
+            match Function::new(&ast, "my_", name, typ) {
+                Some(function) => functions.push(function),
+                None => untested.push(name.to_string()),
+            }
+        }
+        Harness {
+            input: PathBuf::from("/src/lib.c"),
+            module: PathBuf::from("/out/src/lib.rs"),
+            object: Some(PathBuf::from("/build/lib.o")),
+            symbols: prototypes
+                .iter()
+                .map(|(name, _)| name.to_string())
+                .collect(),
+            functions,
+            untested,
+        }
+    }
+
+    fn render(template: &str) -> String {
+        let harness = harness();
+        let json = harness.template_data(
+            "lib",
+            harness.object.as_deref().unwrap(),
+            "my-crate",
+            Path::new("/out"),
+            Path::new("/out/differential/lib"),
+        );
+        templates().render(template, &json).unwrap()
+    }
+
+    #[test]
+    fn extern_block() {
+        let rs = render("differential.rs");
+        assert!(rs.contains("\nextern crate my_crate;\n"));
+        assert!(rs.contains(
+            "    #[link_name = \"c2rust_c_scale\"]
+    fn c_scale(a0: libc::c_int, a1: libc::c_double) -> libc::c_double;
+    #[link_name = \"my_scale\"]
+    fn rust_scale(a0: libc::c_int, a1: libc::c_double) -> libc::c_double;
+"
+        ));
+        assert!(rs.contains("    fn c_len(a0: *const libc::c_char) -> libc::c_int;\n"));
+        assert!(rs.contains("    fn c_upcase(a0: *mut libc::c_char);\n"));
+        assert!(rs.contains("    fn c_count() -> libc::c_int;\n"));
+        assert!(!rs.contains("fn c_sum"));
+    }
+
+    #[test]
+    fn strategies() {
+        let rs = render("differential.rs");
+        assert!(rs.contains(
+            "fn scale_matches(a0 in any::<libc::c_int>(), a1 in any::<libc::c_double>())"
+        ));
+        // Floats are compared by their bits
+        assert!(rs.contains("let c_ret = unsafe { c_scale(a0, a1).to_bits() };"));
+        assert!(rs.contains("fn len_matches(a0 in c_string())"));
+        assert!(rs.contains("let c_ret = unsafe { c_len(a0.as_ptr() as _) };"));
+        assert!(rs.contains("fn upcase_matches(a0 in buffer())"));
+        assert!(rs.contains("let (mut c_a0, mut rust_a0) = (a0.clone(), a0);"));
+        assert!(rs.contains("let rust_ret = unsafe { rust_upcase(rust_a0.as_mut_ptr() as _) };"));
+        assert!(rs.contains("prop_assert_eq!(c_a0, rust_a0);"));
+        // Functions without parameters are called once
+        assert!(rs.contains("\n#[test]\nfn count_matches() {\n"));
+    }
+
+    #[test]
+    fn untested_functions() {
+        let harness = harness();
+        assert_eq!(harness.untested, ["sum"]);
+        let rs = render("differential.rs");
+        assert!(rs.contains(
+            "//! Not tested because of the types of their parameters or return values:\n//! sum\n"
+        ));
+    }
+
+    #[test]
+    fn build_script() {
+        let build_rs = render("build.rs");
+        assert!(build_rs.contains("const OBJECT: &str = \"/build/lib.o\";"));
+        assert!(build_rs.contains("    \"scale\",\n    \"len\",\n"));
+        assert!(render("Cargo.toml").contains("\nmy-crate = { path = \"../..\" }\n"));
+    }
+}
@@ -0,0 +1,115 @@
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Why [`transpile`](crate::transpile) or [`export`](crate::export) could not
+/// complete. Problems confined to a single file or declaration are not errors;
+/// they are listed in the [`TranspileSummary`](crate::TranspileSummary).
+#[derive(Debug)]
+pub enum TranspileError {
+    /// `compile_commands.json` does not exist
+    CompileCommandsNotFound { path: PathBuf, source: io::Error },
+    /// `compile_commands.json` could not be read or parsed
+    InvalidCompileCommands { path: PathBuf, message: String },
+    /// An input file is outside the common ancestor directory of all inputs,
+    /// so there is no place for it in the output directory
+    OutputPath { input: PathBuf, ancestor: PathBuf },
+    /// A file or directory could not be created, read or written
+    Io { path: PathBuf, source: io::Error },
+    /// A path is not valid UTF-8, or has no file name, so it cannot be
+    /// recorded in the dependency information or turned into a module name
+    InvalidPath { path: PathBuf },
+    /// The Clang AST of an input was invalid and `fail_on_error` is set
+    InvalidClangAst { input: PathBuf },
+    /// The translator panicked on an input and `fail_on_error` is set
+    Panicked { input: PathBuf, message: String },
+    /// A declaration of an input could not be translated and `fail_on_error`
+    /// is set
+    FailedDeclaration {
+        input: PathBuf,
+        declaration: Option<String>,
+        message: String,
+    },
+}
+
+impl TranspileError {
+    /// Adapter for `map_err` on I/O operations on `path`.
+    pub(crate) fn io(path: &Path) -> impl FnOnce(io::Error) -> Self {
+        let path = path.to_owned();
+        move |source| Self::Io { path, source }
+    }
+}
+
+/// `path` as a string, as the dependency information records it.
+pub(crate) fn path_str(path: &Path) -> Result<&str, TranspileError> {
+    path.to_str().ok_or_else(|| TranspileError::InvalidPath {
+        path: path.to_owned(),
+    })
+}
+
+/// The file name of `path`, as a string.
+pub(crate) fn file_name_str(path: &Path) -> Result<&str, TranspileError> {
+    let invalid = || TranspileError::InvalidPath {
+        path: path.to_owned(),
+    };
+    path.file_name()
+        .ok_or_else(invalid)?
+        .to_str()
+        .ok_or_else(invalid)
+}
+
+impl Display for TranspileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use TranspileError::*;
+        match self {
+            CompileCommandsNotFound { path, source } => write!(
+                f,
+                "Could not find compile_commands.json file at path {}: {}",
+                path.display(),
+                source
+            ),
+            InvalidCompileCommands { path, message } => write!(
+                f,
+                "Could not parse compile commands from {}: {}",
+                path.display(),
+                message
+            ),
+            OutputPath { input, ancestor } => write!(
+                f,
+                "Couldn't strip common ancestor path {} from {}",
+                ancestor.display(),
+                input.display()
+            ),
+            Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            InvalidPath { path } => write!(f, "Unsupported path {}", path.display()),
+            InvalidClangAst { input } => {
+                write!(f, "Clang AST of {} was invalid", input.display())
+            }
+            Panicked { input, message } => {
+                write!(f, "Translating {} {}", input.display(), message)
+            }
+            FailedDeclaration {
+                input,
+                declaration,
+                message,
+            } => write!(
+                f,
+                "Could not translate {} in {}: {}",
+                declaration.as_deref().unwrap_or("a declaration"),
+                input.display(),
+                message
+            ),
+        }
+    }
+}
+
+impl Error for TranspileError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        use TranspileError::*;
+        match self {
+            CompileCommandsNotFound { source, .. } | Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
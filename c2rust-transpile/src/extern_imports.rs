@@ -0,0 +1,818 @@
+//! Imports of the functions that other modules of a crate define, in place
+//! of the `extern "C"` declarations the translator emits for them.
+//!
+//! A translated module declares every function it calls from another
+//! translation unit in an `extern "C"` block and leaves it to the linker to
+//! find the `#[no_mangle]` definition. When the dependency graph tells which
+//! module of the crate defines the function and both agree on its signature,
+//! the declaration is replaced with a `use` of the definition, so that the
+//! compiler checks the calls. Declarations that disagree with their
+//! definition are kept and listed in the failure report. This is part of
+//! `--reorganize-definitions`, which leaves the formatting to `cargo fmt`.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use deps_builder::DependencyGraph;
+use failure::Error;
+use log::{info, warn};
+use proc_macro2::{Span, TokenStream};
+use syn::spanned::Spanned;
+use syn::{
+    FnArg, ForeignItem, GenericArgument, Item, Lit, Meta, PathArguments, ReturnType, Signature,
+    Type, UseTree, Visibility,
+};
+
+use crate::reorganize::{apply_edits, crate_modules, parse, Source, SHARED_MODULE};
+use crate::report::{Failure, FailureReport};
+use crate::ReplaceMode;
+
+/// How far to follow imports and type aliases
+const MAX_DEPTH: usize = 32;
+
+/// What a name in a module stands for
+enum Def {
+    /// A struct, union, enum or extern type, each of which is its own type
+    Nominal,
+    Alias(Box<Type>),
+    /// An import of the given path
+    Import(Vec<String>),
+}
+
+struct Scope {
+    file: usize,
+    defs: HashMap<String, Def>,
+}
+
+/// A function declared in an `extern "C"` block
+struct Declaration {
+    file: usize,
+    scope: Vec<String>,
+    ident: String,
+    symbol: String,
+    is_pub: bool,
+    sig: Signature,
+    range: Range<usize>,
+    /// The `extern "C"` block and the number of items in it
+    block: (Range<usize>, usize),
+}
+
+/// A `#[no_mangle]` or `#[export_name]` function
+struct Definition {
+    ident: String,
+    sig: Signature,
+}
+
+struct ModuleFile {
+    path: PathBuf,
+    module_path: Vec<String>,
+    source: Source,
+    /// Whether the library, rather than only a binary, includes the module
+    in_lib: bool,
+    definitions: HashMap<String, Definition>,
+}
+
+fn attr_value(attrs: &[syn::Attribute], name: &str) -> Option<String> {
+    attrs.iter().find_map(|attr| match attr.parse_meta() {
+        Ok(Meta::NameValue(nv)) if nv.path.is_ident(name) => match nv.lit {
+            Lit::Str(s) => Some(s.value()),
+            _ => None,
+        },
+        _ => None,
+    })
+}
+
+fn has_attr(attrs: &[syn::Attribute], name: &str) -> bool {
+    attrs.iter().any(|attr| attr.path.is_ident(name))
+}
+
+/// The names a `use` tree brings into scope, with the paths they refer to
+fn use_imports(tree: &UseTree, prefix: &mut Vec<String>, defs: &mut HashMap<String, Def>) {
+    match tree {
+        UseTree::Path(p) => {
+            prefix.push(p.ident.to_string());
+            use_imports(&p.tree, prefix, defs);
+            prefix.pop();
+        }
+        UseTree::Name(n) => {
+            let mut path = prefix.clone();
+            path.push(n.ident.to_string());
+            defs.insert(n.ident.to_string(), Def::Import(path));
+        }
+        UseTree::Rename(r) => {
+            let mut path = prefix.clone();
+            path.push(r.ident.to_string());
+            defs.insert(r.rename.to_string(), Def::Import(path));
+        }
+        UseTree::Group(g) => g.items.iter().for_each(|t| use_imports(t, prefix, defs)),
+        UseTree::Glob(_) => {}
+    }
+}
+
+/// Collects the scopes, declarations and definitions of a module file
+struct Collector<'a> {
+    file: usize,
+    source: &'a Source,
+    module_path: &'a [String],
+    scopes: &'a mut HashMap<Vec<String>, Scope>,
+    declarations: &'a mut Vec<Declaration>,
+    definitions: HashMap<String, Definition>,
+}
+
+impl Collector<'_> {
+    fn collect_scope(&mut self, scope: Vec<String>, items: &[Item]) {
+        let mut defs = HashMap::new();
+        for item in items {
+            match item {
+                Item::Struct(s) => {
+                    defs.insert(s.ident.to_string(), Def::Nominal);
+                }
+                Item::Union(u) => {
+                    defs.insert(u.ident.to_string(), Def::Nominal);
+                }
+                Item::Enum(e) => {
+                    defs.insert(e.ident.to_string(), Def::Nominal);
+                }
+                Item::Type(t) => {
+                    defs.insert(t.ident.to_string(), Def::Alias(t.ty.clone()));
+                }
+                Item::Use(u) => {
+                    let mut prefix = vec![];
+                    if u.leading_colon.is_some() {
+                        prefix.push(String::new());
+                    }
+                    use_imports(&u.tree, &mut prefix, &mut defs);
+                }
+                Item::Mod(m) => {
+                    if let Some((_, items)) = &m.content {
+                        let mut inner = scope.clone();
+                        inner.push(m.ident.to_string());
+                        self.collect_scope(inner, items);
+                    }
+                }
+                Item::ForeignMod(block) => {
+                    let block_range = self.source.range(block.span());
+                    for foreign_item in &block.items {
+                        match foreign_item {
+                            ForeignItem::Type(t) => {
+                                defs.insert(t.ident.to_string(), Def::Nominal);
+                            }
+                            ForeignItem::Fn(f) => {
+                                let ident = f.sig.ident.to_string();
+                                self.declarations.push(Declaration {
+                                    file: self.file,
+                                    scope: scope.clone(),
+                                    symbol: attr_value(&f.attrs, "link_name")
+                                        .unwrap_or_else(|| ident.clone()),
+                                    ident,
+                                    is_pub: matches!(f.vis, Visibility::Public(_)),
+                                    sig: f.sig.clone(),
+                                    range: self.source.range(foreign_item.span()),
+                                    block: (block_range.clone(), block.items.len()),
+                                });
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                Item::Fn(f) if scope == self.module_path => {
+                    let exported = attr_value(&f.attrs, "export_name").or_else(|| {
+                        has_attr(&f.attrs, "no_mangle").then(|| f.sig.ident.to_string())
+                    });
+                    if let (Some(symbol), Visibility::Public(_), Some(_)) =
+                        (exported, &f.vis, &f.sig.abi)
+                    {
+                        let definition = Definition {
+                            ident: f.sig.ident.to_string(),
+                            sig: f.sig.clone(),
+                        };
+                        self.definitions.insert(symbol, definition);
+                    }
+                }
+                _ => {}
+            }
+        }
+        let file = self.file;
+        self.scopes.insert(scope, Scope { file, defs });
+    }
+}
+
+struct Crate {
+    files: Vec<ModuleFile>,
+    scopes: HashMap<Vec<String>, Scope>,
+    declarations: Vec<Declaration>,
+    /// The library crate that binaries link against, if any
+    lib_crate: Option<String>,
+}
+
+/// Whether `segment` is the marker that keeps the modules of a binary apart
+fn is_bin_marker(segment: &str) -> bool {
+    segment.starts_with("<bin ")
+}
+
+impl Crate {
+    /// Make `path`, as written in `scope`, absolute. Paths into other
+    /// crates start with an empty segment.
+    fn absolute(&self, scope: &[String], path: &[String]) -> Vec<String> {
+        let (first, rest) = match path.split_first() {
+            Some(split) => split,
+            None => return vec![],
+        };
+        match first.as_str() {
+            "" => path.to_vec(),
+            "crate" => match scope.first() {
+                Some(bin) if is_bin_marker(bin) => [&[bin.clone()], rest].concat(),
+                _ => rest.to_vec(),
+            },
+            krate if self.lib_crate.as_deref() == Some(krate) => rest.to_vec(),
+            "self" => [scope, rest].concat(),
+            "super" => {
+                let supers = path.iter().take_while(|s| *s == "super").count();
+                let base = &scope[..scope.len().saturating_sub(supers)];
+                [base, &path[supers..]].concat()
+            }
+            _ => {
+                let mut local = scope.to_vec();
+                local.push(first.clone());
+                let defined = self
+                    .scopes
+                    .get(scope)
+                    .map_or(false, |s| s.defs.contains_key(first));
+                if self.scopes.contains_key(&local) || defined {
+                    [scope, path].concat()
+                } else {
+                    [&[String::new()], path].concat()
+                }
+            }
+        }
+    }
+
+    /// Make `path`, as written in `scope`, absolute, following the imports
+    /// along the way
+    fn resolve(&self, scope: &[String], path: &[String], depth: usize) -> Vec<String> {
+        let path = self.absolute(scope, path);
+        if depth > MAX_DEPTH || path.first().map_or(true, String::is_empty) {
+            return path;
+        }
+        for i in 0..path.len() {
+            let (parent, rest) = path.split_at(i);
+            let def = self.scopes.get(parent).and_then(|s| s.defs.get(&rest[0]));
+            if let Some(Def::Import(import)) = def {
+                let import = [import.as_slice(), &rest[1..]].concat();
+                return self.resolve(parent, &import, depth + 1);
+            }
+        }
+        path
+    }
+
+    /// The canonical spelling of the item that `path` refers to in `scope`.
+    /// With `nominal`, the path of the definition of a struct, union or
+    /// enum is used instead of its name, so that the copies of one C type in
+    /// different modules, which are distinct Rust types, compare unequal.
+    fn canonical_path(
+        &self,
+        scope: &[String],
+        path: &[String],
+        nominal: bool,
+        depth: usize,
+    ) -> String {
+        let path = self.resolve(scope, path, depth);
+        let (name, parent) = match path.split_last() {
+            Some(split) => split,
+            None => return String::new(),
+        };
+        if path[0].is_empty() {
+            return path[1..].join("::");
+        }
+        match self.scopes.get(parent).and_then(|s| s.defs.get(name)) {
+            Some(Def::Nominal) if !nominal => name.clone(),
+            Some(Def::Alias(ty)) if depth <= MAX_DEPTH => {
+                self.canonical_type(parent, ty, nominal, depth + 1)
+            }
+            _ => format!("crate::{}", path.join("::")),
+        }
+    }
+
+    fn tokens(&self, scope: &[String], span: Span) -> String {
+        let file = self.scopes.get(scope).map_or(0, |s| s.file);
+        let source = &self.files[file].source;
+        let text = &source.text[source.range(span)];
+        text.parse::<TokenStream>()
+            .map_or_else(|_| text.to_string(), |tokens| tokens.to_string())
+    }
+
+    /// The canonical spelling of `ty` in `scope`, with type aliases
+    /// expanded
+    fn canonical_type(&self, scope: &[String], ty: &Type, nominal: bool, depth: usize) -> String {
+        let canonical = |ty: &Type| self.canonical_type(scope, ty, nominal, depth);
+        match ty {
+            Type::Ptr(p) => {
+                let mutability = if p.mutability.is_some() {
+                    "mut"
+                } else {
+                    "const"
+                };
+                format!("*{} {}", mutability, canonical(&p.elem))
+            }
+            Type::Reference(r) => {
+                let mutability = if r.mutability.is_some() { "mut " } else { "" };
+                format!("&{}{}", mutability, canonical(&r.elem))
+            }
+            Type::Array(a) => format!(
+                "[{}; {}]",
+                canonical(&a.elem),
+                self.tokens(scope, a.len.span())
+            ),
+            Type::Slice(s) => format!("[{}]", canonical(&s.elem)),
+            Type::Paren(p) => canonical(&p.elem),
+            Type::Group(g) => canonical(&g.elem),
+            Type::Tuple(t) => format!(
+                "({})",
+                t.elems.iter().map(canonical).collect::<Vec<_>>().join(", ")
+            ),
+            Type::BareFn(f) => {
+                let inputs = f.inputs.iter().map(|arg| canonical(&arg.ty));
+                let variadic = f.variadic.as_ref().map(|_| "...".to_string());
+                format!(
+                    "{}fn({}) -> {}",
+                    if f.unsafety.is_some() { "unsafe " } else { "" },
+                    inputs.chain(variadic).collect::<Vec<_>>().join(", "),
+                    self.canonical_return(scope, &f.output, nominal, depth)
+                )
+            }
+            Type::Path(p) if p.qself.is_none() && depth <= MAX_DEPTH => {
+                let mut path = p
+                    .path
+                    .segments
+                    .iter()
+                    .map(|s| s.ident.to_string())
+                    .collect::<Vec<_>>();
+                if p.path.leading_colon.is_some() {
+                    path.insert(0, String::new());
+                }
+                let mut out = self.canonical_path(scope, &path, nominal, depth);
+                let args = p.path.segments.iter().flat_map(|s| match &s.arguments {
+                    PathArguments::AngleBracketed(args) => args.args.iter().collect(),
+                    _ => vec![],
+                });
+                let args = args
+                    .map(|arg| match arg {
+                        GenericArgument::Type(ty) => canonical(ty),
+                        arg => self.tokens(scope, arg.span()),
+                    })
+                    .collect::<Vec<_>>();
+                if !args.is_empty() {
+                    out = format!("{}<{}>", out, args.join(", "));
+                }
+                out
+            }
+            ty => self.tokens(scope, ty.span()),
+        }
+    }
+
+    fn canonical_return(
+        &self,
+        scope: &[String],
+        output: &ReturnType,
+        nominal: bool,
+        depth: usize,
+    ) -> String {
+        match output {
+            ReturnType::Default => "()".to_string(),
+            ReturnType::Type(_, ty) => self.canonical_type(scope, ty, nominal, depth),
+        }
+    }
+
+    /// The canonical spelling of a signature, or `None` if it is variadic
+    fn canonical_signature(
+        &self,
+        scope: &[String],
+        sig: &Signature,
+        nominal: bool,
+    ) -> Option<String> {
+        if sig.variadic.is_some() {
+            return None;
+        }
+        let mut inputs = vec![];
+        for input in &sig.inputs {
+            match input {
+                // `args: ...` in a variadic definition
+                FnArg::Typed(arg) if matches!(*arg.ty, Type::Verbatim(_)) => return None,
+                FnArg::Typed(arg) => inputs.push(self.canonical_type(scope, &arg.ty, nominal, 0)),
+                FnArg::Receiver(_) => return None,
+            }
+        }
+        Some(format!(
+            "fn({}) -> {}",
+            inputs.join(", "),
+            self.canonical_return(scope, &sig.output, nominal, 0)
+        ))
+    }
+}
+
+fn canonicalize(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_owned())
+}
+
+/// Replace the `extern "C"` declarations of functions defined in other
+/// modules of the crate with imports. `lib_root` is the root of the library
+/// and `binaries` those of the binaries; only functions defined in the
+/// library can be imported. Binaries that link against the library crate
+/// `lib_crate` import them from it, the others from their own copy of the
+/// library modules. Declarations that do not match their definition are
+/// added to `report`, with the `replace_mode` in effect.
+pub fn import_definitions(
+    lib_root: Option<&Path>,
+    binaries: &[PathBuf],
+    lib_crate: Option<&str>,
+    dependency_graph: &DependencyGraph,
+    fuzz_depends_level: usize,
+    report: &FailureReport,
+    replace_mode: ReplaceMode,
+) -> Result<(), Error> {
+    let lib_modules = match lib_root {
+        Some(root) => crate_modules(root)?,
+        None => return Ok(()),
+    };
+    let lib_files = lib_modules
+        .iter()
+        .map(|(path, _)| canonicalize(path))
+        .collect::<HashSet<_>>();
+    let mut krate = Crate {
+        files: vec![],
+        scopes: HashMap::new(),
+        declarations: vec![],
+        lib_crate: lib_crate.map(str::to_owned),
+    };
+    let mut seen = HashSet::new();
+    let roots = lib_root
+        .map(Path::to_owned)
+        .into_iter()
+        .chain(binaries.iter().cloned());
+    for (i, root) in roots.enumerate() {
+        for (path, mut module_path) in crate_modules(&root)? {
+            if !seen.insert(canonicalize(&path)) {
+                continue;
+            }
+            let in_lib = lib_files.contains(&canonicalize(&path));
+            if !in_lib {
+                // Keep the modules of each binary apart from those of the
+                // library and the other binaries
+                module_path.insert(0, format!("<bin {}>", i));
+            }
+            let (source, file) = parse(&path)?;
+            let mut collector = Collector {
+                file: krate.files.len(),
+                source: &source,
+                module_path: &module_path,
+                scopes: &mut krate.scopes,
+                declarations: &mut krate.declarations,
+                definitions: HashMap::new(),
+            };
+            collector.collect_scope(module_path.clone(), &file.items);
+            let definitions = collector.definitions;
+            krate.files.push(ModuleFile {
+                in_lib,
+                path,
+                module_path,
+                source,
+                definitions,
+            });
+        }
+    }
+
+    let file_of_output = krate
+        .files
+        .iter()
+        .enumerate()
+        .map(|(i, f)| (canonicalize(&f.path), i))
+        .collect::<HashMap<_, _>>();
+    let node_file = |output_path: &str| {
+        file_of_output
+            .get(&canonicalize(Path::new(output_path)))
+            .copied()
+    };
+
+    let mut edits = vec![vec![]; krate.files.len()];
+    // The declarations removed from each `extern "C"` block
+    let mut removed: HashMap<(usize, usize), Vec<usize>> = HashMap::new();
+    let mut imported = 0;
+    for (d, decl) in krate.declarations.iter().enumerate() {
+        // The modules using this declaration, and what they expect the
+        // symbol to be. A module of its own uses the declaration alone; a
+        // shared header module stands for all the modules using the symbol.
+        let module_path = &krate.files[decl.file].module_path;
+        let is_shared = module_path.first().map(String::as_str) == Some(SHARED_MODULE);
+        let using_nodes = dependency_graph
+            .nodes
+            .iter()
+            .filter(|node| is_shared || node_file(&node.output_path) == Some(decl.file))
+            .filter(|node| node.undefined.iter().any(|s| s.name == decl.symbol))
+            .collect::<Vec<_>>();
+        let users = using_nodes
+            .iter()
+            .flat_map(|node| node.undefined.iter().filter(|s| s.name == decl.symbol))
+            .collect::<Vec<_>>();
+        let defining_files = dependency_graph
+            .nodes
+            .iter()
+            .filter(|node| !node.is_main())
+            .filter(|node| {
+                users.iter().any(|symbol| {
+                    node.defined
+                        .iter()
+                        .any(|s| s.depends_on(symbol, fuzz_depends_level))
+                })
+            })
+            .filter_map(|node| node_file(&node.output_path))
+            .collect::<HashSet<_>>();
+        let def_file = match defining_files.into_iter().collect::<Vec<_>>()[..] {
+            [file] if file != decl.file && krate.files[file].in_lib => file,
+            _ => continue,
+        };
+        let def = match krate.files[def_file].definitions.get(&decl.symbol) {
+            Some(def) => def,
+            None => continue,
+        };
+        let def_scope = &krate.files[def_file].module_path;
+        let signatures = |nominal| {
+            (
+                krate.canonical_signature(&decl.scope, &decl.sig, nominal),
+                krate.canonical_signature(def_scope, &def.sig, nominal),
+            )
+        };
+        match signatures(false) {
+            (Some(decl_sig), Some(def_sig)) if decl_sig != def_sig => {
+                let message = format!(
+                    "The extern declaration in {} does not match the definition in {}; \
+                     declared: {}, defined: {}",
+                    krate.files[decl.file].path.display(),
+                    krate.files[def_file].path.display(),
+                    decl_sig,
+                    def_sig
+                );
+                warn!(
+                    "Keeping the extern declaration of `{}`: {}",
+                    decl.symbol, message
+                );
+                for node in &using_nodes {
+                    report.add(Failure::signature_mismatch(
+                        Path::new(&node.input_path),
+                        decl.symbol.clone(),
+                        message.clone(),
+                        replace_mode,
+                    ));
+                }
+                continue;
+            }
+            (Some(_), Some(_)) => {}
+            _ => continue,
+        }
+        // The same C types may still be different Rust types in the two
+        // modules, in which case the declaration has to stay
+        if let (Some(decl_sig), Some(def_sig)) = signatures(true) {
+            if decl_sig != def_sig {
+                continue;
+            }
+        }
+
+        let krate_name = match lib_crate {
+            Some(lib_crate) if !krate.files[decl.file].in_lib => lib_crate,
+            _ => "crate",
+        };
+        let mut path = vec![krate_name.to_string()];
+        path.extend(def_scope.iter().cloned());
+        path.push(def.ident.clone());
+        let rename = if def.ident != decl.ident {
+            format!(" as {}", decl.ident)
+        } else {
+            String::new()
+        };
+        let vis = if decl.is_pub { "pub " } else { "" };
+        let import = format!("{}use {}{};\n", vis, path.join("::"), rename);
+        let block_start = decl.block.0.start;
+        edits[decl.file].push((block_start..block_start, import));
+        removed.entry((decl.file, block_start)).or_default().push(d);
+        imported += 1;
+    }
+
+    for ((file, _), decls) in removed {
+        let source = &krate.files[file].source;
+        let (block, len) = krate.declarations[decls[0]].block.clone();
+        if decls.len() == len {
+            let mut end = block.end;
+            if source.text[end..].starts_with('\n') {
+                end += 1;
+            }
+            edits[file].push((block.start..end, String::new()));
+            continue;
+        }
+        for d in decls {
+            let range = krate.declarations[d].range.clone();
+            let mut end = range.end;
+            if source.text[end..].starts_with('\n') {
+                end += 1;
+            }
+            edits[file].push((range.start..end, String::new()));
+        }
+    }
+
+    for (file, edits) in krate.files.iter().zip(edits) {
+        if edits.is_empty() {
+            continue;
+        }
+        let mut edits = edits;
+        // Insertions go before the removal of a block starting at the same
+        // place
+        edits.sort_by_key(|(range, _)| (range.start, range.end != range.start));
+        fs::write(&file.path, apply_edits(&file.source.text, 0, &edits))?;
+    }
+    if imported > 0 {
+        info!("Imported {} functions defined in other modules", imported);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::FailureKind;
+    use deps_builder::{DependencyInfo, DependencySymbol};
+
+    const LIB_RS: &str = "pub mod a;\npub mod b;\n";
+
+    const A_RS: &str = "pub type size_t = libc::c_ulong;
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct point {
+    pub x: libc::c_int,
+}
+#[no_mangle]
+pub unsafe extern \"C\" fn add(x: libc::c_int, y: libc::c_int) -> libc::c_int {
+    x + y
+}
+#[no_mangle]
+pub unsafe extern \"C\" fn len(s: *const libc::c_char) -> size_t {
+    0
+}
+#[no_mangle]
+pub unsafe extern \"C\" fn origin(p: *mut point) {}
+#[no_mangle]
+pub unsafe extern \"C\" fn scale(x: libc::c_long) -> libc::c_long {
+    x
+}
+";
+
+    const B_RS: &str = "#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct point {
+    pub x: libc::c_int,
+}
+extern \"C\" {
+    fn add(x: libc::c_int, y: libc::c_int) -> libc::c_int;
+    fn len(s: *const libc::c_char) -> libc::c_ulong;
+    fn origin(p: *mut point);
+    fn scale(x: libc::c_int) -> libc::c_int;
+}
+pub unsafe extern \"C\" fn b() -> libc::c_int {
+    add(1, 2)
+}
+";
+
+    const MAIN_RS: &str = "extern \"C\" {
+    fn add(x: libc::c_int, y: libc::c_int) -> libc::c_int;
+}
+fn main() {}
+";
+
+    fn symbols(names: &[&str]) -> Vec<DependencySymbol> {
+        names
+            .iter()
+            .map(|name| DependencySymbol::new(name.to_string(), "/src/a.h".to_string()))
+            .collect()
+    }
+
+    fn node(dir: &Path, name: &str, undefined: &[&str], defined: &[&str]) -> DependencyInfo {
+        DependencyInfo {
+            input_path: format!("/src/{}.c", name),
+            output_path: dir.join(format!("{}.rs", name)).display().to_string(),
+            object_path: None,
+            undefined: symbols(undefined),
+            defined: symbols(defined),
+        }
+    }
+
+    /// A crate with the library modules `a` and `b` and the binary `main`,
+    /// and its dependency graph
+    fn crate_dir() -> (tempfile::TempDir, DependencyGraph) {
+        let dir = tempfile::tempdir().unwrap();
+        let files = [
+            ("lib.rs", LIB_RS),
+            ("a.rs", A_RS),
+            ("b.rs", B_RS),
+            ("main.rs", MAIN_RS),
+        ];
+        for (name, contents) in files {
+            fs::write(dir.path().join(name), contents).unwrap();
+        }
+        let mut graph = DependencyGraph::new();
+        let a = node(dir.path(), "a", &[], &["add", "len", "origin", "scale"]);
+        let b = node(dir.path(), "b", &["add", "len", "origin", "scale"], &["b"]);
+        let main = node(dir.path(), "main", &["add"], &["main"]);
+        for node in [a, b, main] {
+            graph.add_node(node);
+        }
+        (dir, graph)
+    }
+
+    fn import(dir: &Path, graph: &DependencyGraph, lib_crate: Option<&str>) -> FailureReport {
+        let report = FailureReport::default();
+        import_definitions(
+            Some(&dir.join("lib.rs")),
+            &[dir.join("main.rs")],
+            lib_crate,
+            graph,
+            0,
+            &report,
+            ReplaceMode::Extern,
+        )
+        .unwrap();
+        report
+    }
+
+    fn read(dir: &Path, name: &str) -> String {
+        fs::read_to_string(dir.join(name)).unwrap()
+    }
+
+    #[test]
+    fn imports_from_crate() {
+        let (dir, graph) = crate_dir();
+        import(dir.path(), &graph, None);
+
+        let b = read(dir.path(), "b.rs");
+        syn::parse_file(&b).unwrap();
+        assert!(b.contains("use crate::a::add;\n"));
+        assert!(!b.contains("fn add("));
+        // A binary without a library crate uses its own copy of the modules
+        let main = read(dir.path(), "main.rs");
+        assert!(main.starts_with("use crate::a::add;\n"));
+        assert!(!main.contains("extern \"C\""));
+    }
+
+    #[test]
+    fn imports_from_lib_crate() {
+        let (dir, graph) = crate_dir();
+        import(dir.path(), &graph, Some("mylib"));
+
+        let main = read(dir.path(), "main.rs");
+        assert!(main.starts_with("use mylib::a::add;\n"));
+        assert!(!main.contains("extern \"C\""));
+        // The library itself still imports from `crate`
+        assert!(read(dir.path(), "b.rs").contains("use crate::a::add;\n"));
+    }
+
+    #[test]
+    fn type_aliases_match_but_nominal_types_do_not() {
+        let (dir, graph) = crate_dir();
+        let report = import(dir.path(), &graph, None);
+
+        let b = read(dir.path(), "b.rs");
+        // `size_t` is an alias of `c_ulong`
+        assert!(b.contains("use crate::a::len;\n"));
+        assert!(!b.contains("fn len("));
+        // `b::point` is a different type than `a::point`, but has the same
+        // C type, so the declaration is kept without a report
+        assert!(b.contains("    fn origin(p: *mut point);\n"));
+        assert!(!b.contains("use crate::a::origin"));
+        assert!(report
+            .failures()
+            .iter()
+            .all(|f| f.declaration.as_deref() != Some("origin")));
+    }
+
+    #[test]
+    fn reports_signature_mismatches() {
+        let (dir, graph) = crate_dir();
+        let report = import(dir.path(), &graph, None);
+
+        let b = read(dir.path(), "b.rs");
+        assert!(b.contains("    fn scale(x: libc::c_int) -> libc::c_int;\n"));
+        assert!(!b.contains("use crate::a::scale"));
+
+        let failures = report.failures();
+        assert_eq!(failures.len(), 1);
+        let failure = &failures[0];
+        assert_eq!(failure.kind, FailureKind::SignatureMismatch);
+        assert_eq!(failure.input, Path::new("/src/b.c"));
+        assert_eq!(failure.declaration.as_deref(), Some("scale"));
+        assert!(failure
+            .message
+            .contains("declared: fn(libc::c_int) -> libc::c_int"));
+        assert!(failure
+            .message
+            .contains("defined: fn(libc::c_long) -> libc::c_long"));
+        assert_eq!(failure.replace_mode, ReplaceMode::Extern);
+    }
+}
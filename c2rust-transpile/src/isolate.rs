@@ -0,0 +1,57 @@
+//! Panic isolation, so that a translator bug in one declaration or one file
+//! does not abort the translation of everything else.
+
+use std::cell::{Cell, RefCell};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Once;
+
+thread_local! {
+    /// How many `catch_panic` calls are active on this thread
+    static CATCHING: Cell<usize> = Cell::new(0);
+    /// Where the last caught panic happened, recorded by the panic hook
+    static LOCATION: RefCell<Option<String>> = RefCell::new(None);
+}
+
+/// Replace the panic hook with one that stays quiet about panics that are
+/// about to be caught by `catch_panic`; they are reported as translation
+/// failures instead. Every other panic goes to the previous hook.
+fn install_hook() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        let default_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if CATCHING.with(Cell::get) > 0 {
+                let location = info.location().map(ToString::to_string);
+                LOCATION.with(|loc| *loc.borrow_mut() = location);
+            } else {
+                default_hook(info)
+            }
+        }));
+    });
+}
+
+/// Run `f`, turning a panic into an error message that includes the panic
+/// payload and the place in the translator where it happened.
+///
+/// State that `f` shares with the caller may be left half-updated by the
+/// panic; callers only use this around units of work (a declaration, a
+/// file) whose partial results are thrown away.
+pub(crate) fn catch_panic<T>(f: impl FnOnce() -> T) -> Result<T, String> {
+    install_hook();
+    CATCHING.with(|c| c.set(c.get() + 1));
+    let res = panic::catch_unwind(AssertUnwindSafe(f));
+    CATCHING.with(|c| c.set(c.get() - 1));
+    res.map_err(|payload| {
+        let msg = if let Some(msg) = payload.downcast_ref::<&str>() {
+            msg.to_string()
+        } else if let Some(msg) = payload.downcast_ref::<String>() {
+            msg.clone()
+        } else {
+            "Box<dyn Any>".to_string()
+        };
+        match LOCATION.with(|loc| loc.borrow_mut().take()) {
+            Some(location) => format!("panicked at '{}', {}", msg, location),
+            None => format!("panicked at '{}'", msg),
+        }
+    })
+}
@@ -1,49 +1,73 @@
 #![allow(clippy::too_many_arguments)]
 #![feature(drain_filter)]
 
+mod assembly;
+mod ast_cache;
 mod diagnostics;
-
+mod differential;
+mod error;
+mod extern_imports;
+mod isolate;
+mod manifest;
+mod objects;
+
+pub mod abi;
 pub mod build_files;
 pub mod c_ast;
 pub mod cfg;
 mod compile_cmds;
 pub mod convert_type;
+mod parallel;
+pub mod plan;
 pub mod renamer;
+mod reorganize;
+pub mod report;
 pub mod rust_ast;
 pub mod translator;
 pub mod with_stmts;
 
+use std::borrow::Cow;
 use std::collections::{binary_heap, HashSet};
 use std::fs::{self, File};
-use std::io;
-use std::io::prelude::*;
+use std::io::{self, prelude::*};
 use std::path::{Path, PathBuf};
 use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 
-use build_files::get_lib;
+use build_files::{get_bin_preamble, get_lib};
 use failure::Error;
 use itertools::Itertools;
 use log::{info, warn};
 use regex::Regex;
-use serde_derive::Serialize;
+use serde_derive::{Deserialize, Serialize};
 
 use crate::c_ast::Printer;
 use crate::c_ast::*;
+use crate::diagnostics::progress;
 pub use crate::diagnostics::Diagnostic;
-use c2rust_ast_exporter as ast_exporter;
+pub use crate::error::TranspileError;
+use crate::error::{file_name_str, path_str};
 
-use crate::build_files::{emit_build_files, get_build_dir, get_build_dir_raw, CrateConfig};
-use crate::compile_cmds::get_compile_commands;
+use crate::ast_cache::AstCache;
+use crate::build_files::{binary_modules, emit_build_files, get_build_dir, has_rlib, CrateConfig};
+use crate::compile_cmds::{get_compile_commands, CompileCmd, LinkCmd, LinkType};
 use crate::convert_type::RESERVED_NAMES;
+use crate::differential::DifferentialTests;
+use crate::manifest::Generation;
+use crate::objects::{ArchiveMembers, ObjectSymbols};
+use crate::plan::{FileAction, Plan, PlannedFile};
+use crate::report::{Failure, FailureKind, FailureReport};
 pub use crate::translator::ReplaceMode;
 use std::prelude::v1::Vec;
 
 type PragmaVec = Vec<(&'static str, Vec<&'static str>)>;
 type PragmaSet = indexmap::IndexSet<(&'static str, &'static str)>;
 type CrateSet = indexmap::IndexSet<ExternCrate>;
-type TranspileResult = Result<(PathBuf, PragmaVec, CrateSet), ()>;
+/// The emitted file and what it needs in `lib.rs`, or `None` if it was skipped
+type TranspileResult = Result<Option<(PathBuf, PragmaVec, CrateSet)>, TranspileError>;
 
-use deps_builder::{build_dependency, DependencyGraph, DependencyInfo, DependencySymbol};
+use deps_builder::{build_dependency, Binding, DependencyGraph, DependencyInfo, DependencySymbol};
 
 /// Configuration settings for the translation process
 #[derive(Debug, Clone)]
@@ -86,27 +110,154 @@ pub struct TranspilerConfig {
     pub disable_refactoring: bool,
     pub preserve_unused_functions: bool,
     pub log_level: log::LevelFilter,
+    /// Number of translation units to process in parallel; 0 uses every core
+    pub jobs: usize,
+    /// Directory in which to cache exported Clang ASTs across runs
+    pub ast_cache_dir: Option<PathBuf>,
+    /// Only retranslate files whose generation manifest entry is out of date
+    pub incremental: bool,
+    /// Write the failure report as JSON to this file
+    pub report_json: Option<PathBuf>,
+    /// Write the failure report as SARIF to this file
+    pub report_sarif: Option<PathBuf>,
+    /// Print what would be translated and written instead of writing anything
+    pub dry_run: bool,
+    /// Settings for some of the input files, applied in order
+    pub overrides: Vec<InputOverride>,
+    /// An `ec2rust.toml` reproducing this run, saved in the output directory
+    pub effective_config: Option<String>,
 
     // Options that control build files
     /// Emit `Cargo.toml` and `lib.rs`
     pub emit_build_files: bool,
     pub emit_binaries: bool,
+    /// How the binaries of `emit_binaries` include the library modules
+    pub binary_layout: BinaryLayout,
+    /// Combine the binaries into one multi-call binary that selects them by
+    /// the name it is called by, like BusyBox
+    pub multicall: bool,
+    /// Compile the C files that are filtered out or fail to translate with
+    /// the `cc` crate in `build.rs`, so that the crate still links
+    pub hybrid_build: bool,
+    /// Emit a crate for each translation unit that compares its exported
+    /// functions with those of the C object on generated inputs
+    pub emit_differential_tests: bool,
     /// Names of translation units containing main functions that we should make
     /// into binaries
     pub binaries: Vec<String>,
     pub detect_binaries: bool,
     pub dependency_file: PathBuf,
     pub fuzz_depends_level: usize,
+    /// Where the dependency information gets the symbols of each file
+    pub symbol_source: SymbolSource,
+}
+
+/// How a binary emitted with `emit_binaries` includes the library modules it
+/// uses.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BinaryLayout {
+    /// The binary declares the library modules again with `#[path]`, so they
+    /// are compiled once per binary
+    Inline,
+    /// The binary links against the library crate, which is compiled once
+    Library,
+}
+
+/// Where the exported dependency information takes the symbols that each
+/// translation unit defines and uses from.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SymbolSource {
+    /// The declarations in its Clang AST
+    Clang,
+    /// The symbol table of the object file the original build compiled it
+    /// to, or of the archive member of that name; files without one fall
+    /// back to the Clang AST
+    Objects,
+    /// The object file, after checking it against the Clang AST
+    Both,
+}
+
+/// Settings that apply to the input files in (or at) `path` only.
+#[derive(Debug, Clone)]
+pub struct InputOverride {
+    /// An input file, or a directory containing input files
+    pub path: PathBuf,
+    /// Only translate the inputs under `path` that match this filter
+    pub filter: Option<Regex>,
+    /// Translate the inputs as binaries, or as library modules with `false`
+    pub binary: Option<bool>,
+    /// Clang arguments added to those in `compile_commands.json`
+    pub extra_clang_args: Vec<String>,
+    pub replace_unsupported_decls: Option<ReplaceMode>,
 }
 
 impl TranspilerConfig {
-    fn binary_name_from_path(file: &Path) -> String {
+    /// The name of the binary built from the Rust file `file`
+    pub fn binary_name_from_path(file: &Path) -> String {
         let file = Path::new(file.file_stem().unwrap());
         get_module_name(file, false, false, false).unwrap()
     }
 
+    /// The overrides that apply to `input`, in order.
+    fn overrides_for<'a>(&'a self, input: &'a Path) -> impl Iterator<Item = &'a InputOverride> {
+        self.overrides
+            .iter()
+            .filter(move |o| input.starts_with(&o.path))
+    }
+
+    /// Whether `input` passes the filters of all overrides that apply to it.
+    fn is_included(&self, input: &Path) -> bool {
+        self.overrides_for(input).all(|o| {
+            o.filter
+                .as_ref()
+                .map_or(true, |re| re.is_match(&input.to_string_lossy()))
+        })
+    }
+
+    /// The extra Clang arguments for `input`: the ones for every file,
+    /// followed by those of the overrides that apply to it.
+    fn clang_args_for<'a>(&'a self, input: &'a Path, clang_args: &[&'a str]) -> Vec<&'a str> {
+        let mut clang_args = clang_args.to_vec();
+        for o in self.overrides_for(input) {
+            clang_args.extend(o.extra_clang_args.iter().map(String::as_str));
+        }
+        clang_args
+    }
+
+    /// The configuration to translate `input` with, if overrides change it.
+    fn for_input(&self, input: &Path) -> Cow<TranspilerConfig> {
+        let replace_mode = self
+            .overrides_for(input)
+            .filter_map(|o| o.replace_unsupported_decls)
+            .last();
+        match replace_mode {
+            Some(mode) if mode != self.replace_unsupported_decls => {
+                let mut tcfg = self.clone();
+                tcfg.replace_unsupported_decls = mode;
+                Cow::Owned(tcfg)
+            }
+            _ => Cow::Borrowed(self),
+        }
+    }
+
+    /// Whether `dependency_info` is translated into a binary. The utilities
+    /// of a multi-call binary are modules of the library instead.
     fn is_binary(&self, dependency_info: &DependencyInfo) -> bool {
+        !self.multicall && self.has_binary_main(dependency_info)
+    }
+
+    /// Whether `dependency_info` is a utility of the multi-call binary
+    fn is_multicall_util(&self, dependency_info: &DependencyInfo) -> bool {
+        self.multicall && self.has_binary_main(dependency_info)
+    }
+
+    fn has_binary_main(&self, dependency_info: &DependencyInfo) -> bool {
         let file = dependency_info.input_path.as_ref();
+        if let Some(binary) = self.overrides_for(file).filter_map(|o| o.binary).last() {
+            return binary;
+        }
         let module_name = Self::binary_name_from_path(file);
         self.binaries.contains(&module_name)
             || (self.detect_binaries
@@ -138,6 +289,16 @@ impl TranspilerConfig {
         ok
     }
 
+    /// Incremental runs regenerate the build files, since the set of modules
+    /// and their pragmas may have changed since they were emitted.
+    fn overwrite_build_files(&self) -> bool {
+        self.overwrite_existing || self.incremental
+    }
+
+    fn binaries_use_lib(&self) -> bool {
+        self.emit_binaries && self.binary_layout == BinaryLayout::Library
+    }
+
     fn crate_name(&self) -> String {
         self.output_dir
             .as_ref()
@@ -146,7 +307,26 @@ impl TranspilerConfig {
     }
 }
 
-#[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+/// What a successful run of [`transpile`] or [`export`] produced.
+#[derive(Debug, Default)]
+pub struct TranspileSummary {
+    /// The Rust files emitted, or found up to date, by this run
+    pub outputs: Vec<PathBuf>,
+    /// Dependency information of every exported translation unit
+    pub dependencies: Vec<DependencyInfo>,
+    /// Skipped files and declarations that could not be translated
+    pub failures: Vec<Failure>,
+}
+
+impl TranspileSummary {
+    /// Failures caused by the translator panicking; these are bugs in the
+    /// translator rather than limitations in what it supports.
+    pub fn panics(&self) -> impl Iterator<Item = &Failure> {
+        self.failures.iter().filter(|failure| failure.is_panic())
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
 pub enum ExternCrate {
     C2RustBitfields,
     C2RustAsmCasts,
@@ -227,8 +407,8 @@ fn get_module_name(
     } else {
         file.file_name()
     };
-    let fname = &fname.unwrap().to_str().map(String::from);
-    let mut name = str_to_ident_checked(fname, check_reserved).unwrap();
+    let fname = &fname?.to_str().map(String::from);
+    let mut name = str_to_ident_checked(fname, check_reserved)?;
     if keep_extension && is_rs {
         name.push_str(".rs");
     }
@@ -240,31 +420,175 @@ fn get_module_name(
     file.to_str().map(String::from)
 }
 
+fn read_compile_commands(
+    cc_db: &Path,
+    tcfg: &TranspilerConfig,
+) -> Result<Vec<LinkCmd>, TranspileError> {
+    get_compile_commands(cc_db, &tcfg.filter, |input| tcfg.is_included(input)).map_err(|e| {
+        TranspileError::InvalidCompileCommands {
+            path: cc_db.to_owned(),
+            message: e.to_string(),
+        }
+    })
+}
+
+/// An executable linked by the original build
+#[derive(Debug, Clone)]
+pub struct LinkedExecutable {
+    /// Where the build puts the executable
+    pub output: PathBuf,
+    /// The source files linked into it
+    pub inputs: Vec<PathBuf>,
+}
+
+/// The executables that the link commands of the compilation database
+/// `cc_db` produce.
+pub fn linked_executables(cc_db: &Path) -> Result<Vec<LinkedExecutable>, TranspileError> {
+    let lcmds = get_compile_commands(cc_db, &None, |_| true).map_err(|e| {
+        TranspileError::InvalidCompileCommands {
+            path: cc_db.to_owned(),
+            message: e.to_string(),
+        }
+    })?;
+    let executables = lcmds
+        .into_iter()
+        .filter(|lcmd| lcmd.r#type == LinkType::Exe)
+        .filter_map(|lcmd| {
+            let inputs = lcmd.cmd_inputs.iter().chain(&lcmd.native_inputs);
+            Some(LinkedExecutable {
+                inputs: inputs.map(|ccmd| ccmd.abs_file()).collect(),
+                output: PathBuf::from(lcmd.output?),
+            })
+        })
+        .collect();
+    Ok(executables)
+}
+
 /// Main entry point to transpiler. Called from CLI tools with the result of
 /// clap::App::get_matches().
-pub fn transpile(tcfg: TranspilerConfig, cc_db: &Path, extra_clang_args: &[&str]) {
-    let dependency_infos = export(tcfg.clone(), cc_db, extra_clang_args);
-    let dependency_graph = build_dependency(dependency_infos, tcfg.fuzz_depends_level);
+pub fn transpile(
+    tcfg: TranspilerConfig,
+    cc_db: &Path,
+    extra_clang_args: &[&str],
+) -> Result<TranspileSummary, TranspileError> {
+    let exported = export_for_transpile(&tcfg, cc_db, extra_clang_args)?;
+    transpile_exported(tcfg, cc_db, extra_clang_args, exported)
+}
 
-    diagnostics::init(tcfg.enabled_warnings.clone(), tcfg.log_level);
+/// The dependency information of a compilation database, exported with the
+/// Clang ASTs kept for [`transpile_exported`].
+pub struct Exported {
+    pub dependencies: Vec<DependencyInfo>,
+    ast_cache: AstCache,
+}
 
-    let lcmds = get_compile_commands(cc_db, &tcfg.filter).unwrap_or_else(|_| {
-        panic!(
-            "Could not parse compile commands from {}",
-            cc_db.to_string_lossy()
-        )
+/// The first half of [`transpile`]: export the dependency information.
+pub fn export_for_transpile(
+    tcfg: &TranspilerConfig,
+    cc_db: &Path,
+    extra_clang_args: &[&str],
+) -> Result<Exported, TranspileError> {
+    if tcfg.incremental && tcfg.ast_cache_dir.is_none() {
+        warn!("--incremental without --ast-cache-dir still runs Clang on every file");
+    }
+    // Keep the ASTs exported for the dependency graph so that each file is
+    // only parsed by Clang once.
+    let ast_cache = AstCache::new(tcfg.ast_cache_dir.clone(), true, tcfg.dry_run);
+    let dependencies = export_with_cache(tcfg.clone(), cc_db, extra_clang_args, &ast_cache)?;
+    Ok(Exported {
+        dependencies,
+        ast_cache,
+    })
+}
+
+/// The prototypes of the functions that the translation units exported by
+/// [`export_for_transpile`] define, taken from their Clang ASTs.
+pub fn c_prototypes(
+    tcfg: &TranspilerConfig,
+    cc_db: &Path,
+    extra_clang_args: &[&str],
+    exported: &Exported,
+) -> Result<Vec<abi::Prototype>, TranspileError> {
+    let lcmds = read_compile_commands(cc_db, tcfg)?;
+    let clang_args: Vec<String> = get_extra_args_macos()?;
+    let mut clang_args: Vec<&str> = clang_args.iter().map(AsRef::as_ref).collect();
+    clang_args.extend_from_slice(extra_clang_args);
+
+    let cmds = lcmds
+        .iter()
+        .flat_map(|lcmd| lcmd.cmd_inputs.iter().cloned())
+        .collect::<Vec<_>>();
+    let results = parallel::map_ordered(tcfg.jobs, &cmds, |cmd| {
+        let input_path = cmd.abs_file();
+        let clang_args = tcfg.clang_args_for(&input_path, &clang_args);
+        let ast =
+            exported
+                .ast_cache
+                .get_untyped_ast(cmd, cc_db, &clang_args, tcfg.debug_ast_exporter);
+        match ast {
+            Ok(ast) => {
+                let typed_context = ConversionContext::new(&ast).typed_context;
+                if !abi::is_lp64(&typed_context.target) {
+                    warn!(
+                        "{} is compiled for {}, which is not an LP64 target; its prototypes are not compared",
+                        input_path.display(),
+                        typed_context.target
+                    );
+                    return vec![];
+                }
+                abi::prototypes(&typed_context, input_path)
+            }
+            Err(e) => {
+                warn!(
+                    "Could not export the Clang AST of {}: {}",
+                    input_path.display(),
+                    e
+                );
+                vec![]
+            }
+        }
     });
+    // A file linked into several outputs is listed once for each
+    let mut prototypes = results.into_iter().flatten().collect::<Vec<_>>();
+    prototypes.sort_by(|a, b| (&a.name, &a.input).cmp(&(&b.name, &b.input)));
+    prototypes.dedup();
+    Ok(prototypes)
+}
+
+/// The second half of [`transpile`]: translate what [`export_for_transpile`]
+/// exported.
+pub fn transpile_exported(
+    tcfg: TranspilerConfig,
+    cc_db: &Path,
+    extra_clang_args: &[&str],
+    exported: Exported,
+) -> Result<TranspileSummary, TranspileError> {
+    let Exported {
+        dependencies: dependency_infos,
+        ast_cache,
+    } = exported;
+    let dependency_graph = build_dependency(dependency_infos.clone(), tcfg.fuzz_depends_level);
+
+    diagnostics::init(tcfg.enabled_warnings.clone(), tcfg.log_level);
+
+    let lcmds = read_compile_commands(cc_db, &tcfg)?;
 
     // Specify path to system include dir on macOS 10.14 and later. Disable the blocks extension.
-    let clang_args: Vec<String> = get_extra_args_macos();
+    let clang_args: Vec<String> = get_extra_args_macos()?;
     let mut clang_args: Vec<&str> = clang_args.iter().map(AsRef::as_ref).collect();
     clang_args.extend_from_slice(extra_clang_args);
 
     let mut top_level_ccfg = None;
+    let mut top_level_binaries = vec![];
+    let mut top_level_lib_crate = String::new();
     let mut workspace_members = vec![];
     let mut num_transpiled_files = 0;
     let mut transpiled_modules = Vec::new();
     let build_dir = get_build_dir(&tcfg, cc_db);
+    let generation = Generation::load(&tcfg, &build_dir);
+    let report = FailureReport::default();
+    let differential = DifferentialTests::default();
+    let plan = tcfg.dry_run.then(Plan::default);
     for lcmd in &lcmds {
         let cmds = &lcmd.cmd_inputs;
         let lcmd_name = lcmd
@@ -274,9 +598,8 @@ pub fn transpile(tcfg: TranspilerConfig, cc_db: &Path, extra_clang_args: &[&str]
                 let output_path = Path::new(output);
                 output_path
                     .file_stem()
-                    .unwrap()
-                    .to_str()
-                    .unwrap()
+                    .and_then(|stem| stem.to_str())
+                    .unwrap_or(output)
                     .to_owned()
             })
             .unwrap_or_else(|| tcfg.crate_name());
@@ -285,6 +608,8 @@ pub fn transpile(tcfg: TranspilerConfig, cc_db: &Path, extra_clang_args: &[&str]
         } else {
             build_dir.join(&lcmd_name)
         };
+        // The name of the library target in `Cargo.toml`
+        let lib_crate = lcmd_name.replace('-', "_");
 
         // Compute the common ancestor of all input files
         // FIXME: this is quadratic-time in the length of the ancestor path
@@ -307,47 +632,44 @@ pub fn transpile(tcfg: TranspilerConfig, cc_db: &Path, extra_clang_args: &[&str]
             }
         }
 
-        let pre_results = cmds
-            .iter()
-            .filter(|cmd| {
-                !tcfg.is_binary(
-                    &(dependency_graph
-                        .nodes
-                        .iter()
-                        .find(|dep| dep.input_path == cmd.abs_file().to_str().unwrap())
-                        .unwrap()),
-                )
-            })
-            .map(|cmd| {
+        // Files that could not be exported are skipped by `transpile_single`
+        let (bin_cmds, lib_cmds): (Vec<_>, Vec<_>) = cmds.iter().partition(|cmd| {
+            dependency_graph
+                .nodes
+                .iter()
+                .find(|dep| Some(dep.input_path.as_str()) == cmd.abs_file().to_str())
+                .map_or(false, |dep| tcfg.is_binary(dep))
+        });
+
+        let pre_results = parallel::map_ordered(tcfg.jobs, &lib_cmds, |cmd| {
+            isolate_file(&tcfg, cmd, Some(&report), || {
                 transpile_single(
                     &tcfg,
-                    cmd.abs_file(),
-                    cmd.abs_output_file(),
+                    cmd,
                     &ancestor_path,
                     &build_dir,
                     cc_db,
                     &clang_args,
                     &dependency_graph,
-                    |_, _| "".to_string(),
-                )
-            })
-            .collect::<Vec<_>>();
-        let results = cmds
-            .iter()
-            .filter(|cmd| {
-                tcfg.is_binary(
-                    &(dependency_graph
-                        .nodes
-                        .iter()
-                        .find(|dep| dep.input_path == cmd.abs_file().to_str().unwrap())
-                        .unwrap()),
+                    &ast_cache,
+                    &generation,
+                    &report,
+                    &differential,
+                    plan.as_ref(),
+                    &[],
+                    |_, _| Ok(String::new()),
                 )
             })
-            .map(|cmd| {
+        })
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()?;
+        // Binaries only read the library results, so they can run in parallel too
+        let bin_results = parallel::map_ordered(tcfg.jobs, &bin_cmds, |cmd| {
+            isolate_file(&tcfg, cmd, Some(&report), || {
                 let mut modules = vec![];
                 let mut pragmas = PragmaSet::new();
                 let mut crates = CrateSet::new();
-                println!(
+                progress!(
                     "Getting sub dependency graph for {:?}",
                     (
                         Some(cmd.abs_file().to_str().unwrap_or_default().to_string()),
@@ -358,21 +680,20 @@ pub fn transpile(tcfg: TranspilerConfig, cc_db: &Path, extra_clang_args: &[&str]
                 );
                 let sub_dependency_graph = if let Some(idx) = dependency_graph
                     .get_node_index_with_input(
-                        &cmd.abs_file().to_str().unwrap().to_string(),
-                        &cmd.abs_output_file()
-                            .as_ref()
-                            .map(|path| path.to_str().unwrap().to_string()),
+                        &path_str(&cmd.abs_file())?.to_string(),
+                        &object_path_str(&cmd.abs_output_file())?,
                     ) {
-                    println!("Extracting sub dependency graph for {:?}", idx);
+                    progress!("Extracting sub dependency graph for {:?}", idx);
                     dependency_graph.extract_sub_dependency(vec![idx])
                 } else {
                     DependencyGraph::new()
                 };
                 for res in &pre_results {
                     match res {
-                        Ok((module, pragma_vec, crate_set)) => {
-                            if let Some(_) = sub_dependency_graph
-                                .get_node_index_with_output(&module.to_str().unwrap().to_string())
+                        Some((module, pragma_vec, crate_set)) => {
+                            if sub_dependency_graph
+                                .get_node_index_with_output(&path_str(module)?.to_string())
+                                .is_some()
                             {
                                 modules.push(module.clone());
                                 crates.extend(crate_set);
@@ -384,18 +705,25 @@ pub fn transpile(tcfg: TranspilerConfig, cc_db: &Path, extra_clang_args: &[&str]
                                 }
                             }
                         }
-                        Err(_) => {}
+                        None => {}
                     }
                 }
+                modules.sort();
+                let lib_modules = modules.clone();
                 transpile_single(
                     &tcfg,
-                    cmd.abs_file(),
-                    cmd.abs_output_file(),
+                    cmd,
                     &ancestor_path,
                     &build_dir,
                     cc_db,
                     &clang_args,
                     &dependency_graph,
+                    &ast_cache,
+                    &generation,
+                    &report,
+                    &differential,
+                    plan.as_ref(),
+                    &lib_modules,
                     |pragma_vec, crate_set| {
                         crates.extend(crate_set);
                         for (key, vals) in pragma_vec {
@@ -405,28 +733,43 @@ pub fn transpile(tcfg: TranspilerConfig, cc_db: &Path, extra_clang_args: &[&str]
                         }
                         pragmas.sort();
                         crates.sort();
-                        get_lib(
-                            &tcfg,
-                            &build_dir,
-                            modules,
-                            pragmas,
-                            &crates,
-                            &dependency_graph,
-                        )
+                        if tcfg.binaries_use_lib() {
+                            get_bin_preamble(pragmas, &crates, &lib_crate)
+                        } else {
+                            get_lib(
+                                &tcfg,
+                                &build_dir,
+                                modules,
+                                pragmas,
+                                &crates,
+                                &dependency_graph,
+                            )
+                        }
                     },
                 )
             })
-            .collect::<Vec<_>>()
+        });
+        let results = bin_results
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()?
             .into_iter()
             .chain(pre_results.into_iter())
             .collect::<Vec<_>>();
+        let result_cmds = bin_cmds.iter().chain(lib_cmds.iter());
         let mut modules = vec![];
         let mut modules_skipped = false;
+        // Assembly files are always compiled in `build.rs`
+        let mut native_cmds = lcmd
+            .native_inputs
+            .iter()
+            .filter(|cmd| tcfg.hybrid_build || assembly::is_assembly(&cmd.file))
+            .cloned()
+            .collect::<Vec<_>>();
         let mut pragmas = PragmaSet::new();
         let mut crates = CrateSet::new();
-        for res in results {
+        for (res, cmd) in results.into_iter().zip(result_cmds) {
             match res {
-                Ok((module, pragma_vec, crate_set)) => {
+                Some((module, pragma_vec, crate_set)) => {
                     modules.push(module);
                     crates.extend(crate_set);
 
@@ -437,37 +780,81 @@ pub fn transpile(tcfg: TranspilerConfig, cc_db: &Path, extra_clang_args: &[&str]
                         }
                     }
                 }
-                Err(_) => {
+                None if tcfg.hybrid_build => {
+                    native_cmds.push(Arc::clone(cmd));
+                }
+                None => {
                     modules_skipped = true;
                 }
             }
         }
         pragmas.sort();
         crates.sort();
+        // The order of `compile_commands.json` must not show in `lib.rs`
+        // and `Cargo.toml`
+        modules.sort();
 
         transpiled_modules.extend(modules.iter().cloned());
 
         if tcfg.emit_build_files {
             if modules_skipped {
-                // If we skipped a file, we may not have collected all required pragmas
-                warn!("Can't emit build files after incremental transpiler run; skipped.");
-                return;
+                // If we skipped a file that the generation manifest doesn't
+                // know about, we may not have collected all required pragmas
+                warn!("Can't emit build files after skipping files not listed in the generation manifest; skipped.");
+                if let Some(plan) = &plan {
+                    progress!("{}", plan);
+                }
+                return finish_run(
+                    &tcfg,
+                    generation,
+                    &report,
+                    transpiled_modules,
+                    dependency_infos,
+                );
             }
 
+            let binaries = binary_modules(&tcfg, &modules, &dependency_graph);
+            if tcfg.emit_differential_tests {
+                differential.emit(
+                    &tcfg,
+                    &build_dir,
+                    &lcmd_name,
+                    &modules,
+                    has_rlib(&tcfg, &lcmd.r#type, &binaries),
+                    plan.as_ref(),
+                )?;
+            }
             let ccfg = CrateConfig {
                 crate_name: lcmd_name.clone(),
                 modules,
                 pragmas,
                 crates,
                 link_cmd: lcmd,
+                native_cmds,
             };
             if lcmd.top_level {
                 top_level_ccfg = Some(ccfg);
+                top_level_binaries = binaries;
+                top_level_lib_crate = lib_crate;
             } else {
-                let crate_file =
-                    emit_build_files(&tcfg, &build_dir, Some(ccfg), None, &dependency_graph);
-                reorganize_definitions(&tcfg, &build_dir, crate_file)
-                    .unwrap_or_else(|e| warn!("Reorganizing definitions failed: {}", e));
+                let crate_file = emit_build_files(
+                    &tcfg,
+                    &build_dir,
+                    Some(ccfg),
+                    None,
+                    &dependency_graph,
+                    plan.as_ref(),
+                )?;
+                refactor_crate(
+                    &tcfg,
+                    &build_dir,
+                    crate_file,
+                    binaries,
+                    &lib_crate,
+                    &dependency_graph,
+                    &report,
+                )
+                .unwrap_or_else(|e| warn!("Refactoring the crate failed: {}", e));
                 workspace_members.push(lcmd_name);
             }
         }
@@ -475,22 +862,79 @@ pub fn transpile(tcfg: TranspilerConfig, cc_db: &Path, extra_clang_args: &[&str]
 
     if num_transpiled_files == 0 {
         warn!("No C files found in compile_commands.json; nothing to do.");
-        return;
+        return finish_run(
+            &tcfg,
+            generation,
+            &report,
+            transpiled_modules,
+            dependency_infos,
+        );
     }
 
     if tcfg.emit_build_files {
+        workspace_members.sort();
         let crate_file = emit_build_files(
             &tcfg,
             &build_dir,
             top_level_ccfg,
             Some(workspace_members),
             &dependency_graph,
-        );
-        reorganize_definitions(&tcfg, &build_dir, crate_file)
-            .unwrap_or_else(|e| warn!("Reorganizing definitions failed: {}", e));
+            plan.as_ref(),
+        )?;
+        refactor_crate(
+            &tcfg,
+            &build_dir,
+            crate_file,
+            top_level_binaries,
+            &top_level_lib_crate,
+            &dependency_graph,
+            &report,
+        )
+        .unwrap_or_else(|e| warn!("Refactoring the crate failed: {}", e));
     }
 
-    tcfg.check_if_all_binaries_used(&transpiled_modules);
+    // The refactoring adds to the report
+    let summary = finish_run(
+        &tcfg,
+        generation,
+        &report,
+        transpiled_modules,
+        dependency_infos,
+    )?;
+
+    if let Some(plan) = &plan {
+        progress!("{}", plan);
+    }
+    tcfg.check_if_all_binaries_used(&summary.outputs);
+    Ok(summary)
+}
+
+/// Save the generation manifest and the failure report of a `transpile` run,
+/// unless it is a dry run.
+fn finish_run(
+    tcfg: &TranspilerConfig,
+    generation: Generation,
+    report: &FailureReport,
+    outputs: Vec<PathBuf>,
+    dependencies: Vec<DependencyInfo>,
+) -> Result<TranspileSummary, TranspileError> {
+    if !tcfg.dry_run {
+        generation.save();
+        report.write(tcfg.report_json.as_deref(), tcfg.report_sarif.as_deref())?;
+    }
+    let summary = TranspileSummary {
+        outputs,
+        dependencies,
+        failures: report.failures(),
+    };
+    let panics = summary.panics().count();
+    if panics > 0 {
+        warn!(
+            "The translator panicked {} time(s); the affected declarations and files are listed in the failure report.",
+            panics
+        );
+    }
+    Ok(summary)
 }
 
 /// Before translate is called, exporter gens deps info
@@ -499,25 +943,35 @@ pub fn export(
     tcfg: TranspilerConfig,
     cc_db: &Path,
     extra_clang_args: &[&str],
-) -> Vec<DependencyInfo> {
+) -> Result<TranspileSummary, TranspileError> {
+    let ast_cache = AstCache::new(tcfg.ast_cache_dir.clone(), false, tcfg.dry_run);
+    let dependencies = export_with_cache(tcfg, cc_db, extra_clang_args, &ast_cache)?;
+    Ok(TranspileSummary {
+        dependencies,
+        ..Default::default()
+    })
+}
+
+fn export_with_cache(
+    tcfg: TranspilerConfig,
+    cc_db: &Path,
+    extra_clang_args: &[&str],
+    ast_cache: &AstCache,
+) -> Result<Vec<DependencyInfo>, TranspileError> {
     diagnostics::init(tcfg.enabled_warnings.clone(), tcfg.log_level);
 
-    let lcmds = get_compile_commands(cc_db, &tcfg.filter).unwrap_or_else(|_| {
-        panic!(
-            "Could not parse compile commands from {}",
-            cc_db.to_string_lossy()
-        )
-    });
+    let lcmds = read_compile_commands(cc_db, &tcfg)?;
 
     let mut dependency_infos = Vec::<DependencyInfo>::new();
 
     // Specify path to system include dir on macOS 10.14 and later. Disable the blocks extension.
-    let clang_args: Vec<String> = get_extra_args_macos();
+    let clang_args: Vec<String> = get_extra_args_macos()?;
     let mut clang_args: Vec<&str> = clang_args.iter().map(AsRef::as_ref).collect();
     clang_args.extend_from_slice(extra_clang_args);
 
     let mut num_transpiled_files = 0;
-    let build_dir = get_build_dir_raw(&tcfg, cc_db);
+    let num_mismatched_files = AtomicUsize::new(0);
+    let build_dir = get_build_dir(&tcfg, cc_db);
     for lcmd in &lcmds {
         let cmds = &lcmd.cmd_inputs;
         let lcmd_name = lcmd
@@ -527,9 +981,8 @@ pub fn export(
                 let output_path = Path::new(output);
                 output_path
                     .file_stem()
-                    .unwrap()
-                    .to_str()
-                    .unwrap()
+                    .and_then(|stem| stem.to_str())
+                    .unwrap_or(output)
                     .to_owned()
             })
             .unwrap_or_else(|| tcfg.crate_name());
@@ -559,62 +1012,112 @@ pub fn export(
                     .unwrap_or_else(PathBuf::new);
             }
         }
-        let results = cmds
-            .iter()
-            .map(|cmd| {
-                export_single(
-                    &tcfg,
-                    cmd.abs_file(),
-                    cmd.abs_output_file(),
-                    &ancestor_path,
-                    &build_dir,
-                    cc_db,
-                    &clang_args,
-                )
-            })
-            .collect::<Vec<Result<DependencyInfo, ()>>>();
+        let archive_members = match tcfg.symbol_source {
+            SymbolSource::Clang => ArchiveMembers::default(),
+            SymbolSource::Objects | SymbolSource::Both => ArchiveMembers::new(lcmd),
+        };
+        let results: Vec<Result<Option<DependencyInfo>, TranspileError>> =
+            parallel::map_ordered(tcfg.jobs, cmds, |cmd| {
+                isolate_file(&tcfg, cmd, None, || {
+                    let export_clang = || {
+                        export_single(
+                            &tcfg,
+                            cmd,
+                            &ancestor_path,
+                            &build_dir,
+                            cc_db,
+                            &clang_args,
+                            ast_cache,
+                        )
+                    };
+                    if tcfg.symbol_source == SymbolSource::Clang {
+                        return export_clang();
+                    }
+                    let symbols = match archive_members.symbols(cmd) {
+                        Ok(symbols) => symbols,
+                        Err(e) => {
+                            warn!("{}; reading its symbols from the Clang AST instead", e);
+                            return export_clang();
+                        }
+                    };
+                    let info =
+                        export_single_object(&tcfg, cmd, symbols, &ancestor_path, &build_dir)?;
+                    if let (SymbolSource::Both, Some(info)) = (tcfg.symbol_source, &info) {
+                        if let Some(clang_info) = export_clang()? {
+                            if !objects::cross_validate(&clang_info, info) {
+                                num_mismatched_files.fetch_add(1, Ordering::Relaxed);
+                            }
+                        }
+                    }
+                    Ok(info)
+                })
+            });
+
+        let results = results.into_iter().collect::<Result<Vec<_>, _>>()?;
 
         // add all dependencies from results to the dependency_infos
         for res in &results {
             match res {
-                Ok(_) => {
+                Some(_) => {
                     num_transpiled_files += 1;
                 }
-                Err(_) => {}
+                None => {}
             }
         }
-        dependency_infos.extend(results.into_iter().filter_map(|res| res.ok()));
+        dependency_infos.extend(results.into_iter().flatten());
+
+        // Assembly files are compiled as they are, but their symbols still
+        // link the C files together
+        let asm_cmds = lcmd
+            .native_inputs
+            .iter()
+            .filter(|cmd| assembly::is_assembly(&cmd.file))
+            .collect::<Vec<_>>();
+        let results = parallel::map_ordered(tcfg.jobs, &asm_cmds, |cmd| {
+            assembly::export_assembly(cmd).map_err(|e| {
+                warn!(
+                    "Could not read the symbols of {}: {}",
+                    cmd.abs_file().display(),
+                    e
+                )
+            })
+        });
+        dependency_infos.extend(results.into_iter().flatten());
     }
 
     if num_transpiled_files == 0 {
         warn!("No C files found in compile_commands.json; nothing to do.");
-        return dependency_infos;
+        return Ok(dependency_infos);
     }
 
-    let mut dep_file = match File::create(&tcfg.dependency_file) {
-        Ok(file) => file,
-        Err(e) => panic!(
-            "Unable to open file {} for writing: {}",
-            tcfg.dependency_file.display(),
-            e
-        ),
-    };
+    if tcfg.symbol_source != SymbolSource::Clang {
+        objects::resolve_undefined(&mut dependency_infos);
+    }
+    if tcfg.symbol_source == SymbolSource::Both {
+        let mismatched = num_mismatched_files.into_inner();
+        progress!(
+            "The symbols of the object files and the Clang ASTs differ for {} of {} files",
+            mismatched,
+            num_transpiled_files
+        );
+    }
+
+    if tcfg.dry_run {
+        return Ok(dependency_infos);
+    }
+
+    let mut dep_file =
+        File::create(&tcfg.dependency_file).map_err(TranspileError::io(&tcfg.dependency_file))?;
 
-    println!(
+    progress!(
         "Writing dependencies to file {}",
         tcfg.dependency_file.display()
     );
 
-    match dep_file.write_all(serde_json::to_string(&dependency_infos).unwrap().as_bytes()) {
-        Ok(()) => (),
-        Err(e) => panic!(
-            "Unable to write dependencies to file {}: {}",
-            tcfg.dependency_file.display(),
-            e
-        ),
-    };
+    serde_json::to_writer(&mut dep_file, &dependency_infos)
+        .map_err(|e| TranspileError::io(&tcfg.dependency_file)(e.into()))?;
 
-    dependency_infos
+    Ok(dependency_infos)
 }
 
 /// Ensure that clang can locate the system headers on macOS 10.14+.
@@ -625,7 +1128,7 @@ pub fn export(
 /// It is possible to install a package which puts the headers in
 /// `/usr/include` but the user doesn't have to since we can find
 /// the system headers we need by running `xcrun --show-sdk-path`.
-fn get_extra_args_macos() -> Vec<String> {
+fn get_extra_args_macos() -> Result<Vec<String>, TranspileError> {
     let mut args = vec![];
     if cfg!(target_os = "macos") {
         let usr_incl = Path::new("/usr/include");
@@ -633,10 +1136,10 @@ fn get_extra_args_macos() -> Vec<String> {
             let output = process::Command::new("xcrun")
                 .args(&["--show-sdk-path"])
                 .output()
-                .expect("failed to run `xcrun` subcommand");
-            let mut sdk_path = String::from_utf8(output.stdout).unwrap();
-            let olen = sdk_path.len();
-            sdk_path.truncate(olen - 1);
+                .map_err(TranspileError::io(Path::new("xcrun")))?;
+            let mut sdk_path = String::from_utf8_lossy(&output.stdout)
+                .trim_end()
+                .to_owned();
             sdk_path.push_str("/usr/include");
 
             args.push("-isystem".to_owned());
@@ -646,25 +1149,53 @@ fn get_extra_args_macos() -> Vec<String> {
         // disable Apple's blocks extension; see https://github.com/immunant/c2rust/issues/229
         args.push("-fno-blocks".to_owned());
     }
-    args
+    Ok(args)
 }
 
-fn invoke_refactor(_build_dir: &Path) -> Result<(), Error> {
-    Ok(())
-}
-
-fn reorganize_definitions(
+/// With `--reorganize-definitions`, refactor the freshly emitted crate in
+/// `build_dir`: merge its header submodules (see [`reorganize`]), import the
+/// functions its modules define for each other (see [`extern_imports`]) and
+/// format it with `cargo fmt`. `binaries` are the roots of its binaries, which
+/// either include the module tree of the library or link against the
+/// library crate `lib_crate`, depending on the [`BinaryLayout`].
+fn refactor_crate(
     tcfg: &TranspilerConfig,
     build_dir: &Path,
     crate_file: Option<PathBuf>,
+    binaries: Vec<PathBuf>,
+    lib_crate: &str,
+    dependency_graph: &DependencyGraph,
+    report: &FailureReport,
 ) -> Result<(), Error> {
-    // We only run the reorganization refactoring if we emitted a fresh crate file
-    if crate_file.is_none() || tcfg.disable_refactoring || !tcfg.reorganize_definitions {
+    // We only run the refactorings if we emitted a fresh crate file
+    if crate_file.is_none()
+        || tcfg.disable_refactoring
+        || tcfg.dry_run
+        || !tcfg.reorganize_definitions
+    {
         return Ok(());
     }
 
-    invoke_refactor(build_dir)?;
-    // fix the formatting of the output of `c2rust-refactor`
+    // With `--emit-no-lib`, the crate file is `build.rs`
+    let lib_root = crate_file.filter(|file| !file.ends_with("build.rs"));
+    let lib_crate = tcfg.binaries_use_lib().then_some(lib_crate);
+    reorganize::merge_header_modules(
+        build_dir,
+        lib_root.as_deref(),
+        &binaries,
+        lib_crate,
+        tcfg.incremental,
+    )?;
+    extern_imports::import_definitions(
+        lib_root.as_deref(),
+        &binaries,
+        lib_crate,
+        dependency_graph,
+        tcfg.fuzz_depends_level,
+        report,
+        tcfg.replace_unsupported_decls,
+    )?;
+    // fix the formatting of the edited modules
     let status = process::Command::new("cargo")
         .args(&["fmt"])
         .current_dir(build_dir)
@@ -675,212 +1206,348 @@ fn reorganize_definitions(
     Ok(())
 }
 
+/// Run the translation (or export) of a single file, treating a panic like
+/// any other reason to skip the file, unless `fail_on_error` is set.
+fn isolate_file<T>(
+    tcfg: &TranspilerConfig,
+    cmd: &CompileCmd,
+    report: Option<&FailureReport>,
+    f: impl FnOnce() -> Result<Option<T>, TranspileError>,
+) -> Result<Option<T>, TranspileError> {
+    let message = match isolate::catch_panic(f) {
+        Ok(res) => return res,
+        Err(message) => message,
+    };
+    let input_path = cmd.abs_file();
+    if tcfg.fail_on_error {
+        return Err(TranspileError::Panicked {
+            input: input_path,
+            message,
+        });
+    }
+    warn!(
+        "Skipping {}; the translator {}",
+        input_path.display(),
+        message
+    );
+    if let Some(report) = report {
+        report.add(Failure::panicked_file(
+            &input_path,
+            message,
+            tcfg.replace_unsupported_decls,
+        ));
+    }
+    Ok(None)
+}
+
 fn transpile_single(
     tcfg: &TranspilerConfig,
-    input_path: PathBuf,
-    output_path: Option<PathBuf>,
+    cmd: &CompileCmd,
     ancestor_path: &Path,
     build_dir: &Path,
     cc_db: &Path,
     extra_clang_args: &[&str],
     dependency_graph: &DependencyGraph,
-    get_prefix: impl FnOnce(&PragmaVec, &CrateSet) -> String,
+    ast_cache: &AstCache,
+    generation: &Generation,
+    report: &FailureReport,
+    differential: &DifferentialTests,
+    plan: Option<&Plan>,
+    lib_modules: &[PathBuf],
+    get_prefix: impl FnOnce(&PragmaVec, &CrateSet) -> io::Result<String>,
 ) -> TranspileResult {
+    let input_path = cmd.abs_file();
+    let tcfg = tcfg.for_input(&input_path);
+    let tcfg = &*tcfg;
+    let extra_clang_args = tcfg.clang_args_for(&input_path, extra_clang_args);
+    let extra_clang_args = &extra_clang_args[..];
+    let replace_mode = tcfg.replace_unsupported_decls;
+    let input_str = path_str(&input_path)?;
+    let dependency_index = dependency_graph
+        .nodes
+        .iter()
+        .position(|dep| dep.input_path == input_str);
+    let dependency_info = dependency_index.map(|i| &dependency_graph.nodes[i]);
+    let is_binary = dependency_info.map_or(false, |dep| tcfg.is_binary(dep));
+    let output_path = cmd.abs_output_file();
     let output_path = get_output_path(
         tcfg,
         input_path.clone(),
         output_path,
         ancestor_path,
         build_dir,
-        tcfg.is_binary(
-            &(dependency_graph
-                .nodes
-                .iter()
-                .find(|dep| dep.input_path == input_path.to_str().unwrap())
-                .unwrap()),
-        ),
-    );
-    if output_path.exists() && !tcfg.overwrite_existing {
+        is_binary,
+    )?;
+    let plan_file = |action| {
+        if let Some(plan) = plan {
+            plan.add_file(PlannedFile {
+                input: input_path.clone(),
+                output: output_path.clone(),
+                is_binary,
+                action,
+            });
+        }
+    };
+    if dependency_info.is_none() {
+        warn!("{} was not exported, skipping!", input_path.display());
+        let msg = "The file could not be exported".to_string();
+        plan_file(FileAction::Skip(msg.clone()));
+        report.add(Failure::skipped_file(&input_path, msg, replace_mode));
+        return Ok(None);
+    }
+
+    let args = cmd
+        .args()
+        .into_iter()
+        .chain(extra_clang_args.iter().map(|arg| arg.to_string()))
+        .collect::<Vec<_>>();
+    if let Some((pragmas, crates, failures)) =
+        generation.reuse_if_unchanged(&output_path, &input_path, &args, lib_modules)
+    {
+        progress!("Skipping unchanged file {}", output_path.display());
+        plan_file(FileAction::Unchanged);
+        report.extend(failures);
+        return Ok(Some((output_path, pragmas, crates)));
+    }
+    if output_path.exists()
+        && !tcfg.overwrite_existing
+        && !(tcfg.incremental && generation.owns(&output_path))
+    {
         warn!("Skipping existing file {}", output_path.display());
-        return Err(());
+        plan_file(FileAction::SkipExisting);
+        // The manifest still tells us what the existing file needs in lib.rs
+        return match generation.reuse_existing(&output_path) {
+            Some((pragmas, crates, failures)) => {
+                report.extend(failures);
+                Ok(Some((output_path, pragmas, crates)))
+            }
+            None => {
+                let msg = format!("Output file {} already exists", output_path.display());
+                report.add(Failure::skipped_file(&input_path, msg, replace_mode));
+                Ok(None)
+            }
+        };
     }
 
-    let file = input_path.file_name().unwrap().to_str().unwrap();
+    let file = file_name_str(&input_path)?;
     if !input_path.exists() {
         warn!(
             "Input C file {} does not exist, skipping!",
             input_path.display()
         );
-        return Err(());
+        let msg = "Input C file does not exist".to_string();
+        plan_file(FileAction::Skip(msg.clone()));
+        report.add(Failure::skipped_file(&input_path, msg, replace_mode));
+        return Ok(None);
+    }
+
+    if plan.is_some() {
+        plan_file(FileAction::write(&output_path));
+        return Ok(Some((output_path, PragmaVec::new(), CrateSet::new())));
     }
 
     if tcfg.verbose {
-        println!("Additional Clang arguments: {}", extra_clang_args.join(" "));
+        progress!("Additional Clang arguments: {}", extra_clang_args.join(" "));
     }
 
     // Extract the untyped AST from the CBOR file
-    let untyped_context = match ast_exporter::get_untyped_ast(
-        input_path.as_path(),
-        cc_db,
-        extra_clang_args,
-        tcfg.debug_ast_exporter,
-    ) {
-        Err(e) => {
-            warn!(
-                "Error: {}. Skipping {}; is it well-formed C?",
-                e,
-                input_path.display()
-            );
-            return Err(());
-        }
-        Ok(cxt) => cxt,
-    };
+    let untyped_context =
+        match ast_cache.get_untyped_ast(cmd, cc_db, extra_clang_args, tcfg.debug_ast_exporter) {
+            Err(e) => {
+                warn!(
+                    "Error: {}. Skipping {}; is it well-formed C?",
+                    e,
+                    input_path.display()
+                );
+                let msg = format!("Could not export the Clang AST: {}", e);
+                report.add(Failure::skipped_file(&input_path, msg, replace_mode));
+                return Ok(None);
+            }
+            Ok(cxt) => cxt,
+        };
+    let sources = crate::ast_cache::ast_sources(&untyped_context);
 
-    println!("Transpiling {}", file);
+    progress!("Transpiling {}", file);
 
     if tcfg.dump_untyped_context {
-        println!("CBOR Clang AST");
-        println!("{:#?}", untyped_context);
+        progress!("CBOR Clang AST");
+        progress!("{:#?}", untyped_context);
     }
 
     // Convert this into a typed AST
-    let typed_context = {
+    let (typed_context, mut failures) = {
         let conv = ConversionContext::new(&untyped_context);
         if conv.invalid_clang_ast && tcfg.fail_on_error {
-            panic!("Clang AST was invalid");
+            return Err(TranspileError::InvalidClangAst {
+                input: input_path.clone(),
+            });
         }
-        conv.typed_context
+        let failures = conv
+            .invalid_clang_ast_errors
+            .iter()
+            .map(|e| {
+                let kind = FailureKind::InvalidClangAst;
+                Failure::from_error(kind, &input_path, None, None, e, replace_mode)
+            })
+            .collect::<Vec<_>>();
+        (conv.typed_context, failures)
     };
 
     if tcfg.dump_typed_context {
-        println!("Clang AST");
-        println!("{:#?}", typed_context);
+        progress!("Clang AST");
+        progress!("{:#?}", typed_context);
     }
 
     if tcfg.pretty_typed_context {
-        println!("Pretty-printed Clang AST");
-        println!("{:#?}", Printer::new(io::stdout()).print(&typed_context));
+        progress!("Pretty-printed Clang AST");
+        progress!("{}", pretty_print(&typed_context));
+    }
+
+    if tcfg.emit_differential_tests && !is_binary {
+        if let Some(dependency_info) = dependency_info {
+            differential.add(tcfg, &typed_context, dependency_info, &output_path);
+        }
     }
 
     // Perform the translation
-    let (mut translated_string, pragmas, crates) = translator::translate(
+    let multicall_util = dependency_info
+        .filter(|dep| tcfg.is_multicall_util(dep))
+        .map(|_| TranspilerConfig::binary_name_from_path(&output_path));
+    let overridden_symbols = dependency_index
+        .map(|i| dependency_graph.overridden_definitions(i, tcfg.fuzz_depends_level))
+        .unwrap_or_default()
+        .into_iter()
+        .map(str::to_owned)
+        .collect();
+    let (mut translated_string, pragmas, crates, translation_failures) = translator::translate(
         typed_context,
         tcfg,
         &input_path,
-        tcfg.is_binary(
-            &(dependency_graph
-                .nodes
-                .iter()
-                .find(|dep| dep.input_path == input_path.to_str().unwrap())
-                .unwrap()),
-        ),
+        is_binary,
+        multicall_util.as_deref(),
+        overridden_symbols,
     );
 
-    if tcfg.emit_binaries
-        && tcfg.is_binary(
-            &(dependency_graph
-                .nodes
-                .iter()
-                .find(|dep| dep.input_path == input_path.to_str().unwrap())
-                .unwrap()),
-        )
-    {
-        translated_string = get_prefix(&pragmas, &crates) + &translated_string;
+    if tcfg.fail_on_error {
+        let failed = translation_failures
+            .iter()
+            .find(|failure| failure.kind == FailureKind::FailedDeclaration);
+        if let Some(failure) = failed.filter(|failure| failure.is_panic()) {
+            return Err(TranspileError::Panicked {
+                input: input_path.clone(),
+                message: failure.message.clone(),
+            });
+        }
+        if let Some(failure) = failed {
+            return Err(TranspileError::FailedDeclaration {
+                input: input_path.clone(),
+                declaration: failure.declaration.clone(),
+                message: failure.message.clone(),
+            });
+        }
     }
 
-    let mut file = match File::create(&output_path) {
-        Ok(file) => file,
-        Err(e) => panic!(
-            "Unable to open file {} for writing: {}",
-            output_path.display(),
-            e
-        ),
-    };
-
-    match file.write_all(translated_string.as_bytes()) {
-        Ok(()) => (),
-        Err(e) => panic!(
-            "Unable to write translation to file {}: {}",
-            output_path.display(),
-            e
-        ),
-    };
+    if tcfg.emit_binaries && is_binary {
+        let prefix = get_prefix(&pragmas, &crates).map_err(TranspileError::io(&output_path))?;
+        translated_string = prefix + &translated_string;
+    }
+    failures.extend(translation_failures);
+
+    let output_dir = output_path.parent().unwrap_or_else(|| Path::new(""));
+    fs::create_dir_all(output_dir).map_err(TranspileError::io(output_dir))?;
+    let mut file = File::create(&output_path).map_err(TranspileError::io(&output_path))?;
+    file.write_all(translated_string.as_bytes())
+        .map_err(TranspileError::io(&output_path))?;
+    generation.record(
+        &output_path,
+        &input_path,
+        args,
+        sources,
+        &translated_string,
+        lib_modules,
+        &pragmas,
+        &crates,
+        &failures,
+    );
+    report.extend(failures);
 
-    Ok((output_path, pragmas, crates))
+    Ok(Some((output_path, pragmas, crates)))
 }
 
 fn export_single(
     tcfg: &TranspilerConfig,
-    input_path: PathBuf,
-    output_path: Option<PathBuf>,
+    cmd: &CompileCmd,
     ancestor_path: &Path,
     build_dir: &Path,
     cc_db: &Path,
     extra_clang_args: &[&str],
-) -> Result<DependencyInfo, ()> {
+    ast_cache: &AstCache,
+) -> Result<Option<DependencyInfo>, TranspileError> {
+    let input_path = cmd.abs_file();
+    let extra_clang_args = tcfg.clang_args_for(&input_path, extra_clang_args);
+    let extra_clang_args = &extra_clang_args[..];
+    let output_path = cmd.abs_output_file();
     let raw_output_path = get_output_path_raw(
         tcfg,
         input_path.clone(),
         &output_path,
         ancestor_path,
         build_dir,
-    );
+    )?;
 
-    let file = input_path.file_name().unwrap().to_str().unwrap();
+    let file = file_name_str(&input_path)?;
     if !input_path.exists() {
         warn!(
             "Input C file {} does not exist, skipping!",
             input_path.display()
         );
-        return Err(());
+        return Ok(None);
     }
 
     if tcfg.verbose {
-        println!("Additional Clang arguments: {}", extra_clang_args.join(" "));
+        progress!("Additional Clang arguments: {}", extra_clang_args.join(" "));
     }
 
     // Extract the untyped AST from the CBOR file
-    let untyped_context = match ast_exporter::get_untyped_ast(
-        input_path.as_path(),
-        cc_db,
-        extra_clang_args,
-        tcfg.debug_ast_exporter,
-    ) {
-        Err(e) => {
-            warn!(
-                "Error: {}. Skipping {}; is it well-formed C?",
-                e,
-                input_path.display()
-            );
-            return Err(());
-        }
-        Ok(cxt) => cxt,
-    };
+    let untyped_context =
+        match ast_cache.get_untyped_ast(cmd, cc_db, extra_clang_args, tcfg.debug_ast_exporter) {
+            Err(e) => {
+                warn!(
+                    "Error: {}. Skipping {}; is it well-formed C?",
+                    e,
+                    input_path.display()
+                );
+                return Ok(None);
+            }
+            Ok(cxt) => cxt,
+        };
 
-    println!("Exporting {}", file);
+    progress!("Exporting {}", file);
 
     if tcfg.dump_untyped_context {
-        println!("CBOR Clang AST");
-        println!("{:#?}", untyped_context);
+        progress!("CBOR Clang AST");
+        progress!("{:#?}", untyped_context);
     }
 
     // Convert this into a typed AST
     let typed_context = {
         let conv = ConversionContext::new(&untyped_context);
         if conv.invalid_clang_ast && tcfg.fail_on_error {
-            panic!("Clang AST was invalid");
+            return Err(TranspileError::InvalidClangAst {
+                input: input_path.clone(),
+            });
         }
         conv.typed_context
     };
 
     if tcfg.dump_typed_context {
-        println!("Clang AST");
-        println!("{:#?}", typed_context);
+        progress!("Clang AST");
+        progress!("{:#?}", typed_context);
     }
 
     if tcfg.pretty_typed_context {
-        println!("Pretty-printed Clang AST");
-        println!("{:#?}", Printer::new(io::stdout()).print(&typed_context));
+        progress!("Pretty-printed Clang AST");
+        progress!("{}", pretty_print(&typed_context));
     }
 
     let mut export_context = typed_context.clone();
@@ -888,11 +1555,9 @@ fn export_single(
     export_context.prune_unwanted_decls(tcfg.preserve_unused_functions);
 
     let mut dependency_info = DependencyInfo {
-        input_path: input_path.to_str().unwrap().to_string(),
-        output_path: raw_output_path.to_str().unwrap().to_string(),
-        object_path: output_path
-            .clone()
-            .map(|path| path.to_str().unwrap().to_string()),
+        input_path: path_str(&input_path)?.to_string(),
+        output_path: path_str(&raw_output_path)?.to_string(),
+        object_path: object_path_str(&output_path)?,
         undefined: vec![],
         defined: vec![],
     };
@@ -903,62 +1568,56 @@ fn export_single(
                 is_global: true,
                 name,
                 body,
+                attrs,
                 ..
             } => {
-                let decl_file = export_context
-                    .get_file_path(export_context.file_id(decl).unwrap())
-                    .unwrap();
-                // println!(
-                //     "Function: {}, is_global: {}, is_implicit: {}, is_extern: {}, body: {}",
-                //     name,
-                //     is_global,
-                //     is_implicit,
-                //     is_extern,
-                //     body.is_some()
-                // );
-
-                if !body.is_some() {
-                    println!("U {}", name);
-                    dependency_info.undefined.push(DependencySymbol {
-                        name: name.to_string(),
-                        path: decl_file.to_str().unwrap().to_string(),
-                    });
-                } else if body.is_some() {
-                    println!("T {}", name);
-                    dependency_info.defined.push(DependencySymbol {
-                        name: name.to_string(),
-                        path: decl_file.to_str().unwrap().to_string(),
-                    });
+                let decl_file = decl_file_str(&export_context, decl, &input_path)?;
+                let mut symbol = DependencySymbol::new(name.to_string(), decl_file);
+                symbol.alias_of = alias_of(attrs);
+
+                // An alias defines the function, without a body
+                if body.is_none() && symbol.alias_of.is_none() {
+                    progress!("U {}", name);
+                    dependency_info.undefined.push(symbol);
                 } else {
-                    assert!(false);
+                    symbol.binding = definition_binding(attrs, false);
+                    progress!(
+                        "{} {}",
+                        if symbol.binding.is_strong() { "T" } else { "W" },
+                        name
+                    );
+                    dependency_info.defined.push(symbol);
                 }
             }
             CDeclKind::Variable {
                 is_externally_visible: true,
                 is_defn,
+                has_thread_duration,
                 ident,
+                initializer,
+                attrs,
                 ..
             } => {
-                let decl_file = export_context
-                    .get_file_path(export_context.file_id(decl).unwrap())
-                    .unwrap();
-
-                // println!(
-                //     "Variable: {:?}, is_defn: {}, is_externally_visible: {}",
-                //     name, is_defn, true
-                // );
-                if *is_defn {
-                    println!("b {}", ident);
-                    dependency_info.defined.push(DependencySymbol {
-                        name: ident.to_string(),
-                        path: decl_file.to_str().unwrap().to_string(),
-                    });
+                let decl_file = decl_file_str(&export_context, decl, &input_path)?;
+                let mut symbol = DependencySymbol::new(ident.to_string(), decl_file);
+                symbol.alias_of = alias_of(attrs);
+
+                if *is_defn || symbol.alias_of.is_some() {
+                    // A tentative definition, `int x;`, has no initializer.
+                    // Thread-local ones are never common.
+                    let tentative =
+                        symbol.alias_of.is_none() && initializer.is_none() && !has_thread_duration;
+                    symbol.binding = definition_binding(attrs, tentative);
+                    let kind = match symbol.binding {
+                        Binding::Strong => "b",
+                        Binding::Common => "C",
+                        Binding::Weak => "V",
+                    };
+                    progress!("{} {}", kind, ident);
+                    dependency_info.defined.push(symbol);
                 } else {
-                    println!("U {}", ident);
-                    dependency_info.undefined.push(DependencySymbol {
-                        name: ident.to_string(),
-                        path: decl_file.to_str().unwrap().to_string(),
-                    });
+                    progress!("U {}", ident);
+                    dependency_info.undefined.push(symbol);
                 }
             }
             _ => {}
@@ -972,11 +1631,111 @@ fn export_single(
         ancestor_path,
         build_dir,
         tcfg.is_binary(&dependency_info),
-    );
+    )?;
+
+    dependency_info.output_path = path_str(&output_path)?.to_string();
+
+    Ok(Some(dependency_info))
+}
+
+/// The object file of a compile command, as the dependency information
+/// records it
+fn object_path_str(object_path: &Option<PathBuf>) -> Result<Option<String>, TranspileError> {
+    object_path
+        .as_deref()
+        .map(|path| path_str(path).map(str::to_owned))
+        .transpose()
+}
+
+/// The file that `decl` is in, falling back to the translation unit
+/// `input_path` for declarations without a location
+fn decl_file_str(
+    context: &TypedAstContext,
+    decl: &CDecl,
+    input_path: &Path,
+) -> Result<String, TranspileError> {
+    let decl_file = context
+        .file_id(decl)
+        .and_then(|id| context.get_file_path(id))
+        .unwrap_or(input_path);
+    Ok(path_str(decl_file)?.to_owned())
+}
+
+/// The C source of `context`, printed into a string so that it goes through
+/// the output capture of the file rather than straight to stdout
+fn pretty_print(context: &TypedAstContext) -> String {
+    let mut buffer = vec![];
+    if let Err(e) = Printer::new(&mut buffer).print(context) {
+        return format!("Could not print the Clang AST: {}", e);
+    }
+    String::from_utf8_lossy(&buffer).into_owned()
+}
+
+/// How the linker treats a definition with `attrs`; `tentative` is for
+/// tentative definitions of variables
+fn definition_binding(attrs: &indexmap::IndexSet<c_ast::Attribute>, tentative: bool) -> Binding {
+    if attrs.contains(&c_ast::Attribute::Weak) {
+        Binding::Weak
+    } else if tentative {
+        Binding::Common
+    } else {
+        Binding::Strong
+    }
+}
+
+/// The target of `__attribute__((alias))` among `attrs`
+fn alias_of(attrs: &indexmap::IndexSet<c_ast::Attribute>) -> Option<String> {
+    attrs.iter().find_map(|attr| match attr {
+        c_ast::Attribute::Alias(target) => Some(target.clone()),
+        _ => None,
+    })
+}
+
+/// Like [`export_single`], but with the symbols of the object file that the
+/// original build compiled `cmd` to instead of the declarations of its AST.
+fn export_single_object(
+    tcfg: &TranspilerConfig,
+    cmd: &CompileCmd,
+    symbols: ObjectSymbols,
+    ancestor_path: &Path,
+    build_dir: &Path,
+) -> Result<Option<DependencyInfo>, TranspileError> {
+    let input_path = cmd.abs_file();
+    let output_path = cmd.abs_output_file();
+    if !input_path.exists() {
+        warn!(
+            "Input C file {} does not exist, skipping!",
+            input_path.display()
+        );
+        return Ok(None);
+    }
 
-    dependency_info.output_path = output_path.to_str().unwrap().to_string();
+    let file = file_name_str(&input_path)?;
+    progress!("Exporting the symbols of {}", file);
 
-    Ok(dependency_info)
+    // Undefined symbols get the path of their definition later, once every
+    // object is read
+    let path = path_str(&input_path)?.to_string();
+    let (defined, undefined) = symbols.into_dependency_symbols(&path);
+    let mut dependency_info = DependencyInfo {
+        input_path: path,
+        output_path: String::new(),
+        object_path: object_path_str(&output_path)?,
+        undefined,
+        defined,
+    };
+
+    let output_path = get_output_path(
+        tcfg,
+        input_path.clone(),
+        output_path,
+        ancestor_path,
+        build_dir,
+        tcfg.is_binary(&dependency_info),
+    )?;
+    dependency_info.output_path = path_str(&output_path)?.to_string();
+
+    Ok(Some(dependency_info))
 }
 
 fn get_output_path(
@@ -986,94 +1745,92 @@ fn get_output_path(
     ancestor_path: &Path,
     build_dir: &Path,
     is_binary: bool,
-) -> PathBuf {
+) -> Result<PathBuf, TranspileError> {
     // When an output file name is not explictly specified, we should convert files
     // with dashes to underscores, as they are not allowed in rust file names.
     if let Some(output_path) = output_path {
         input_path = output_path;
     }
-    let file_name = input_path
-        .file_name()
-        .unwrap()
-        .to_str()
-        .unwrap()
-        .replace('-', "_");
+    let file_name = file_name_str(&input_path)?.replace('-', "_");
 
     input_path.set_file_name(file_name);
     input_path.set_extension("rs");
 
     if tcfg.output_dir.is_some() {
-        let path_buf = input_path
-            .strip_prefix(ancestor_path)
-            .expect("Couldn't strip common ancestor path");
+        let path_buf = strip_ancestor(&input_path, ancestor_path)?;
 
         // Place the source files in build_dir/src/
         let mut output_path = build_dir.to_path_buf();
+        let invalid = || TranspileError::InvalidPath {
+            path: input_path.clone(),
+        };
         if is_binary {
-            let elem = path_buf.iter().last().unwrap();
+            let elem = path_buf.iter().last().ok_or_else(invalid)?;
             let path = Path::new(elem);
-            let name = get_module_name(path, false, true, false).unwrap();
+            let name = get_module_name(path, false, true, false).ok_or_else(invalid)?;
             output_path.push(name);
         } else {
             output_path.push("src");
             for elem in path_buf.iter() {
                 let path = Path::new(elem);
-                let name = get_module_name(path, false, true, false).unwrap();
+                let name = get_module_name(path, false, true, false).ok_or_else(invalid)?;
                 output_path.push(name);
             }
         }
 
-        // Create the parent directory if it doesn't exist
-        let parent = output_path.parent().unwrap();
-        if !parent.exists() {
-            fs::create_dir_all(&parent).unwrap_or_else(|_| {
-                panic!("couldn't create source directory: {}", parent.display())
-            });
-        }
-        output_path
+        Ok(output_path)
     } else {
-        input_path
+        Ok(input_path)
     }
 }
 
+fn strip_ancestor<'a>(
+    input_path: &'a Path,
+    ancestor_path: &Path,
+) -> Result<&'a Path, TranspileError> {
+    input_path
+        .strip_prefix(ancestor_path)
+        .map_err(|_| TranspileError::OutputPath {
+            input: input_path.to_owned(),
+            ancestor: ancestor_path.to_owned(),
+        })
+}
+
 fn get_output_path_raw(
     tcfg: &TranspilerConfig,
     mut input_path: PathBuf,
     output_path: &Option<PathBuf>,
     ancestor_path: &Path,
     build_dir: &Path,
-) -> PathBuf {
+) -> Result<PathBuf, TranspileError> {
     // When an output file name is not explictly specified, we should convert files
     // with dashes to underscores, as they are not allowed in rust file names.
     if let Some(output_path) = output_path {
         input_path = output_path.clone();
     }
-    let file_name = input_path
-        .file_name()
-        .unwrap()
-        .to_str()
-        .unwrap()
-        .replace('-', "_");
+    let file_name = file_name_str(&input_path)?.replace('-', "_");
 
     input_path.set_file_name(file_name);
     input_path.set_extension("rs");
 
     if tcfg.output_dir.is_some() {
-        let path_buf = input_path
-            .strip_prefix(ancestor_path)
-            .expect("Couldn't strip common ancestor path");
+        let path_buf = strip_ancestor(&input_path, ancestor_path)?;
 
         // Place the source files in build_dir/src/
         let mut output_path = build_dir.to_path_buf();
         output_path.push("src");
         for elem in path_buf.iter() {
             let path = Path::new(elem);
-            let name = get_module_name(path, false, true, false).unwrap();
+            let name = get_module_name(path, false, true, false).ok_or_else(|| {
+                TranspileError::InvalidPath {
+                    path: input_path.clone(),
+                }
+            })?;
             output_path.push(name);
         }
 
-        output_path
+        Ok(output_path)
     } else {
-        input_path
+        Ok(input_path)
     }
 }
@@ -0,0 +1,565 @@
+//! The generation manifest: a record, kept in the output directory, of how
+//! each emitted Rust file was produced.
+//!
+//! For every translated file it lists the C input, the compile flags, the
+//! hashes of the input and of every header it included, and the tool version
+//! and translation options used. It also remembers what the translation
+//! contributed to `lib.rs` (pragmas and extern crates), so the build files can
+//! be emitted again without retranslating every file. With `--incremental`,
+//! files whose record is still accurate are not translated again. Clang still
+//! parses every translation unit to build the dependency information, though;
+//! only the AST cache (`--ast-cache-dir`) avoids that.
+//!
+//! Next to the manifest we keep the effective `ec2rust.toml` of the last run,
+//! if the caller provided one, so that the run can be reproduced.
+
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use c2rust_ast_exporter as ast_exporter;
+use failure::Error;
+use log::warn;
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::ast_cache::SourceHash;
+use crate::report::Failure;
+use crate::{CrateSet, ExternCrate, PragmaVec, TranspilerConfig};
+
+pub(crate) const MANIFEST_FILE_NAME: &str = "c2rust-manifest.json";
+pub(crate) const EFFECTIVE_CONFIG_FILE_NAME: &str = "ec2rust-effective.toml";
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct GenerationManifest {
+    /// Emitted files, by path relative to the output directory
+    files: BTreeMap<PathBuf, ManifestEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct ManifestEntry {
+    input: PathBuf,
+    /// Compiler arguments from `compile_commands.json` followed by the extra
+    /// Clang arguments
+    args: Vec<String>,
+    /// The input and every header it included
+    sources: Vec<SourceHash>,
+    /// Hash of the emitted file as the run left it, after the refactoring of
+    /// the crate, to notice manual edits
+    output_hash: String,
+    tool_version: String,
+    /// Hash of the options that affect translation
+    config: String,
+    /// Library modules declared at the top of this file (binaries only)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    lib_modules: Vec<PathBuf>,
+    pragmas: Vec<(String, Vec<String>)>,
+    crates: Vec<ExternCrate>,
+    /// Failures reported while translating this file
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    failures: Vec<Failure>,
+}
+
+/// What a translation unit translated in an earlier run contributes to the
+/// build files and the failure report.
+pub(crate) type RecordedResult = (PragmaVec, CrateSet, Vec<Failure>);
+
+impl ManifestEntry {
+    fn result(&self) -> RecordedResult {
+        let pragmas = self
+            .pragmas
+            .iter()
+            .map(|(key, vals)| (intern(key), vals.iter().map(|val| intern(val)).collect()))
+            .collect();
+        let crates = self.crates.iter().copied().collect();
+        (pragmas, crates, self.failures.clone())
+    }
+}
+
+/// Pragmas are `&'static str`s since the translator only ever produces
+/// literals; recorded ones are leaked, but only once per distinct string.
+fn intern(s: &str) -> &'static str {
+    static INTERNED: Mutex<Option<HashSet<&'static str>>> = Mutex::new(None);
+    let mut interned = INTERNED.lock().unwrap();
+    let interned = interned.get_or_insert_with(HashSet::new);
+    match interned.get(s) {
+        Some(s) => s,
+        None => {
+            let s: &'static str = Box::leak(s.to_owned().into_boxed_str());
+            interned.insert(s);
+            s
+        }
+    }
+}
+
+fn hash_bytes(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+fn tool_version() -> String {
+    format!(
+        "{}+{}",
+        env!("CARGO_PKG_VERSION"),
+        ast_exporter::exporter_version()
+    )
+}
+
+/// Hash of every option that changes the translation of a file. Options that
+/// only select files or control logging and build files are left out.
+fn config_fingerprint(tcfg: &TranspilerConfig) -> String {
+    let options = format!(
+        "{:?}",
+        (
+            (
+                tcfg.incremental_relooper,
+                tcfg.fail_on_multiple,
+                tcfg.debug_relooper_labels,
+                &tcfg.prefix_function_names,
+                tcfg.translate_asm,
+                tcfg.use_c_loop_info,
+                tcfg.use_c_multiple_info,
+                tcfg.simplify_structures,
+                tcfg.panic_on_translator_failure,
+                tcfg.emit_modules,
+                tcfg.fail_on_error,
+                tcfg.replace_unsupported_decls,
+            ),
+            (
+                tcfg.translate_valist,
+                tcfg.reduce_type_annotations,
+                tcfg.reorganize_definitions,
+                tcfg.emit_no_std,
+                tcfg.emit_no_lib,
+                tcfg.translate_const_macros,
+                tcfg.translate_fn_macros,
+                tcfg.preserve_unused_functions,
+                tcfg.emit_binaries,
+                &tcfg.binaries,
+                tcfg.detect_binaries,
+                tcfg.fuzz_depends_level,
+            ),
+            (tcfg.binary_layout, tcfg.multicall),
+            &tcfg.overrides,
+        )
+    );
+    hash_bytes(options.as_bytes())
+}
+
+fn read_manifest(path: &Path) -> Result<GenerationManifest, Error> {
+    Ok(serde_json::from_slice(&fs::read(path)?)?)
+}
+
+/// The manifest of the previous run, and the one being built by this run.
+pub(crate) struct Generation {
+    /// Where the manifest is kept; `None` if there is no output directory
+    path: Option<PathBuf>,
+    build_dir: PathBuf,
+    incremental: bool,
+    tool_version: String,
+    config: String,
+    effective_config: Option<String>,
+    previous: GenerationManifest,
+    current: Mutex<GenerationManifest>,
+    /// Files translated (rather than reused) by this run
+    regenerated: Mutex<HashSet<PathBuf>>,
+}
+
+impl Generation {
+    pub fn load(tcfg: &TranspilerConfig, build_dir: &Path) -> Self {
+        let path = tcfg
+            .output_dir
+            .as_ref()
+            .map(|_| build_dir.join(MANIFEST_FILE_NAME));
+        let previous = path
+            .as_ref()
+            .filter(|path| path.exists())
+            .and_then(|path| match read_manifest(path) {
+                Ok(manifest) => Some(manifest),
+                Err(e) => {
+                    warn!("Ignoring unreadable {}: {}", path.display(), e);
+                    None
+                }
+            })
+            .unwrap_or_default();
+        Self {
+            path,
+            build_dir: build_dir.to_owned(),
+            incremental: tcfg.incremental,
+            tool_version: tool_version(),
+            config: config_fingerprint(tcfg),
+            effective_config: tcfg.effective_config.clone(),
+            previous,
+            current: Default::default(),
+            regenerated: Default::default(),
+        }
+    }
+
+    fn key(&self, output_path: &Path) -> PathBuf {
+        output_path
+            .strip_prefix(&self.build_dir)
+            .unwrap_or(output_path)
+            .to_owned()
+    }
+
+    /// The previous entry for `output_path`, if the file is still exactly as
+    /// we emitted it.
+    fn unmodified_entry(&self, output_path: &Path) -> Option<&ManifestEntry> {
+        let entry = self.previous.files.get(&self.key(output_path))?;
+        let contents = fs::read(output_path).ok()?;
+        (hash_bytes(&contents) == entry.output_hash).then_some(entry)
+    }
+
+    fn keep(&self, output_path: &Path, entry: &ManifestEntry) -> RecordedResult {
+        self.current
+            .lock()
+            .unwrap()
+            .files
+            .insert(self.key(output_path), entry.clone());
+        entry.result()
+    }
+
+    /// Whether `output_path` was emitted by an earlier run and not edited
+    /// since, so that it can be overwritten without losing anything.
+    pub fn owns(&self, output_path: &Path) -> bool {
+        self.unmodified_entry(output_path).is_some()
+    }
+
+    /// In incremental mode, reuse the earlier translation of `input` if none
+    /// of its sources, flags, prepended library modules, the tool or the
+    /// translation options changed.
+    pub fn reuse_if_unchanged(
+        &self,
+        output_path: &Path,
+        input: &Path,
+        args: &[String],
+        lib_modules: &[PathBuf],
+    ) -> Option<RecordedResult> {
+        if !self.incremental {
+            return None;
+        }
+        let entry = self.unmodified_entry(output_path)?;
+        let regenerated = self.regenerated.lock().unwrap();
+        let unchanged = entry.input == input
+            && entry.args == args
+            && entry.tool_version == self.tool_version
+            && entry.config == self.config
+            && entry.lib_modules == lib_modules
+            && !lib_modules.iter().any(|m| regenerated.contains(m))
+            && entry.sources.iter().all(SourceHash::is_current);
+        drop(regenerated);
+        unchanged.then(|| self.keep(output_path, entry))
+    }
+
+    /// Reuse what an existing, unmodified output file recorded about itself,
+    /// even though it may be out of date. Used when existing files are not
+    /// overwritten, so that the build files still cover them.
+    pub fn reuse_existing(&self, output_path: &Path) -> Option<RecordedResult> {
+        let entry = self.unmodified_entry(output_path)?;
+        Some(self.keep(output_path, entry))
+    }
+
+    /// Record a file translated by this run.
+    pub fn record(
+        &self,
+        output_path: &Path,
+        input: &Path,
+        args: Vec<String>,
+        sources: Vec<SourceHash>,
+        output: &str,
+        lib_modules: &[PathBuf],
+        pragmas: &PragmaVec,
+        crates: &CrateSet,
+        failures: &[Failure],
+    ) {
+        let entry = ManifestEntry {
+            input: input.to_owned(),
+            args,
+            sources,
+            output_hash: hash_bytes(output.as_bytes()),
+            tool_version: self.tool_version.clone(),
+            config: self.config.clone(),
+            lib_modules: lib_modules.to_owned(),
+            pragmas: pragmas
+                .iter()
+                .map(|(key, vals)| {
+                    let vals = vals.iter().map(|val| val.to_string()).collect();
+                    (key.to_string(), vals)
+                })
+                .collect(),
+            crates: crates.iter().copied().collect(),
+            failures: failures.to_owned(),
+        };
+        self.current
+            .lock()
+            .unwrap()
+            .files
+            .insert(self.key(output_path), entry);
+        self.regenerated
+            .lock()
+            .unwrap()
+            .insert(output_path.to_owned());
+    }
+
+    /// Write the manifest and the effective configuration. Entries of files
+    /// this run did not visit (e.g. because of `--filter`) are carried over
+    /// unchanged.
+    pub fn save(self) {
+        let path = match self.path {
+            Some(path) => path,
+            None => return,
+        };
+        let mut manifest = self.current.into_inner().unwrap();
+        // The refactoring of the crate, like `cargo fmt`, rewrites files after
+        // they are recorded; what counts as unedited is what it left on disk
+        for (key, entry) in &mut manifest.files {
+            if let Ok(contents) = fs::read(self.build_dir.join(key)) {
+                entry.output_hash = hash_bytes(&contents);
+            }
+        }
+        for (key, entry) in self.previous.files {
+            manifest.files.entry(key).or_insert(entry);
+        }
+        let json = serde_json::to_string_pretty(&manifest).unwrap();
+        let mut files = vec![(path, json)];
+        if let Some(config) = self.effective_config {
+            files.push((self.build_dir.join(EFFECTIVE_CONFIG_FILE_NAME), config));
+        }
+        for (path, contents) in files {
+            let written =
+                fs::create_dir_all(&self.build_dir).and_then(|()| fs::write(&path, contents));
+            if let Err(e) = written {
+                warn!("Unable to write {}: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Fixture {
+        dir: tempfile::TempDir,
+        input: PathBuf,
+        header: PathBuf,
+        output: PathBuf,
+        args: Vec<String>,
+    }
+
+    impl Fixture {
+        /// An output file emitted from `main.c`, which includes `main.h`.
+        fn new() -> Self {
+            let dir = tempfile::tempdir().unwrap();
+            let input = dir.path().join("main.c");
+            let header = dir.path().join("main.h");
+            let output = dir.path().join("main.rs");
+            fs::write(
+                &input,
+                "#include \"main.h\"\nint main(void) { return f(); }\n",
+            )
+            .unwrap();
+            fs::write(&header, "static int f(void) { return 0; }\n").unwrap();
+            fs::write(&output, "pub fn main() {}\n").unwrap();
+            let args = vec!["-c".to_owned(), "-O2".to_owned()];
+            Self {
+                dir,
+                input,
+                header,
+                output,
+                args,
+            }
+        }
+
+        fn entry(&self) -> ManifestEntry {
+            ManifestEntry {
+                input: self.input.clone(),
+                args: self.args.clone(),
+                sources: vec![
+                    SourceHash::new(&self.input).unwrap(),
+                    SourceHash::new(&self.header).unwrap(),
+                ],
+                output_hash: hash_bytes(&fs::read(&self.output).unwrap()),
+                tool_version: "1.0+exporter".to_owned(),
+                config: "options".to_owned(),
+                lib_modules: vec![],
+                pragmas: vec![("feature".to_owned(), vec!["linkage".to_owned()])],
+                crates: vec![],
+                failures: vec![],
+            }
+        }
+
+        /// A generation whose previous run recorded `entry` for the output.
+        fn generation(&self, entry: ManifestEntry, incremental: bool) -> Generation {
+            let mut previous = GenerationManifest::default();
+            previous.files.insert(PathBuf::from("main.rs"), entry);
+            self.generation_after(previous, incremental)
+        }
+
+        /// A generation whose previous run left the manifest `previous`
+        fn generation_after(&self, previous: GenerationManifest, incremental: bool) -> Generation {
+            Generation {
+                path: Some(self.dir.path().join(MANIFEST_FILE_NAME)),
+                build_dir: self.dir.path().to_owned(),
+                incremental,
+                tool_version: "1.0+exporter".to_owned(),
+                config: "options".to_owned(),
+                effective_config: None,
+                previous,
+                current: Default::default(),
+                regenerated: Default::default(),
+            }
+        }
+
+        fn reused(&self, generation: &Generation) -> bool {
+            generation
+                .reuse_if_unchanged(&self.output, &self.input, &self.args, &[])
+                .is_some()
+        }
+    }
+
+    #[test]
+    fn reuses_unchanged() {
+        let fixture = Fixture::new();
+        let generation = fixture.generation(fixture.entry(), true);
+        let (pragmas, crates, failures) = generation
+            .reuse_if_unchanged(&fixture.output, &fixture.input, &fixture.args, &[])
+            .unwrap();
+        assert_eq!(pragmas, vec![("feature", vec!["linkage"])]);
+        assert!(crates.is_empty());
+        assert!(failures.is_empty());
+        // The reused entry is carried into the new manifest
+        let current = generation.current.lock().unwrap();
+        assert!(current.files.contains_key(Path::new("main.rs")));
+    }
+
+    #[test]
+    fn not_incremental() {
+        let fixture = Fixture::new();
+        assert!(!fixture.reused(&fixture.generation(fixture.entry(), false)));
+    }
+
+    #[test]
+    fn input_changed() {
+        let fixture = Fixture::new();
+        let generation = fixture.generation(fixture.entry(), true);
+        fs::write(&fixture.input, "int main(void) { return 1; }\n").unwrap();
+        assert!(!fixture.reused(&generation));
+    }
+
+    #[test]
+    fn header_changed() {
+        let fixture = Fixture::new();
+        let generation = fixture.generation(fixture.entry(), true);
+        fs::write(&fixture.header, "static int f(void) { return 1; }\n").unwrap();
+        assert!(!fixture.reused(&generation));
+    }
+
+    #[test]
+    fn header_removed() {
+        let fixture = Fixture::new();
+        let generation = fixture.generation(fixture.entry(), true);
+        fs::remove_file(&fixture.header).unwrap();
+        assert!(!fixture.reused(&generation));
+    }
+
+    #[test]
+    fn args_changed() {
+        let fixture = Fixture::new();
+        let generation = fixture.generation(fixture.entry(), true);
+        let args = vec!["-c".to_owned(), "-O0".to_owned()];
+        assert!(generation
+            .reuse_if_unchanged(&fixture.output, &fixture.input, &args, &[])
+            .is_none());
+    }
+
+    #[test]
+    fn config_changed() {
+        let fixture = Fixture::new();
+        let entry = ManifestEntry {
+            config: "other options".to_owned(),
+            ..fixture.entry()
+        };
+        assert!(!fixture.reused(&fixture.generation(entry, true)));
+    }
+
+    #[test]
+    fn tool_version_changed() {
+        let fixture = Fixture::new();
+        let entry = ManifestEntry {
+            tool_version: "0.9+exporter".to_owned(),
+            ..fixture.entry()
+        };
+        assert!(!fixture.reused(&fixture.generation(entry, true)));
+    }
+
+    #[test]
+    fn output_edited() {
+        let fixture = Fixture::new();
+        let generation = fixture.generation(fixture.entry(), true);
+        fs::write(&fixture.output, "pub fn main() { edited() }\n").unwrap();
+        assert!(!fixture.reused(&generation));
+        assert!(!generation.owns(&fixture.output));
+    }
+
+    #[test]
+    fn lib_module_regenerated() {
+        let fixture = Fixture::new();
+        let module = fixture.dir.path().join("lib_module.rs");
+        let entry = ManifestEntry {
+            lib_modules: vec![module.clone()],
+            ..fixture.entry()
+        };
+        let generation = fixture.generation(entry, true);
+        let lib_modules = [module.clone()];
+        let reuse = || {
+            generation.reuse_if_unchanged(
+                &fixture.output,
+                &fixture.input,
+                &fixture.args,
+                &lib_modules,
+            )
+        };
+        assert!(reuse().is_some());
+        generation.record(
+            &module,
+            &fixture.input,
+            vec![],
+            vec![],
+            "",
+            &[],
+            &Default::default(),
+            &Default::default(),
+            &[],
+        );
+        assert!(reuse().is_none());
+    }
+
+    #[test]
+    fn reuses_refactored_output() {
+        let fixture = Fixture::new();
+        let translated = "pub fn main(){}";
+        fs::write(&fixture.output, translated).unwrap();
+        let first = fixture.generation_after(GenerationManifest::default(), true);
+        first.record(
+            &fixture.output,
+            &fixture.input,
+            fixture.args.clone(),
+            fixture.entry().sources,
+            translated,
+            &[],
+            &Default::default(),
+            &Default::default(),
+            &[],
+        );
+        // `cargo fmt` and the other refactorings rewrite the file before the
+        // manifest is saved
+        fs::write(&fixture.output, "pub fn main() {}\n").unwrap();
+        first.save();
+
+        let manifest = read_manifest(&fixture.dir.path().join(MANIFEST_FILE_NAME)).unwrap();
+        let second = fixture.generation_after(manifest, true);
+        assert!(second.owns(&fixture.output));
+        assert!(fixture.reused(&second));
+    }
+}
@@ -0,0 +1,318 @@
+//! Dependency information from the object files of an existing build, as an
+//! alternative to the Clang AST. The symbol tables list what the compiler
+//! actually emitted: functions generated by macros, weak and common symbols,
+//! and the functions it calls on its own, like `memcpy`.
+
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use deps_builder::{Binding, DependencyInfo, DependencySymbol};
+use failure::{format_err, Error};
+use log::{info, warn};
+use object::read::archive::ArchiveFile;
+use object::{Object, ObjectSymbol, SymbolKind};
+
+use crate::compile_cmds::{CompileCmd, LinkCmd, LinkType};
+
+/// The global symbols that an object file defines and those it uses
+#[derive(Debug, Default, Clone)]
+pub struct ObjectSymbols {
+    pub defined: Vec<(String, Binding)>,
+    pub undefined: Vec<String>,
+}
+
+impl ObjectSymbols {
+    /// Read the symbol table of the object file `data`
+    pub fn parse(data: &[u8]) -> Result<Self, Error> {
+        let object = object::File::parse(data)?;
+        let mut defined = BTreeSet::new();
+        let mut undefined = BTreeSet::new();
+        for symbol in object.symbols() {
+            let name = symbol.name()?;
+            if name.is_empty() || !symbol.is_global() {
+                continue;
+            }
+            if symbol.is_undefined() {
+                undefined.insert(name.to_owned());
+            } else if symbol.is_definition() || symbol.kind() == SymbolKind::Tls {
+                let binding = if symbol.is_weak() {
+                    Binding::Weak
+                } else if symbol.is_common() {
+                    Binding::Common
+                } else {
+                    Binding::Strong
+                };
+                defined.insert((name.to_owned(), binding));
+            }
+        }
+        Ok(Self {
+            defined: defined.into_iter().collect(),
+            undefined: undefined.into_iter().collect(),
+        })
+    }
+
+    /// The defined and the undefined symbols, as those of the file at
+    /// `path`
+    pub fn into_dependency_symbols(
+        self,
+        path: &str,
+    ) -> (Vec<DependencySymbol>, Vec<DependencySymbol>) {
+        let defined = self
+            .defined
+            .into_iter()
+            .map(|(name, binding)| DependencySymbol {
+                binding,
+                ..DependencySymbol::new(name, path.to_owned())
+            })
+            .collect();
+        let undefined = self
+            .undefined
+            .into_iter()
+            .map(|name| DependencySymbol::new(name, path.to_owned()))
+            .collect();
+        (defined, undefined)
+    }
+
+    /// Read the object file at `path`
+    pub fn read(path: &Path) -> Result<Self, Error> {
+        let data = fs::read(path).map_err(|e| format_err!("{}: {}", path.display(), e))?;
+        Self::parse(&data).map_err(|e| format_err!("{}: {}", path.display(), e))
+    }
+}
+
+/// The object files that a link command reads, by file name, from the
+/// archives it names: `.a` inputs, `-l` libraries found in its library
+/// directories, and its own output if it creates an archive. These stand in
+/// for object files of the build that were removed or moved.
+#[derive(Debug, Default)]
+pub struct ArchiveMembers {
+    members: HashMap<String, Vec<(PathBuf, ObjectSymbols)>>,
+}
+
+impl ArchiveMembers {
+    pub fn new(lcmd: &LinkCmd) -> Self {
+        let dir = &lcmd.directory;
+        let mut archives = vec![];
+        if lcmd.r#type == LinkType::Static {
+            archives.extend(lcmd.output.iter().map(PathBuf::from));
+        }
+        archives.extend(
+            lcmd.inputs
+                .iter()
+                .filter(|input| input.ends_with(".a"))
+                .map(|input| dir.join(input)),
+        );
+        for lib in &lcmd.libs {
+            let file_name = format!("lib{}.a", lib);
+            let found = lcmd
+                .lib_dirs
+                .iter()
+                .map(|lib_dir| dir.join(lib_dir).join(&file_name))
+                .find(|path| path.exists());
+            archives.extend(found);
+        }
+
+        let mut members = Self::default();
+        for archive in archives.iter().filter(|path| path.exists()) {
+            if let Err(e) = members.add_archive(archive) {
+                warn!("Could not read the archive {}: {}", archive.display(), e);
+            }
+        }
+        members
+    }
+
+    fn add_archive(&mut self, path: &Path) -> Result<(), Error> {
+        let data = fs::read(path)?;
+        let archive = ArchiveFile::parse(&*data)?;
+        for member in archive.members() {
+            let member = member?;
+            let name = String::from_utf8_lossy(member.name()).into_owned();
+            match ObjectSymbols::parse(member.data(&*data)?) {
+                Ok(symbols) => self
+                    .members
+                    .entry(name)
+                    .or_default()
+                    .push((path.to_owned(), symbols)),
+                // Archives can hold other files, like the `__.SYMDEF` of BSD ar
+                Err(e) => info!("Skipping {}({}): {}", path.display(), name, e),
+            }
+        }
+        Ok(())
+    }
+
+    /// The symbols of the object file that `cmd` compiles to: the file
+    /// itself, or else the archive member of the same name.
+    pub fn symbols(&self, cmd: &CompileCmd) -> Result<ObjectSymbols, Error> {
+        let object = cmd
+            .abs_output_file()
+            .ok_or_else(|| format_err!("{} names no output file", cmd.abs_file().display()))?;
+        if object.exists() {
+            return ObjectSymbols::read(&object);
+        }
+        let name = object.file_name().unwrap().to_string_lossy();
+        match self.members.get(&*name).map(Vec::as_slice) {
+            Some([(archive, symbols)]) => {
+                info!("Reading {} from {}", name, archive.display());
+                Ok(symbols.clone())
+            }
+            Some(found) => Err(format_err!(
+                "{} does not exist, and {} archive members have its name",
+                object.display(),
+                found.len()
+            )),
+            None => Err(format_err!(
+                "{} does not exist, nor is it in an archive of the link command",
+                object.display()
+            )),
+        }
+    }
+}
+
+/// Point every undefined symbol, and every common or weak definition, at
+/// the definition of that name in `infos`, which are in link order, that the
+/// linker resolves it to: the first strong one, else the first common one,
+/// else the first weak one. Symbol tables have no declarations, so this takes
+/// the place of matching the files that the Clang AST declares symbols in.
+pub fn resolve_undefined(infos: &mut [DependencyInfo]) {
+    let mut definitions: HashMap<String, (Binding, String)> = HashMap::new();
+    for symbol in infos.iter().flat_map(|info| &info.defined) {
+        match definitions.get(&symbol.name) {
+            Some((binding, _)) if binding.rank() >= symbol.binding.rank() => {}
+            _ => {
+                let definition = (symbol.binding, symbol.path.clone());
+                definitions.insert(symbol.name.clone(), definition);
+            }
+        }
+    }
+    for info in infos.iter_mut() {
+        let overridable = info.defined.iter_mut().filter(|s| !s.binding.is_strong());
+        for symbol in info.undefined.iter_mut().chain(overridable) {
+            if let Some((_, path)) = definitions.get(&symbol.name) {
+                symbol.path = path.clone();
+            }
+        }
+    }
+}
+
+fn names(symbols: &[DependencySymbol]) -> BTreeSet<&str> {
+    symbols.iter().map(|symbol| symbol.name.as_str()).collect()
+}
+
+/// Warn about the symbols that only one of the exports of a translation
+/// unit lists, and return whether they agree.
+pub fn cross_validate(clang: &DependencyInfo, objects: &DependencyInfo) -> bool {
+    let mut agree = true;
+    for (kind, from_clang, from_objects) in [
+        ("defined", &clang.defined, &objects.defined),
+        ("undefined", &clang.undefined, &objects.undefined),
+    ] {
+        let (from_clang, from_objects) = (names(from_clang), names(from_objects));
+        for (only, source) in [
+            (from_clang.difference(&from_objects), "the Clang AST"),
+            (from_objects.difference(&from_clang), "the object file"),
+        ] {
+            let only = only.copied().collect::<Vec<_>>();
+            if !only.is_empty() {
+                warn!(
+                    "{}: {} symbols only in {}: {}",
+                    objects.input_path,
+                    kind,
+                    source,
+                    only.join(", ")
+                );
+                agree = false;
+            }
+        }
+    }
+    agree
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The dependency information of `path`, which defines `defined` and
+    /// uses `undefined`
+    fn info(path: &str, defined: &[(&str, Binding)], undefined: &[&str]) -> DependencyInfo {
+        let symbols = ObjectSymbols {
+            defined: defined
+                .iter()
+                .map(|&(name, binding)| (name.to_owned(), binding))
+                .collect(),
+            undefined: undefined.iter().map(|&name| name.to_owned()).collect(),
+        };
+        let (defined, undefined) = symbols.into_dependency_symbols(path);
+        DependencyInfo {
+            input_path: path.to_owned(),
+            output_path: path.replace(".c", ".rs"),
+            object_path: Some(path.replace(".c", ".o")),
+            undefined,
+            defined,
+        }
+    }
+
+    fn path<'a>(symbols: &'a [DependencySymbol], name: &str) -> &'a str {
+        &symbols
+            .iter()
+            .find(|symbol| symbol.name == name)
+            .unwrap()
+            .path
+    }
+
+    #[test]
+    fn resolve_by_binding() {
+        use Binding::*;
+        let mut infos = [
+            info("weak.c", &[("x", Weak), ("y", Weak), ("z", Weak)], &[]),
+            info("common.c", &[("x", Common), ("y", Common)], &[]),
+            info("strong.c", &[("x", Strong)], &[]),
+            info("common2.c", &[("y", Common)], &["x", "y", "z", "printf"]),
+        ];
+        resolve_undefined(&mut infos);
+        let [weak, common, strong, common2] = &infos;
+
+        // A strong definition beats common and weak ones, wherever it is
+        assert_eq!(path(&weak.defined, "x"), "strong.c");
+        assert_eq!(path(&common.defined, "x"), "strong.c");
+        assert_eq!(path(&strong.defined, "x"), "strong.c");
+        assert_eq!(path(&common2.undefined, "x"), "strong.c");
+        // A common definition beats a weak one, and the first common one wins
+        assert_eq!(path(&weak.defined, "y"), "common.c");
+        assert_eq!(path(&common2.defined, "y"), "common.c");
+        assert_eq!(path(&common2.undefined, "y"), "common.c");
+        // The first weak definition is used if there is no other
+        assert_eq!(path(&common2.undefined, "z"), "weak.c");
+        // Symbols that no file defines keep the file that uses them
+        assert_eq!(path(&common2.undefined, "printf"), "common2.c");
+    }
+
+    #[test]
+    fn strong_definitions_are_not_redirected() {
+        use Binding::*;
+        // Two strong definitions are a link error; each keeps its own
+        let mut infos = [
+            info("a.c", &[("f", Strong)], &[]),
+            info("b.c", &[("f", Strong)], &["f"]),
+        ];
+        resolve_undefined(&mut infos);
+        assert_eq!(path(&infos[0].defined, "f"), "a.c");
+        assert_eq!(path(&infos[1].defined, "f"), "b.c");
+        assert_eq!(path(&infos[1].undefined, "f"), "a.c");
+    }
+
+    #[test]
+    fn cross_validation() {
+        use Binding::*;
+        let clang = info("a.c", &[("f", Strong), ("x", Common)], &["g"]);
+        let objects = info("a.c", &[("f", Strong), ("x", Strong)], &["g"]);
+        // Only names are compared
+        assert!(cross_validate(&clang, &objects));
+
+        // `memcpy` is called by the compiler, not the source
+        let objects = info("a.c", &[("f", Strong), ("x", Common)], &["g", "memcpy"]);
+        assert!(!cross_validate(&clang, &objects));
+        let objects = info("a.c", &[("f", Strong)], &["g"]);
+        assert!(!cross_validate(&clang, &objects));
+    }
+}
@@ -0,0 +1,78 @@
+//! Helpers for processing translation units on several threads at once.
+
+use std::collections::BTreeMap;
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+
+use crate::diagnostics;
+
+/// Translating large functions recurses deeply (e.g. in the relooper), so
+/// give workers the same headroom the main thread usually has.
+const WORKER_STACK_SIZE: usize = 64 * 1024 * 1024;
+
+/// Resolve the `-j` setting to an actual number of worker threads;
+/// `0` means one worker per available core.
+pub(crate) fn num_workers(jobs: usize) -> usize {
+    match jobs {
+        0 => thread::available_parallelism()
+            .map(NonZeroUsize::get)
+            .unwrap_or(1),
+        n => n,
+    }
+}
+
+/// Apply `f` to every element of `items` using up to `jobs` threads.
+///
+/// Results are returned in the order of `items`, regardless of which worker
+/// finished first. Everything a worker prints or logs while running `f` is
+/// buffered and written out in that same order, so the console output of a
+/// parallel run reads exactly like the output of a sequential one.
+pub(crate) fn map_ordered<I, T, F>(jobs: usize, items: &[I], f: F) -> Vec<T>
+where
+    I: Sync,
+    T: Send,
+    F: Fn(&I) -> T + Sync,
+{
+    let workers = num_workers(jobs).min(items.len());
+    if workers <= 1 {
+        return items.iter().map(f).collect();
+    }
+
+    let next = AtomicUsize::new(0);
+    let (tx, rx) = mpsc::channel();
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            let tx = tx.clone();
+            let next = &next;
+            let f = &f;
+            thread::Builder::new()
+                .stack_size(WORKER_STACK_SIZE)
+                .spawn_scoped(scope, move || loop {
+                    let idx = next.fetch_add(1, Ordering::Relaxed);
+                    if idx >= items.len() {
+                        break;
+                    }
+                    let res = diagnostics::capture(|| f(&items[idx]));
+                    if tx.send((idx, res)).is_err() {
+                        break;
+                    }
+                })
+                .expect("failed to spawn worker thread");
+        }
+        drop(tx);
+
+        // Flush results in input order as soon as a contiguous prefix is done.
+        let mut pending = BTreeMap::new();
+        let mut results = Vec::with_capacity(items.len());
+        for (idx, res) in rx {
+            pending.insert(idx, res);
+            while let Some((value, output)) = pending.remove(&results.len()) {
+                output.replay();
+                results.push(value);
+            }
+        }
+        results
+    })
+}
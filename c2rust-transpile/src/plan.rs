@@ -0,0 +1,146 @@
+//! The plan printed by `--dry-run`: what a run would translate and write,
+//! collected instead of touching the output directory.
+
+use std::fmt::{self, Display};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// What would happen to an output file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileAction {
+    /// The file does not exist yet and would be written
+    Create,
+    /// The existing file would be replaced
+    Overwrite,
+    /// The file is up to date according to the generation manifest
+    Unchanged,
+    /// The file exists and would be left alone
+    SkipExisting,
+    /// The translation unit would not be translated at all
+    Skip(String),
+}
+
+impl FileAction {
+    /// `Create` or `Overwrite`, depending on whether `path` exists.
+    pub fn write(path: &Path) -> Self {
+        if path.exists() {
+            FileAction::Overwrite
+        } else {
+            FileAction::Create
+        }
+    }
+}
+
+impl Display for FileAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FileAction::Create => write!(f, "create"),
+            FileAction::Overwrite => write!(f, "overwrite"),
+            FileAction::Unchanged => write!(f, "unchanged"),
+            FileAction::SkipExisting => write!(f, "skip existing file"),
+            FileAction::Skip(reason) => write!(f, "skip: {}", reason),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PlannedFile {
+    pub input: PathBuf,
+    pub output: PathBuf,
+    pub is_binary: bool,
+    pub action: FileAction,
+}
+
+#[derive(Debug, Clone)]
+pub struct PlannedCrate {
+    pub name: String,
+    pub build_dir: PathBuf,
+    pub is_library: bool,
+    pub modules: Vec<PathBuf>,
+}
+
+#[derive(Debug, Default)]
+pub struct Plan {
+    files: Mutex<Vec<PlannedFile>>,
+    crates: Mutex<Vec<PlannedCrate>>,
+    workspace_members: Mutex<Vec<String>>,
+    build_files: Mutex<Vec<(PathBuf, FileAction)>>,
+}
+
+impl Plan {
+    pub fn add_file(&self, file: PlannedFile) {
+        self.files.lock().unwrap().push(file);
+    }
+
+    pub fn add_crate(&self, krate: PlannedCrate) {
+        self.crates.lock().unwrap().push(krate);
+    }
+
+    pub fn add_workspace_members(&self, members: &[String]) {
+        self.workspace_members
+            .lock()
+            .unwrap()
+            .extend_from_slice(members);
+    }
+
+    pub fn add_build_file(&self, path: &Path, action: FileAction) {
+        self.build_files
+            .lock()
+            .unwrap()
+            .push((path.to_owned(), action));
+    }
+}
+
+impl Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Dry run; nothing was written.")?;
+
+        // Files are planned in whatever order the workers finish them
+        let mut files = self.files.lock().unwrap().clone();
+        files.sort_by(|a, b| a.input.cmp(&b.input));
+        writeln!(f, "Translation units:")?;
+        for file in &files {
+            writeln!(
+                f,
+                "  [{}] {} -> {} ({})",
+                if file.is_binary { "bin" } else { "lib" },
+                file.input.display(),
+                file.output.display(),
+                file.action
+            )?;
+        }
+
+        let crates = self.crates.lock().unwrap().clone();
+        if !crates.is_empty() {
+            writeln!(f, "Crates:")?;
+            for krate in &crates {
+                writeln!(
+                    f,
+                    "  {} ({}) in {}, {} module(s)",
+                    krate.name,
+                    if krate.is_library {
+                        "library"
+                    } else {
+                        "binary"
+                    },
+                    krate.build_dir.display(),
+                    krate.modules.len()
+                )?;
+            }
+        }
+
+        let members = self.workspace_members.lock().unwrap();
+        if !members.is_empty() {
+            writeln!(f, "Workspace members: {}", members.join(", "))?;
+        }
+
+        let build_files = self.build_files.lock().unwrap().clone();
+        if !build_files.is_empty() {
+            writeln!(f, "Build files:")?;
+            for (path, action) in &build_files {
+                writeln!(f, "  {} ({})", path.display(), action)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -68,6 +68,17 @@ impl<T: Clone + Eq + Hash> Renamer<T> {
         self.scopes.pop();
     }
 
+    /// The number of scopes currently introduced, including the outermost one
+    pub fn scope_depth(&self) -> usize {
+        self.scopes.len()
+    }
+
+    /// Drops every scope introduced after the renamer had `depth` scopes, e.g.
+    /// when their owner was abandoned without dropping them
+    pub fn truncate_scopes(&mut self, depth: usize) {
+        self.scopes.truncate(depth.max(1));
+    }
+
     fn current_scope(&self) -> &Scope<T> {
         self.scopes.last().expect("Expected a scope")
     }
@@ -0,0 +1,743 @@
+//! Definition reorganization for `--reorganize-definitions`.
+//!
+//! With `-r`, every translated module carries one submodule per included
+//! header, marked with `#[c2rust::header_src]`, so a crate ends up with a copy
+//! of a header's declarations for every file that includes it. This pass
+//! moves the header submodules of all modules of a crate into one shared
+//! module, [`SHARED_MODULE`], keeps a single copy of each group of identical
+//! submodules and rewrites the paths that referred to them.
+//!
+//! The files are edited in place rather than printed again from the syntax
+//! tree, so that the comments carried over from C survive; `cargo fmt` tidies
+//! up afterwards.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use failure::{format_err, Error};
+use log::info;
+use proc_macro2::{LineColumn, Span, TokenStream};
+use syn::spanned::Spanned;
+use syn::visit::{self, Visit};
+use syn::{Attribute, Ident, Item, ItemUse, Lit, Meta, UseTree};
+
+/// The module, declared in every crate root, that holds the merged header
+/// submodules
+pub const SHARED_MODULE: &str = "c2rust_headers";
+
+/// A source file along with the byte offset of each of its lines, to map
+/// span locations back into the text.
+pub(crate) struct Source {
+    pub text: String,
+    line_starts: Vec<usize>,
+}
+
+impl Source {
+    pub fn new(text: String) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        Source { text, line_starts }
+    }
+
+    fn offset(&self, loc: LineColumn) -> usize {
+        let start = self.line_starts[loc.line - 1];
+        self.text[start..]
+            .char_indices()
+            .nth(loc.column)
+            .map_or(self.text.len(), |(i, _)| start + i)
+    }
+
+    pub fn range(&self, span: Span) -> Range<usize> {
+        self.offset(span.start())..self.offset(span.end())
+    }
+}
+
+/// A path segment and where it is in the source
+#[derive(Debug, Clone)]
+struct Segment {
+    name: String,
+    range: Range<usize>,
+}
+
+/// The first two segments of a path or of a branch of a `use` tree
+#[derive(Debug)]
+struct PathPrefix {
+    first: Segment,
+    second: Option<Segment>,
+}
+
+/// Collects the prefixes of all relative paths in the visited items
+struct PrefixCollector<'a> {
+    source: &'a Source,
+    prefixes: Vec<PathPrefix>,
+}
+
+impl PrefixCollector<'_> {
+    fn segment(&self, ident: &Ident) -> Segment {
+        Segment {
+            name: ident.to_string(),
+            range: self.source.range(ident.span()),
+        }
+    }
+
+    /// The segments a `use` tree can continue with
+    fn use_heads(&self, tree: &UseTree, heads: &mut Vec<Segment>) {
+        match tree {
+            UseTree::Path(p) => heads.push(self.segment(&p.ident)),
+            UseTree::Name(n) => heads.push(self.segment(&n.ident)),
+            UseTree::Rename(r) => heads.push(self.segment(&r.ident)),
+            UseTree::Glob(g) => heads.push(Segment {
+                name: "*".to_string(),
+                range: self.source.range(g.star_token.span()),
+            }),
+            UseTree::Group(g) => g.items.iter().for_each(|t| self.use_heads(t, heads)),
+        }
+    }
+
+    fn use_tree(&mut self, tree: &UseTree) {
+        match tree {
+            UseTree::Path(p) => {
+                let first = self.segment(&p.ident);
+                let mut heads = vec![];
+                self.use_heads(&p.tree, &mut heads);
+                for second in heads {
+                    self.prefixes.push(PathPrefix {
+                        first: first.clone(),
+                        second: Some(second),
+                    });
+                }
+            }
+            UseTree::Name(n) => {
+                let first = self.segment(&n.ident);
+                self.prefixes.push(PathPrefix {
+                    first,
+                    second: None,
+                });
+            }
+            UseTree::Group(g) => g.items.iter().for_each(|t| self.use_tree(t)),
+            UseTree::Rename(_) | UseTree::Glob(_) => {}
+        }
+    }
+}
+
+impl<'ast> Visit<'ast> for PrefixCollector<'_> {
+    fn visit_path(&mut self, path: &'ast syn::Path) {
+        if path.leading_colon.is_none() {
+            let mut segments = path.segments.iter().map(|s| self.segment(&s.ident));
+            if let Some(first) = segments.next() {
+                let second = segments.next();
+                self.prefixes.push(PathPrefix { first, second });
+            }
+        }
+        visit::visit_path(self, path);
+    }
+
+    fn visit_item_use(&mut self, item: &'ast ItemUse) {
+        if item.leading_colon.is_none() {
+            self.use_tree(&item.tree);
+        }
+    }
+}
+
+/// A header submodule of a translated module
+struct Unit {
+    file: usize,
+    name: String,
+    /// The header it was translated from, without the include line
+    header: String,
+    range: Range<usize>,
+    ident: Range<usize>,
+    /// The text between the braces
+    body: Range<usize>,
+    prefixes: Vec<PathPrefix>,
+    /// The sibling header submodules this one refers to through `super::`
+    siblings: Vec<usize>,
+    movable: bool,
+}
+
+struct ModuleFile {
+    path: PathBuf,
+    source: Source,
+    /// Path prefixes outside of header submodules
+    prefixes: Vec<PathPrefix>,
+    units: HashMap<String, usize>,
+    /// Whether the file declares the shared module
+    declares_shared: bool,
+}
+
+fn header_src(attrs: &[Attribute]) -> Option<String> {
+    attrs.iter().find_map(|attr| {
+        let segments = attr.path.segments.iter().map(|s| s.ident.to_string());
+        if !segments.eq(["c2rust", "header_src"]) {
+            return None;
+        }
+        match attr.parse_meta() {
+            Ok(Meta::NameValue(nv)) => match nv.lit {
+                Lit::Str(s) => {
+                    let value = s.value();
+                    // Strip the line number of the `#include`, which differs
+                    // between the including files
+                    let header = value.rsplit_once(':').map_or(&*value, |(header, _)| header);
+                    Some(header.to_string())
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    })
+}
+
+fn path_attr(attrs: &[Attribute]) -> Option<String> {
+    attrs.iter().find_map(|attr| match attr.parse_meta() {
+        Ok(Meta::NameValue(nv)) if nv.path.is_ident("path") => match nv.lit {
+            Lit::Str(s) => Some(s.value()),
+            _ => None,
+        },
+        _ => None,
+    })
+}
+
+fn declared_modules(
+    items: &[Item],
+    dir: &Path,
+    module_path: &[String],
+    modules: &mut Vec<(PathBuf, Vec<String>)>,
+) {
+    for item in items {
+        let module = match item {
+            Item::Mod(module) => module,
+            _ => continue,
+        };
+        let name = module.ident.to_string();
+        let mut module_path = module_path.to_vec();
+        module_path.push(name.clone());
+        match &module.content {
+            Some((_, items)) => declared_modules(items, &dir.join(&name), &module_path, modules),
+            None => {
+                let path = match path_attr(&module.attrs) {
+                    Some(path) => dir.join(path),
+                    None if dir.join(&name).join("mod.rs").exists() => {
+                        dir.join(&name).join("mod.rs")
+                    }
+                    None => dir.join(format!("{}.rs", name)),
+                };
+                if path.exists() {
+                    modules.push((path, module_path));
+                }
+            }
+        }
+    }
+}
+
+/// The crate root `root` and the files of the modules it declares, with
+/// their paths in the crate. This follows the `mod name { ... }` and
+/// `#[path = "..."] mod name;` declarations of the generated `lib.rs`.
+pub(crate) fn crate_modules(root: &Path) -> Result<Vec<(PathBuf, Vec<String>)>, Error> {
+    let (_, file) = parse(root)?;
+    let mut modules = vec![(root.to_owned(), vec![])];
+    declared_modules(&file.items, root.parent().unwrap(), &[], &mut modules);
+    Ok(modules)
+}
+
+pub(crate) fn parse(path: &Path) -> Result<(Source, syn::File), Error> {
+    let text = fs::read_to_string(path)?;
+    let file = syn::parse_file(&text)
+        .map_err(|e| format_err!("Could not parse {}: {}", path.display(), e))?;
+    Ok((Source::new(text), file))
+}
+
+/// Apply non-overlapping replacements to `text`, which starts at byte
+/// `base` of the file the ranges refer to.
+pub(crate) fn apply_edits(text: &str, base: usize, edits: &[(Range<usize>, String)]) -> String {
+    let mut edits = edits
+        .iter()
+        .filter(|(range, _)| range.start >= base && range.end <= base + text.len())
+        .collect::<Vec<_>>();
+    edits.sort_by_key(|(range, _)| range.start);
+    let mut out = String::with_capacity(text.len());
+    let mut pos = base;
+    for (range, replacement) in edits {
+        out.push_str(&text[pos - base..range.start - base]);
+        out.push_str(replacement);
+        pos = range.end;
+    }
+    out.push_str(&text[pos - base..]);
+    out
+}
+
+/// Merge the header submodules of all modules reachable from the library
+/// root `lib_root` and the `binaries` into the shared module
+/// `build_dir/c2rust_headers.rs`. Binaries that link against the library
+/// crate `lib_crate` import the shared module from it instead of declaring
+/// it again.
+///
+/// When `keep_existing` is set, the header submodules of an existing shared
+/// module are kept under their names, since modules that were not translated
+/// again may still refer to them.
+pub fn merge_header_modules(
+    build_dir: &Path,
+    lib_root: Option<&Path>,
+    binaries: &[PathBuf],
+    lib_crate: Option<&str>,
+    keep_existing: bool,
+) -> Result<(), Error> {
+    let roots = lib_root
+        .map(Path::to_owned)
+        .into_iter()
+        .chain(binaries.iter().cloned())
+        .collect::<Vec<_>>();
+    let shared_path = build_dir.join(format!("{}.rs", SHARED_MODULE));
+    let mut paths = vec![];
+    if keep_existing && shared_path.exists() {
+        paths.push(shared_path.clone());
+    }
+    let mut modules = BTreeSet::new();
+    for root in &roots {
+        let declared = crate_modules(root)?.into_iter().map(|(path, _)| path);
+        modules.extend(declared.filter(|path| *path != shared_path));
+    }
+    paths.extend(modules);
+
+    let mut files = vec![];
+    let mut units: Vec<Unit> = vec![];
+    let mut extern_uses = BTreeSet::new();
+    for path in paths {
+        let (source, file) = parse(&path)?;
+        let file_idx = files.len();
+        let mut collector = PrefixCollector {
+            source: &source,
+            prefixes: vec![],
+        };
+        let mut file_units = HashMap::new();
+        let mut declares_shared = false;
+        for item in &file.items {
+            match item {
+                Item::Mod(module) if module.ident == SHARED_MODULE => declares_shared = true,
+                Item::Use(ItemUse {
+                    tree: UseTree::Path(path),
+                    ..
+                }) if matches!(&*path.tree, UseTree::Name(n) if n.ident == SHARED_MODULE) => {
+                    declares_shared = true
+                }
+                Item::Mod(module) => match (&module.content, header_src(&module.attrs)) {
+                    (Some((brace, items)), Some(header)) => {
+                        let mut unit_collector = PrefixCollector {
+                            source: &source,
+                            prefixes: vec![],
+                        };
+                        items.iter().for_each(|i| unit_collector.visit_item(i));
+                        let braces = source.range(brace.span);
+                        file_units.insert(module.ident.to_string(), units.len());
+                        units.push(Unit {
+                            file: file_idx,
+                            name: module.ident.to_string(),
+                            header,
+                            range: source.range(module.span()),
+                            ident: source.range(module.ident.span()),
+                            body: braces.start + 1..braces.end - 1,
+                            prefixes: unit_collector.prefixes,
+                            siblings: vec![],
+                            movable: true,
+                        });
+                    }
+                    _ => collector.visit_item(item),
+                },
+                // The extern crates the header submodules may refer to
+                Item::Use(ItemUse {
+                    leading_colon: Some(_),
+                    tree: UseTree::Name(name),
+                    ..
+                }) => {
+                    extern_uses.insert(format!("use ::{};", name.ident));
+                }
+                _ => collector.visit_item(item),
+            }
+        }
+        let prefixes = collector.prefixes;
+        files.push(ModuleFile {
+            path,
+            source,
+            prefixes,
+            units: file_units,
+            declares_shared,
+        });
+    }
+
+    // A header submodule can only be moved if everything it refers to
+    // through `super::` is a header submodule that moves along with it
+    for unit in &mut units {
+        let file = &files[unit.file];
+        for prefix in unit.prefixes.iter().filter(|p| p.first.name == "super") {
+            match prefix.second.as_ref().and_then(|s| file.units.get(&s.name)) {
+                Some(&sibling) if !unit.siblings.contains(&sibling) => unit.siblings.push(sibling),
+                Some(_) => {}
+                None => unit.movable = false,
+            }
+        }
+    }
+    loop {
+        let stuck = (0..units.len())
+            .filter(|&u| units[u].movable && units[u].siblings.iter().any(|&s| !units[s].movable))
+            .collect::<Vec<_>>();
+        if stuck.is_empty() {
+            break;
+        }
+        for u in stuck {
+            units[u].movable = false;
+        }
+    }
+    let moved = (0..units.len())
+        .filter(|&u| units[u].movable)
+        .collect::<Vec<_>>();
+    if moved.is_empty() {
+        return Ok(());
+    }
+
+    // Group identical header submodules. Two submodules are identical if
+    // they come from the same header, have the same tokens up to the names
+    // of the siblings they refer to, and those siblings are identical too;
+    // the groups are refined until they are stable.
+    let mut class = HashMap::new();
+    let mut keys = HashMap::new();
+    for &u in &moved {
+        let unit = &units[u];
+        let file = &files[unit.file];
+        let placeholders = unit
+            .prefixes
+            .iter()
+            .filter(|p| p.first.name == "super")
+            .filter_map(|p| p.second.as_ref())
+            .map(|s| {
+                let sibling = file.units[&s.name];
+                let k = unit.siblings.iter().position(|&x| x == sibling).unwrap();
+                (s.range.clone(), format!("__c2rust_sibling_{}", k))
+            })
+            .collect::<Vec<_>>();
+        let body = &file.source.text[unit.body.clone()];
+        let body = apply_edits(body, unit.body.start, &placeholders);
+        let tokens = body
+            .parse::<TokenStream>()
+            .map_err(|e| format_err!("Could not tokenize {}: {}", unit.name, e))?
+            .to_string();
+        let n = keys.len();
+        class.insert(u, *keys.entry((unit.header.clone(), tokens)).or_insert(n));
+    }
+    let mut num_classes = keys.len();
+    loop {
+        let mut keys = HashMap::new();
+        let refined = moved
+            .iter()
+            .map(|&u| {
+                let siblings = units[u]
+                    .siblings
+                    .iter()
+                    .map(|s| class[s])
+                    .collect::<Vec<_>>();
+                let n = keys.len();
+                (u, *keys.entry((class[&u], siblings)).or_insert(n))
+            })
+            .collect::<HashMap<_, _>>();
+        class = refined;
+        if keys.len() == num_classes {
+            break;
+        }
+        num_classes = keys.len();
+    }
+
+    // Name each group after its first submodule
+    let mut representatives = vec![None; num_classes];
+    let mut canonical = vec![String::new(); num_classes];
+    let mut used_names = HashSet::new();
+    for &u in &moved {
+        let c = class[&u];
+        if representatives[c].is_some() {
+            continue;
+        }
+        representatives[c] = Some(u);
+        let base = &units[u].name;
+        let mut name = base.clone();
+        for i in 0.. {
+            if used_names.insert(name.clone()) {
+                break;
+            }
+            name = format!("{}_{}", base, i);
+        }
+        canonical[c] = name;
+    }
+    let canonical_of = |u: usize| -> Option<&str> { class.get(&u).map(|&c| &*canonical[c]) };
+    let shared_path_of =
+        |u: usize| format!("crate::{}::{}", SHARED_MODULE, canonical_of(u).unwrap());
+
+    // Point the paths that go through a moved submodule at the shared module
+    let mut edits = vec![vec![]; files.len()];
+    // Moved submodules that a submodule staying behind still refers to as
+    // `super::name`; they are replaced by an import of the shared one
+    let mut aliased = HashSet::new();
+    for (f, file) in files.iter().enumerate() {
+        let moved_unit = |name: &str| {
+            file.units
+                .get(name)
+                .copied()
+                .filter(|u| class.contains_key(u))
+        };
+        for prefix in &file.prefixes {
+            match (&*prefix.first.name, &prefix.second) {
+                ("self", Some(second)) => {
+                    if let Some(u) = moved_unit(&second.name) {
+                        edits[f].push((
+                            prefix.first.range.start..second.range.end,
+                            shared_path_of(u),
+                        ));
+                    }
+                }
+                (first, Some(_)) => {
+                    if let Some(u) = moved_unit(first) {
+                        edits[f].push((prefix.first.range.clone(), shared_path_of(u)));
+                    }
+                }
+                _ => {}
+            }
+        }
+        for &u in file.units.values() {
+            let unit = &units[u];
+            for prefix in unit.prefixes.iter().filter(|p| p.first.name == "super") {
+                let second = match &prefix.second {
+                    Some(second) => second,
+                    None => continue,
+                };
+                let sibling = match moved_unit(&second.name) {
+                    Some(sibling) => sibling,
+                    None => continue,
+                };
+                if unit.movable {
+                    let name = canonical_of(sibling).unwrap().to_string();
+                    edits[f].push((second.range.clone(), name));
+                } else {
+                    aliased.insert(sibling);
+                }
+            }
+        }
+    }
+
+    let mut shared = extern_uses.into_iter().collect::<Vec<_>>().join("\n");
+    for u in representatives.into_iter().flatten() {
+        let unit = &units[u];
+        let file = &files[unit.file];
+        let mut unit_edits = edits[unit.file].clone();
+        unit_edits.push((unit.ident.clone(), canonical_of(u).unwrap().to_string()));
+        let text = &file.source.text[unit.range.clone()];
+        shared.push_str("\n\n");
+        shared.push_str(&apply_edits(text, unit.range.start, &unit_edits));
+    }
+    shared.push('\n');
+
+    for &u in &moved {
+        let unit = &units[u];
+        let text = &files[unit.file].source.text;
+        let mut end = unit.range.end;
+        if text[end..].starts_with('\n') {
+            end += 1;
+        }
+        let file_edits = &mut edits[unit.file];
+        file_edits
+            .retain(|(range, _)| range.start < unit.range.start || range.end > unit.range.end);
+        let replacement = if aliased.contains(&u) {
+            format!("use {} as {};\n", shared_path_of(u), unit.name)
+        } else {
+            String::new()
+        };
+        file_edits.push((unit.range.start..end, replacement));
+    }
+
+    for (f, file) in files.iter().enumerate() {
+        if file.path == shared_path {
+            continue;
+        }
+        let mut text = apply_edits(&file.source.text, 0, &edits[f]);
+        if let (true, Some(lib_crate)) = (binaries.contains(&file.path), lib_crate) {
+            if !file.declares_shared {
+                text.push_str(&format!("\nuse {}::{};\n", lib_crate, SHARED_MODULE));
+            }
+        } else if roots.contains(&file.path) && !file.declares_shared {
+            text.push_str(&format!("\npub mod {};\n", SHARED_MODULE));
+        } else if edits[f].is_empty() {
+            continue;
+        }
+        fs::write(&file.path, text)?;
+    }
+    fs::write(&shared_path, shared)?;
+
+    info!(
+        "Merged {} header submodules into {} shared modules in {}",
+        moved.len(),
+        num_classes,
+        shared_path.display()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIB_RS: &str = "pub mod a;\npub mod b;\npub mod c;\n";
+
+    /// `counter.h`, as translated into each file that includes it
+    fn counter_h(line: u32) -> String {
+        format!(
+            "#[c2rust::header_src = \"/src/counter.h:{}\"]
+pub mod counter_h {{
+    pub type counter_t = libc::c_int;
+    extern \"C\" {{
+        pub fn counter_next(c: *mut counter_t) -> counter_t;
+    }}
+}}
+",
+            line
+        )
+    }
+
+    const A_RS: &str = "pub unsafe extern \"C\" fn a() -> counter_t {
+    let mut c: counter_t = 0;
+    counter_next(&mut c)
+}
+pub use self::counter_h::counter_t;
+use self::counter_h::counter_next;
+";
+
+    const B_RS: &str = "#[c2rust::header_src = \"/src/limits.h:2\"]
+pub mod limits_h {
+    pub const LIMIT: libc::c_int = 10;
+}
+pub unsafe extern \"C\" fn b() -> bool {
+    let mut c: counter_h::counter_t = 0;
+    counter_next(&mut c) < limits_h::LIMIT
+}
+use self::counter_h::{counter_next, counter_t};
+";
+
+    const C_RS: &str = "#[c2rust::header_src = \"/src/limits.h:1\"]
+pub mod limits_h {
+    pub const LIMIT: libc::c_int = 20;
+}
+#[c2rust::header_src = \"/src/config.h:1\"]
+pub mod config_h {
+    pub use super::helper;
+}
+pub fn helper() {}
+pub use self::config_h::helper as config_helper;
+pub use self::limits_h::LIMIT;
+";
+
+    fn crate_dir() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        let files = [
+            ("lib.rs", LIB_RS.to_owned()),
+            ("a.rs", counter_h(1) + A_RS),
+            ("b.rs", counter_h(3) + B_RS),
+            ("c.rs", counter_h(1) + C_RS),
+        ];
+        for (name, contents) in files {
+            fs::write(dir.path().join(name), contents).unwrap();
+        }
+        dir
+    }
+
+    fn read(dir: &Path, name: &str) -> String {
+        fs::read_to_string(dir.join(name)).unwrap()
+    }
+
+    #[test]
+    fn merges_identical_header_modules() {
+        let dir = crate_dir();
+        let lib_rs = dir.path().join("lib.rs");
+        merge_header_modules(dir.path(), Some(&lib_rs), &[], None, false).unwrap();
+
+        let shared = read(dir.path(), "c2rust_headers.rs");
+        syn::parse_file(&shared).unwrap();
+        // The three copies of counter.h are merged, regardless of the line
+        // of the `#include`
+        assert_eq!(shared.matches("pub mod counter_h").count(), 1);
+        assert_eq!(shared.matches("fn counter_next").count(), 1);
+        assert!(read(dir.path(), "lib.rs").ends_with("\npub mod c2rust_headers;\n"));
+
+        let a = read(dir.path(), "a.rs");
+        assert!(!a.contains("mod counter_h"));
+        assert!(a.contains("pub use crate::c2rust_headers::counter_h::counter_t;"));
+        assert!(a.contains("use crate::c2rust_headers::counter_h::counter_next;"));
+
+        let b = read(dir.path(), "b.rs");
+        assert!(!b.contains("mod counter_h"));
+        assert!(b.contains("use crate::c2rust_headers::counter_h::{counter_next, counter_t};"));
+        assert!(b.contains("let mut c: crate::c2rust_headers::counter_h::counter_t = 0;"));
+    }
+
+    #[test]
+    fn keeps_different_header_modules_apart() {
+        let dir = crate_dir();
+        let lib_rs = dir.path().join("lib.rs");
+        merge_header_modules(dir.path(), Some(&lib_rs), &[], None, false).unwrap();
+
+        // The two versions of limits.h both move, under different names
+        let shared = read(dir.path(), "c2rust_headers.rs");
+        assert!(shared.contains("pub mod limits_h {\n    pub const LIMIT: libc::c_int = 10;"));
+        assert!(shared.contains("pub mod limits_h_0 {\n    pub const LIMIT: libc::c_int = 20;"));
+        let b = read(dir.path(), "b.rs");
+        assert!(b.contains("< crate::c2rust_headers::limits_h::LIMIT"));
+        let c = read(dir.path(), "c.rs");
+        assert!(c.contains("pub use crate::c2rust_headers::limits_h_0::LIMIT;"));
+    }
+
+    #[test]
+    fn keeps_immovable_header_modules() {
+        let dir = crate_dir();
+        let lib_rs = dir.path().join("lib.rs");
+        merge_header_modules(dir.path(), Some(&lib_rs), &[], None, false).unwrap();
+
+        // config.h refers to a function of its including module
+        let c = read(dir.path(), "c.rs");
+        assert!(c.contains("pub mod config_h {\n    pub use super::helper;\n}"));
+        assert!(c.contains("pub use self::config_h::helper as config_helper;"));
+        assert!(!read(dir.path(), "c2rust_headers.rs").contains("config_h"));
+    }
+
+    #[test]
+    fn binaries_import_from_library() {
+        let dir = crate_dir();
+        let lib_rs = dir.path().join("lib.rs");
+        let main_rs = dir.path().join("main.rs");
+        fs::write(
+            &main_rs,
+            counter_h(2) + A_RS.replace("fn a()", "fn main_a()").as_str(),
+        )
+        .unwrap();
+        merge_header_modules(
+            dir.path(),
+            Some(&lib_rs),
+            &[main_rs],
+            Some("lib_crate"),
+            false,
+        )
+        .unwrap();
+
+        let main = read(dir.path(), "main.rs");
+        assert!(!main.contains("mod counter_h"));
+        assert!(main.contains("pub use crate::c2rust_headers::counter_h::counter_t;"));
+        assert!(main.ends_with("\nuse lib_crate::c2rust_headers;\n"));
+        assert!(!main.contains("pub mod c2rust_headers"));
+    }
+
+    #[test]
+    fn nothing_to_merge() {
+        let dir = tempfile::tempdir().unwrap();
+        let lib_rs = dir.path().join("lib.rs");
+        fs::write(&lib_rs, "pub mod a;\n").unwrap();
+        fs::write(dir.path().join("a.rs"), A_RS).unwrap();
+        merge_header_modules(dir.path(), Some(&lib_rs), &[], None, false).unwrap();
+        assert!(!dir.path().join("c2rust_headers.rs").exists());
+        assert_eq!(read(dir.path(), "lib.rs"), "pub mod a;\n");
+    }
+}
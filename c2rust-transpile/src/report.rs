@@ -0,0 +1,394 @@
+//! Machine-readable report of everything that could not be translated:
+//! skipped files, declarations that failed to translate (or were replaced by
+//! an `extern` declaration) and constructs the Clang AST could not express.
+//!
+//! The report can be written as plain JSON or as SARIF 2.1.0, which most CI
+//! systems can display and track over time.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use failure::Fail;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::c_ast::DisplaySrcSpan;
+use crate::diagnostics::{TranslationError, TranslationErrorKind};
+use crate::{ReplaceMode, TranspileError};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum FailureKind {
+    /// A whole translation unit was not translated
+    SkippedFile,
+    /// A declaration was left out of the translation
+    FailedDeclaration,
+    /// A function could not be translated and was replaced by an `extern`
+    /// declaration, according to `TranspilerConfig::replace_unsupported_decls`
+    ReplacedDeclaration,
+    /// The exported Clang AST contained a construct we could not represent
+    InvalidClangAst,
+    /// The `extern` declaration of a function disagrees with its definition in
+    /// another module, so it was not replaced by an import of the definition
+    SignatureMismatch,
+}
+
+impl FailureKind {
+    const ALL: [FailureKind; 5] = [
+        FailureKind::SkippedFile,
+        FailureKind::FailedDeclaration,
+        FailureKind::ReplacedDeclaration,
+        FailureKind::InvalidClangAst,
+        FailureKind::SignatureMismatch,
+    ];
+
+    /// The identifier of the kind, as used in the reports
+    pub fn id(self) -> &'static str {
+        match self {
+            FailureKind::SkippedFile => "skipped-file",
+            FailureKind::FailedDeclaration => "failed-declaration",
+            FailureKind::ReplacedDeclaration => "replaced-declaration",
+            FailureKind::InvalidClangAst => "invalid-clang-ast",
+            FailureKind::SignatureMismatch => "signature-mismatch",
+        }
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            FailureKind::SkippedFile => "The C file was not translated",
+            FailureKind::FailedDeclaration => "The C declaration could not be translated",
+            FailureKind::ReplacedDeclaration => {
+                "The C function could not be translated and was replaced by an extern declaration"
+            }
+            FailureKind::InvalidClangAst => "The Clang AST contained an unsupported construct",
+            FailureKind::SignatureMismatch => {
+                "The extern declaration of a function does not match its definition"
+            }
+        }
+    }
+
+    fn sarif_level(self) -> &'static str {
+        match self {
+            FailureKind::SkippedFile | FailureKind::FailedDeclaration => "error",
+            FailureKind::ReplacedDeclaration
+            | FailureKind::InvalidClangAst
+            | FailureKind::SignatureMismatch => "warning",
+        }
+    }
+}
+
+/// A position in the C sources, as in [`DisplaySrcSpan`].
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SourceLocation {
+    pub file: Option<PathBuf>,
+    pub begin_line: u64,
+    pub begin_column: u64,
+    pub end_line: u64,
+    pub end_column: u64,
+}
+
+impl From<&DisplaySrcSpan> for SourceLocation {
+    fn from(loc: &DisplaySrcSpan) -> Self {
+        let span = loc.span();
+        Self {
+            file: loc.file().map(Path::to_owned),
+            begin_line: span.begin_line,
+            begin_column: span.begin_column,
+            end_line: span.end_line,
+            end_column: span.end_column,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Failure {
+    pub kind: FailureKind,
+    /// The translation unit being translated
+    pub input: PathBuf,
+    /// Name of the declaration (usually a function), if there is one
+    pub declaration: Option<String>,
+    pub location: Option<SourceLocation>,
+    /// The `TranslationErrorKind` of the underlying error
+    pub error_kind: Option<String>,
+    pub message: String,
+    /// The `TranspilerConfig::replace_unsupported_decls` mode in effect
+    pub replace_mode: ReplaceMode,
+}
+
+impl Failure {
+    /// A failure caused by a `TranslationError`. The location of the
+    /// declaration is preferred over the innermost location of the error.
+    pub fn from_error(
+        kind: FailureKind,
+        input: &Path,
+        declaration: Option<String>,
+        location: Option<DisplaySrcSpan>,
+        error: &TranslationError,
+        replace_mode: ReplaceMode,
+    ) -> Self {
+        let location = location.as_ref().or_else(|| error.locations().first());
+        let message = match error.cause() {
+            Some(cause) => cause.to_string(),
+            None => error.kind().to_string(),
+        };
+        Self {
+            kind,
+            input: input.to_owned(),
+            declaration,
+            location: location.map(SourceLocation::from),
+            error_kind: Some(format!("{:?}", error.kind())),
+            message,
+            replace_mode,
+        }
+    }
+
+    pub fn skipped_file(input: &Path, message: String, replace_mode: ReplaceMode) -> Self {
+        Self {
+            kind: FailureKind::SkippedFile,
+            input: input.to_owned(),
+            declaration: None,
+            location: None,
+            error_kind: None,
+            message,
+            replace_mode,
+        }
+    }
+
+    /// An `extern` declaration of `declaration`, used by the translation unit
+    /// `input`, that was kept because it does not match the definition.
+    pub fn signature_mismatch(
+        input: &Path,
+        declaration: String,
+        message: String,
+        replace_mode: ReplaceMode,
+    ) -> Self {
+        Self {
+            kind: FailureKind::SignatureMismatch,
+            declaration: Some(declaration),
+            ..Self::skipped_file(input, message, replace_mode)
+        }
+    }
+
+    /// A file skipped because the translator panicked while processing it.
+    pub fn panicked_file(input: &Path, message: String, replace_mode: ReplaceMode) -> Self {
+        Self {
+            error_kind: Some(format!("{:?}", TranslationErrorKind::Panic)),
+            ..Self::skipped_file(input, message, replace_mode)
+        }
+    }
+
+    /// Whether this failure was caused by the translator panicking.
+    pub fn is_panic(&self) -> bool {
+        self.error_kind.as_deref() == Some("Panic")
+    }
+
+    fn to_sarif(&self) -> serde_json::Value {
+        let (file, region) = match &self.location {
+            Some(loc) => {
+                // SARIF lines and columns are 1-based; 0 means unknown
+                let region = (loc.begin_line > 0).then(|| {
+                    json!({
+                        "startLine": loc.begin_line,
+                        "startColumn": loc.begin_column.max(1),
+                        "endLine": loc.end_line.max(loc.begin_line),
+                        "endColumn": loc.end_column.max(1),
+                    })
+                });
+                (loc.file.as_deref().unwrap_or(&self.input), region)
+            }
+            None => (self.input.as_path(), None),
+        };
+        let mut physical_location = json!({
+            "artifactLocation": { "uri": file_uri(file) },
+        });
+        if let Some(region) = region {
+            physical_location["region"] = region;
+        }
+        let message = match &self.declaration {
+            Some(decl) => format!("{}: {}", decl, self.message),
+            None => self.message.clone(),
+        };
+        json!({
+            "ruleId": self.kind.id(),
+            "level": self.kind.sarif_level(),
+            "message": { "text": message },
+            "locations": [{ "physicalLocation": physical_location }],
+            "properties": {
+                "input": self.input,
+                "declaration": self.declaration,
+                "errorKind": self.error_kind,
+                "replaceMode": self.replace_mode,
+            },
+        })
+    }
+}
+
+fn file_uri(path: &Path) -> String {
+    let path = path.to_string_lossy().replace('\\', "/");
+    if path.starts_with('/') {
+        format!("file://{}", path)
+    } else {
+        path
+    }
+}
+
+/// Failures collected from all translation units of a run.
+#[derive(Debug, Default)]
+pub struct FailureReport {
+    failures: Mutex<Vec<Failure>>,
+}
+
+impl FailureReport {
+    pub fn add(&self, failure: Failure) {
+        self.failures.lock().unwrap().push(failure);
+    }
+
+    pub fn extend(&self, failures: impl IntoIterator<Item = Failure>) {
+        self.failures.lock().unwrap().extend(failures);
+    }
+
+    /// All failures, grouped by translation unit. The order within a
+    /// translation unit is the order in which they occurred.
+    pub fn failures(&self) -> Vec<Failure> {
+        let mut failures = self.failures.lock().unwrap().clone();
+        failures.sort_by(|a, b| a.input.cmp(&b.input));
+        failures
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({ "failures": self.failures() })
+    }
+
+    pub fn to_sarif(&self) -> serde_json::Value {
+        let rules = FailureKind::ALL
+            .iter()
+            .map(|kind| {
+                json!({
+                    "id": kind.id(),
+                    "shortDescription": { "text": kind.description() },
+                    "defaultConfiguration": { "level": kind.sarif_level() },
+                })
+            })
+            .collect::<Vec<_>>();
+        let results = self
+            .failures()
+            .iter()
+            .map(Failure::to_sarif)
+            .collect::<Vec<_>>();
+        json!({
+            "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+            "version": "2.1.0",
+            "runs": [{
+                "tool": {
+                    "driver": {
+                        "name": "c2rust-transpile",
+                        "version": env!("CARGO_PKG_VERSION"),
+                        "rules": rules,
+                    },
+                },
+                "results": results,
+            }],
+        })
+    }
+
+    /// Write the report files requested by `--report-json` and `--report-sarif`.
+    pub fn write(
+        &self,
+        json_path: Option<&Path>,
+        sarif_path: Option<&Path>,
+    ) -> Result<(), TranspileError> {
+        if let Some(path) = json_path {
+            write_json(path, &self.to_json())?;
+        }
+        if let Some(path) = sarif_path {
+            write_json(path, &self.to_sarif())?;
+        }
+        Ok(())
+    }
+}
+
+fn write_json(path: &Path, value: &serde_json::Value) -> Result<(), TranspileError> {
+    let json =
+        serde_json::to_string_pretty(value).map_err(|e| TranspileError::io(path)(e.into()))?;
+    fs::write(path, json).map_err(TranspileError::io(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report() -> FailureReport {
+        let report = FailureReport::default();
+        let error = TranslationError::generic("Unsupported builtin");
+        let mut failure = Failure::from_error(
+            FailureKind::FailedDeclaration,
+            Path::new("/src/b.c"),
+            Some("frobnicate".to_owned()),
+            None,
+            &error,
+            ReplaceMode::None,
+        );
+        failure.location = Some(SourceLocation {
+            file: Some(PathBuf::from("/src/b.h")),
+            begin_line: 12,
+            begin_column: 0,
+            end_line: 14,
+            end_column: 2,
+        });
+        report.add(failure);
+        report.add(Failure::skipped_file(
+            Path::new("/src/a.c"),
+            "The file could not be exported".to_owned(),
+            ReplaceMode::Extern,
+        ));
+        report
+    }
+
+    #[test]
+    fn sarif() {
+        let sarif = report().to_sarif();
+        assert_eq!(sarif["version"], "2.1.0");
+        let run = &sarif["runs"][0];
+        let rules = run["tool"]["driver"]["rules"].as_array().unwrap();
+        assert_eq!(rules.len(), FailureKind::ALL.len());
+
+        let results = run["results"].as_array().unwrap();
+        assert_eq!(results.len(), 2);
+
+        // Sorted by translation unit
+        let skipped = &results[0];
+        assert_eq!(skipped["ruleId"], "skipped-file");
+        assert_eq!(skipped["level"], "error");
+        assert_eq!(skipped["message"]["text"], "The file could not be exported");
+        let location = &skipped["locations"][0]["physicalLocation"];
+        assert_eq!(location["artifactLocation"]["uri"], "file:///src/a.c");
+        assert!(location.get("region").is_none());
+        assert_eq!(skipped["properties"]["replaceMode"], "extern");
+
+        let failed = &results[1];
+        assert_eq!(failed["ruleId"], "failed-declaration");
+        assert_eq!(failed["message"]["text"], "frobnicate: Unsupported builtin");
+        let location = &failed["locations"][0]["physicalLocation"];
+        assert_eq!(location["artifactLocation"]["uri"], "file:///src/b.h");
+        assert_eq!(location["region"]["startLine"], 12);
+        assert_eq!(location["region"]["startColumn"], 1);
+        assert_eq!(location["region"]["endLine"], 14);
+        assert_eq!(location["region"]["endColumn"], 2);
+        assert_eq!(failed["properties"]["input"], "/src/b.c");
+        assert_eq!(failed["properties"]["declaration"], "frobnicate");
+        assert_eq!(failed["properties"]["errorKind"], "Generic");
+        assert!(rules.iter().any(|rule| rule["id"] == failed["ruleId"]));
+    }
+
+    #[test]
+    fn json() {
+        let json = report().to_json();
+        let failures = json["failures"].as_array().unwrap();
+        assert_eq!(failures[0]["kind"], "skipped-file");
+        assert_eq!(failures[1]["kind"], "failed-declaration");
+        let failure: Failure = serde_json::from_value(failures[1].clone()).unwrap();
+        assert_eq!(failure.declaration.as_deref(), Some("frobnicate"));
+        assert!(!failure.is_panic());
+    }
+}
@@ -1,14 +1,19 @@
 //! This module implements the translation of main functions in C.
 //! Translating main requires us to wrap the C implementation to
 //! a helper that can be called from a generated main function in
-//! Rust.
+//! Rust. For a multi-call binary, the generated function is instead
+//! named `<util>_main` and takes the arguments from the dispatcher.
 
 use super::*;
 use failure::format_err;
 use proc_macro2::{TokenStream, TokenTree};
 
 impl<'c> Translation<'c> {
-    pub fn convert_main(&self, main_id: CDeclId) -> TranslationResult<Box<Item>> {
+    pub fn convert_main(
+        &self,
+        main_id: CDeclId,
+        multicall_util: Option<&str>,
+    ) -> TranslationResult<Box<Item>> {
         if let CDeclKind::Function {
             ref parameters,
             typ,
@@ -35,12 +40,34 @@ impl<'c> Translation<'c> {
                 .get(&main_id)
                 .expect("Could not find main function in renamer");
 
-            let decl = mk().fn_decl("main", vec![], None, ReturnType::Default);
+            let n = parameters.len();
+
+            let (decl, args_iter) = match multicall_util {
+                Some(util) => {
+                    // The dispatcher passes the arguments, starting with the
+                    // name of the utility
+                    let name = if n >= 2 { "arguments" } else { "_arguments" };
+                    let arguments = mk().arg(
+                        mk().path_ty(vec![mk().path_segment_with_args(
+                            "Vec",
+                            mk().angle_bracketed_args(vec![mk().path_ty(vec!["String"])]),
+                        )]),
+                        mk().ident_pat(name),
+                    );
+                    let fn_name = format!("{}_main", util);
+                    let decl = mk().fn_decl(fn_name, vec![arguments], None, ReturnType::Default);
+                    (decl, mk().path_expr(vec![name]))
+                }
+                None => {
+                    let decl = mk().fn_decl("main", vec![], None, ReturnType::Default);
+                    let args_fn = mk().abs_path_expr(vec!["std", "env", "args"]);
+                    (decl, mk().call_expr(args_fn, vec![]))
+                }
+            };
 
             let main_fn = mk().path_expr(vec![main_fn_name]);
 
             let exit_fn = mk().abs_path_expr(vec!["std", "process", "exit"]);
-            let args_fn = mk().abs_path_expr(vec!["std", "env", "args"]);
             let vars_fn = mk().abs_path_expr(vec!["std", "env", "vars"]);
 
             let no_args: Vec<Box<Expr>> = vec![];
@@ -48,8 +75,6 @@ impl<'c> Translation<'c> {
             let mut stmts: Vec<Stmt> = vec![];
             let mut main_args: Vec<Box<Expr>> = vec![];
 
-            let n = parameters.len();
-
             if n >= 2 {
                 // `argv` and `argc`
 
@@ -65,7 +90,7 @@ impl<'c> Translation<'c> {
                 ))));
                 stmts.push(mk().semi_stmt(mk().for_expr(
                     mk().ident_pat("arg"),
-                    mk().call_expr(args_fn, vec![]),
+                    args_iter,
                     mk().block(vec![mk().semi_stmt(mk().method_call_expr(
                         mk().path_expr(vec!["args"]),
                         "push",
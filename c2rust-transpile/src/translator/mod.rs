@@ -1,6 +1,6 @@
 use std::cell::RefCell;
 use std::char;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::mem;
 use std::ops::Index;
 use std::path::{self, PathBuf};
@@ -13,6 +13,7 @@ use indexmap::indexmap;
 use indexmap::{IndexMap, IndexSet};
 use log::{error, info, trace, warn};
 use proc_macro2::{Punct, Spacing::*, Span, TokenStream, TokenTree};
+use serde_derive::{Deserialize, Serialize};
 use syn::spanned::Spanned as _;
 use syn::*;
 use syn::{BinOp, UnOp}; // To override c_ast::{BinOp,UnOp} from glob import
@@ -30,7 +31,9 @@ use crate::c_ast::iterators::{DFExpr, SomeId};
 use crate::c_ast::*;
 use crate::cfg;
 use crate::convert_type::TypeConverter;
+use crate::isolate;
 use crate::renamer::Renamer;
+use crate::report::{Failure, FailureKind};
 use crate::with_stmts::WithStmts;
 use crate::{c_ast, format_translation_err};
 use crate::{ExternCrate, ExternCrateDetails, TranspilerConfig};
@@ -94,7 +97,8 @@ impl From<bool> for DecayRef {
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum ReplaceMode {
     None,
     Extern,
@@ -248,6 +252,7 @@ pub struct Translation<'c> {
     pub features: RefCell<IndexSet<&'static str>>,
     sectioned_static_initializers: RefCell<Vec<Stmt>>,
     extern_crates: RefCell<CrateSet>,
+    failures: RefCell<Vec<Failure>>,
 
     // Translation state and utilities
     type_converter: RefCell<TypeConverter>,
@@ -271,11 +276,16 @@ pub struct Translation<'c> {
 
     // The main file id that the translator is operating on
     main_file: FileId,
+    main_file_path: PathBuf,
 
     // While expanding an item, store the current file id that item is
     // expanded from. This is needed in order to note imports in items when
     // encountering DeclRefs.
     cur_file: RefCell<Option<FileId>>,
+
+    // The common and weak definitions of this file that the linker resolves
+    // to those of other files, which are only declared here
+    overridden_symbols: HashSet<String>,
 }
 
 fn simple_metaitem(name: &str) -> NestedMeta {
@@ -383,6 +393,14 @@ pub fn stmts_block(mut stmts: Vec<Stmt>) -> Block {
     mk().block(stmts)
 }
 
+/// The symbol that `__attribute__((alias))` among `attrs` names
+fn alias_target(attrs: &IndexSet<c_ast::Attribute>) -> Option<&str> {
+    attrs.iter().find_map(|attr| match attr {
+        c_ast::Attribute::Alias(target) => Some(target.as_str()),
+        _ => None,
+    })
+}
+
 /// Generate link attributes needed to ensure that the generated Rust libraries have the right symbol values.
 fn mk_linkage(in_extern_block: bool, new_name: &str, old_name: &str) -> Builder {
     if new_name == old_name {
@@ -480,20 +498,15 @@ fn clean_path(mod_names: &RefCell<IndexMap<String, PathBuf>>, path: Option<&path
     file_path
 }
 
-pub fn translate_failure(tcfg: &TranspilerConfig, msg: &str) {
-    error!("{}", msg);
-    if tcfg.fail_on_error {
-        panic!("Translation failed, see error above");
-    }
-}
-
 pub fn translate(
     ast_context: TypedAstContext,
     tcfg: &TranspilerConfig,
     main_file: &PathBuf,
     is_binary: bool,
-) -> (String, PragmaVec, CrateSet) {
-    let mut t = Translation::new(ast_context, tcfg, main_file.as_path());
+    multicall_util: Option<&str>,
+    overridden_symbols: HashSet<String>,
+) -> (String, PragmaVec, CrateSet, Vec<Failure>) {
+    let mut t = Translation::new(ast_context, tcfg, main_file.as_path(), overridden_symbols);
     let ctx = ExprContext {
         used: true,
         is_static: false,
@@ -643,11 +656,12 @@ pub fn translate(
                 if t.tcfg.reorganize_definitions {
                     *t.cur_file.borrow_mut() = decl_file_id;
                 }
-                match t.convert_decl(ctx, decl_id) {
+                match t.isolated(decl_id, || t.convert_decl(ctx, decl_id)) {
                     Err(e) => {
                         let k = &t.ast_context.get_decl(&decl_id).map(|x| &x.kind);
                         let msg = format!("Skipping declaration {:?} due to error: {}", k, e);
-                        translate_failure(t.tcfg, &msg);
+                        t.report_failure(FailureKind::FailedDeclaration, decl_id, &e);
+                        error!("{}", msg);
                     }
                     Ok(converted_decl) => {
                         use ConvertedDecl::*;
@@ -717,7 +731,7 @@ pub fn translate(
                 {
                     *t.cur_file.borrow_mut() = decl_file_id;
                 }
-                match t.convert_decl(ctx, *top_id) {
+                match t.isolated(*top_id, || t.convert_decl(ctx, *top_id)) {
                     Err(e) => {
                         let decl = &t.ast_context.get_decl(top_id);
                         let msg = match decl {
@@ -734,7 +748,8 @@ pub fn translate(
                             }
                             _ => format!("Failed to translate declaration: {}", e,),
                         };
-                        translate_failure(t.tcfg, &msg);
+                        t.report_failure(FailureKind::FailedDeclaration, *top_id, &e);
+                        error!("{}", msg);
                     }
                     Ok(converted_decl) => {
                         use ConvertedDecl::*;
@@ -766,11 +781,12 @@ pub fn translate(
 
         // Add the main entry point
         if let Some(main_id) = t.ast_context.c_main {
-            match t.convert_main(main_id) {
+            match t.isolated(main_id, || t.convert_main(main_id, multicall_util)) {
                 Ok(item) => t.items.borrow_mut()[&t.main_file].add_item(item),
                 Err(e) => {
                     let msg = format!("Failed to translate main: {}", e);
-                    translate_failure(t.tcfg, &msg)
+                    t.report_failure(FailureKind::FailedDeclaration, main_id, &e);
+                    error!("{}", msg);
                 }
             }
         }
@@ -786,6 +802,7 @@ pub fn translate(
 
         let pragmas = t.get_pragmas();
         let crates = t.extern_crates.borrow().clone();
+        let failures = t.failures.take();
 
         let mut mod_items: Vec<Box<Item>> = Vec::new();
 
@@ -886,7 +903,7 @@ pub fn translate(
                 items: all_items.into_iter().map(|x| *x).collect(),
             }
         });
-        (translation, pragmas, crates)
+        (translation, pragmas, crates, failures)
     }
 }
 
@@ -1204,6 +1221,7 @@ impl<'c> Translation<'c> {
         mut ast_context: TypedAstContext,
         tcfg: &'c TranspilerConfig,
         main_file: &path::Path,
+        overridden_symbols: HashSet<String>,
     ) -> Self {
         let comment_context = CommentContext::new(&mut ast_context);
         let mut type_converter = TypeConverter::new();
@@ -1212,6 +1230,7 @@ impl<'c> Translation<'c> {
             type_converter.translate_valist = true
         }
 
+        let main_file_path = main_file.to_owned();
         let main_file = ast_context.find_file_id(main_file).unwrap_or(0);
         let items = indexmap! {main_file => ItemStore::new()};
 
@@ -1244,11 +1263,47 @@ impl<'c> Translation<'c> {
             items: RefCell::new(items),
             mod_names: RefCell::new(IndexMap::new()),
             main_file,
+            main_file_path,
             extern_crates: RefCell::new(IndexSet::new()),
+            failures: RefCell::new(Vec::new()),
             cur_file: RefCell::new(None),
+            overridden_symbols,
         }
     }
 
+    /// Record a declaration that could not be translated as-is in the
+    /// failure report.
+    fn report_failure(&self, kind: FailureKind, decl_id: CDeclId, error: &TranslationError) {
+        let decl = self.ast_context.get_decl(&decl_id);
+        self.failures.borrow_mut().push(Failure::from_error(
+            kind,
+            &self.main_file_path,
+            decl.and_then(|decl| decl.kind.get_name().cloned()),
+            decl.and_then(|decl| self.ast_context.display_loc(&decl.loc)),
+            error,
+            self.tcfg.replace_unsupported_decls,
+        ));
+    }
+
+    /// Run `f`, the translation of `decl_id`, turning a panic into a
+    /// `TranslationError` so that it only costs us this declaration.
+    fn isolated<T>(
+        &self,
+        decl_id: CDeclId,
+        f: impl FnOnce() -> TranslationResult<T>,
+    ) -> TranslationResult<T> {
+        let scope_depth = self.renamer.borrow().scope_depth();
+        isolate::catch_panic(f).unwrap_or_else(|msg| {
+            // Scopes the panicking translation introduced were never dropped
+            self.renamer.borrow_mut().truncate_scopes(scope_depth);
+            let loc = self
+                .ast_context
+                .get_decl(&decl_id)
+                .and_then(|decl| self.ast_context.display_loc(&decl.loc));
+            Err(TranslationError::panic(msg).add_loc(loc))
+        })
+    }
+
     fn use_crate(&self, extern_crate: ExternCrate) {
         self.extern_crates.borrow_mut().insert(extern_crate);
     }
@@ -1272,6 +1327,110 @@ impl<'c> Translation<'c> {
         f(item_store)
     }
 
+    /// Whether the linker resolves `name`, which this file defines as a
+    /// common or weak symbol, to the definition of another file instead
+    fn is_overridden(&self, name: &str) -> bool {
+        self.overridden_symbols.contains(name)
+    }
+
+    /// Whether `decl` is a weak declaration of a variable or function, one
+    /// that may have no definition at all, like
+    /// `extern int x __attribute__((weak));`
+    fn is_weak_declaration(&self, decl: &CDeclKind) -> bool {
+        match decl {
+            CDeclKind::Variable {
+                is_externally_visible: true,
+                is_defn: false,
+                attrs,
+                ..
+            }
+            | CDeclKind::Function {
+                is_global: true,
+                body: None,
+                attrs,
+                ..
+            } => attrs.contains(&c_ast::Attribute::Weak),
+            _ => false,
+        }
+    }
+
+    /// Whether `expr` refers to a weakly declared function, whose
+    /// translation is already a function pointer
+    fn is_weak_function_ref(&self, expr: CExprId) -> bool {
+        match self.ast_context[expr].kind {
+            CExprKind::Paren(_, expr) => self.is_weak_function_ref(expr),
+            CExprKind::DeclRef(_, decl_id, _) => {
+                let decl = &self.ast_context[decl_id].kind;
+                matches!(decl, CDeclKind::Function { .. }) && self.is_weak_declaration(decl)
+            }
+            _ => false,
+        }
+    }
+
+    /// Define the symbol `name` as an alias of `target`, a function or
+    /// variable that this file defines, unless the linker overrides it. Rust
+    /// has no alias attribute, so this is an ELF `.set` directive in a
+    /// `global_asm!` of the main module, which also defines `target`. Returns
+    /// whether `name` refers to the alias, rather than to `target` directly,
+    /// which is the case unless `target` is not found here.
+    fn define_alias(&self, name: &str, target: &str, attrs: &IndexSet<c_ast::Attribute>) -> bool {
+        let target_id = self.ast_context.iter_decls().find_map(|(&id, decl)| {
+            let defines_target = match decl.kind {
+                CDeclKind::Function {
+                    name: ref n,
+                    body: Some(_),
+                    ..
+                } => n == target,
+                CDeclKind::Variable {
+                    ident: ref n,
+                    is_defn: true,
+                    ref attrs,
+                    ..
+                } => n == target && alias_target(attrs).is_none(),
+                _ => false,
+            };
+            defines_target.then_some(id)
+        });
+        let target_name = match target_id.and_then(|id| self.renamer.borrow().get(&id)) {
+            Some(target_name) => target_name,
+            None => return false,
+        };
+        if self.is_overridden(name) {
+            return true;
+        }
+
+        let directive = if attrs.contains(&c_ast::Attribute::Weak) {
+            ".weak"
+        } else {
+            ".globl"
+        };
+        let tokens = vec![
+            TokenTree::Literal(proc_macro2::Literal::string(&format!(
+                "{} {}",
+                directive, name
+            ))),
+            TokenTree::Punct(Punct::new(',', Alone)),
+            TokenTree::Literal(proc_macro2::Literal::string(&format!(
+                ".set {}, {{0}}",
+                name
+            ))),
+            TokenTree::Punct(Punct::new(',', Alone)),
+            TokenTree::Ident(Ident::new("sym", Span::call_site())),
+            TokenTree::Ident(Ident::new(&target_name, Span::call_site())),
+        ];
+        let mac = mk().mac(
+            mk().path(vec!["global_asm"]),
+            tokens.into_iter().collect::<TokenStream>(),
+            MacroDelimiter::Paren(Default::default()),
+        );
+        self.use_feature("asm_sym");
+        let mut items = self.items.borrow_mut();
+        let item_store = &mut items[&self.main_file];
+        item_store.add_use(vec!["core".into(), "arch".into()], "global_asm");
+        item_store.add_item(mk().mac_item(mac));
+        true
+    }
+
     /// Called when translation makes use of a language feature that will require a feature-gate.
     pub fn use_feature(&self, feature: &'static str) {
         self.features.borrow_mut().insert(feature);
@@ -1904,28 +2063,18 @@ impl<'c> Translation<'c> {
 
                 let is_main = self.ast_context.c_main == Some(decl_id);
 
-                let converted_function = self.convert_function(
-                    ctx,
-                    span,
-                    is_global,
-                    is_inline,
-                    is_main,
-                    is_variadic,
-                    is_extern,
-                    new_name,
-                    name,
-                    &args,
-                    ret,
-                    body,
-                    attrs,
-                );
+                // A weak definition that the linker overrides with that of
+                // another file is only declared here
+                let body = body.filter(|_| !self.is_overridden(name));
 
-                converted_function.or_else(|e| match self.tcfg.replace_unsupported_decls {
-                    ReplaceMode::Extern if body.is_none() => self.convert_function(
+                // Catch panics here already, so that they are subject to
+                // `replace_unsupported_decls` like any other error
+                let converted_function = self.isolated(decl_id, || {
+                    self.convert_function(
                         ctx,
                         span,
                         is_global,
-                        false,
+                        is_inline,
                         is_main,
                         is_variadic,
                         is_extern,
@@ -1933,9 +2082,31 @@ impl<'c> Translation<'c> {
                         name,
                         &args,
                         ret,
-                        None,
+                        body,
                         attrs,
-                    ),
+                    )
+                });
+
+                converted_function.or_else(|e| match self.tcfg.replace_unsupported_decls {
+                    ReplaceMode::Extern if body.is_none() => {
+                        let replacement = self.convert_function(
+                            ctx,
+                            span,
+                            is_global,
+                            false,
+                            is_main,
+                            is_variadic,
+                            is_extern,
+                            new_name,
+                            name,
+                            &args,
+                            ret,
+                            None,
+                            attrs,
+                        )?;
+                        self.report_failure(FailureKind::ReplacedDeclaration, decl_id, &e);
+                        Ok(replacement)
+                    }
                     _ => Err(e),
                 })
             }
@@ -1967,24 +2138,26 @@ impl<'c> Translation<'c> {
                 ))
             }
 
-            // Externally-visible variable without initializer (definition elsewhere)
+            // Externally-visible variable without initializer (definition elsewhere),
+            // alias of another variable, or tentative or weak definition that the
+            // linker overrides with that of another file
             Variable {
                 is_externally_visible: true,
                 has_static_duration,
                 has_thread_duration,
-                is_defn: false,
+                is_defn,
                 ref ident,
                 initializer,
                 typ,
                 ref attrs,
                 ..
-            } => {
+            } if !is_defn || alias_target(attrs).is_some() || self.is_overridden(ident) => {
                 assert!(
                     has_static_duration || has_thread_duration,
                     "An extern variable must be static or thread-local"
                 );
                 assert!(
-                    initializer.is_none(),
+                    initializer.is_none() || is_defn,
                     "An extern variable that isn't a definition can't have an initializer"
                 );
 
@@ -1997,8 +2170,11 @@ impl<'c> Translation<'c> {
                     .borrow()
                     .get(&decl_id)
                     .expect("Variables should already be renamed");
-                let ConvertedVariable { ty, mutbl, init: _ } =
-                    self.convert_variable(ctx.static_(), None, typ)?;
+                let ConvertedVariable {
+                    mut ty,
+                    mutbl,
+                    init: _,
+                } = self.convert_variable(ctx.static_(), None, typ)?;
                 // When putting extern statics into submodules, they need to be public to be accessible
                 let visibility = if self.tcfg.reorganize_definitions {
                     "pub"
@@ -2012,14 +2188,19 @@ impl<'c> Translation<'c> {
                 if has_thread_duration {
                     extern_item = extern_item.single_attr("thread_local");
                 }
+                if self.is_weak_declaration(&self.ast_context[decl_id].kind) {
+                    // rustc only allows this linkage for a static of pointer
+                    // type, which holds the address of the symbol or null;
+                    // references to the variable dereference it
+                    self.use_feature("linkage");
+                    extern_item = extern_item.str_attr("linkage", "extern_weak");
+                    ty = mk().set_mutbl(mutbl).ptr_ty(ty);
+                }
 
-                for attr in attrs {
-                    extern_item = match attr {
-                        c_ast::Attribute::Alias(aliasee) => {
-                            extern_item.str_attr("link_name", aliasee)
-                        }
-                        _ => continue,
-                    };
+                if let Some(target) = alias_target(attrs) {
+                    if !self.define_alias(ident, target, attrs) {
+                        extern_item = extern_item.str_attr("link_name", target);
+                    }
                 }
 
                 Ok(ConvertedDecl::ForeignItem(
@@ -2116,6 +2297,10 @@ impl<'c> Translation<'c> {
                         c_ast::Attribute::Section(name) => {
                             static_def.str_attr("link_section", name)
                         }
+                        c_ast::Attribute::Weak if is_externally_visible => {
+                            self.use_feature("linkage");
+                            static_def.str_attr("linkage", "weak")
+                        }
                         _ => continue,
                     }
                 }
@@ -2381,6 +2566,10 @@ impl<'c> Translation<'c> {
                         c_ast::Attribute::AlwaysInline => mk_.call_attr("inline", vec!["always"]),
                         c_ast::Attribute::Cold => mk_.single_attr("cold"),
                         c_ast::Attribute::NoInline => mk_.call_attr("inline", vec!["never"]),
+                        c_ast::Attribute::Weak if is_global && !is_inline => {
+                            self.use_feature("linkage");
+                            mk_.str_attr("linkage", "weak")
+                        }
                         _ => continue,
                     };
                 }
@@ -2426,11 +2615,24 @@ impl<'c> Translation<'c> {
 
                 let mut mk_ = mk_linkage(true, new_name, name).span(span).vis(visibility);
 
-                for attr in attrs {
-                    mk_ = match attr {
-                        c_ast::Attribute::Alias(aliasee) => mk_.str_attr("link_name", aliasee),
-                        _ => continue,
-                    };
+                // A weak declaration may be left undefined, for a null address.
+                // rustc ignores the linkage of foreign functions, so this is a
+                // static of pointer type holding the address of the function,
+                // which references to the function transmute to a function
+                // pointer.
+                if is_global && attrs.contains(&c_ast::Attribute::Weak) {
+                    self.use_feature("linkage");
+                    let ty = mk().ptr_ty(mk().path_ty(vec!["libc", "c_void"]));
+                    let static_item = mk_
+                        .str_attr("linkage", "extern_weak")
+                        .static_foreign_item(new_name, ty);
+                    return Ok(ConvertedDecl::ForeignItem(static_item));
+                }
+
+                if let Some(target) = alias_target(attrs) {
+                    if !self.define_alias(name, target, attrs) {
+                        mk_ = mk_.str_attr("link_name", target);
+                    }
                 }
 
                 let function_decl = mk_.fn_foreign_item(decl);
@@ -3328,6 +3530,24 @@ impl<'c> Translation<'c> {
 
                 let mut val = mk().path_expr(vec![rustname]);
 
+                // The static of a weak declaration is a pointer to the variable
+                // or function
+                let mut set_unsafe = false;
+                let is_weak = self.is_weak_declaration(decl);
+                if is_weak {
+                    if let CDeclKind::Function { .. } = decl {
+                        let fn_ty = self.convert_type(qual_ty.ctype)?;
+                        let param = mk().angle_bracketed_args(vec![fn_ty]);
+                        let fn_ptr_ty =
+                            mk().path_ty(vec![mk().path_segment_with_args("Option", param)]);
+                        let address_ty = mk().ptr_ty(mk().path_ty(vec!["libc", "c_void"]));
+                        val = transmute_expr(address_ty, fn_ptr_ty, val);
+                        set_unsafe = true;
+                    } else {
+                        val = mk().unary_expr(UnOp::Deref(Default::default()), val);
+                    }
+                }
+
                 // If the variable is volatile and used as something that isn't an LValue, this
                 // constitutes a volatile read.
                 if lrvalue.is_rvalue() && qual_ty.qualifiers.is_volatile {
@@ -3350,8 +3570,7 @@ impl<'c> Translation<'c> {
 
                 // If we are referring to a function and need its address, we
                 // need to cast it to fn() to ensure that it has a real address.
-                let mut set_unsafe = false;
-                if ctx.needs_address() {
+                if ctx.needs_address() && !is_weak {
                     if let &CDeclKind::Function { ref parameters, .. } = decl {
                         let ty = self.convert_type(qual_ty.ctype)?;
                         let actual_ty = self
@@ -3736,7 +3955,12 @@ impl<'c> Translation<'c> {
                     // callee is a declref
                     if matches!(self.ast_context[fexp].kind, CExprKind::DeclRef(..)) =>
                         {
-                            self.convert_expr(ctx.used(), fexp)?
+                            let callee = self.convert_expr(ctx.used(), fexp)?;
+                            if self.is_weak_function_ref(fexp) {
+                                callee.map(unwrap_function_pointer)
+                            } else {
+                                callee
+                            }
                         }
 
                     // Builtin function call
@@ -4298,6 +4522,9 @@ impl<'c> Translation<'c> {
             CastKind::LValueToRValue | CastKind::ToVoid | CastKind::ConstCast => Ok(val),
 
             CastKind::FunctionToPointerDecay | CastKind::BuiltinFnToFnPtr => {
+                if expr.map_or(false, |expr| self.is_weak_function_ref(expr)) {
+                    return Ok(val);
+                }
                 Ok(val.map(|x| mk().call_expr(mk().ident_expr("Some"), vec![x])))
             }
 
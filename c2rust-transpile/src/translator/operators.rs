@@ -836,9 +836,12 @@ impl<'c> Translation<'c> {
                 // In this translation, there are only pointers to functions and
                 // & becomes a no-op when applied to a function.
 
+                let is_weak_function = self.is_weak_function_ref(arg);
                 let arg = self.convert_expr(ctx.used().set_needs_address(true), arg)?;
 
-                if self.ast_context.is_function_pointer(ctype) {
+                if is_weak_function {
+                    Ok(arg)
+                } else if self.ast_context.is_function_pointer(ctype) {
                     Ok(arg.map(|x| mk().call_expr(mk().ident_expr("Some"), vec![x])))
                 } else {
                     let pointee_ty =
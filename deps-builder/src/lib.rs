@@ -5,16 +5,62 @@ use std::io::BufReader;
 use std::option;
 use std::path::Path;
 
+/// How the linker treats a definition when others of the same name exist
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Binding {
+    /// A definition that must be unique
+    #[default]
+    Strong,
+    /// A definition of C's tentative definitions (`int x;`), which the
+    /// linker merges into one, unless there is a strong definition
+    Common,
+    /// A definition, e.g. `__attribute__((weak))`, that a strong or common
+    /// definition overrides; otherwise the first one is used
+    Weak,
+}
+
+impl Binding {
+    pub fn is_strong(&self) -> bool {
+        *self == Binding::Strong
+    }
+
+    /// Which definition the linker picks: the one that ranks highest
+    pub fn rank(self) -> u8 {
+        match self {
+            Binding::Strong => 2,
+            Binding::Common => 1,
+            Binding::Weak => 0,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct DependencySymbol {
     pub name: String,
     pub path: String,
+    /// The binding of a definition; references are always strong
+    #[serde(default, skip_serializing_if = "Binding::is_strong")]
+    pub binding: Binding,
+    /// For a definition by `__attribute__((alias))`, the symbol it aliases
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alias_of: Option<String>,
 }
 
 impl DependencySymbol {
+    /// A strong symbol
+    pub fn new(name: String, path: String) -> Self {
+        Self {
+            name,
+            path,
+            binding: Binding::Strong,
+            alias_of: None,
+        }
+    }
+
     pub fn depends_on(&self, other: &Self, fuzz_depends_level: usize) -> bool {
         match fuzz_depends_level {
-            0 => self == other,
+            0 => self.name == other.name && self.path == other.path,
             1 => {
                 self.name == other.name
                     && Path::new(&self.path).parent() == Path::new(&other.path).parent()
@@ -49,6 +95,26 @@ impl DependencyInfo {
     pub fn is_main(&self) -> bool {
         self.defined.iter().any(|s| s.name == "main")
     }
+
+    /// The file name of the translated module, or of the input for nodes
+    /// that are not translated, such as assembly files
+    pub fn label(&self) -> &str {
+        let path = match self.output_path.is_empty() {
+            true => &self.input_path,
+            false => &self.output_path,
+        };
+        Path::new(path)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or(path)
+    }
+
+    /// The file stem of the translated module, if there is one
+    pub fn output_stem(&self) -> Option<&str> {
+        Path::new(&self.output_path)
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+    }
 }
 
 #[derive(Debug)]
@@ -76,20 +142,83 @@ impl DependencyGraph {
         self.edges[from].push(to);
     }
 
-    pub fn build_dependency_edges(&mut self, fuzz_depends_level: usize) {
-        for (i, node) in self.nodes.iter().enumerate() {
-            for symbol in &node.undefined {
-                self.nodes.iter().enumerate().for_each(|(j, n)| {
-                    if !n.is_main()
-                        && n.defined
-                            .iter()
-                            .any(|s| s.depends_on(symbol, fuzz_depends_level))
-                    {
-                        self.edges[i].push(j);
+    /// The nodes whose definitions `symbol` resolves to, as the linker
+    /// picks them, and their binding: every strong definition, of which there
+    /// is one unless `fuzz_depends_level` matches those of several programs;
+    /// else the first common one, which the others are merged into; else the
+    /// first weak one.
+    fn resolve_with_binding(
+        &self,
+        symbol: &DependencySymbol,
+        fuzz_depends_level: usize,
+    ) -> Option<(Binding, Vec<usize>)> {
+        let mut found: Option<(Binding, Vec<usize>)> = None;
+        for (j, node) in self.nodes.iter().enumerate() {
+            if node.is_main() {
+                continue;
+            }
+            let binding = match node
+                .defined
+                .iter()
+                .filter(|s| s.depends_on(symbol, fuzz_depends_level))
+                .map(|s| s.binding)
+                .max_by_key(|binding| binding.rank())
+            {
+                Some(binding) => binding,
+                None => continue,
+            };
+            match &mut found {
+                Some((best, nodes)) if best.rank() == binding.rank() => {
+                    if binding.is_strong() {
+                        nodes.push(j);
                     }
-                });
+                }
+                Some((best, _)) if best.rank() > binding.rank() => {}
+                _ => found = Some((binding, vec![j])),
             }
         }
+        found
+    }
+
+    /// The nodes whose definitions `symbol` resolves to, as the linker
+    /// picks them (see [`Binding`])
+    pub fn resolve(&self, symbol: &DependencySymbol, fuzz_depends_level: usize) -> Vec<usize> {
+        self.resolve_with_binding(symbol, fuzz_depends_level)
+            .map(|(_, nodes)| nodes)
+            .unwrap_or_default()
+    }
+
+    pub fn build_dependency_edges(&mut self, fuzz_depends_level: usize) {
+        for i in 0..self.nodes.len() {
+            let targets = self.nodes[i]
+                .undefined
+                .iter()
+                .flat_map(|symbol| self.resolve(symbol, fuzz_depends_level))
+                .collect::<Vec<_>>();
+            for j in targets {
+                if !self.edges[i].contains(&j) {
+                    self.edges[i].push(j);
+                }
+            }
+        }
+    }
+
+    /// The names of the common and weak definitions of node `index` that
+    /// the linker resolves to the definition of another node instead.
+    pub fn overridden_definitions(&self, index: usize, fuzz_depends_level: usize) -> Vec<&str> {
+        let node = &self.nodes[index];
+        node.defined
+            .iter()
+            .filter(|s| !s.binding.is_strong())
+            .filter(|s| match self.resolve_with_binding(s, fuzz_depends_level) {
+                // Binaries are never picked, as other nodes cannot depend on
+                // them, so they keep a definition unless it ranks lower
+                Some((binding, _)) if node.is_main() => binding.rank() > s.binding.rank(),
+                Some((_, nodes)) => !nodes.contains(&index),
+                None => false,
+            })
+            .map(|s| s.name.as_str())
+            .collect()
     }
 
     pub fn get_node_index_with_input(
@@ -199,3 +328,140 @@ pub fn build_dependency(
 
     dependency_graph
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(input_path: &str, output_path: &str) -> DependencyInfo {
+        DependencyInfo {
+            input_path: input_path.to_string(),
+            output_path: output_path.to_string(),
+            object_path: None,
+            undefined: vec![],
+            defined: vec![],
+        }
+    }
+
+    #[test]
+    fn label() {
+        let node = info("/src/main.c", "/out/src/main.rs");
+        assert_eq!(node.label(), "main.rs");
+        assert_eq!(node.output_stem(), Some("main"));
+    }
+
+    #[test]
+    fn label_without_output() {
+        // Assembly files are not translated
+        let node = info("/src/start.S", "");
+        assert_eq!(node.label(), "start.S");
+        assert_eq!(node.output_stem(), None);
+    }
+
+    /// A node that defines `defined`, all declared in `symbols.h`
+    fn node(input_path: &str, defined: &[(&str, Binding)]) -> DependencyInfo {
+        let defined = defined
+            .iter()
+            .map(|&(name, binding)| DependencySymbol {
+                binding,
+                ..DependencySymbol::new(name.to_string(), "symbols.h".to_string())
+            })
+            .collect();
+        DependencyInfo {
+            defined,
+            ..info(input_path, &input_path.replace(".c", ".rs"))
+        }
+    }
+
+    fn graph(nodes: Vec<DependencyInfo>) -> DependencyGraph {
+        let mut graph = DependencyGraph::new();
+        for node in nodes {
+            graph.add_node(node);
+        }
+        graph
+    }
+
+    fn symbol(name: &str) -> DependencySymbol {
+        DependencySymbol::new(name.to_string(), "symbols.h".to_string())
+    }
+
+    #[test]
+    fn strong_beats_common() {
+        use Binding::*;
+        let graph = graph(vec![
+            node("a.c", &[("x", Common)]),
+            node("b.c", &[("x", Strong)]),
+        ]);
+        assert_eq!(
+            graph.resolve_with_binding(&symbol("x"), 0),
+            Some((Strong, vec![1]))
+        );
+        assert_eq!(graph.overridden_definitions(0, 0), vec!["x"]);
+        assert!(graph.overridden_definitions(1, 0).is_empty());
+    }
+
+    #[test]
+    fn common_beats_weak() {
+        use Binding::*;
+        let graph = graph(vec![
+            node("a.c", &[("x", Weak)]),
+            node("b.c", &[("x", Common)]),
+        ]);
+        assert_eq!(
+            graph.resolve_with_binding(&symbol("x"), 0),
+            Some((Common, vec![1]))
+        );
+        assert_eq!(graph.overridden_definitions(0, 0), vec!["x"]);
+        assert!(graph.overridden_definitions(1, 0).is_empty());
+    }
+
+    #[test]
+    fn first_of_several_commons() {
+        use Binding::*;
+        let graph = graph(vec![
+            node("a.c", &[("x", Weak)]),
+            node("b.c", &[("x", Common)]),
+            node("c.c", &[("x", Common)]),
+        ]);
+        // The linker merges the others into the first one
+        assert_eq!(
+            graph.resolve_with_binding(&symbol("x"), 0),
+            Some((Common, vec![1]))
+        );
+        assert_eq!(graph.overridden_definitions(0, 0), vec!["x"]);
+        assert!(graph.overridden_definitions(1, 0).is_empty());
+        assert_eq!(graph.overridden_definitions(2, 0), vec!["x"]);
+        assert_eq!(graph.resolve(&symbol("y"), 0), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn several_strong_definitions() {
+        use Binding::*;
+        // Several programs can each define a symbol
+        let graph = graph(vec![
+            node("a.c", &[("x", Strong)]),
+            node("b.c", &[("x", Weak)]),
+            node("c.c", &[("x", Strong)]),
+        ]);
+        assert_eq!(graph.resolve(&symbol("x"), 0), vec![0, 2]);
+        assert_eq!(graph.overridden_definitions(1, 0), vec!["x"]);
+    }
+
+    #[test]
+    fn binary_keeps_its_weak_definition() {
+        use Binding::*;
+        let graph = graph(vec![
+            node("main.c", &[("main", Strong), ("x", Weak), ("y", Weak)]),
+            node("a.c", &[("x", Weak)]),
+            node("b.c", &[("y", Strong)]),
+        ]);
+        // The binary is not picked, but the weak definition of another node
+        // does not override its own
+        assert_eq!(
+            graph.resolve_with_binding(&symbol("x"), 0),
+            Some((Weak, vec![1]))
+        );
+        assert_eq!(graph.overridden_definitions(0, 0), vec!["y"]);
+        assert!(graph.overridden_definitions(1, 0).is_empty());
+    }
+}
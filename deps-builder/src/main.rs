@@ -4,7 +4,7 @@ use dot_structures::*;
 use graphviz_rust::printer::{DotPrinter, PrinterContext};
 use std::fs::File;
 use std::io::Write;
-use std::path::{Path, PathBuf};
+use std::path::PathBuf;
 use std::process;
 
 use deps_builder::{build_dependency, read_dependencies};
@@ -63,14 +63,7 @@ fn main() {
     } else if !emit_binaries.is_empty() {
         for bin in emit_binaries {
             for (i, node) in dependency_infos.iter().enumerate() {
-                if PathBuf::from(&node.output_path)
-                    .file_stem()
-                    .unwrap()
-                    .to_str()
-                    .unwrap()
-                    == bin
-                    && node.defined.iter().any(|s| s.name == "main")
-                {
+                if node.output_stem() == Some(bin.as_str()) && node.is_main() {
                     bin_nodes.push(i);
                 }
             }
@@ -95,11 +88,11 @@ fn main() {
     for (i, node) in dependency_graph.nodes.iter().enumerate() {
         if let Some(_) = node.defined.iter().find(|s| s.name == "main") {
             dependency_dot_graph.add_stmt(Stmt::Node(
-                node!(i;attr!("color", "red"), attr!("label", (format!("\"{}\"", (Path::new(&node.output_path).file_name().unwrap().to_str().unwrap()))))),
+                node!(i;attr!("color", "red"), attr!("label", (format!("\"{}\"", node.label())))),
             ));
         } else {
             dependency_dot_graph.add_stmt(Stmt::Node(
-                node!(i;attr!("label", (format!("\"{}\"", (Path::new(&node.output_path).file_name().unwrap().to_str().unwrap()))))),
+                node!(i;attr!("label", (format!("\"{}\"", node.label())))),
             ));
         }
     }
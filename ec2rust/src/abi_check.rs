@@ -0,0 +1,594 @@
+//! `ec2rust abi-check`: compare the symbols that the original C objects of a
+//! library define with those of the Rust library built from its translation,
+//! and the C prototypes of its functions with their translated signatures.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context};
+use c2rust_transpile::abi::{AbiType, Prototype};
+use c2rust_transpile::{c_prototypes, export_for_transpile};
+use clap::{CommandFactory, ErrorKind, Parser};
+use log::warn;
+use object::read::archive::ArchiveFile;
+use object::{Object, ObjectKind, ObjectSymbol, SymbolKind};
+use serde::Serialize;
+use syn::{FnArg, Item, ReturnType, Signature, Type};
+
+use crate::check::symbol_name;
+use crate::verify::build_crate;
+use crate::{parse_args_with, process_args, Args};
+
+#[derive(Debug, Parser)]
+pub struct AbiCheckArgs {
+    #[clap(flatten)]
+    args: Args,
+
+    /// The built Rust library to compare (default: the staticlib or cdylib of the crate in its target directory)
+    #[clap(long, value_name = "FILE")]
+    artifact: Option<PathBuf>,
+
+    /// Compare the release build of the crate
+    #[clap(long)]
+    release: bool,
+
+    /// Do not run cargo build on the crate first
+    #[clap(long)]
+    no_build: bool,
+
+    /// Also write the report as JSON to FILE
+    #[clap(long, value_name = "FILE")]
+    abi_report: Option<PathBuf>,
+}
+
+/// Parse the command line of `ec2rust abi-check`, like [`crate::parse_args`].
+pub fn parse_abi_check_args() -> AbiCheckArgs {
+    let command = AbiCheckArgs::command()
+        .name("ec2rust-abi-check")
+        .about("Compare the symbols and signatures of the C library with those of its translation");
+    let args = parse_args_with(command, |abi_check: &mut AbiCheckArgs| &mut abi_check.args);
+    if args.args.output_dir.is_none() {
+        AbiCheckArgs::command()
+            .error(
+                ErrorKind::MissingRequiredArgument,
+                "ec2rust abi-check requires --output-dir, the directory of the translated crate",
+            )
+            .exit();
+    }
+    args
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SymbolType {
+    Function,
+    Data,
+    Other,
+}
+
+/// A global symbol that an object file defines
+#[derive(Debug, Clone, Serialize)]
+pub struct Symbol {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: SymbolType,
+    pub size: u64,
+    /// The object file, or the library member, that defines it
+    pub file: PathBuf,
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            SymbolType::Function => write!(f, "function"),
+            SymbolType::Data => write!(f, "data of {} bytes", self.size),
+            SymbolType::Other => write!(f, "other symbol"),
+        }
+    }
+}
+
+/// Whether `name` is defined by the Rust runtime rather than translated
+fn is_rust_internal(name: &str) -> bool {
+    let v0_mangled = name.strip_prefix("_R").map_or(false, |rest| {
+        rest.starts_with(|c: char| c.is_ascii_uppercase())
+    });
+    name.starts_with("_ZN")
+        || v0_mangled
+        || name.starts_with("__rust")
+        || name.starts_with("__rg_")
+        || name.starts_with("__rdl_")
+        || name.starts_with("__rde_")
+        || name.starts_with("rust_")
+}
+
+/// Add the global symbols that the object file `data` defines to `symbols`.
+/// Shared libraries are read through their dynamic symbol table.
+fn add_symbols(data: &[u8], file: &Path, symbols: &mut Vec<Symbol>) -> anyhow::Result<()> {
+    let object = object::File::parse(data).with_context(|| file.display().to_string())?;
+    let table = match object.kind() {
+        ObjectKind::Dynamic => object.dynamic_symbols(),
+        _ => object.symbols(),
+    };
+    for symbol in table {
+        // Common symbols, the tentative definitions of `-fcommon`, are data
+        // whose size is that of the symbol
+        let defined =
+            symbol.is_definition() || symbol.is_common() || symbol.kind() == SymbolKind::Tls;
+        if !defined || symbol.is_undefined() || !symbol.is_global() {
+            continue;
+        }
+        let kind = match symbol.kind() {
+            _ if symbol.is_common() => SymbolType::Data,
+            SymbolKind::Text => SymbolType::Function,
+            SymbolKind::Data | SymbolKind::Tls => SymbolType::Data,
+            _ => SymbolType::Other,
+        };
+        symbols.push(Symbol {
+            name: symbol.name()?.to_owned(),
+            kind,
+            size: symbol.size(),
+            file: file.to_owned(),
+        });
+    }
+    Ok(())
+}
+
+/// The global symbols that the object file, archive or shared library at
+/// `path` defines. In an archive, only the members whose names start with
+/// `member_prefix`, if given, are read: the staticlib of a crate also holds
+/// the standard library and the C parts of `compiler_builtins`.
+fn read_symbols(path: &Path, member_prefix: Option<&str>) -> anyhow::Result<Vec<Symbol>> {
+    let data = fs::read(path).with_context(|| path.display().to_string())?;
+    let mut symbols = vec![];
+    match ArchiveFile::parse(&*data) {
+        Ok(archive) => {
+            for member in archive.members() {
+                let member = member?;
+                let name = String::from_utf8_lossy(member.name());
+                if !member_prefix.map_or(true, |prefix| name.starts_with(prefix)) {
+                    continue;
+                }
+                let file = path.join(&*name);
+                add_symbols(member.data(&*data)?, &file, &mut symbols)?;
+            }
+        }
+        Err(_) => add_symbols(&data, path, &mut symbols)?,
+    }
+    symbols.retain(|symbol| !is_rust_internal(&symbol.name));
+    Ok(symbols)
+}
+
+/// The name of the library target of the crate in `crate_dir`
+fn library_name(crate_dir: &Path) -> anyhow::Result<String> {
+    let path = crate_dir.join("Cargo.toml");
+    let manifest = fs::read_to_string(&path).with_context(|| path.display().to_string())?;
+    let manifest = manifest
+        .parse::<toml::Value>()
+        .with_context(|| path.display().to_string())?;
+    let name = manifest
+        .get("lib")
+        .and_then(|lib| lib.get("name"))
+        .or_else(|| manifest.get("package")?.get("name"))
+        .and_then(toml::Value::as_str)
+        .ok_or_else(|| anyhow!("{} names no library", path.display()))?;
+    Ok(name.replace('-', "_"))
+}
+
+/// The staticlib or cdylib of the library `name`, in `target_dir`
+fn find_artifact(name: &str, target_dir: &Path) -> anyhow::Result<PathBuf> {
+    for file_name in [format!("lib{}.a", name), format!("lib{}.so", name)] {
+        let path = target_dir.join(file_name);
+        if path.exists() {
+            return Ok(path);
+        }
+    }
+    bail!(
+        "neither lib{0}.a nor lib{0}.so is in {1}; is the crate a staticlib or cdylib?",
+        name,
+        target_dir.display()
+    )
+}
+
+/// The exported functions of a translated crate, with the type aliases and
+/// records needed to classify their parameter types
+#[derive(Debug, Default)]
+struct RustCrate {
+    functions: BTreeMap<String, (PathBuf, Signature)>,
+    aliases: HashMap<String, Type>,
+    records: HashSet<String>,
+}
+
+impl RustCrate {
+    /// Read the Rust files in `dir`, except for those of the build output
+    /// and of the differential tests.
+    fn read(dir: &Path) -> anyhow::Result<Self> {
+        let mut krate = Self::default();
+        krate.read_dir(dir)?;
+        Ok(krate)
+    }
+
+    fn read_dir(&mut self, dir: &Path) -> anyhow::Result<()> {
+        for entry in fs::read_dir(dir).with_context(|| dir.display().to_string())? {
+            let path = entry?.path();
+            let name = path.file_name().unwrap().to_string_lossy();
+            if path.is_dir() {
+                if !matches!(&*name, "target" | "differential") && !name.starts_with('.') {
+                    self.read_dir(&path)?;
+                }
+            } else if path.extension().map_or(false, |ext| ext == "rs") {
+                let source = fs::read_to_string(&path)?;
+                match syn::parse_file(&source) {
+                    Ok(file) => self.add_items(&file.items, &path),
+                    Err(e) => warn!("Could not parse {}: {}", path.display(), e),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn add_items(&mut self, items: &[Item], file: &Path) {
+        for item in items {
+            match item {
+                Item::Fn(f) => {
+                    let exported = f.attrs.iter().any(|attr| {
+                        attr.path.is_ident("no_mangle") || attr.path.is_ident("export_name")
+                    });
+                    if exported {
+                        let name = symbol_name(&f.attrs, &f.sig.ident);
+                        self.functions
+                            .insert(name, (file.to_owned(), f.sig.clone()));
+                    }
+                }
+                Item::Type(t) => {
+                    self.aliases.insert(t.ident.to_string(), (*t.ty).clone());
+                }
+                Item::Struct(s) => {
+                    self.records.insert(s.ident.to_string());
+                }
+                Item::Union(u) => {
+                    self.records.insert(u.ident.to_string());
+                }
+                Item::Mod(m) => {
+                    if let Some((_, items)) = &m.content {
+                        self.add_items(items, file);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// How a value of type `ty` is passed, like [`AbiType`]s of C types
+    fn abi_type(&self, ty: &Type, depth: usize) -> AbiType {
+        match ty {
+            Type::Ptr(_) | Type::Reference(_) | Type::BareFn(_) => AbiType::Pointer,
+            Type::Tuple(t) if t.elems.is_empty() => AbiType::Void,
+            Type::Never(_) => AbiType::Void,
+            Type::Paren(t) => self.abi_type(&t.elem, depth),
+            Type::Group(t) => self.abi_type(&t.elem, depth),
+            Type::Path(p) => {
+                let name = match p.path.segments.last() {
+                    Some(segment) => segment.ident.to_string(),
+                    None => return AbiType::Other,
+                };
+                match &*name {
+                    // Function pointers
+                    "Option" => AbiType::Pointer,
+                    "c_void" => AbiType::Void,
+                    "bool" | "i8" | "u8" | "c_char" | "c_schar" | "c_uchar" => AbiType::Integer(1),
+                    "i16" | "u16" | "c_short" | "c_ushort" => AbiType::Integer(2),
+                    "i32" | "u32" | "c_int" | "c_uint" => AbiType::Integer(4),
+                    "i64" | "u64" | "isize" | "usize" | "c_long" | "c_ulong" | "c_longlong"
+                    | "c_ulonglong" => AbiType::Integer(8),
+                    "i128" | "u128" => AbiType::Integer(16),
+                    "f32" | "c_float" => AbiType::Float(4),
+                    "f64" | "c_double" => AbiType::Float(8),
+                    _ => match self.aliases.get(&name) {
+                        // Aliases of aliases, but not cycles of them
+                        Some(ty) if depth < 16 => self.abi_type(ty, depth + 1),
+                        _ if self.records.contains(&name) => AbiType::Record,
+                        _ => AbiType::Other,
+                    },
+                }
+            }
+            _ => AbiType::Other,
+        }
+    }
+
+    /// The signature of `name` in the terms of a C prototype
+    fn prototype(&self, name: &str) -> Option<Prototype> {
+        let (file, sig) = self.functions.get(name)?;
+        let ret = match &sig.output {
+            ReturnType::Default => AbiType::Void,
+            ReturnType::Type(_, ty) => self.abi_type(ty, 0),
+        };
+        let params = sig
+            .inputs
+            .iter()
+            .filter_map(|arg| match arg {
+                FnArg::Typed(arg) => Some(self.abi_type(&arg.ty, 0)),
+                FnArg::Receiver(_) => None,
+            })
+            .collect();
+        Some(Prototype {
+            name: name.to_owned(),
+            input: file.clone(),
+            ret,
+            params,
+            variadic: sig.variadic.is_some(),
+        })
+    }
+}
+
+/// Whether a function called through `c` can call `rust`
+fn same_signature(c: &Prototype, rust: &Prototype) -> bool {
+    c.ret.matches(rust.ret)
+        && c.variadic == rust.variadic
+        && c.params.len() == rust.params.len()
+        && c.params
+            .iter()
+            .zip(&rust.params)
+            .all(|(c, rust)| c.matches(*rust))
+}
+
+#[derive(Debug, Serialize)]
+pub struct SymbolChange {
+    pub c: Symbol,
+    pub rust: Symbol,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SignatureChange {
+    pub c: Prototype,
+    pub rust: Prototype,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AbiReport {
+    /// The Rust library that was compared
+    pub artifact: PathBuf,
+    /// The number of symbols that the C objects define
+    pub symbols: usize,
+    /// Symbols that the C objects define and the Rust library does not
+    pub missing: Vec<Symbol>,
+    /// Symbols that the Rust library defines and the C objects do not
+    pub extra: Vec<Symbol>,
+    /// Symbols of another type, or data symbols of another size
+    pub changed: Vec<SymbolChange>,
+    /// Functions whose translated signature does not match the C prototype
+    pub signatures: Vec<SignatureChange>,
+}
+
+impl AbiReport {
+    fn new(
+        artifact: PathBuf,
+        c_symbols: Vec<Symbol>,
+        rust_symbols: Vec<Symbol>,
+        prototypes: &[Prototype],
+        krate: &RustCrate,
+    ) -> Self {
+        // Only the first definition of a symbol counts, as for the linker
+        let by_name = |symbols: Vec<Symbol>| {
+            let mut map = BTreeMap::new();
+            for symbol in symbols {
+                map.entry(symbol.name.clone()).or_insert(symbol);
+            }
+            map
+        };
+        let c_symbols = by_name(c_symbols);
+        let mut rust_symbols = by_name(rust_symbols);
+        let mut report = AbiReport {
+            artifact,
+            symbols: c_symbols.len(),
+            missing: vec![],
+            extra: vec![],
+            changed: vec![],
+            signatures: vec![],
+        };
+        for (name, c) in c_symbols {
+            match rust_symbols.remove(&name) {
+                None => report.missing.push(c),
+                Some(rust) => {
+                    let resized = c.kind == SymbolType::Data && c.size != rust.size;
+                    if c.kind != rust.kind || resized {
+                        report.changed.push(SymbolChange { c, rust });
+                    }
+                }
+            }
+        }
+        report.extra = rust_symbols.into_values().collect();
+
+        for c in prototypes {
+            if let Some(rust) = krate.prototype(&c.name) {
+                if !same_signature(c, &rust) {
+                    report
+                        .signatures
+                        .push(SignatureChange { c: c.clone(), rust });
+                }
+            }
+        }
+        report
+    }
+
+    pub fn passed(&self) -> bool {
+        self.missing.is_empty()
+            && self.extra.is_empty()
+            && self.changed.is_empty()
+            && self.signatures.is_empty()
+    }
+}
+
+impl fmt::Display for AbiReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !self.missing.is_empty() {
+            writeln!(f, "Missing from {}:", self.artifact.display())?;
+            for symbol in &self.missing {
+                let file = symbol.file.display();
+                writeln!(f, "  {} ({}, in {})", symbol.name, symbol, file)?;
+            }
+        }
+        if !self.extra.is_empty() {
+            writeln!(f, "Not defined by the C objects:")?;
+            for symbol in &self.extra {
+                writeln!(f, "  {} ({})", symbol.name, symbol)?;
+            }
+        }
+        if !self.changed.is_empty() {
+            writeln!(f, "Changed:")?;
+            for change in &self.changed {
+                let name = &change.c.name;
+                writeln!(f, "  {}: {} in C, {} in Rust", name, change.c, change.rust)?;
+            }
+        }
+        if !self.signatures.is_empty() {
+            writeln!(f, "Signatures that differ from the C prototype:")?;
+            for change in &self.signatures {
+                writeln!(f, "  {} ({})", change.c.name, change.c.input.display())?;
+                writeln!(f, "    C:    {}", change.c)?;
+                writeln!(f, "    Rust: {}", change.rust)?;
+            }
+        }
+        writeln!(
+            f,
+            "{} C symbols: {} missing, {} extra, {} changed; {} signatures differ",
+            self.symbols,
+            self.missing.len(),
+            self.extra.len(),
+            self.changed.len(),
+            self.signatures.len()
+        )
+    }
+}
+
+/// Compare the objects of the files that the dependency information lists
+/// without a `main` with the built Rust library.
+pub fn abi_check(args: AbiCheckArgs) -> anyhow::Result<AbiReport> {
+    let AbiCheckArgs {
+        args,
+        artifact,
+        release,
+        no_build,
+        abi_report,
+    } = args;
+    let (tcfg, cc_json_path, extra_args) = process_args(args)?;
+    let extra_args = extra_args.iter().map(AsRef::as_ref).collect::<Vec<_>>();
+    let crate_dir = tcfg.output_dir.clone().unwrap();
+
+    let exported = export_for_transpile(&tcfg, &cc_json_path, &extra_args)?;
+    let prototypes = c_prototypes(&tcfg, &cc_json_path, &extra_args, &exported)?;
+    let mut c_symbols = vec![];
+    for dependency in exported.dependencies.iter().filter(|dep| !dep.is_main()) {
+        match &dependency.object_path {
+            Some(object) if Path::new(object).exists() => {
+                c_symbols.extend(read_symbols(Path::new(object), None)?);
+            }
+            _ => warn!(
+                "The object file of {} does not exist; is the C library built?",
+                dependency.input_path
+            ),
+        }
+    }
+
+    let target_dir = build_crate(&crate_dir, "--lib", release, !no_build)?;
+    let name = library_name(&crate_dir)?;
+    let artifact = match artifact {
+        Some(artifact) => artifact,
+        None => find_artifact(&name, &target_dir)?,
+    };
+    // The object files of the crate are named `<name>-<hash>.<cgu>.rcgu.o`
+    let rust_symbols = read_symbols(&artifact, Some(&format!("{}-", name)))?;
+    let krate = RustCrate::read(&crate_dir)?;
+
+    let report = AbiReport::new(artifact, c_symbols, rust_symbols, &prototypes, &krate);
+    if let Some(path) = abi_report {
+        let json = serde_json::to_string_pretty(&report).unwrap();
+        fs::write(&path, json).with_context(|| path.display().to_string())?;
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prototype(ret: AbiType, params: &[AbiType], variadic: bool) -> Prototype {
+        Prototype {
+            name: "f".into(),
+            input: PathBuf::from("f.c"),
+            ret,
+            params: params.to_vec(),
+            variadic,
+        }
+    }
+
+    #[test]
+    fn same_signatures() {
+        use AbiType::*;
+        let c = prototype(Integer(4), &[Pointer, Integer(8)], false);
+        assert!(same_signature(&c, &c.clone()));
+        // Types that are not compared match any type
+        let rust = prototype(Other, &[Pointer, Other], false);
+        assert!(same_signature(&c, &rust));
+
+        let rust = prototype(Integer(8), &[Pointer, Integer(8)], false);
+        assert!(!same_signature(&c, &rust));
+        let rust = prototype(Integer(4), &[Pointer, Float(8)], false);
+        assert!(!same_signature(&c, &rust));
+        let rust = prototype(Integer(4), &[Pointer], false);
+        assert!(!same_signature(&c, &rust));
+        let rust = prototype(Integer(4), &[Pointer, Integer(8)], true);
+        assert!(!same_signature(&c, &rust));
+    }
+
+    fn krate(source: &str) -> RustCrate {
+        let mut krate = RustCrate::default();
+        let file = syn::parse_file(source).unwrap();
+        krate.add_items(&file.items, Path::new("lib.rs"));
+        krate
+    }
+
+    fn abi_type(krate: &RustCrate, ty: &str) -> AbiType {
+        krate.abi_type(&syn::parse_str(ty).unwrap(), 0)
+    }
+
+    #[test]
+    fn abi_type_of_aliases() {
+        let krate = krate(
+            "
+            pub type size_t = ulong;
+            pub type ulong = libc::c_ulong;
+            pub type callback = Option<unsafe extern \"C\" fn()>;
+            pub type node_t = node;
+            pub struct node { next: *mut node }
+            pub mod m { pub type inner = super::size_t; }
+            pub type a = b;
+            pub type b = a;
+            ",
+        );
+        assert_eq!(abi_type(&krate, "size_t"), AbiType::Integer(8));
+        assert_eq!(abi_type(&krate, "crate::m::inner"), AbiType::Integer(8));
+        assert_eq!(abi_type(&krate, "callback"), AbiType::Pointer);
+        assert_eq!(abi_type(&krate, "node_t"), AbiType::Record);
+        assert_eq!(abi_type(&krate, "(size_t)"), AbiType::Integer(8));
+        // A cycle of aliases is not compared
+        assert_eq!(abi_type(&krate, "a"), AbiType::Other);
+        assert_eq!(abi_type(&krate, "unknown_t"), AbiType::Other);
+    }
+
+    #[test]
+    fn prototype_of_exported_function() {
+        let krate = krate(
+            "
+            pub type len_t = libc::c_int;
+            #[no_mangle]
+            pub unsafe extern \"C\" fn f(p: *mut u8, n: len_t) -> f64 { 0.0 }
+            unsafe extern \"C\" fn g() {}
+            ",
+        );
+        let f = krate.prototype("f").unwrap();
+        assert_eq!(f.to_string(), "fn(pointer, int32) -> float64");
+        assert!(krate.prototype("g").is_none());
+    }
+}
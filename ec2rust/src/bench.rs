@@ -0,0 +1,513 @@
+//! `ec2rust bench`: time every invocation of the C and the Rust builds of the
+//! binaries during the test suite of the original build tree. Each binary is
+//! replaced by a wrapper that runs it through `ec2rust-bench --record`, which
+//! appends its wall and CPU time to a log. The totals of several runs of the
+//! test suite are summarized like hyperfine does.
+
+use std::ffi::OsString;
+use std::fmt::Write as _;
+use std::fs::{self, OpenOptions};
+use std::io::Write as _;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+use std::time::Instant;
+
+use anyhow::{bail, Context};
+use clap::Parser;
+use serde::Serialize;
+
+use crate::make_check::{print_restore_hint, BuildTreeArgs, Installed, Replacement};
+
+#[derive(Debug, Parser)]
+#[clap(
+    name = "ec2rust-bench",
+    about = "Time each invocation of the C and Rust binaries during the test suite of the original build tree"
+)]
+pub struct BenchArgs {
+    #[clap(flatten)]
+    pub tree: BuildTreeArgs,
+
+    /// Number of runs of the test suite with each version of the binaries
+    #[clap(short = 'r', long, default_value_t = 10)]
+    runs: usize,
+
+    /// Directory for the invocation logs (default: target/ec2rust-bench in the crate)
+    #[clap(long, value_name = "DIR")]
+    log_dir: Option<PathBuf>,
+
+    /// Export the results as CSV to FILE
+    #[clap(long, value_name = "FILE")]
+    export_csv: Option<PathBuf>,
+
+    /// Export the results as JSON to FILE
+    #[clap(long, value_name = "FILE")]
+    export_json: Option<PathBuf>,
+
+    /// Export the results as a Markdown table to FILE
+    #[clap(long, value_name = "FILE")]
+    export_markdown: Option<PathBuf>,
+}
+
+/// The flag of `ec2rust-bench` to run a binary and record its times, as the
+/// wrappers do: `--record LOG BINARY ARG0 ARGS...`.
+pub const RECORD_FLAG: &str = "--record";
+
+/// CPU time of the terminated children of this process, in nanoseconds
+fn children_cpu_time() -> (u128, u128) {
+    let mut usage = std::mem::MaybeUninit::<libc::rusage>::zeroed();
+    // SAFETY: `getrusage` only writes to the `rusage` it is given
+    let usage = unsafe {
+        libc::getrusage(libc::RUSAGE_CHILDREN, usage.as_mut_ptr());
+        usage.assume_init()
+    };
+    let nanos = |t: libc::timeval| t.tv_sec as u128 * 1_000_000_000 + t.tv_usec as u128 * 1000;
+    (nanos(usage.ru_utime), nanos(usage.ru_stime))
+}
+
+/// Run `binary` with `args`, append `wall user system` in nanoseconds to
+/// `log`, and exit like the binary did. This is what the wrappers run.
+pub fn record(log: &Path, binary: &Path, arg0: OsString, args: Vec<OsString>) -> ! {
+    let start = Instant::now();
+    let status = Command::new(binary).arg0(arg0).args(args).status();
+    let wall = start.elapsed().as_nanos();
+    let status = match status {
+        Ok(status) => status,
+        Err(e) => {
+            eprintln!("ec2rust-bench: could not run {}: {}", binary.display(), e);
+            process::exit(127);
+        }
+    };
+    let (user, system) = children_cpu_time();
+    let line = format!("{} {} {}\n", wall, user, system);
+    // Single appends of a line are atomic, so parallel tests can share a log
+    let written = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log)
+        .and_then(|mut file| file.write_all(line.as_bytes()));
+    if let Err(e) = written {
+        eprintln!("ec2rust-bench: could not write {}: {}", log.display(), e);
+    }
+    if let Some(signal) = status.signal() {
+        // SAFETY: terminate like the binary did, with the default action
+        unsafe {
+            libc::signal(signal, libc::SIG_DFL);
+            libc::raise(signal);
+        }
+    }
+    process::exit(status.code().unwrap_or(1))
+}
+
+/// The two versions of each binary
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Version {
+    C,
+    Rust,
+}
+
+impl Version {
+    fn name(self) -> &'static str {
+        match self {
+            Version::C => "C",
+            Version::Rust => "Rust",
+        }
+    }
+}
+
+/// Write a wrapper script at the place of the C executable of `replacement`
+/// that records the times of `binary` in `log`
+fn write_wrapper(
+    replacement: &Replacement,
+    binary: &Path,
+    log: &Path,
+    recorder: &Path,
+) -> anyhow::Result<()> {
+    let quote = |path: &Path| shlex::quote(&path.to_string_lossy()).into_owned();
+    let script = format!(
+        "#!/bin/sh\nexec {} {} {} {} \"$0\" \"$@\"\n",
+        quote(recorder),
+        RECORD_FLAG,
+        quote(log),
+        quote(binary)
+    );
+    fs::write(&replacement.c, script)?;
+    fs::set_permissions(&replacement.c, fs::Permissions::from_mode(0o755))?;
+    Ok(())
+}
+
+/// The times of the invocations of one binary in one run
+#[derive(Debug, Default, Clone, Copy)]
+struct Totals {
+    wall: u128,
+    user: u128,
+    system: u128,
+}
+
+impl Totals {
+    fn read(log: &Path) -> anyhow::Result<Self> {
+        let mut totals = Self::default();
+        let contents = match fs::read_to_string(log) {
+            Ok(contents) => contents,
+            // Not invoked in this run
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(totals),
+            Err(e) => return Err(e.into()),
+        };
+        for line in contents.lines() {
+            let fields = line
+                .split_whitespace()
+                .map(str::parse::<u128>)
+                .collect::<Result<Vec<_>, _>>()
+                .with_context(|| format!("invalid line in {}: {:?}", log.display(), line))?;
+            if let [wall, user, system] = fields[..] {
+                totals.wall += wall;
+                totals.user += user;
+                totals.system += system;
+            }
+        }
+        Ok(totals)
+    }
+}
+
+/// The summary of one version of a binary over all runs, with the fields of
+/// hyperfine's exports. Times are in seconds.
+#[derive(Debug, Serialize)]
+pub struct BenchResult {
+    pub command: String,
+    pub mean: f64,
+    pub stddev: Option<f64>,
+    pub median: f64,
+    pub user: f64,
+    pub system: f64,
+    pub min: f64,
+    pub max: f64,
+    /// The total wall time of each run
+    pub times: Vec<f64>,
+    #[serde(skip)]
+    binary: String,
+}
+
+fn seconds(nanos: u128) -> f64 {
+    nanos as f64 / 1e9
+}
+
+impl BenchResult {
+    fn new(binary: &str, version: Version, runs: &[Totals]) -> Self {
+        let times = runs.iter().map(|t| seconds(t.wall)).collect::<Vec<_>>();
+        let n = times.len() as f64;
+        let mean = times.iter().sum::<f64>() / n;
+        let stddev = (times.len() > 1).then(|| {
+            let variance = times.iter().map(|t| (t - mean).powi(2)).sum::<f64>() / (n - 1.0);
+            variance.sqrt()
+        });
+        let mut sorted = times.clone();
+        sorted.sort_by(f64::total_cmp);
+        let middle = sorted.len() / 2;
+        let median = match sorted.len() % 2 {
+            0 => (sorted[middle - 1] + sorted[middle]) / 2.0,
+            _ => sorted[middle],
+        };
+        Self {
+            command: format!("{} ({})", binary, version.name()),
+            mean,
+            stddev,
+            median,
+            user: runs.iter().map(|t| seconds(t.user)).sum::<f64>() / n,
+            system: runs.iter().map(|t| seconds(t.system)).sum::<f64>() / n,
+            min: sorted[0],
+            max: sorted[sorted.len() - 1],
+            times,
+            binary: binary.to_owned(),
+        }
+    }
+}
+
+/// The results of all binaries, the C version of each before the Rust one
+#[derive(Debug, Serialize)]
+pub struct BenchReport {
+    pub results: Vec<BenchResult>,
+    /// Binaries that only the test suite with the C version invoked, which
+    /// cannot be compared
+    pub c_only: Vec<String>,
+    /// Binaries that only the test suite with the Rust version invoked
+    pub rust_only: Vec<String>,
+}
+
+impl BenchReport {
+    /// Read the logs of `runs` runs in `log_dir`. Binaries that were never
+    /// invoked are left out, and those invoked with one version only are
+    /// listed apart.
+    fn read(log_dir: &Path, runs: usize, binaries: &[String]) -> anyhow::Result<Self> {
+        let mut report = Self {
+            results: vec![],
+            c_only: vec![],
+            rust_only: vec![],
+        };
+        for binary in binaries {
+            let mut versions = vec![];
+            for version in [Version::C, Version::Rust] {
+                let totals = (0..runs)
+                    .map(|run| Totals::read(&log_path(log_dir, run, version, binary)))
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                versions.push((version, totals));
+            }
+            let invoked = |i: usize| versions[i].1.iter().any(|t| t.wall > 0);
+            match (invoked(0), invoked(1)) {
+                (true, true) => {
+                    for (version, totals) in versions {
+                        report
+                            .results
+                            .push(BenchResult::new(binary, version, &totals));
+                    }
+                }
+                (true, false) => report.c_only.push(binary.clone()),
+                (false, true) => report.rust_only.push(binary.clone()),
+                (false, false) => {}
+            }
+        }
+        Ok(report)
+    }
+
+    /// The mean of each result relative to the faster version of its binary,
+    /// with its standard deviation, as hyperfine computes it
+    fn relative(&self, result: &BenchResult) -> (f64, Option<f64>) {
+        let fastest = self
+            .results
+            .iter()
+            .filter(|r| r.binary == result.binary)
+            .min_by(|a, b| a.mean.total_cmp(&b.mean))
+            .unwrap();
+        let ratio = result.mean / fastest.mean;
+        let stddev = result.stddev.zip(fastest.stddev).map(|(sd, fastest_sd)| {
+            ratio * ((sd / result.mean).powi(2) + (fastest_sd / fastest.mean).powi(2)).sqrt()
+        });
+        (ratio, stddev)
+    }
+
+    pub fn to_csv(&self) -> String {
+        let mut csv = "command,mean,stddev,median,user,system,min,max\n".to_owned();
+        for r in &self.results {
+            let stddev = r.stddev.map(|sd| sd.to_string()).unwrap_or_default();
+            writeln!(
+                csv,
+                "{},{},{},{},{},{},{},{}",
+                r.command, r.mean, stddev, r.median, r.user, r.system, r.min, r.max
+            )
+            .unwrap();
+        }
+        csv
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let mut md = "| Command | Mean [s] | Min [s] | Max [s] | Relative |\n".to_owned();
+        md.push_str("|:---|---:|---:|---:|---:|\n");
+        for r in &self.results {
+            let mean = match r.stddev {
+                Some(sd) => format!("{:.3} ± {:.3}", r.mean, sd),
+                None => format!("{:.3}", r.mean),
+            };
+            let relative = match self.relative(r) {
+                (ratio, Some(sd)) if ratio != 1.0 => format!("{:.2} ± {:.2}", ratio, sd),
+                (ratio, _) => format!("{:.2}", ratio),
+            };
+            writeln!(
+                md,
+                "| `{}` | {} | {:.3} | {:.3} | {} |",
+                r.command, mean, r.min, r.max, relative
+            )
+            .unwrap();
+        }
+        for (binaries, version) in [(&self.c_only, Version::C), (&self.rust_only, Version::Rust)] {
+            if !binaries.is_empty() {
+                writeln!(
+                    md,
+                    "\nOnly invoked with the {} version: {}",
+                    version.name(),
+                    binaries.join(", ")
+                )
+                .unwrap();
+            }
+        }
+        md
+    }
+}
+
+fn log_path(log_dir: &Path, run: usize, version: Version, binary: &str) -> PathBuf {
+    log_dir
+        .join(run.to_string())
+        .join(version.name())
+        .join(format!("{}.log", binary))
+}
+
+fn binary_name(replacement: &Replacement) -> String {
+    replacement
+        .c
+        .file_name()
+        .unwrap()
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Run the test suite `runs` times with each version of the binaries behind
+/// timing wrappers, then summarize the logs and export them.
+pub fn bench(args: BenchArgs) -> anyhow::Result<BenchReport> {
+    if args.runs == 0 {
+        bail!("--runs must be at least 1");
+    }
+    let tree = &args.tree;
+    let replacements = tree.replacements()?;
+    let recorder = std::env::current_exe()?;
+    let log_dir = args
+        .log_dir
+        .clone()
+        .unwrap_or_else(|| tree.crate_dir.join("target").join("ec2rust-bench"));
+    // Logs of an earlier benchmark would add up with this one
+    if log_dir.exists() {
+        fs::remove_dir_all(&log_dir)?;
+    }
+    let binaries = replacements.iter().map(binary_name).collect::<Vec<_>>();
+
+    print_restore_hint("bench");
+    for run in 0..args.runs {
+        // Alternate the versions, so that both see the same drift in load
+        for version in [Version::C, Version::Rust] {
+            let dir = log_dir.join(run.to_string()).join(version.name());
+            fs::create_dir_all(&dir)?;
+            eprintln!(
+                "Run {}/{}: make {} with the {} binaries",
+                run + 1,
+                args.runs,
+                tree.target,
+                version.name()
+            );
+            let _installed = Installed::install_with(&replacements, |replacement| {
+                let binary = match version {
+                    Version::C => replacement.backup(),
+                    Version::Rust => replacement.rust.clone(),
+                };
+                let log = dir.join(format!("{}.log", binary_name(replacement)));
+                write_wrapper(replacement, &binary, &log, &recorder)
+            })?;
+            // Failing tests still time the binaries
+            tree.run_make(&dir.join("make.log"))?;
+        }
+    }
+
+    let report = BenchReport::read(&log_dir, args.runs, &binaries)?;
+    if let Some(path) = &args.export_csv {
+        fs::write(path, report.to_csv())?;
+    }
+    if let Some(path) = &args.export_json {
+        fs::write(path, serde_json::to_string_pretty(&report)?)?;
+    }
+    if let Some(path) = &args.export_markdown {
+        fs::write(path, report.to_markdown())?;
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn totals(walls: &[u128]) -> Vec<Totals> {
+        walls
+            .iter()
+            .map(|&wall| Totals {
+                wall: wall * 1_000_000_000,
+                user: wall * 500_000_000,
+                system: 0,
+            })
+            .collect()
+    }
+
+    fn write_log(log_dir: &Path, run: usize, version: Version, binary: &str, lines: &str) {
+        let path = log_path(log_dir, run, version, binary);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, lines).unwrap();
+    }
+
+    #[test]
+    fn result_statistics() {
+        let result = BenchResult::new("cat", Version::Rust, &totals(&[4, 1, 3, 2]));
+        assert_eq!(result.command, "cat (Rust)");
+        assert_eq!(result.mean, 2.5);
+        assert_eq!(result.median, 2.5);
+        // The sample standard deviation
+        assert!((result.stddev.unwrap() - (5.0f64 / 3.0).sqrt()).abs() < 1e-12);
+        assert_eq!((result.min, result.max), (1.0, 4.0));
+        assert_eq!(result.user, 1.25);
+        assert_eq!(result.times, [4.0, 1.0, 3.0, 2.0]);
+
+        let result = BenchResult::new("cat", Version::C, &totals(&[3, 9, 6]));
+        assert_eq!(result.median, 6.0);
+        assert_eq!(result.mean, 6.0);
+        assert_eq!(result.stddev, Some(3.0));
+
+        let result = BenchResult::new("cat", Version::C, &totals(&[2]));
+        assert_eq!(result.stddev, None);
+    }
+
+    #[test]
+    fn read_totals() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("cat.log");
+        fs::write(&log, "100 20 3\n200 40 6\n\n").unwrap();
+        let totals = Totals::read(&log).unwrap();
+        assert_eq!((totals.wall, totals.user, totals.system), (300, 60, 9));
+
+        // Not invoked
+        let totals = Totals::read(&dir.path().join("ls.log")).unwrap();
+        assert_eq!((totals.wall, totals.user, totals.system), (0, 0, 0));
+
+        fs::write(&log, "100 20 x\n").unwrap();
+        let error = Totals::read(&log).unwrap_err();
+        assert!(format!("{:#}", error).contains("invalid line"));
+    }
+
+    #[test]
+    fn report_of_one_sided_binaries() {
+        let dir = tempfile::tempdir().unwrap();
+        for run in 0..2 {
+            write_log(dir.path(), run, Version::C, "cat", "2000000000 0 0\n");
+            write_log(dir.path(), run, Version::Rust, "cat", "1000000000 0 0\n");
+            write_log(dir.path(), run, Version::C, "ls", "1000000000 0 0\n");
+            write_log(dir.path(), run, Version::Rust, "ls", "");
+        }
+        write_log(dir.path(), 1, Version::Rust, "rm", "1000 0 0\n");
+        let binaries = ["cat", "ls", "rm", "cp"].map(str::to_owned);
+        let report = BenchReport::read(dir.path(), 2, &binaries).unwrap();
+
+        let commands = report.results.iter().map(|r| r.command.as_str());
+        assert_eq!(commands.collect::<Vec<_>>(), ["cat (C)", "cat (Rust)"]);
+        assert_eq!(report.c_only, ["ls"]);
+        assert_eq!(report.rust_only, ["rm"]);
+        assert_eq!(report.relative(&report.results[0]), (2.0, Some(0.0)));
+
+        let md = report.to_markdown();
+        assert!(md.contains("| `cat (C)` | 2.000 ± 0.000 | 2.000 | 2.000 | 2.00 ± 0.00 |\n"));
+        assert!(md.contains("| `cat (Rust)` | 1.000 ± 0.000 | 1.000 | 1.000 | 1.00 |\n"));
+        assert!(md.contains("\nOnly invoked with the C version: ls\n"));
+        assert!(md.contains("\nOnly invoked with the Rust version: rm\n"));
+        assert!(!md.contains("NaN") && !md.contains("inf"));
+    }
+
+    #[test]
+    fn csv() {
+        let report = BenchReport {
+            results: vec![
+                BenchResult::new("cat", Version::C, &totals(&[1, 3])),
+                BenchResult::new("cat", Version::Rust, &totals(&[2])),
+            ],
+            c_only: vec![],
+            rust_only: vec![],
+        };
+        assert_eq!(
+            report.to_csv(),
+            "command,mean,stddev,median,user,system,min,max\n\
+             cat (C),2,1.4142135623730951,2,1,0,1,3\n\
+             cat (Rust),2,,2,1,0,2,2\n"
+        );
+    }
+}
@@ -1,10 +1,14 @@
-use ec2rust::process_args;
-use ec2rust::Args;
-use clap::Parser;
+use ec2rust::{parse_args, process_args};
+use std::process;
 
 fn main() {
-    let args = Args::parse();
-    let (tcfg, cc_json_path, extra_args) = process_args(args);
-    let extra_args = extra_args.iter().map(AsRef::as_ref).collect::<Vec<_>>();
-    c2rust_transpile::export(tcfg, &cc_json_path, &extra_args);
+    let args = parse_args();
+    let result = process_args(args).and_then(|(tcfg, cc_json_path, extra_args)| {
+        let extra_args = extra_args.iter().map(AsRef::as_ref).collect::<Vec<_>>();
+        c2rust_transpile::export(tcfg, &cc_json_path, &extra_args)
+    });
+    if let Err(e) = result {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
 }
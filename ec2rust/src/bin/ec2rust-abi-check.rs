@@ -0,0 +1,19 @@
+use ec2rust::abi_check::{abi_check, parse_abi_check_args};
+use std::process;
+
+fn main() {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
+    let args = parse_abi_check_args();
+    match abi_check(args) {
+        Ok(report) => {
+            print!("{}", report);
+            if !report.passed() {
+                process::exit(1);
+            }
+        }
+        Err(e) => {
+            eprintln!("error: {:#}", e);
+            process::exit(1);
+        }
+    }
+}
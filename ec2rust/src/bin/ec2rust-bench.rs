@@ -0,0 +1,45 @@
+use clap::Parser;
+use ec2rust::bench::{bench, record, BenchArgs, RECORD_FLAG};
+use ec2rust::make_check::restore;
+use std::env;
+use std::path::PathBuf;
+use std::process;
+
+fn main() {
+    // Run by the wrappers, so don't use `clap` and keep the arguments as is
+    let mut args = env::args_os().skip(1);
+    if args.next().map_or(false, |arg| arg == RECORD_FLAG) {
+        match (args.next(), args.next(), args.next()) {
+            (Some(log), Some(binary), Some(arg0)) => record(
+                &PathBuf::from(log),
+                &PathBuf::from(binary),
+                arg0,
+                args.collect(),
+            ),
+            _ => {
+                eprintln!(
+                    "usage: ec2rust-bench {} LOG BINARY ARG0 [ARGS...]",
+                    RECORD_FLAG
+                );
+                process::exit(2);
+            }
+        }
+    }
+
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
+    let args = BenchArgs::parse();
+    if args.tree.restore {
+        if let Err(e) = restore(&args.tree) {
+            eprintln!("error: {:#}", e);
+            process::exit(1);
+        }
+        return;
+    }
+    match bench(args) {
+        Ok(report) => print!("{}", report.to_markdown()),
+        Err(e) => {
+            eprintln!("error: {:#}", e);
+            process::exit(1);
+        }
+    }
+}
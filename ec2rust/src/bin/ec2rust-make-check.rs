@@ -0,0 +1,27 @@
+use clap::Parser;
+use ec2rust::make_check::{make_check, restore, MakeCheckArgs};
+use std::process;
+
+fn main() {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
+    let args = MakeCheckArgs::parse();
+    if args.tree.restore {
+        if let Err(e) = restore(&args.tree) {
+            eprintln!("error: {:#}", e);
+            process::exit(1);
+        }
+        return;
+    }
+    match make_check(args) {
+        Ok(report) => {
+            print!("{}", report);
+            if !report.passed() {
+                process::exit(1);
+            }
+        }
+        Err(e) => {
+            eprintln!("error: {:#}", e);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,14 @@
+use ec2rust::migrate::{migrate, parse_migrate_args};
+use std::process;
+
+fn main() {
+    let args = parse_migrate_args();
+    match migrate(args) {
+        Ok(report) if report.succeeded() => {}
+        Ok(_) => process::exit(1),
+        Err(e) => {
+            eprintln!("error: {}", e);
+            process::exit(1);
+        }
+    }
+}
@@ -1,10 +1,33 @@
-use ec2rust::process_args;
-use ec2rust::Args;
-use clap::Parser;
+use c2rust_transpile::build_files::get_build_dir;
+use ec2rust::check::{check_translation, CheckOptions};
+use ec2rust::{parse_args, process_args};
+use std::process;
 
 fn main() {
-    let args = Args::parse();
-    let (tcfg, cc_json_path, extra_args) = process_args(args);
-    let extra_args = extra_args.iter().map(AsRef::as_ref).collect::<Vec<_>>();
-    c2rust_transpile::transpile(tcfg, &cc_json_path, &extra_args);
+    let args = parse_args();
+    let check = CheckOptions::from_args(&args);
+    let result = process_args(args).and_then(|(tcfg, cc_json_path, extra_args)| {
+        let extra_args = extra_args.iter().map(AsRef::as_ref).collect::<Vec<_>>();
+        let build_dir = get_build_dir(&tcfg, &cc_json_path);
+        let dry_run = tcfg.dry_run;
+        let summary = c2rust_transpile::transpile(tcfg, &cc_json_path, &extra_args)?;
+        Ok((summary, build_dir, dry_run))
+    });
+    let (summary, build_dir, dry_run) = match result {
+        Ok(result) => result,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            process::exit(1);
+        }
+    };
+    if let Some(check) = check.filter(|_| !dry_run) {
+        match check_translation(&build_dir, &summary.dependencies, &check) {
+            Ok(true) => {}
+            Ok(false) => process::exit(1),
+            Err(e) => {
+                eprintln!("error: cargo check: {}", e);
+                process::exit(1);
+            }
+        }
+    }
 }
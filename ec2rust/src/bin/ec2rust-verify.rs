@@ -0,0 +1,20 @@
+use clap::Parser;
+use ec2rust::verify::{verify, VerifyArgs};
+use std::process;
+
+fn main() {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
+    let args = VerifyArgs::parse();
+    match verify(args) {
+        Ok(verification) => {
+            print!("{}", verification);
+            if !verification.passed() {
+                process::exit(1);
+            }
+        }
+        Err(e) => {
+            eprintln!("error: {:#}", e);
+            process::exit(1);
+        }
+    }
+}
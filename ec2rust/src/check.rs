@@ -0,0 +1,536 @@
+//! Running `cargo check` on a translated crate and collecting the
+//! diagnostics of the Rust compiler, mapped back to the C code they come
+//! from.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use deps_builder::DependencyInfo;
+use serde::{Deserialize, Serialize};
+use syn::spanned::Spanned;
+use syn::{Attribute, ForeignItem, Item, Lit, Meta};
+
+use crate::Args;
+
+/// One line of `cargo --message-format=json` output. Only compiler messages
+/// are of interest.
+#[derive(Debug, Deserialize)]
+struct CargoMessage {
+    reason: String,
+    message: Option<Diagnostic>,
+}
+
+/// A diagnostic of the Rust compiler, as in its JSON output
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Diagnostic {
+    pub message: String,
+    pub level: String,
+    pub code: Option<DiagnosticCode>,
+    pub spans: Vec<DiagnosticSpan>,
+    pub rendered: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiagnosticCode {
+    pub code: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiagnosticSpan {
+    pub file_name: String,
+    pub line_start: usize,
+    pub line_end: usize,
+    pub column_start: usize,
+    pub column_end: usize,
+    pub is_primary: bool,
+}
+
+impl Diagnostic {
+    pub fn is_error(&self) -> bool {
+        self.level == "error"
+    }
+
+    /// The error code, or a placeholder for diagnostics without one
+    pub fn code(&self) -> &str {
+        self.code.as_ref().map_or("(no code)", |code| &code.code)
+    }
+
+    pub fn primary_span(&self) -> Option<&DiagnosticSpan> {
+        self.spans.iter().find(|span| span.is_primary)
+    }
+}
+
+/// The outcome of `cargo check`
+#[derive(Debug)]
+pub struct CheckOutput {
+    pub success: bool,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// Counts of the diagnostics of `cargo check`, for reports
+#[derive(Debug, Default, Serialize)]
+pub struct CheckStats {
+    pub success: bool,
+    pub errors: usize,
+    pub warnings: usize,
+    /// Number of errors by error code
+    pub error_codes: BTreeMap<String, usize>,
+}
+
+impl CheckOutput {
+    pub fn errors(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.diagnostics.iter().filter(|d| d.is_error())
+    }
+
+    pub fn stats(&self) -> CheckStats {
+        let mut stats = CheckStats {
+            success: self.success,
+            ..Default::default()
+        };
+        for diagnostic in &self.diagnostics {
+            match diagnostic.level.as_str() {
+                "error" => {
+                    stats.errors += 1;
+                    *stats
+                        .error_codes
+                        .entry(diagnostic.code().to_owned())
+                        .or_default() += 1;
+                }
+                "warning" => stats.warnings += 1,
+                _ => {}
+            }
+        }
+        stats
+    }
+}
+
+/// Run `cargo check` on the crate or workspace in `dir`. The progress of
+/// cargo goes to our standard error.
+pub fn cargo_check(dir: &Path) -> anyhow::Result<CheckOutput> {
+    let output = Command::new("cargo")
+        .args(["check", "--workspace", "--message-format=json"])
+        .current_dir(dir)
+        .stderr(Stdio::inherit())
+        .output()?;
+    Ok(CheckOutput {
+        success: output.status.success(),
+        diagnostics: parse_messages(&String::from_utf8_lossy(&output.stdout)),
+    })
+}
+
+/// The diagnostics in the `--message-format=json` output of cargo
+fn parse_messages(stdout: &str) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+    for line in stdout.lines() {
+        // Build scripts may print lines that are not JSON
+        let message = match serde_json::from_str::<CargoMessage>(line) {
+            Ok(message) => message,
+            Err(_) => continue,
+        };
+        if message.reason != "compiler-message" {
+            continue;
+        }
+        if let Some(diagnostic) = message.message {
+            // Summaries like "aborting due to 3 previous errors"
+            if diagnostic.spans.is_empty() && diagnostic.code.is_none() {
+                continue;
+            }
+            diagnostics.push(diagnostic);
+        }
+    }
+    diagnostics
+}
+
+/// Where `--check` writes its report, if it is given
+pub struct CheckOptions {
+    pub report: Option<PathBuf>,
+}
+
+impl CheckOptions {
+    pub fn from_args(args: &Args) -> Option<Self> {
+        args.check.then(|| Self {
+            report: args.check_report.clone(),
+        })
+    }
+}
+
+/// The C origin of a Rust item: the C file, from the `c2rust::header_src`
+/// attribute of the header module around it or else the translation unit,
+/// and the line of the C declaration, from its `c2rust::src_loc` attribute.
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct COrigin {
+    pub file: Option<PathBuf>,
+    pub line: Option<usize>,
+    /// The C function, for diagnostics within a function
+    pub function: Option<String>,
+}
+
+impl fmt::Display for COrigin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.file {
+            Some(file) => write!(f, "{}", file.display())?,
+            None => write!(f, "<unknown file>")?,
+        }
+        if let Some(line) = self.line {
+            write!(f, ":{}", line)?;
+        }
+        match &self.function {
+            Some(function) => write!(f, " in {}", function),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The value of the `#[c2rust::{name} = "..."]` attribute in `attrs`
+fn c2rust_attr(attrs: &[Attribute], name: &str) -> Option<String> {
+    attrs.iter().find_map(|attr| {
+        let segments = attr.path.segments.iter().map(|s| s.ident.to_string());
+        if !segments.eq(["c2rust", name]) {
+            return None;
+        }
+        match attr.parse_meta() {
+            Ok(Meta::NameValue(meta)) => match meta.lit {
+                Lit::Str(s) => Some(s.value()),
+                _ => None,
+            },
+            _ => None,
+        }
+    })
+}
+
+/// The symbol name of a function, which is the C name unless it was renamed
+pub(crate) fn symbol_name(attrs: &[Attribute], ident: &syn::Ident) -> String {
+    let export_name = attrs.iter().find_map(|attr| match attr.parse_meta() {
+        Ok(Meta::NameValue(meta)) if meta.path.is_ident("export_name") => match meta.lit {
+            Lit::Str(s) => Some(s.value()),
+            _ => None,
+        },
+        _ => None,
+    });
+    export_name.unwrap_or_else(|| ident.to_string())
+}
+
+/// The lines of the items of one Rust file, with their C origins
+struct OriginMap {
+    items: Vec<(RangeInclusive<usize>, COrigin)>,
+}
+
+impl OriginMap {
+    fn new(file: &syn::File, c_file: Option<&Path>) -> Self {
+        let mut map = Self { items: vec![] };
+        map.add_items(&file.items, c_file);
+        map
+    }
+
+    fn add(&mut self, span: proc_macro2::Span, origin: COrigin) {
+        self.items
+            .push((span.start().line..=span.end().line, origin));
+    }
+
+    fn add_items(&mut self, items: &[Item], c_file: Option<&Path>) {
+        for item in items {
+            let origin = |attrs: &[Attribute], function: Option<String>| COrigin {
+                file: c_file.map(Path::to_owned),
+                line: c2rust_attr(attrs, "src_loc")
+                    .and_then(|loc| loc.split(':').next()?.parse().ok()),
+                function,
+            };
+            match item {
+                Item::Fn(f) => {
+                    let name = symbol_name(&f.attrs, &f.sig.ident);
+                    self.add(item.span(), origin(&f.attrs, Some(name)));
+                }
+                Item::Mod(m) => {
+                    // `path:line`, where the line is that of the `#include`
+                    let header =
+                        c2rust_attr(&m.attrs, "header_src").map(|src| match src.rsplit_once(':') {
+                            Some((path, _)) => PathBuf::from(path),
+                            None => PathBuf::from(src),
+                        });
+                    let c_file = header.as_deref().or(c_file);
+                    if let Some((_, items)) = &m.content {
+                        self.add_items(items, c_file);
+                    }
+                }
+                Item::ForeignMod(m) => {
+                    for foreign_item in &m.items {
+                        let (attrs, function) = match foreign_item {
+                            ForeignItem::Fn(f) => {
+                                (&f.attrs, Some(symbol_name(&f.attrs, &f.sig.ident)))
+                            }
+                            ForeignItem::Static(s) => (&s.attrs, None),
+                            ForeignItem::Type(t) => (&t.attrs, None),
+                            _ => continue,
+                        };
+                        self.add(foreign_item.span(), origin(attrs, function));
+                    }
+                }
+                Item::Const(i) => self.add(item.span(), origin(&i.attrs, None)),
+                Item::Static(i) => self.add(item.span(), origin(&i.attrs, None)),
+                Item::Struct(i) => self.add(item.span(), origin(&i.attrs, None)),
+                Item::Union(i) => self.add(item.span(), origin(&i.attrs, None)),
+                Item::Enum(i) => self.add(item.span(), origin(&i.attrs, None)),
+                Item::Type(i) => self.add(item.span(), origin(&i.attrs, None)),
+                _ => {}
+            }
+        }
+    }
+
+    /// The origin of the innermost item around `line`
+    fn lookup(&self, line: usize) -> Option<&COrigin> {
+        self.items
+            .iter()
+            .filter(|(lines, _)| lines.contains(&line))
+            .min_by_key(|(lines, _)| lines.end() - lines.start())
+            .map(|(_, origin)| origin)
+    }
+}
+
+/// Maps the spans of diagnostics in the Rust files of a translated crate to
+/// C origins
+struct OriginMapper<'a> {
+    dir: &'a Path,
+    /// Translation unit of each emitted Rust file
+    inputs: HashMap<PathBuf, PathBuf>,
+    maps: HashMap<PathBuf, Option<OriginMap>>,
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_owned())
+}
+
+impl<'a> OriginMapper<'a> {
+    fn new(dir: &'a Path, dependencies: &[DependencyInfo]) -> Self {
+        let inputs = dependencies
+            .iter()
+            .filter(|dep| !dep.output_path.is_empty())
+            .map(|dep| {
+                let output = canonical(Path::new(&dep.output_path));
+                (output, PathBuf::from(&dep.input_path))
+            })
+            .collect();
+        Self {
+            dir,
+            inputs,
+            maps: HashMap::new(),
+        }
+    }
+
+    fn origin(&mut self, span: &DiagnosticSpan) -> COrigin {
+        let rust_file = canonical(&self.dir.join(&span.file_name));
+        let input = self.inputs.get(&rust_file);
+        let map = self.maps.entry(rust_file.clone()).or_insert_with(|| {
+            let source = fs::read_to_string(&rust_file).ok()?;
+            let file = syn::parse_file(&source).ok()?;
+            Some(OriginMap::new(&file, input.map(PathBuf::as_path)))
+        });
+        let origin = map.as_ref().and_then(|map| map.lookup(span.line_start));
+        origin.cloned().unwrap_or_else(|| COrigin {
+            file: input.cloned(),
+            ..Default::default()
+        })
+    }
+}
+
+/// An error of `cargo check` in a report
+#[derive(Debug, Serialize)]
+pub struct CheckError {
+    pub message: String,
+    pub rust_file: Option<String>,
+    pub rust_line: Option<usize>,
+}
+
+/// The errors from one C origin, by error code
+#[derive(Debug, Serialize)]
+pub struct OriginErrors {
+    #[serde(flatten)]
+    pub origin: COrigin,
+    pub errors: BTreeMap<String, Vec<CheckError>>,
+}
+
+/// The report of `--check`
+#[derive(Debug, Serialize)]
+pub struct CheckReport {
+    #[serde(flatten)]
+    pub stats: CheckStats,
+    pub origins: Vec<OriginErrors>,
+}
+
+impl CheckReport {
+    /// Group the errors of `output`, from checking the crate in `dir`, by
+    /// their C origin and error code. `dependencies` tell the translation
+    /// unit of each Rust file.
+    pub fn new(output: &CheckOutput, dir: &Path, dependencies: &[DependencyInfo]) -> Self {
+        let mut mapper = OriginMapper::new(dir, dependencies);
+        let mut origins = BTreeMap::<COrigin, BTreeMap<String, Vec<CheckError>>>::new();
+        for error in output.errors() {
+            let span = error.primary_span();
+            let origin = span.map(|span| mapper.origin(span)).unwrap_or_default();
+            let error_codes = origins.entry(origin).or_default();
+            error_codes
+                .entry(error.code().to_owned())
+                .or_default()
+                .push(CheckError {
+                    message: error.message.clone(),
+                    rust_file: span.map(|span| span.file_name.clone()),
+                    rust_line: span.map(|span| span.line_start),
+                });
+        }
+        Self {
+            stats: output.stats(),
+            origins: origins
+                .into_iter()
+                .map(|(origin, errors)| OriginErrors { origin, errors })
+                .collect(),
+        }
+    }
+}
+
+impl fmt::Display for CheckReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "cargo check: {} errors, {} warnings",
+            self.stats.errors, self.stats.warnings
+        )?;
+        for OriginErrors { origin, errors } in &self.origins {
+            writeln!(f, "{}:", origin)?;
+            for (code, errors) in errors {
+                writeln!(f, "  {} ({}):", code, errors.len())?;
+                for error in errors {
+                    match (&error.rust_file, error.rust_line) {
+                        (Some(file), Some(line)) => {
+                            writeln!(f, "    {}:{}: {}", file, line, error.message)?
+                        }
+                        _ => writeln!(f, "    {}", error.message)?,
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Run `cargo check` on the crate translated into `dir`, print its errors by
+/// C origin and error code, and write them as JSON if `options` ask for it.
+/// Returns whether the crate compiles.
+pub fn check_translation(
+    dir: &Path,
+    dependencies: &[DependencyInfo],
+    options: &CheckOptions,
+) -> anyhow::Result<bool> {
+    let output = cargo_check(dir)?;
+    let report = CheckReport::new(&output, dir, dependencies);
+    print!("{}", report);
+    if let Some(path) = &options.report {
+        fs::write(path, serde_json::to_string_pretty(&report)?)?;
+    }
+    Ok(output.success)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `cargo check --message-format=json` of a crate with errors in
+    /// `src/a.rs` and `src/b.rs`
+    const MESSAGES: &str = include_str!("../tests/fixtures/cargo_check.jsonl");
+
+    const A_RS: &str = "#[c2rust::header_src = \"/src/point.h:1\"]
+pub mod point_h {
+    #[c2rust::src_loc = \"4:1\"]
+    pub type coord = libc::c_int;
+}
+#[no_mangle]
+#[c2rust::src_loc = \"10:1\"]
+pub unsafe extern \"C\" fn add(x: libc::c_int) -> libc::c_int {
+    let y: coord = x;
+    let z = y;
+    y + 1
+}
+";
+
+    fn origin(file: &str, line: usize, function: Option<&str>) -> COrigin {
+        COrigin {
+            file: Some(PathBuf::from(file)),
+            line: Some(line),
+            function: function.map(str::to_owned),
+        }
+    }
+
+    #[test]
+    fn parse_cargo_messages() {
+        let diagnostics = parse_messages(MESSAGES);
+        // Artifacts, other lines and the summary are left out
+        let codes = diagnostics.iter().map(Diagnostic::code).collect::<Vec<_>>();
+        assert_eq!(codes, ["E0308", "unused_variables", "E0425"]);
+        let span = diagnostics[0].primary_span().unwrap();
+        assert_eq!(span.file_name, "src/a.rs");
+        assert_eq!(span.line_start, 9);
+
+        let output = CheckOutput {
+            success: false,
+            diagnostics,
+        };
+        let stats = output.stats();
+        assert_eq!(stats.errors, 2);
+        assert_eq!(stats.warnings, 1);
+        assert_eq!(
+            stats.error_codes.into_iter().collect::<Vec<_>>(),
+            [("E0308".to_owned(), 1), ("E0425".to_owned(), 1)]
+        );
+    }
+
+    #[test]
+    fn origin_map_lookup() {
+        let file = syn::parse_file(A_RS).unwrap();
+        let map = OriginMap::new(&file, Some(Path::new("/src/a.c")));
+        // Items of a header module come from the header
+        assert_eq!(map.lookup(4), Some(&origin("/src/point.h", 4, None)));
+        // The innermost item is the function, wherever its attributes are
+        assert_eq!(map.lookup(6), Some(&origin("/src/a.c", 10, Some("add"))));
+        assert_eq!(map.lookup(9), Some(&origin("/src/a.c", 10, Some("add"))));
+        // Outside of any item
+        assert_eq!(map.lookup(5), None);
+        assert_eq!(map.lookup(20), None);
+    }
+
+    #[test]
+    fn report_by_origin() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("src")).unwrap();
+        let a_rs = dir.path().join("src/a.rs");
+        fs::write(&a_rs, A_RS).unwrap();
+        let dependencies = [DependencyInfo {
+            input_path: "/src/a.c".to_owned(),
+            output_path: a_rs.display().to_string(),
+            object_path: None,
+            undefined: vec![],
+            defined: vec![],
+        }];
+        let output = CheckOutput {
+            success: false,
+            diagnostics: parse_messages(MESSAGES),
+        };
+        let report = CheckReport::new(&output, dir.path(), &dependencies);
+
+        assert_eq!(report.origins.len(), 2);
+        // `src/b.rs` is not a translated file
+        let unknown = &report.origins[0];
+        assert_eq!(unknown.origin, COrigin::default());
+        assert_eq!(unknown.errors["E0425"].len(), 1);
+        let add = &report.origins[1];
+        assert_eq!(add.origin, origin("/src/a.c", 10, Some("add")));
+        let errors = &add.errors["E0308"];
+        assert_eq!(errors[0].message, "mismatched types");
+        assert_eq!(errors[0].rust_file.as_deref(), Some("src/a.rs"));
+        assert_eq!(errors[0].rust_line, Some(9));
+        // Warnings are only counted
+        assert_eq!(report.stats.warnings, 1);
+        assert!(!add.errors.contains_key("unused_variables"));
+    }
+}
@@ -0,0 +1,455 @@
+//! The project configuration file, `ec2rust.toml`.
+//!
+//! Every command-line option can be given in the file under its long name
+//! (e.g. `output-dir = "out"`), along with the translator settings that have
+//! no command-line option. `[[override]]` tables change the filter, binary
+//! detection, extra Clang arguments and replacement mode for the input files
+//! in a directory or a single input file. Options given on the command line
+//! take precedence over the file. Relative paths in the file are relative to
+//! the directory containing it.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use clap::{ArgMatches, ValueEnum, ValueSource};
+use log::LevelFilter;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use c2rust_transpile::{Diagnostic, InputOverride, ReplaceMode};
+
+use crate::{Args, BinaryLayout, InvalidCodes, SymbolsFrom};
+
+/// The file read from the current directory if `--config` is not given
+pub const CONFIG_FILE_NAME: &str = "ec2rust.toml";
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ProjectConfig {
+    prefix_function_names: Option<String>,
+    dump_untyped_clang_ast: Option<bool>,
+    dump_typed_clang_ast: Option<bool>,
+    pretty_typed_clang_ast: Option<bool>,
+    debug_ast_exporter: Option<bool>,
+    verbose: Option<bool>,
+    translate_const_macros: Option<bool>,
+    translate_fn_macros: Option<bool>,
+    no_incremental_relooper: Option<bool>,
+    no_simplify_structures: Option<bool>,
+    ignore_c_loop_info: Option<bool>,
+    ignore_c_multiple_info: Option<bool>,
+    #[serde(rename = "ddump-function-cfgs")]
+    dump_function_cfgs: Option<bool>,
+    json_function_cfgs: Option<bool>,
+    #[serde(rename = "ddump-cfgs-liveness")]
+    dump_cfgs_liveness: Option<bool>,
+    #[serde(rename = "ddump-structures")]
+    dump_structures: Option<bool>,
+    #[serde(rename = "ddebug-labels")]
+    debug_labels: Option<bool>,
+    invalid_code: Option<String>,
+    emit_modules: Option<bool>,
+    emit_build_files: Option<bool>,
+    emit_binaries: Option<bool>,
+    binary_layout: Option<String>,
+    multicall: Option<bool>,
+    hybrid_build: Option<bool>,
+    emit_differential_tests: Option<bool>,
+    output_dir: Option<PathBuf>,
+    filter: Option<String>,
+    fail_on_error: Option<bool>,
+    binary: Option<Vec<String>>,
+    detect_binary: Option<bool>,
+    overwrite_existing: Option<bool>,
+    reduce_type_annotations: Option<bool>,
+    reorganize_definitions: Option<bool>,
+    extra_clang_args: Option<Vec<String>>,
+    warn: Option<String>,
+    emit_no_std: Option<bool>,
+    emit_no_lib: Option<bool>,
+    disable_refactoring: Option<bool>,
+    preserve_unused_functions: Option<bool>,
+    log_level: Option<String>,
+    fail_on_multiple: Option<bool>,
+    dependency_file: Option<PathBuf>,
+    fuzz_depends_level: Option<usize>,
+    symbols_from: Option<String>,
+    jobs: Option<usize>,
+    ast_cache_dir: Option<PathBuf>,
+    incremental: Option<bool>,
+    report_json: Option<PathBuf>,
+    report_sarif: Option<PathBuf>,
+    dry_run: Option<bool>,
+    check: Option<bool>,
+    check_report: Option<PathBuf>,
+
+    // Settings without a command-line option
+    translate_asm: Option<bool>,
+    translate_valist: Option<bool>,
+    replace_unsupported_decls: Option<ReplaceMode>,
+
+    #[serde(rename = "override", skip_serializing_if = "Vec::is_empty")]
+    overrides: Vec<OverrideConfig>,
+}
+
+/// An `[[override]]` table.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct OverrideConfig {
+    path: PathBuf,
+    filter: Option<String>,
+    binary: Option<bool>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    extra_clang_args: Vec<String>,
+    replace_unsupported_decls: Option<ReplaceMode>,
+}
+
+fn absolute(path: &Path) -> PathBuf {
+    if path.is_absolute() {
+        path.to_owned()
+    } else {
+        env::current_dir().unwrap_or_default().join(path)
+    }
+}
+
+fn parse_regex(re: &str) -> Result<Regex, String> {
+    Regex::new(re).map_err(|e| format!("invalid filter {:?}: {}", re, e))
+}
+
+/// Copy the given fields from the configuration file into `Args`, unless
+/// they were given on the command line.
+macro_rules! merge {
+    ($config:ident, $args:ident, $matches:ident; $($field:ident),* $(,)?) => {
+        $(
+            if let Some(value) = $config.$field.take() {
+                if !given_on_command_line($matches, stringify!($field)) {
+                    $args.$field = value.into();
+                }
+            }
+        )*
+    };
+}
+
+fn given_on_command_line(matches: &ArgMatches, field: &str) -> bool {
+    matches.value_source(&*field.replace('_', "-")) == Some(ValueSource::CommandLine)
+}
+
+impl ProjectConfig {
+    /// Read a configuration file, making its relative paths relative to the
+    /// directory containing it.
+    pub fn read(path: &Path) -> Result<Self, String> {
+        let contents = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let mut config: Self = toml::from_str(&contents).map_err(|e| e.to_string())?;
+        let dir = absolute(path.parent().unwrap_or_else(|| Path::new("")));
+        let paths = [
+            &mut config.output_dir,
+            &mut config.dependency_file,
+            &mut config.ast_cache_dir,
+            &mut config.report_json,
+            &mut config.report_sarif,
+            &mut config.check_report,
+        ];
+        for path in paths.into_iter().flatten() {
+            *path = dir.join(&*path);
+        }
+        for o in &mut config.overrides {
+            o.path = dir.join(&o.path);
+        }
+        Ok(config)
+    }
+
+    /// Fill in the options of `args` that `matches` did not get from the
+    /// command line.
+    pub fn merge_into(mut self, args: &mut Args, matches: &ArgMatches) -> Result<(), String> {
+        let config = &mut self;
+        merge!(config, args, matches;
+            prefix_function_names,
+            dump_untyped_clang_ast,
+            dump_typed_clang_ast,
+            pretty_typed_clang_ast,
+            debug_ast_exporter,
+            verbose,
+            translate_const_macros,
+            translate_fn_macros,
+            no_incremental_relooper,
+            no_simplify_structures,
+            ignore_c_loop_info,
+            ignore_c_multiple_info,
+            dump_function_cfgs,
+            json_function_cfgs,
+            dump_cfgs_liveness,
+            dump_structures,
+            debug_labels,
+            emit_modules,
+            emit_build_files,
+            emit_binaries,
+            multicall,
+            hybrid_build,
+            emit_differential_tests,
+            output_dir,
+            fail_on_error,
+            binary,
+            detect_binary,
+            overwrite_existing,
+            reduce_type_annotations,
+            reorganize_definitions,
+            extra_clang_args,
+            emit_no_std,
+            emit_no_lib,
+            disable_refactoring,
+            preserve_unused_functions,
+            fail_on_multiple,
+            dependency_file,
+            fuzz_depends_level,
+            jobs,
+            ast_cache_dir,
+            incremental,
+            report_json,
+            report_sarif,
+            dry_run,
+            check,
+            check_report,
+        );
+
+        // Options that need parsing
+        if let Some(value) = config.invalid_code.take() {
+            if !given_on_command_line(matches, "invalid_code") {
+                args.invalid_code = InvalidCodes::from_str(&value, false)
+                    .map_err(|e| format!("invalid-code: {}", e))?;
+            }
+        }
+        if let Some(value) = config.binary_layout.take() {
+            if !given_on_command_line(matches, "binary_layout") {
+                args.binary_layout = BinaryLayout::from_str(&value, false)
+                    .map_err(|e| format!("binary-layout: {}", e))?;
+            }
+        }
+        if let Some(value) = config.symbols_from.take() {
+            if !given_on_command_line(matches, "symbols_from") {
+                args.symbols_from = SymbolsFrom::from_str(&value, false)
+                    .map_err(|e| format!("symbols-from: {}", e))?;
+            }
+        }
+        if let Some(value) = config.filter.take() {
+            if !given_on_command_line(matches, "filter") {
+                args.filter = Some(parse_regex(&value)?);
+            }
+        }
+        if let Some(value) = config.warn.take() {
+            if !given_on_command_line(matches, "warn") {
+                let warn = value.parse::<Diagnostic>();
+                args.warn = Some(warn.map_err(|_| format!("unknown warning {:?}", value))?);
+            }
+        }
+        if let Some(value) = config.log_level.take() {
+            if !given_on_command_line(matches, "log_level") {
+                let level = value.parse::<LevelFilter>();
+                args.log_level = level.map_err(|_| format!("invalid log-level {:?}", value))?;
+            }
+        }
+
+        args.translate_asm = config.translate_asm;
+        args.translate_valist = config.translate_valist;
+        args.replace_unsupported_decls = config.replace_unsupported_decls;
+        args.overrides = config
+            .overrides
+            .drain(..)
+            .map(|o| {
+                Ok(InputOverride {
+                    path: o.path,
+                    filter: o.filter.as_deref().map(parse_regex).transpose()?,
+                    binary: o.binary,
+                    extra_clang_args: o.extra_clang_args,
+                    replace_unsupported_decls: o.replace_unsupported_decls,
+                })
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(())
+    }
+
+    /// The configuration in effect for `args`, with absolute paths, so that
+    /// it reproduces the run from any directory.
+    pub fn effective(args: &Args) -> Self {
+        let path = |path: &Path| Some(absolute(path));
+        Self {
+            prefix_function_names: args.prefix_function_names.clone(),
+            dump_untyped_clang_ast: Some(args.dump_untyped_clang_ast),
+            dump_typed_clang_ast: Some(args.dump_typed_clang_ast),
+            pretty_typed_clang_ast: Some(args.pretty_typed_clang_ast),
+            debug_ast_exporter: Some(args.debug_ast_exporter),
+            verbose: Some(args.verbose),
+            translate_const_macros: Some(args.translate_const_macros),
+            translate_fn_macros: Some(args.translate_fn_macros),
+            no_incremental_relooper: Some(args.no_incremental_relooper),
+            no_simplify_structures: Some(args.no_simplify_structures),
+            ignore_c_loop_info: Some(args.ignore_c_loop_info),
+            ignore_c_multiple_info: Some(args.ignore_c_multiple_info),
+            dump_function_cfgs: Some(args.dump_function_cfgs),
+            json_function_cfgs: Some(args.json_function_cfgs),
+            dump_cfgs_liveness: Some(args.dump_cfgs_liveness),
+            dump_structures: Some(args.dump_structures),
+            debug_labels: Some(args.debug_labels),
+            invalid_code: args
+                .invalid_code
+                .to_possible_value()
+                .map(|value| value.get_name().to_owned()),
+            emit_modules: Some(args.emit_modules),
+            emit_build_files: Some(args.emit_build_files),
+            emit_binaries: Some(args.emit_binaries),
+            multicall: Some(args.multicall),
+            hybrid_build: Some(args.hybrid_build),
+            emit_differential_tests: Some(args.emit_differential_tests),
+            binary_layout: args
+                .binary_layout
+                .to_possible_value()
+                .map(|value| value.get_name().to_owned()),
+            output_dir: args.output_dir.as_deref().and_then(path),
+            filter: args.filter.as_ref().map(|re| re.as_str().to_owned()),
+            fail_on_error: Some(args.fail_on_error),
+            binary: args.binary.clone(),
+            detect_binary: Some(args.detect_binary),
+            overwrite_existing: Some(args.overwrite_existing),
+            reduce_type_annotations: Some(args.reduce_type_annotations),
+            reorganize_definitions: Some(args.reorganize_definitions),
+            extra_clang_args: Some(args.extra_clang_args.clone()),
+            warn: args.warn.as_ref().map(ToString::to_string),
+            emit_no_std: Some(args.emit_no_std),
+            emit_no_lib: Some(args.emit_no_lib),
+            disable_refactoring: Some(args.disable_refactoring),
+            preserve_unused_functions: Some(args.preserve_unused_functions),
+            log_level: Some(args.log_level.to_string().to_lowercase()),
+            fail_on_multiple: Some(args.fail_on_multiple),
+            dependency_file: path(&args.dependency_file),
+            fuzz_depends_level: Some(args.fuzz_depends_level),
+            symbols_from: args
+                .symbols_from
+                .to_possible_value()
+                .map(|value| value.get_name().to_owned()),
+            jobs: Some(args.jobs),
+            ast_cache_dir: args.ast_cache_dir.as_deref().and_then(path),
+            incremental: Some(args.incremental),
+            report_json: args.report_json.as_deref().and_then(path),
+            report_sarif: args.report_sarif.as_deref().and_then(path),
+            dry_run: Some(args.dry_run),
+            check: Some(args.check),
+            check_report: args.check_report.as_deref().and_then(path),
+            translate_asm: args.translate_asm,
+            translate_valist: args.translate_valist,
+            replace_unsupported_decls: args.replace_unsupported_decls,
+            overrides: args
+                .overrides
+                .iter()
+                .map(|o| OverrideConfig {
+                    path: absolute(&o.path),
+                    filter: o.filter.as_ref().map(|re| re.as_str().to_owned()),
+                    binary: o.binary,
+                    extra_clang_args: o.extra_clang_args.clone(),
+                    replace_unsupported_decls: o.replace_unsupported_decls,
+                })
+                .collect(),
+        }
+    }
+
+    /// Render as `ec2rust.toml`, headed by the command that reproduces the
+    /// run with it.
+    pub fn to_toml(&self, compile_commands: &Path) -> String {
+        format!(
+            "# Reproduce with: ec2rust-transpile --config <this file> {}\n{}",
+            absolute(compile_commands).display(),
+            toml::to_string(self).expect("the configuration is valid TOML"),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::{CommandFactory, FromArgMatches};
+
+    use super::*;
+
+    /// Parse `command_line` and merge the configuration file `toml`, as
+    /// [`crate::parse_args`] does.
+    fn parse(toml: &str, command_line: &[&str]) -> Result<Args, String> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(CONFIG_FILE_NAME);
+        fs::write(&path, toml).unwrap();
+        let command_line = ["ec2rust-transpile"]
+            .iter()
+            .chain(command_line)
+            .chain(&["compile_commands.json"]);
+        let matches = Args::command().try_get_matches_from(command_line).unwrap();
+        let mut args = Args::from_arg_matches(&matches).unwrap();
+        ProjectConfig::read(&path)?.merge_into(&mut args, &matches)?;
+        Ok(args)
+    }
+
+    #[test]
+    fn command_line_wins() {
+        let toml = "jobs = 4\nreorganize-definitions = true\nlog-level = \"info\"\n";
+        let args = parse(toml, &["-j", "2", "--log-level", "error"]).unwrap();
+        assert_eq!(args.jobs, 2);
+        assert_eq!(args.log_level, LevelFilter::Error);
+        // Not given on the command line
+        assert!(args.reorganize_definitions);
+    }
+
+    #[test]
+    fn defaults_do_not_win() {
+        let toml = "jobs = 4\nsymbols-from = \"objects\"\nfilter = \"^src/\"\n";
+        let args = parse(toml, &[]).unwrap();
+        assert_eq!(args.jobs, 4);
+        assert_eq!(args.symbols_from, SymbolsFrom::Objects);
+        assert_eq!(args.filter.unwrap().as_str(), "^src/");
+    }
+
+    #[test]
+    fn paths_relative_to_config_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_dir = dir.path().join("project");
+        fs::create_dir(&config_dir).unwrap();
+        let path = config_dir.join(CONFIG_FILE_NAME);
+        let toml = "output-dir = \"out\"\n\
+                    report-json = \"/tmp/report.json\"\n\
+                    [[override]]\n\
+                    path = \"src/main.c\"\n\
+                    binary = true\n";
+        fs::write(&path, toml).unwrap();
+        let config = ProjectConfig::read(&path).unwrap();
+        assert_eq!(config.output_dir, Some(config_dir.join("out")));
+        assert_eq!(config.report_json, Some(PathBuf::from("/tmp/report.json")));
+        assert_eq!(config.overrides[0].path, config_dir.join("src/main.c"));
+        assert_eq!(config.overrides[0].binary, Some(true));
+    }
+
+    #[test]
+    fn unknown_fields() {
+        let e = parse("no-such-option = true\n", &[]).unwrap_err();
+        assert!(e.contains("unknown field `no-such-option`"), "{}", e);
+        let toml = "[[override]]\npath = \"a.c\"\nbinaries = true\n";
+        let e = parse(toml, &[]).unwrap_err();
+        assert!(e.contains("unknown field `binaries`"), "{}", e);
+    }
+
+    #[test]
+    fn invalid_values() {
+        let e = parse("binary-layout = \"shared\"\n", &[]).unwrap_err();
+        assert!(e.starts_with("binary-layout:"), "{}", e);
+        let e = parse("filter = \"(\"\n", &[]).unwrap_err();
+        assert!(e.starts_with("invalid filter"), "{}", e);
+    }
+
+    #[test]
+    fn effective_config_round_trips() {
+        let toml = "jobs = 3\nbinary = [\"main\"]\n[[override]]\npath = \"/src/a.c\"\n";
+        let args = parse(toml, &["--incremental", "-o", "/out"]).unwrap();
+        let effective = ProjectConfig::effective(&args).to_toml(Path::new("/cc.json"));
+        assert!(effective.starts_with("# Reproduce with: "));
+        let config: ProjectConfig = toml::from_str(&effective).unwrap();
+        assert_eq!(config.jobs, Some(3));
+        assert_eq!(config.incremental, Some(true));
+        assert_eq!(config.output_dir, Some(PathBuf::from("/out")));
+        assert_eq!(config.binary, Some(vec!["main".to_owned()]));
+        assert_eq!(config.overrides[0].path, PathBuf::from("/src/a.c"));
+    }
+}
@@ -1,9 +1,19 @@
-use clap::{Parser, ValueEnum};
+use clap::{Command, CommandFactory, ErrorKind, FromArgMatches, Parser, ValueEnum};
 use log::LevelFilter;
 use regex::Regex;
 use std::path::{Path, PathBuf};
 
-use c2rust_transpile::{Diagnostic, ReplaceMode, TranspilerConfig};
+use c2rust_transpile::{Diagnostic, InputOverride, ReplaceMode, TranspileError, TranspilerConfig};
+
+pub mod abi_check;
+pub mod bench;
+pub mod check;
+pub mod config;
+pub mod make_check;
+pub mod migrate;
+pub mod verify;
+
+use crate::config::{ProjectConfig, CONFIG_FILE_NAME};
 
 #[derive(Debug, Parser)]
 #[clap(
@@ -101,10 +111,26 @@ pub struct Args {
     #[clap(short = 'e', long)]
     emit_build_files: bool,
 
-    /// Emit binary files in root dir for each binary target. Implies --emit-build-files.
-    #[clap(long, requires = "output-dir")]
+    /// Emit binary files in root dir for each binary target. Implies --emit-build-files. Requires --output-dir.
+    #[clap(long)]
     emit_binaries: bool,
 
+    /// How binaries get the library modules: declare them again (inline) or link against the library crate (library)
+    #[clap(long, value_enum, default_value_t = BinaryLayout::Inline)]
+    binary_layout: BinaryLayout,
+
+    /// Combine the binaries into one multi-call binary that selects them by the name it is called by (e.g., through the symlinks made by the generated install-links.sh) or by its first argument. Implies --emit-build-files.
+    #[clap(long)]
+    multicall: bool,
+
+    /// Compile the C files that are filtered out, are assembly or fail to translate with the cc crate in the generated build.rs, so that the crate still links. Implies --emit-build-files.
+    #[clap(long)]
+    hybrid_build: bool,
+
+    /// Emit a test crate for each translation unit in DIR/differential that links its C object next to the translated library and compares their exported functions with scalar or C string parameters on inputs generated by proptest. Implies --emit-build-files.
+    #[clap(long)]
+    emit_differential_tests: bool,
+
     /// Path to output directory. Rust sources will be emitted in DIR/src/ and build files will be emitted in DIR/.
     #[clap(short = 'o', long, value_name = "DIR")]
     output_dir: Option<PathBuf>,
@@ -133,7 +159,10 @@ pub struct Args {
     #[clap(long)]
     reduce_type_annotations: bool,
 
-    /// Output file in such a way that the refactoring tool can deduplicate code
+    /// Put header declarations in submodules and merge identical ones across
+    /// the modules of a crate into the shared `c2rust_headers` module, and
+    /// import the functions that sibling modules define instead of declaring
+    /// them `extern`
     #[clap(short = 'r', long)]
     reorganize_definitions: bool,
 
@@ -176,6 +205,126 @@ pub struct Args {
     /// Fuzz dependency checking level
     #[clap(long, default_value_t = 0)]
     fuzz_depends_level: usize,
+
+    /// Where to read the symbols of the dependency information from: the Clang AST, the object files of an existing build of the C code (falling back to the Clang AST for files without one), or both, warning about the symbols only one of them lists
+    #[clap(long, value_enum, default_value_t = SymbolsFrom::Clang)]
+    symbols_from: SymbolsFrom,
+
+    /// Number of translation units to process in parallel (0 uses all available cores); Clang still parses one file at a time
+    #[clap(short = 'j', long, default_value_t = 1)]
+    jobs: usize,
+
+    /// Cache exported Clang ASTs in DIR, so that later runs skip Clang for unchanged files
+    #[clap(long, value_name = "DIR")]
+    ast_cache_dir: Option<PathBuf>,
+
+    /// Only retranslate files whose C sources, headers or flags changed since the last run into the output directory (as recorded in c2rust-manifest.json there), and regenerate the build files. Requires --output-dir. Clang still parses every file unless --ast-cache-dir is given as well.
+    #[clap(long)]
+    incremental: bool,
+
+    /// Write a JSON report of skipped files and declarations that could not be translated to FILE
+    #[clap(long, value_name = "FILE")]
+    report_json: Option<PathBuf>,
+
+    /// Write the report of skipped files and declarations that could not be translated to FILE in SARIF format
+    #[clap(long, value_name = "FILE")]
+    report_sarif: Option<PathBuf>,
+
+    /// Print which files would be translated, overwritten or skipped, and which crates and build files would be emitted, without writing any file or creating any directory
+    #[clap(long)]
+    dry_run: bool,
+
+    /// Run cargo check on the emitted crate and report its errors by the C function and error code they come from. Implies --emit-build-files and requires --reorganize-definitions, whose c2rust::src_loc attributes locate the C declarations.
+    #[clap(long)]
+    check: bool,
+
+    /// Also write the report of --check as JSON to FILE
+    #[clap(long, value_name = "FILE")]
+    check_report: Option<PathBuf>,
+
+    /// Read options not given on the command line from FILE (default: ./ec2rust.toml, if it exists)
+    #[clap(long, value_name = "FILE")]
+    config: Option<PathBuf>,
+
+    // Settings that can only be given in the configuration file
+    #[clap(skip)]
+    translate_asm: Option<bool>,
+    #[clap(skip)]
+    translate_valist: Option<bool>,
+    #[clap(skip)]
+    replace_unsupported_decls: Option<ReplaceMode>,
+    #[clap(skip)]
+    overrides: Vec<InputOverride>,
+}
+
+/// Parse the command line, taking the options it does not give from the
+/// project configuration file. Exits on invalid arguments, like
+/// [`Parser::parse`].
+pub fn parse_args() -> Args {
+    parse_args_with(Args::command(), |args| args)
+}
+
+/// Like [`parse_args`], for a `command` that flattens [`Args`] into `T`.
+/// `args_of` gets the flattened [`Args`] out of `T`.
+pub fn parse_args_with<T: FromArgMatches>(
+    mut command: Command,
+    args_of: fn(&mut T) -> &mut Args,
+) -> T {
+    let matches = command.clone().get_matches();
+    let mut parsed = T::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    let args = args_of(&mut parsed);
+
+    let config_path = args.config.clone().or_else(|| {
+        let path = PathBuf::from(CONFIG_FILE_NAME);
+        path.is_file().then_some(path)
+    });
+    if let Some(path) = config_path {
+        ProjectConfig::read(&path)
+            .and_then(|config| config.merge_into(args, &matches))
+            .unwrap_or_else(|e| {
+                let msg = format!("Invalid configuration file {}: {}", path.display(), e);
+                command.error(ErrorKind::InvalidValue, msg).exit()
+            });
+    }
+
+    for (flag, given) in [
+        ("--emit-binaries", args.emit_binaries),
+        ("--incremental", args.incremental),
+    ] {
+        if given && args.output_dir.is_none() {
+            let msg = format!("{} requires --output-dir", flag);
+            command
+                .error(ErrorKind::MissingRequiredArgument, msg)
+                .exit();
+        }
+    }
+    if args.check_report.is_some() && !args.check {
+        let msg = "--check-report requires --check";
+        command
+            .error(ErrorKind::MissingRequiredArgument, msg)
+            .exit();
+    }
+    if args.check && !args.reorganize_definitions {
+        let msg = "--check requires --reorganize-definitions, whose c2rust::src_loc \
+                   attributes locate the C declarations";
+        command
+            .error(ErrorKind::MissingRequiredArgument, msg)
+            .exit();
+    }
+    if args.binary_layout == BinaryLayout::Library && args.emit_no_lib {
+        let msg = "--binary-layout library cannot be used with --emit-no-lib";
+        command.error(ErrorKind::ArgumentConflict, msg).exit();
+    }
+    for (flag, given) in [
+        ("--emit-binaries", args.emit_binaries),
+        ("--emit-no-lib", args.emit_no_lib),
+    ] {
+        if given && args.multicall {
+            let msg = format!("--multicall cannot be used with {}", flag);
+            command.error(ErrorKind::ArgumentConflict, msg).exit();
+        }
+    }
+    parsed
 }
 
 #[derive(Debug, PartialEq, Eq, ValueEnum, Clone)]
@@ -185,7 +334,24 @@ enum InvalidCodes {
     CompileError,
 }
 
-pub fn process_args(args: Args) -> (TranspilerConfig, PathBuf, Vec<String>) {
+#[derive(Debug, PartialEq, Eq, ValueEnum, Clone)]
+enum BinaryLayout {
+    Inline,
+    Library,
+}
+
+#[derive(Debug, PartialEq, Eq, ValueEnum, Clone)]
+enum SymbolsFrom {
+    Clang,
+    Objects,
+    Both,
+}
+
+pub fn process_args(
+    args: Args,
+) -> Result<(TranspilerConfig, PathBuf, Vec<String>), TranspileError> {
+    let effective_config = ProjectConfig::effective(&args).to_toml(&args.compile_commands);
+
     // Build a TranspilerConfig from the command line
     let mut tcfg = TranspilerConfig {
         dump_untyped_context: args.dump_untyped_clang_ast,
@@ -206,14 +372,14 @@ pub fn process_args(args: Args) -> (TranspilerConfig, PathBuf, Vec<String>) {
         prefix_function_names: args.prefix_function_names,
 
         // We used to guard asm translation with a command-line
-        // option. Defaulting to enabled now, can add an option to disable if
-        // needed.
-        translate_asm: true,
+        // option. Defaulting to enabled now; it can be disabled in the
+        // configuration file.
+        translate_asm: args.translate_asm.unwrap_or(true),
 
         // We used to guard varargs with a command-line option before nightly
-        // support landed. We may still want to disable this option to target
-        // stable rust output.
-        translate_valist: true,
+        // support landed. It can be disabled in the configuration file to
+        // target stable rust output.
+        translate_valist: args.translate_valist.unwrap_or(true),
 
         translate_const_macros: args.translate_const_macros,
         translate_fn_macros: args.translate_fn_macros,
@@ -229,20 +395,49 @@ pub fn process_args(args: Args) -> (TranspilerConfig, PathBuf, Vec<String>) {
         emit_modules: args.emit_modules,
         emit_build_files: args.emit_build_files,
         emit_binaries: args.emit_binaries,
+        binary_layout: match args.binary_layout {
+            BinaryLayout::Inline => c2rust_transpile::BinaryLayout::Inline,
+            BinaryLayout::Library => c2rust_transpile::BinaryLayout::Library,
+        },
+        multicall: args.multicall,
+        hybrid_build: args.hybrid_build,
+        emit_differential_tests: args.emit_differential_tests,
         output_dir: args.output_dir,
         binaries: args.binary.unwrap_or_default(),
         detect_binaries: args.detect_binary,
         panic_on_translator_failure: args.invalid_code == InvalidCodes::Panic,
-        replace_unsupported_decls: ReplaceMode::Extern,
+        replace_unsupported_decls: args
+            .replace_unsupported_decls
+            .unwrap_or(ReplaceMode::Extern),
         emit_no_std: args.emit_no_std,
         emit_no_lib: args.emit_no_lib,
         enabled_warnings: args.warn.into_iter().collect(),
         log_level: args.log_level,
         dependency_file: args.dependency_file,
         fuzz_depends_level: args.fuzz_depends_level,
+        symbol_source: match args.symbols_from {
+            SymbolsFrom::Clang => c2rust_transpile::SymbolSource::Clang,
+            SymbolsFrom::Objects => c2rust_transpile::SymbolSource::Objects,
+            SymbolsFrom::Both => c2rust_transpile::SymbolSource::Both,
+        },
+        jobs: args.jobs,
+        ast_cache_dir: args.ast_cache_dir,
+        incremental: args.incremental,
+        report_json: args.report_json,
+        report_sarif: args.report_sarif,
+        dry_run: args.dry_run,
+        overrides: args.overrides,
+        effective_config: Some(effective_config),
     };
     // binaries imply emit-build-files
-    if !tcfg.binaries.is_empty() || tcfg.detect_binaries || tcfg.emit_binaries {
+    if !tcfg.binaries.is_empty()
+        || tcfg.detect_binaries
+        || tcfg.emit_binaries
+        || tcfg.multicall
+        || tcfg.hybrid_build
+        || tcfg.emit_differential_tests
+        || args.check
+    {
         tcfg.emit_build_files = true
     };
     // emit-build-files implies emit-modules
@@ -251,12 +446,13 @@ pub fn process_args(args: Args) -> (TranspilerConfig, PathBuf, Vec<String>) {
     };
 
     let cc_json_path = Path::new(&args.compile_commands);
-    let cc_json_path = cc_json_path.canonicalize().unwrap_or_else(|_| {
-        panic!(
-            "Could not find compile_commands.json file at path: {}",
-            cc_json_path.display()
-        )
-    });
-
-    (tcfg, cc_json_path, args.extra_clang_args.clone())
-}
\ No newline at end of file
+    let cc_json_path =
+        cc_json_path
+            .canonicalize()
+            .map_err(|source| TranspileError::CompileCommandsNotFound {
+                path: cc_json_path.to_owned(),
+                source,
+            })?;
+
+    Ok((tcfg, cc_json_path, args.extra_clang_args.clone()))
+}
@@ -63,12 +63,19 @@ impl SubCommand {
     /// Get all known [`SubCommand`]s.  These have no [`SubCommand::path`].
     /// Even if the subcommand executables aren't there, we can still suggest them.
     pub fn known() -> impl Iterator<Item = Self> {
-        ["transpile", "instrument", "pdg", "analyze"]
-            .into_iter()
-            .map(|name| Self {
-                path: None,
-                name: name.into(),
-            })
+        [
+            "transpile",
+            "migrate",
+            "verify",
+            "make-check",
+            "bench",
+            "abi-check",
+        ]
+        .into_iter()
+        .map(|name| Self {
+            path: None,
+            name: name.into(),
+        })
     }
 
     /// Get all known ([`Self::known`]) and actual, found ([`Self::find_all`]) subcommands,
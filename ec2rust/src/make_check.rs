@@ -0,0 +1,588 @@
+//! `ec2rust make-check`: run the test suite of the original GNU-style build
+//! tree against the translated binaries. The executables its link commands
+//! produce, such as `ld/ld-new` or `src/cat`, are replaced by the Rust
+//! builds while the check target runs, and restored afterwards, or with
+//! `--restore` if the run was interrupted.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{anyhow, bail, Context};
+use c2rust_transpile::{linked_executables, TranspilerConfig};
+use clap::{Args, Parser};
+use deps_builder::read_dependencies;
+use log::{info, warn};
+use regex::Regex;
+
+use crate::verify::build_binaries;
+
+#[derive(Debug, Parser)]
+#[clap(
+    name = "ec2rust-make-check",
+    about = "Run the test suite of the original build tree with the translated binaries installed"
+)]
+pub struct MakeCheckArgs {
+    #[clap(flatten)]
+    pub tree: BuildTreeArgs,
+}
+
+/// The build tree whose executables are replaced, and the crate replacing
+/// them
+#[derive(Debug, Args)]
+pub struct BuildTreeArgs {
+    /// Directory of the original build, where make runs
+    #[clap(long, value_name = "DIR")]
+    pub c_build: PathBuf,
+
+    /// Directory of the translated crate
+    #[clap(long = "crate", value_name = "DIR")]
+    pub crate_dir: PathBuf,
+
+    /// Compilation database of the original build, with its link commands (default: compile_commands.json in the build directory)
+    #[clap(long, value_name = "FILE")]
+    compile_commands: Option<PathBuf>,
+
+    /// Dependency information written by the translation, to find the binaries
+    #[clap(long, default_value = "./dependencies.json")]
+    dependency_file: PathBuf,
+
+    /// The make target that runs the test suite
+    #[clap(long, default_value = "check")]
+    pub target: String,
+
+    /// Use the release build of the crate
+    #[clap(long)]
+    release: bool,
+
+    /// Do not run cargo build on the crate first
+    #[clap(long)]
+    no_build: bool,
+
+    /// Only put back the C executables that an interrupted run left replaced
+    #[clap(long)]
+    pub restore: bool,
+
+    /// Extra arguments to make, e.g., -j8
+    #[clap(last = true)]
+    make_args: Vec<String>,
+}
+
+/// Where the C executable `c` is kept while it is replaced
+fn backup_path(c: &Path) -> PathBuf {
+    let mut name = c.file_name().unwrap().to_owned();
+    name.push(".c2rust-orig");
+    c.with_file_name(name)
+}
+
+/// Move the backups of `executables` that exist back in their place, and
+/// return the executables restored
+fn restore_backups(executables: impl IntoIterator<Item = PathBuf>) -> anyhow::Result<Vec<PathBuf>> {
+    let mut restored = vec![];
+    for c in executables {
+        let backup = backup_path(&c);
+        if backup.exists() {
+            fs::rename(&backup, &c)
+                .with_context(|| format!("could not restore {}", c.display()))?;
+            restored.push(c);
+        }
+    }
+    Ok(restored)
+}
+
+/// Put back the C executables of the build tree that an interrupted
+/// `make-check` or `bench` left replaced
+pub fn restore(tree: &BuildTreeArgs) -> anyhow::Result<()> {
+    let executables = linked_executables(&tree.compile_commands())?;
+    let restored = restore_backups(executables.into_iter().map(|e| e.output))?;
+    if restored.is_empty() {
+        eprintln!("No replaced executables in {}", tree.c_build.display());
+    }
+    for c in restored {
+        eprintln!("Restored {}", c.display());
+    }
+    Ok(())
+}
+
+/// Tell how to undo the replacements if the run does not get to it
+pub(crate) fn print_restore_hint(tool: &str) {
+    eprintln!(
+        "If this is interrupted, run ec2rust {} again with --restore to put the C executables back",
+        tool
+    );
+}
+
+/// A C executable of the build tree and the Rust binary that replaces it
+#[derive(Debug)]
+pub struct Replacement {
+    pub c: PathBuf,
+    pub rust: PathBuf,
+}
+
+impl Replacement {
+    /// Where the C executable is kept while it is replaced
+    pub fn backup(&self) -> PathBuf {
+        backup_path(&self.c)
+    }
+}
+
+impl BuildTreeArgs {
+    fn compile_commands(&self) -> PathBuf {
+        self.compile_commands
+            .clone()
+            .unwrap_or_else(|| self.c_build.join("compile_commands.json"))
+    }
+
+    /// Build the crate unless asked not to, and find the replacements
+    pub fn replacements(&self) -> anyhow::Result<Vec<Replacement>> {
+        let target_dir = build_binaries(&self.crate_dir, self.release, !self.no_build)?;
+        let replacements = replacements(self, &target_dir)?;
+        if replacements.is_empty() {
+            bail!("none of the executables of the build has a translated main function");
+        }
+        Ok(replacements)
+    }
+
+    /// Run the check target, saving its output to `log`
+    pub fn run_make(&self, log: &Path) -> anyhow::Result<TestSuiteResults> {
+        let output = Command::new("make")
+            .arg(&self.target)
+            .args(&self.make_args)
+            .current_dir(&self.c_build)
+            .output()
+            .context("could not run make")?;
+        let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+        text.push_str(&String::from_utf8_lossy(&output.stderr));
+        fs::write(log, &text)?;
+        Ok(TestSuiteResults::parse(output.status.success(), &text))
+    }
+}
+
+/// The replacements to install, from the executables that the link commands
+/// produce and the Rust binaries of the translation units defining `main`
+fn replacements(args: &BuildTreeArgs, target_dir: &Path) -> anyhow::Result<Vec<Replacement>> {
+    let executables = linked_executables(&args.compile_commands())?;
+    let dependencies = read_dependencies(&args.dependency_file)
+        .map_err(|e| anyhow!("{}: {}", args.dependency_file.display(), e))?;
+    let mut replacements = vec![];
+    for executable in executables {
+        let main = dependencies.iter().find(|dep| {
+            dep.is_main()
+                && !dep.output_path.is_empty()
+                && executable
+                    .inputs
+                    .iter()
+                    .any(|input| input == Path::new(&dep.input_path))
+        });
+        let main = match main {
+            Some(main) => main,
+            None => {
+                info!(
+                    "{} has no translated main function, keeping it",
+                    executable.output.display()
+                );
+                continue;
+            }
+        };
+        let name = TranspilerConfig::binary_name_from_path(Path::new(&main.output_path));
+        let rust = target_dir.join(name);
+        if !rust.is_file() {
+            warn!(
+                "{} was not built, keeping {}",
+                rust.display(),
+                executable.output.display()
+            );
+            continue;
+        }
+        replacements.push(Replacement {
+            c: executable.output,
+            rust,
+        });
+    }
+    Ok(replacements)
+}
+
+/// The installed replacements; the original executables are restored when
+/// this is dropped, whether or not the test suite completed. A signal like
+/// Ctrl-C skips that, which is what `--restore` is for.
+pub struct Installed<'a> {
+    replacements: &'a [Replacement],
+    installed: usize,
+}
+
+impl<'a> Installed<'a> {
+    /// Install the Rust binaries
+    pub fn install(replacements: &'a [Replacement]) -> anyhow::Result<Self> {
+        // The copy is newer than the objects of the executable, so make does
+        // not link it again
+        Self::install_with(replacements, |replacement| {
+            fs::copy(&replacement.rust, &replacement.c)?;
+            Ok(())
+        })
+    }
+
+    /// Move the C executables to their backups, and put what `install`
+    /// makes in their place
+    pub fn install_with(
+        replacements: &'a [Replacement],
+        install: impl Fn(&Replacement) -> anyhow::Result<()>,
+    ) -> anyhow::Result<Self> {
+        let mut installed = Self {
+            replacements,
+            installed: 0,
+        };
+        for replacement in replacements {
+            let backup = replacement.backup();
+            if backup.exists() {
+                bail!(
+                    "{} already exists; restore it to {} first, e.g., with --restore",
+                    backup.display(),
+                    replacement.c.display()
+                );
+            }
+            fs::rename(&replacement.c, &backup)
+                .with_context(|| format!("could not back up {}", replacement.c.display()))?;
+            installed.installed += 1;
+            install(replacement)
+                .with_context(|| format!("could not replace {}", replacement.c.display()))?;
+        }
+        Ok(installed)
+    }
+}
+
+impl Drop for Installed<'_> {
+    fn drop(&mut self) {
+        for replacement in &self.replacements[..self.installed] {
+            if let Err(e) = fs::rename(replacement.backup(), &replacement.c) {
+                warn!("could not restore {}: {}", replacement.c.display(), e);
+            }
+        }
+    }
+}
+
+/// The outcomes of a test suite run
+#[derive(Debug, Default)]
+pub struct TestSuiteResults {
+    pub success: bool,
+    /// Number of tests by outcome, e.g., `PASS`
+    pub counts: BTreeMap<String, usize>,
+    /// The outcome of each test that the output names
+    pub tests: BTreeMap<String, String>,
+}
+
+/// Outcomes as the Automake test harness and DejaGnu print them
+const OUTCOMES: [&str; 8] = [
+    "PASS",
+    "FAIL",
+    "XPASS",
+    "XFAIL",
+    "SKIP",
+    "ERROR",
+    "UNRESOLVED",
+    "UNSUPPORTED",
+];
+
+/// DejaGnu summary lines, like `# of expected passes    12`
+const DEJAGNU_SUMMARY: [(&str, &str); 7] = [
+    ("expected passes", "PASS"),
+    ("unexpected failures", "FAIL"),
+    ("unexpected successes", "XPASS"),
+    ("expected failures", "XFAIL"),
+    ("unresolved testcases", "UNRESOLVED"),
+    ("unsupported tests", "UNSUPPORTED"),
+    ("untested testcases", "SKIP"),
+];
+
+impl TestSuiteResults {
+    /// Read the results from the output of make. The totals of the Automake
+    /// harness (`# PASS: 12`) and of DejaGnu (`# of expected passes 12`) are
+    /// summed over all test suites; without any, the tests are counted.
+    fn parse(success: bool, output: &str) -> Self {
+        let test = Regex::new(&format!(r"^({}): (.+)$", OUTCOMES.join("|"))).unwrap();
+        let automake_total = Regex::new(&format!(r"^# ({}): +(\d+)$", OUTCOMES.join("|"))).unwrap();
+        let dejagnu_total = Regex::new(r"^# of ([a-z ]+?)\s+(\d+)$").unwrap();
+        let mut results = Self {
+            success,
+            ..Default::default()
+        };
+        let mut totals = BTreeMap::new();
+        for line in output.lines() {
+            let line = line.trim_end();
+            if let Some(c) = automake_total.captures(line) {
+                *totals.entry(c[1].to_owned()).or_default() += c[2].parse::<usize>().unwrap();
+            } else if let Some(c) = dejagnu_total.captures(line) {
+                if let Some((_, outcome)) = DEJAGNU_SUMMARY.iter().find(|(name, _)| *name == &c[1])
+                {
+                    *totals.entry(outcome.to_string()).or_default() +=
+                        c[2].parse::<usize>().unwrap();
+                }
+            } else if let Some(c) = test.captures(line) {
+                results.tests.insert(c[2].to_owned(), c[1].to_owned());
+            }
+        }
+        results.counts = match totals.is_empty() {
+            false => totals,
+            true => {
+                let mut counts = BTreeMap::new();
+                for outcome in results.tests.values() {
+                    *counts.entry(outcome.clone()).or_default() += 1;
+                }
+                counts
+            }
+        };
+        results
+    }
+}
+
+/// The test suite results with the original and with the Rust binaries
+pub struct MakeCheckReport {
+    pub replacements: Vec<Replacement>,
+    pub c: TestSuiteResults,
+    pub rust: TestSuiteResults,
+}
+
+impl MakeCheckReport {
+    fn is_failure(outcome: &str) -> bool {
+        matches!(outcome, "FAIL" | "XPASS" | "ERROR" | "UNRESOLVED")
+    }
+
+    /// Tests that failed with the Rust binaries only
+    pub fn regressions(&self) -> impl Iterator<Item = &str> {
+        self.rust
+            .tests
+            .iter()
+            .filter(|(_, outcome)| Self::is_failure(outcome))
+            .filter(|(test, _)| {
+                self.c
+                    .tests
+                    .get(*test)
+                    .map_or(false, |outcome| !Self::is_failure(outcome))
+            })
+            .map(|(test, _)| test.as_str())
+    }
+
+    pub fn passed(&self) -> bool {
+        self.rust.success && self.regressions().next().is_none()
+    }
+}
+
+impl fmt::Display for MakeCheckReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Replaced {} executables:", self.replacements.len())?;
+        for replacement in &self.replacements {
+            writeln!(
+                f,
+                "  {} <- {}",
+                replacement.c.display(),
+                replacement.rust.display()
+            )?;
+        }
+        let status = |success| if success { "ok" } else { "failed" };
+        writeln!(f, "{:12} {:>8} {:>8}", "", "C", "Rust")?;
+        writeln!(
+            f,
+            "{:12} {:>8} {:>8}",
+            "make",
+            status(self.c.success),
+            status(self.rust.success)
+        )?;
+        let outcomes = self.c.counts.keys().chain(self.rust.counts.keys());
+        let outcomes = outcomes.collect::<BTreeSet<_>>();
+        for outcome in outcomes {
+            let count = |results: &TestSuiteResults| results.counts.get(outcome).copied();
+            writeln!(
+                f,
+                "{:12} {:>8} {:>8}",
+                outcome,
+                count(&self.c).unwrap_or(0),
+                count(&self.rust).unwrap_or(0)
+            )?;
+        }
+        let regressions = self.regressions().collect::<Vec<_>>();
+        if !regressions.is_empty() {
+            writeln!(f, "Failing with the Rust binaries only:")?;
+            for test in regressions {
+                writeln!(f, "  {}", test)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Run the test suite with the original executables, then with the Rust
+/// binaries installed in their place.
+pub fn make_check(args: MakeCheckArgs) -> anyhow::Result<MakeCheckReport> {
+    let tree = args.tree;
+    let replacements = tree.replacements()?;
+    let log_dir = tree.crate_dir.join("target").join("ec2rust-make-check");
+    fs::create_dir_all(&log_dir)?;
+
+    eprintln!("Running make {} with the C executables", tree.target);
+    let c = tree.run_make(&log_dir.join("c.log"))?;
+    eprintln!("Running make {} with the Rust binaries", tree.target);
+    print_restore_hint("make-check");
+    let rust = {
+        let _installed = Installed::install(&replacements)?;
+        tree.run_make(&log_dir.join("rust.log"))?
+    };
+    eprintln!("The output of make is in {}", log_dir.display());
+    Ok(MakeCheckReport {
+        replacements,
+        c,
+        rust,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The end of `make check` with the Automake parallel test harness
+    const AUTOMAKE_LOG: &str = "\
+make  check-TESTS
+PASS: tests/cat/basic.sh
+FAIL: tests/cat/number.sh
+SKIP: tests/cat/tty.sh
+XFAIL: tests/cat/broken.sh
+============================================================================
+Testsuite summary for coreutils 9.1
+============================================================================
+# TOTAL: 4
+# PASS:  1
+# SKIP:  1
+# XFAIL: 1
+# FAIL:  1
+# XPASS: 0
+# ERROR: 0
+============================================================================
+";
+
+    /// The summaries of two DejaGnu tools, as in binutils
+    const DEJAGNU_LOG: &str = "\
+Running /src/ld/testsuite/ld-elf/elf.exp ...
+PASS: ld-elf/start
+FAIL: ld-elf/pr12345
+UNSUPPORTED: ld-elf/x86-only
+
+\t\t=== ld Summary ===
+
+# of expected passes\t\t1
+# of unexpected failures\t1
+# of unsupported tests\t\t1
+Running /src/gas/testsuite/gas/all/gas.exp ...
+PASS: gas/all/cond
+XPASS: gas/all/fixed
+
+\t\t=== gas Summary ===
+
+# of expected passes\t\t1
+# of unexpected successes\t1
+";
+
+    fn counts(results: &TestSuiteResults) -> Vec<(&str, usize)> {
+        results
+            .counts
+            .iter()
+            .map(|(outcome, count)| (outcome.as_str(), *count))
+            .collect()
+    }
+
+    fn results(tests: &[(&str, &str)]) -> TestSuiteResults {
+        TestSuiteResults {
+            success: true,
+            counts: BTreeMap::new(),
+            tests: tests
+                .iter()
+                .map(|(test, outcome)| (test.to_string(), outcome.to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn parse_automake() {
+        let results = TestSuiteResults::parse(false, AUTOMAKE_LOG);
+        assert!(!results.success);
+        assert_eq!(
+            counts(&results),
+            [
+                ("ERROR", 0),
+                ("FAIL", 1),
+                ("PASS", 1),
+                ("SKIP", 1),
+                ("XFAIL", 1),
+                ("XPASS", 0)
+            ]
+        );
+        assert_eq!(results.tests.len(), 4);
+        assert_eq!(results.tests["tests/cat/number.sh"], "FAIL");
+    }
+
+    #[test]
+    fn parse_dejagnu() {
+        let results = TestSuiteResults::parse(true, DEJAGNU_LOG);
+        // The totals of both tools add up
+        assert_eq!(
+            counts(&results),
+            [("FAIL", 1), ("PASS", 2), ("UNSUPPORTED", 1), ("XPASS", 1)]
+        );
+        assert_eq!(results.tests["ld-elf/pr12345"], "FAIL");
+        assert_eq!(results.tests["gas/all/fixed"], "XPASS");
+    }
+
+    #[test]
+    fn parse_without_totals() {
+        let results = TestSuiteResults::parse(true, "PASS: a\nPASS: b\nERROR: c\nother output\n");
+        assert_eq!(counts(&results), [("ERROR", 1), ("PASS", 2)]);
+    }
+
+    #[test]
+    fn regressions() {
+        let report = MakeCheckReport {
+            replacements: vec![],
+            c: results(&[
+                ("fixed", "FAIL"),
+                ("broken", "PASS"),
+                ("still-failing", "FAIL"),
+                ("unresolved", "SKIP"),
+                ("kept", "PASS"),
+            ]),
+            rust: results(&[
+                ("fixed", "PASS"),
+                ("broken", "FAIL"),
+                ("still-failing", "FAIL"),
+                ("unresolved", "UNRESOLVED"),
+                ("kept", "PASS"),
+                ("rust-only", "FAIL"),
+            ]),
+        };
+        // Tests that the C run did not name are not regressions
+        assert_eq!(
+            report.regressions().collect::<Vec<_>>(),
+            ["broken", "unresolved"]
+        );
+        assert!(!report.passed());
+    }
+
+    #[test]
+    fn restore_interrupted_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let c = dir.path().join("cat");
+        let rust = dir.path().join("cat-rust");
+        fs::write(&c, "c").unwrap();
+        fs::write(&rust, "rust").unwrap();
+        let replacements = [Replacement { c: c.clone(), rust }];
+        let installed = Installed::install(&replacements).unwrap();
+        // As if interrupted
+        std::mem::forget(installed);
+        assert_eq!(fs::read_to_string(&c).unwrap(), "rust");
+        assert!(Installed::install(&replacements).is_err());
+
+        let other = dir.path().join("ls");
+        let restored = restore_backups([c.clone(), other]).unwrap();
+        assert_eq!(restored, [c.clone()]);
+        assert_eq!(fs::read_to_string(&c).unwrap(), "c");
+        assert!(!replacements[0].backup().exists());
+    }
+}
@@ -0,0 +1,295 @@
+//! `ec2rust migrate`: export the dependencies, build the dependency graph,
+//! translate and `cargo check` the result in one process, stopping at the
+//! first stage that fails, and report on all of them together.
+
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+
+use clap::{CommandFactory, ErrorKind, Parser};
+use deps_builder::{build_dependency, DependencyInfo};
+use serde::Serialize;
+
+use c2rust_transpile::{
+    export_for_transpile, transpile_exported, TranspileError, TranspileSummary,
+};
+
+use crate::check::{cargo_check, CheckOptions, CheckReport, CheckStats};
+use crate::{parse_args_with, process_args, Args};
+
+#[derive(Debug, Parser)]
+pub struct MigrateArgs {
+    #[clap(flatten)]
+    args: Args,
+
+    /// Write the combined report of all stages as JSON to FILE
+    #[clap(long, value_name = "FILE")]
+    migrate_report: Option<PathBuf>,
+
+    /// Stop after the translation instead of running cargo check on the result
+    #[clap(long)]
+    no_check: bool,
+}
+
+/// Parse the command line of `ec2rust migrate`, like [`crate::parse_args`].
+pub fn parse_migrate_args() -> MigrateArgs {
+    let command = MigrateArgs::command()
+        .name("ec2rust-migrate")
+        .about("Translate C code to Rust and check the result, in one go");
+    let args = parse_args_with(command, |migrate: &mut MigrateArgs| &mut migrate.args);
+    if args.args.output_dir.is_none() {
+        MigrateArgs::command()
+            .error(
+                ErrorKind::MissingRequiredArgument,
+                "ec2rust migrate requires --output-dir",
+            )
+            .exit();
+    }
+    args
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum StageStatus {
+    Ok,
+    Failed,
+    Skipped,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Stage {
+    pub name: &'static str,
+    pub status: StageStatus,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DependencyStats {
+    /// Translation units, including assembly files
+    pub files: usize,
+    /// Files that define `main`
+    pub binaries: usize,
+    /// Dependencies between files
+    pub edges: usize,
+    /// Symbols that files use but no file defines, e.g., from libc
+    pub external_symbols: usize,
+}
+
+impl DependencyStats {
+    fn new(dependencies: &[DependencyInfo], fuzz_depends_level: usize) -> Self {
+        let defined = dependencies
+            .iter()
+            .flat_map(|dep| &dep.defined)
+            .map(|symbol| &symbol.name)
+            .collect::<HashSet<_>>();
+        let external_symbols = dependencies
+            .iter()
+            .flat_map(|dep| &dep.undefined)
+            .map(|symbol| &symbol.name)
+            .filter(|name| !defined.contains(name))
+            .collect::<HashSet<_>>()
+            .len();
+        let graph = build_dependency(dependencies.to_vec(), fuzz_depends_level);
+        Self {
+            files: graph.nodes.len(),
+            binaries: graph.nodes.iter().filter(|node| node.is_main()).count(),
+            edges: graph.edges.iter().map(Vec::len).sum(),
+            external_symbols,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct TranslationStats {
+    /// Rust files emitted or found up to date
+    pub outputs: usize,
+    /// Number of failures by kind
+    pub failures: BTreeMap<&'static str, usize>,
+    /// Failures caused by translator bugs
+    pub panics: usize,
+}
+
+impl TranslationStats {
+    fn new(summary: &TranspileSummary) -> Self {
+        let mut failures = BTreeMap::new();
+        for failure in &summary.failures {
+            *failures.entry(failure.kind.id()).or_default() += 1;
+        }
+        Self {
+            outputs: summary.outputs.len(),
+            failures,
+            panics: summary.panics().count(),
+        }
+    }
+}
+
+/// The combined report of all stages of a migration
+#[derive(Debug, Serialize)]
+pub struct MigrationReport {
+    pub stages: Vec<Stage>,
+    pub dependencies: Option<DependencyStats>,
+    pub translation: Option<TranslationStats>,
+    pub check: Option<CheckStats>,
+}
+
+impl MigrationReport {
+    pub fn succeeded(&self) -> bool {
+        self.stages
+            .iter()
+            .all(|stage| !matches!(stage.status, StageStatus::Failed))
+    }
+
+    fn finish(&mut self, name: &'static str, result: Result<(), String>) {
+        let (status, error) = match result {
+            Ok(()) => (StageStatus::Ok, None),
+            Err(e) => (StageStatus::Failed, Some(e)),
+        };
+        self.stages.push(Stage {
+            name,
+            status,
+            error,
+        });
+    }
+
+    fn skip(&mut self, names: &[&'static str]) {
+        for &name in names {
+            self.stages.push(Stage {
+                name,
+                status: StageStatus::Skipped,
+                error: None,
+            });
+        }
+    }
+}
+
+impl fmt::Display for MigrationReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for stage in &self.stages {
+            match (&stage.status, &stage.error) {
+                (StageStatus::Failed, Some(error)) => {
+                    writeln!(f, "{}: failed: {}", stage.name, error)?
+                }
+                (status, _) => writeln!(f, "{}: {:?}", stage.name, status)?,
+            }
+        }
+        if let Some(deps) = &self.dependencies {
+            writeln!(
+                f,
+                "Dependency graph: {} files, {} binaries, {} edges, {} external symbols",
+                deps.files, deps.binaries, deps.edges, deps.external_symbols
+            )?;
+        }
+        if let Some(translation) = &self.translation {
+            write!(f, "Translation: {} Rust files", translation.outputs)?;
+            for (kind, count) in &translation.failures {
+                write!(f, ", {} {}", count, kind)?;
+            }
+            writeln!(f, " ({} translator panics)", translation.panics)?;
+        }
+        if let Some(check) = &self.check {
+            write!(f, "cargo check: {} errors", check.errors)?;
+            if !check.error_codes.is_empty() {
+                let codes = check
+                    .error_codes
+                    .iter()
+                    .map(|(code, count)| format!("{}: {}", code, count))
+                    .collect::<Vec<_>>();
+                write!(f, " ({})", codes.join(", "))?;
+            }
+            writeln!(f, ", {} warnings", check.warnings)?;
+        }
+        Ok(())
+    }
+}
+
+/// Run the whole pipeline. Errors in the arguments are returned; failing
+/// stages are recorded in the report.
+pub fn migrate(migrate_args: MigrateArgs) -> Result<MigrationReport, TranspileError> {
+    let MigrateArgs {
+        args,
+        migrate_report,
+        no_check,
+    } = migrate_args;
+    let check_options = CheckOptions::from_args(&args);
+    let (mut tcfg, cc_json_path, extra_args) = process_args(args)?;
+    // The translation has to be a crate to check it
+    tcfg.emit_build_files = true;
+    tcfg.emit_modules = true;
+    let extra_args = extra_args.iter().map(AsRef::as_ref).collect::<Vec<_>>();
+    let output_dir = tcfg.output_dir.clone().unwrap();
+    let mut report = MigrationReport {
+        stages: vec![],
+        dependencies: None,
+        translation: None,
+        check: None,
+    };
+
+    let exported = export_for_transpile(&tcfg, &cc_json_path, &extra_args);
+    let exported = match exported {
+        Ok(exported) => {
+            report.finish("export", Ok(()));
+            exported
+        }
+        Err(e) => {
+            report.finish("export", Err(e.to_string()));
+            report.skip(&["dependency graph", "transpile", "check"]);
+            return write_report(report, migrate_report);
+        }
+    };
+
+    let stats = DependencyStats::new(&exported.dependencies, tcfg.fuzz_depends_level);
+    report.dependencies = Some(stats);
+    report.finish("dependency graph", Ok(()));
+
+    let dry_run = tcfg.dry_run;
+    let summary = match transpile_exported(tcfg, &cc_json_path, &extra_args, exported) {
+        Ok(summary) => {
+            report.translation = Some(TranslationStats::new(&summary));
+            report.finish("transpile", Ok(()));
+            summary
+        }
+        Err(e) => {
+            report.finish("transpile", Err(e.to_string()));
+            report.skip(&["check"]);
+            return write_report(report, migrate_report);
+        }
+    };
+
+    if no_check || dry_run {
+        report.skip(&["check"]);
+        return write_report(report, migrate_report);
+    }
+    let result = cargo_check(&output_dir).map_err(|e| e.to_string());
+    let result = result.and_then(|output| {
+        if let Some(options) = &check_options {
+            let check_report = CheckReport::new(&output, &output_dir, &summary.dependencies);
+            print!("{}", check_report);
+            if let Some(path) = &options.report {
+                let json = serde_json::to_string_pretty(&check_report).unwrap();
+                fs::write(path, json).map_err(|e| format!("{}: {}", path.display(), e))?;
+            }
+        }
+        let stats = output.stats();
+        let success = stats.success;
+        report.check = Some(stats);
+        match success {
+            true => Ok(()),
+            false => Err("the translated crate does not compile".to_string()),
+        }
+    });
+    report.finish("check", result);
+    write_report(report, migrate_report)
+}
+
+fn write_report(
+    report: MigrationReport,
+    path: Option<PathBuf>,
+) -> Result<MigrationReport, TranspileError> {
+    print!("{}", report);
+    if let Some(path) = path {
+        let json = serde_json::to_string_pretty(&report).unwrap();
+        fs::write(&path, json).map_err(|source| TranspileError::Io { path, source })?;
+    }
+    Ok(report)
+}
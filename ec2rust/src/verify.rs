@@ -0,0 +1,629 @@
+//! `ec2rust verify`: differential testing of the translated binaries. Every
+//! test of a spec runs the C and the Rust build of a binary in fresh
+//! temporary directories, and compares their exit status, standard output,
+//! standard error and output files. A run that takes longer than the timeout
+//! of its test is killed and the test fails.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::os::unix::process::CommandExt;
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Output, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Context};
+use c2rust_transpile::TranspilerConfig;
+use clap::Parser;
+use deps_builder::{build_dependency, read_dependencies};
+use is_executable::IsExecutable;
+use log::warn;
+use serde::Deserialize;
+
+#[derive(Debug, Parser)]
+#[clap(
+    name = "ec2rust-verify",
+    about = "Run the C and Rust builds of the translated binaries side by side and compare them"
+)]
+pub struct VerifyArgs {
+    /// Directory of the original C build
+    #[clap(long, value_name = "DIR")]
+    c_build: PathBuf,
+
+    /// Directory of the translated crate
+    #[clap(long = "crate", value_name = "DIR")]
+    crate_dir: PathBuf,
+
+    /// Test spec in TOML
+    #[clap(long, value_name = "FILE")]
+    spec: PathBuf,
+
+    /// Dependency information written by the translation, to find the binaries
+    #[clap(long, default_value = "./dependencies.json")]
+    dependency_file: PathBuf,
+
+    /// Use the release build of the crate
+    #[clap(long)]
+    release: bool,
+
+    /// Do not run cargo build on the crate first
+    #[clap(long)]
+    no_build: bool,
+}
+
+/// A test spec: the tests, and where to find the C binaries that are not
+/// simply the only executable of their name in the C build directory.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Spec {
+    /// C binary paths by name, relative to the C build directory
+    binaries: BTreeMap<String, PathBuf>,
+    /// Seconds each run of a test may take, unless the test says otherwise
+    timeout: Option<u64>,
+    #[serde(rename = "test")]
+    tests: Vec<TestSpec>,
+}
+
+/// One `[[test]]`: a command line of a binary, and what it runs in
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct TestSpec {
+    name: String,
+    binary: String,
+    args: Vec<String>,
+    stdin: Option<String>,
+    env: BTreeMap<String, String>,
+    /// Files created in the directory of the test before it runs
+    files: BTreeMap<PathBuf, String>,
+    /// Files the test writes, compared afterwards
+    outputs: Vec<PathBuf>,
+    /// Seconds each run of the test may take
+    timeout: Option<u64>,
+}
+
+/// How long a run of a test may take if the spec does not say
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+
+impl Spec {
+    pub fn read(path: &Path) -> anyhow::Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        let spec: Self = toml::from_str(&contents)?;
+        for test in &spec.tests {
+            if test.name.is_empty() || test.binary.is_empty() {
+                bail!("every test needs a name and a binary");
+            }
+        }
+        Ok(spec)
+    }
+
+    fn timeout(&self, test: &TestSpec) -> Duration {
+        test.timeout
+            .or(self.timeout)
+            .map_or(DEFAULT_TIMEOUT, Duration::from_secs)
+    }
+}
+
+/// Read all of `pipe` in another thread
+fn read_pipe(mut pipe: impl Read + Send + 'static) -> thread::JoinHandle<io::Result<Vec<u8>>> {
+    thread::spawn(move || {
+        let mut buf = vec![];
+        pipe.read_to_end(&mut buf)?;
+        Ok(buf)
+    })
+}
+
+/// Wait for `child` to exit, for at most `timeout`
+fn wait_timeout(child: &mut Child, timeout: Duration) -> io::Result<Option<ExitStatus>> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if Instant::now() >= deadline {
+            return Ok(None);
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// What a run of one build of a binary did
+struct Run {
+    output: Output,
+    /// Contents of the output files, `None` for files not written
+    files: Vec<Option<Vec<u8>>>,
+}
+
+impl TestSpec {
+    /// Run `binary` in a fresh directory set up as the test says, killing it
+    /// after `timeout`
+    fn run(&self, binary: &Path, timeout: Duration) -> anyhow::Result<Run> {
+        let dir = tempfile::tempdir()?;
+        for (path, contents) in &self.files {
+            let path = dir.path().join(path);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(path, contents)?;
+        }
+        let mut child = Command::new(binary)
+            // Both builds see the same program name, e.g., in their messages
+            .arg0(&self.binary)
+            .args(&self.args)
+            .envs(&self.env)
+            .current_dir(dir.path())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("could not run {}", binary.display()))?;
+        let mut stdin = child.stdin.take().unwrap();
+        let input = self.stdin.clone().unwrap_or_default();
+        // Write from another thread so that a binary that does not read all
+        // of its input cannot block us
+        let writer = thread::spawn(move || stdin.write_all(input.as_bytes()));
+        let stdout = read_pipe(child.stdout.take().unwrap());
+        let stderr = read_pipe(child.stderr.take().unwrap());
+        let status = match wait_timeout(&mut child, timeout)? {
+            Some(status) => status,
+            None => {
+                let _ = child.kill();
+                let _ = child.wait();
+                bail!(
+                    "{} timed out after {} s",
+                    binary.display(),
+                    timeout.as_secs_f64()
+                );
+            }
+        };
+        let _ = writer.join();
+        let output = Output {
+            status,
+            stdout: stdout.join().unwrap()?,
+            stderr: stderr.join().unwrap()?,
+        };
+        let files = self
+            .outputs
+            .iter()
+            .map(|path| fs::read(dir.path().join(path)).ok())
+            .collect();
+        Ok(Run { output, files })
+    }
+}
+
+/// The outcome of one aspect of a test
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Comparison {
+    Same,
+    Different,
+    /// The test has nothing to compare, e.g., no output files
+    NotApplicable,
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // `pad`, so that the report can align the columns
+        f.pad(match self {
+            Comparison::Same => "ok",
+            Comparison::Different => "DIFF",
+            Comparison::NotApplicable => "-",
+        })
+    }
+}
+
+fn compare<T: PartialEq>(c: T, rust: T) -> Comparison {
+    match c == rust {
+        true => Comparison::Same,
+        false => Comparison::Different,
+    }
+}
+
+fn describe_status(status: &ExitStatus) -> String {
+    match (status.code(), status.signal()) {
+        (Some(code), _) => format!("exit code {}", code),
+        (None, Some(signal)) => format!("signal {}", signal),
+        (None, None) => status.to_string(),
+    }
+}
+
+/// The line number of the first line where `c` and `rust` differ
+fn first_difference(c: &[u8], rust: &[u8]) -> usize {
+    let c_lines = c.split(|&b| b == b'\n');
+    let rust_lines = rust.split(|&b| b == b'\n');
+    c_lines
+        .zip(rust_lines)
+        .position(|(c, rust)| c != rust)
+        .unwrap_or_else(|| {
+            c.iter()
+                .filter(|&&b| b == b'\n')
+                .count()
+                .min(rust.iter().filter(|&&b| b == b'\n').count())
+        })
+        + 1
+}
+
+/// The result of a test
+pub struct TestResult {
+    pub binary: String,
+    pub test: String,
+    status: Comparison,
+    stdout: Comparison,
+    stderr: Comparison,
+    files: Comparison,
+    /// Why the test failed, or could not run
+    notes: Vec<String>,
+}
+
+impl TestResult {
+    pub fn passed(&self) -> bool {
+        self.notes.is_empty()
+    }
+
+    fn error(test: &TestSpec, error: anyhow::Error) -> Self {
+        Self {
+            binary: test.binary.clone(),
+            test: test.name.clone(),
+            status: Comparison::NotApplicable,
+            stdout: Comparison::NotApplicable,
+            stderr: Comparison::NotApplicable,
+            files: Comparison::NotApplicable,
+            notes: vec![format!("error: {:#}", error)],
+        }
+    }
+
+    fn new(test: &TestSpec, c: &Run, rust: &Run) -> Self {
+        let mut notes = vec![];
+        let status = compare(c.output.status, rust.output.status);
+        if status == Comparison::Different {
+            notes.push(format!(
+                "C {}, Rust {}",
+                describe_status(&c.output.status),
+                describe_status(&rust.output.status)
+            ));
+        }
+        let mut stream = |name, c: &[u8], rust: &[u8]| {
+            let comparison = compare(c, rust);
+            if comparison == Comparison::Different {
+                notes.push(format!(
+                    "{} differs from line {}",
+                    name,
+                    first_difference(c, rust)
+                ));
+            }
+            comparison
+        };
+        let stdout = stream("stdout", &c.output.stdout, &rust.output.stdout);
+        let stderr = stream("stderr", &c.output.stderr, &rust.output.stderr);
+        let mut files = match test.outputs.is_empty() {
+            true => Comparison::NotApplicable,
+            false => Comparison::Same,
+        };
+        for ((path, c), rust) in test.outputs.iter().zip(&c.files).zip(&rust.files) {
+            if compare(c, rust) == Comparison::Different {
+                files = Comparison::Different;
+                notes.push(match (c, rust) {
+                    (Some(c), Some(rust)) => format!(
+                        "{} differs from line {}",
+                        path.display(),
+                        first_difference(c, rust)
+                    ),
+                    (Some(_), None) => format!("{} not written by Rust", path.display()),
+                    _ => format!("{} not written by C", path.display()),
+                });
+            }
+        }
+        Self {
+            binary: test.binary.clone(),
+            test: test.name.clone(),
+            status,
+            stdout,
+            stderr,
+            files,
+            notes,
+        }
+    }
+}
+
+/// The pass/fail matrix of all binaries
+pub struct Verification {
+    pub results: Vec<TestResult>,
+    /// Binaries of the dependency graph without any test
+    pub untested: Vec<String>,
+}
+
+impl Verification {
+    pub fn passed(&self) -> bool {
+        self.results.iter().all(TestResult::passed)
+    }
+}
+
+impl fmt::Display for Verification {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let binary_width = self
+            .results
+            .iter()
+            .map(|r| r.binary.len())
+            .chain(self.untested.iter().map(String::len))
+            .chain(["binary".len()])
+            .max()
+            .unwrap();
+        let test_width = self
+            .results
+            .iter()
+            .map(|r| r.test.len())
+            .chain(["test".len()])
+            .max()
+            .unwrap();
+        writeln!(
+            f,
+            "{:bw$}  {:tw$}  status stdout stderr files  result",
+            "binary",
+            "test",
+            bw = binary_width,
+            tw = test_width
+        )?;
+        for r in &self.results {
+            writeln!(
+                f,
+                "{:bw$}  {:tw$}  {:6} {:6} {:6} {:6} {}",
+                r.binary,
+                r.test,
+                r.status,
+                r.stdout,
+                r.stderr,
+                r.files,
+                if r.passed() { "pass" } else { "FAIL" },
+                bw = binary_width,
+                tw = test_width
+            )?;
+        }
+        for binary in &self.untested {
+            writeln!(f, "{:bw$}  (no tests)", binary, bw = binary_width)?;
+        }
+        for r in self.results.iter().filter(|r| !r.passed()) {
+            writeln!(f, "\n{} {}:", r.binary, r.test)?;
+            for note in &r.notes {
+                writeln!(f, "  {}", note)?;
+            }
+        }
+        let passed = self.results.iter().filter(|r| r.passed()).count();
+        writeln!(f, "\n{} of {} tests passed", passed, self.results.len())
+    }
+}
+
+/// The only executable file named `name` under `dir`, searched breadth-first
+fn find_executable(dir: &Path, name: &str) -> Option<PathBuf> {
+    let mut found = vec![];
+    let mut dirs = vec![dir.to_owned()];
+    while !dirs.is_empty() {
+        let mut next = vec![];
+        for dir in dirs {
+            let entries = match fs::read_dir(&dir) {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+            for entry in entries.flatten() {
+                let path = entry.path();
+                let file_type = match entry.file_type() {
+                    Ok(file_type) => file_type,
+                    Err(_) => continue,
+                };
+                if file_type.is_dir() {
+                    next.push(path);
+                } else if entry.file_name() == name && path.is_executable() {
+                    found.push(path);
+                }
+            }
+        }
+        // Prefer the shallowest match
+        if !found.is_empty() {
+            break;
+        }
+        dirs = next;
+    }
+    if found.len() > 1 {
+        found.sort();
+        warn!(
+            "several C binaries named {}, using {}; name one in [binaries] of the spec",
+            name,
+            found[0].display()
+        );
+    }
+    found.into_iter().next()
+}
+
+/// Build the binaries of the crate in `crate_dir` if `build` is set, and
+/// return the directory they are in.
+pub(crate) fn build_binaries(
+    crate_dir: &Path,
+    release: bool,
+    build: bool,
+) -> anyhow::Result<PathBuf> {
+    build_crate(crate_dir, "--bins", release, build)
+}
+
+/// Build the `targets` (a target selection option of `cargo build`) of the
+/// crate in `crate_dir` if `build` is set, and return the directory they are
+/// in.
+pub(crate) fn build_crate(
+    crate_dir: &Path,
+    targets: &str,
+    release: bool,
+    build: bool,
+) -> anyhow::Result<PathBuf> {
+    if build {
+        let mut cargo = Command::new("cargo");
+        cargo.args(["build", targets]).current_dir(crate_dir);
+        if release {
+            cargo.arg("--release");
+        }
+        if !cargo.status()?.success() {
+            bail!("the translated crate does not build");
+        }
+    }
+    let profile = if release { "release" } else { "debug" };
+    Ok(crate_dir.join("target").join(profile))
+}
+
+/// Build the crate unless asked not to, then run every test of the spec.
+pub fn verify(args: VerifyArgs) -> anyhow::Result<Verification> {
+    let spec = Spec::read(&args.spec)
+        .with_context(|| format!("invalid test spec {}", args.spec.display()))?;
+    let dependencies = read_dependencies(&args.dependency_file)
+        .map_err(|e| anyhow!("{}: {}", args.dependency_file.display(), e))?;
+    let graph = build_dependency(dependencies, 0);
+    let binaries = graph
+        .nodes
+        .iter()
+        .filter(|node| node.is_main() && !node.output_path.is_empty())
+        .map(|node| TranspilerConfig::binary_name_from_path(Path::new(&node.output_path)))
+        .collect::<Vec<_>>();
+
+    let target_dir = build_binaries(&args.crate_dir, args.release, !args.no_build)?;
+
+    let mut c_binaries = HashMap::new();
+    let mut results = vec![];
+    for test in &spec.tests {
+        if !binaries.contains(&test.binary) {
+            let error = anyhow!("no binary {} in the dependency graph", test.binary);
+            results.push(TestResult::error(test, error));
+            continue;
+        }
+        let c_binary = c_binaries
+            .entry(test.binary.clone())
+            .or_insert_with(|| match spec.binaries.get(&test.binary) {
+                Some(path) => Some(args.c_build.join(path)),
+                None => find_executable(&args.c_build, &test.binary),
+            })
+            .clone();
+        let c_binary = match c_binary {
+            Some(path) => path,
+            None => {
+                let error = anyhow!("no C binary {} in {}", test.binary, args.c_build.display());
+                results.push(TestResult::error(test, error));
+                continue;
+            }
+        };
+        let rust_binary = target_dir.join(&test.binary);
+        let timeout = spec.timeout(test);
+        let runs = test
+            .run(&c_binary, timeout)
+            .and_then(|c| Ok((c, test.run(&rust_binary, timeout)?)));
+        results.push(match runs {
+            Ok((c, rust)) => TestResult::new(test, &c, &rust),
+            Err(e) => TestResult::error(test, e),
+        });
+    }
+    let untested = binaries
+        .into_iter()
+        .filter(|binary| !spec.tests.iter().any(|test| &test.binary == binary))
+        .collect();
+    Ok(Verification { results, untested })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPEC: &str = r#"
+timeout = 5
+
+[binaries]
+cat = "src/cat"
+
+[[test]]
+name = "number-lines"
+binary = "cat"
+args = ["-n", "input.txt"]
+files = { "input.txt" = "one\ntwo\n" }
+
+[[test]]
+name = "slow"
+binary = "cat"
+timeout = 1
+"#;
+
+    fn read_spec(contents: &str) -> anyhow::Result<Spec> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("spec.toml");
+        fs::write(&path, contents)?;
+        Spec::read(&path)
+    }
+
+    fn sh(script: &str) -> TestSpec {
+        TestSpec {
+            name: "sh".to_owned(),
+            binary: "sh".to_owned(),
+            args: vec!["-c".to_owned(), script.to_owned()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn read_spec_file() {
+        let spec = read_spec(SPEC).unwrap();
+        assert_eq!(spec.binaries["cat"], Path::new("src/cat"));
+        assert_eq!(spec.tests.len(), 2);
+        let test = &spec.tests[0];
+        assert_eq!(test.args, ["-n", "input.txt"]);
+        assert_eq!(test.files[Path::new("input.txt")], "one\ntwo\n");
+        assert_eq!(spec.timeout(test), Duration::from_secs(5));
+        assert_eq!(spec.timeout(&spec.tests[1]), Duration::from_secs(1));
+        assert_eq!(Spec::default().timeout(test), DEFAULT_TIMEOUT);
+    }
+
+    #[test]
+    fn invalid_spec_files() {
+        let error = read_spec("[[test]]\nname = \"no-binary\"\n").unwrap_err();
+        assert_eq!(error.to_string(), "every test needs a name and a binary");
+        assert!(read_spec("[[test]]\nname = \"a\"\nbinary = \"b\"\nstdout = \"\"\n").is_err());
+        assert!(read_spec("timeout = \"never\"\n").is_err());
+    }
+
+    #[test]
+    fn first_differences() {
+        assert_eq!(first_difference(b"a\nb\nc\n", b"a\nx\nc\n"), 2);
+        assert_eq!(first_difference(b"a\n", b"b\n"), 1);
+        // One output is a prefix of the other
+        assert_eq!(first_difference(b"a\nb\n", b"a\nb\nc\n"), 3);
+        assert_eq!(first_difference(b"", b"a\n"), 1);
+        // Only the final newline differs
+        assert_eq!(first_difference(b"a\nb", b"a\nb\n"), 2);
+    }
+
+    #[test]
+    fn comparisons_fill_their_column() {
+        assert_eq!(format!("{:6}|", Comparison::Same), "ok    |");
+        assert_eq!(format!("{:6}|", Comparison::Different), "DIFF  |");
+    }
+
+    #[test]
+    fn run_in_test_directory() {
+        let mut test = sh("cat input.txt; echo err >&2; echo out > out.txt; exit 3");
+        test.files.insert("input.txt".into(), "in\n".to_owned());
+        test.outputs = vec!["out.txt".into(), "missing.txt".into()];
+        let run = test.run(Path::new("/bin/sh"), DEFAULT_TIMEOUT).unwrap();
+        assert_eq!(run.output.status.code(), Some(3));
+        assert_eq!(run.output.stdout, b"in\n");
+        assert_eq!(run.output.stderr, b"err\n");
+        assert_eq!(run.files, [Some(b"out\n".to_vec()), None]);
+    }
+
+    #[test]
+    fn run_times_out() {
+        let test = sh("sleep 10");
+        let start = Instant::now();
+        let error = test
+            .run(Path::new("/bin/sh"), Duration::from_millis(100))
+            .err()
+            .unwrap();
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(error.to_string(), "/bin/sh timed out after 0.1 s");
+
+        let result = TestResult::error(&test, error);
+        assert!(!result.passed());
+        assert_eq!(result.notes, ["error: /bin/sh timed out after 0.1 s"]);
+    }
+}
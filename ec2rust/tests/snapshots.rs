@@ -0,0 +1,542 @@
+//! Golden snapshot tests of the transpiler.
+//!
+//! Each directory in `tests/snapshots` is a case: a small C program, or
+//! library, made of the `.c` and `.h` files in it. The test writes a
+//! `compile_commands.json` for the case, translates it with
+//! `ec2rust-transpile` into a crate, and compares every `.rs` file of the
+//! crate with the snapshot of the same path in the `expected` directory of
+//! the case. A case with a `main.c` is translated into a binary named `main`.
+//!
+//! The test reads these environment variables:
+//!
+//! - `EC2RUST_BLESS=1` - Replace the snapshots with the current translation
+//!   instead of comparing them.
+//! - `EC2RUST_SNAPSHOT_BUILD=1` - Also `cargo build` the translation of each
+//!   binary, and compare the exit status and standard output of the Rust and
+//!   the C program, built with `cc`.
+//! - `EC2RUST_SNAPSHOT_FILTER=<substring>` - Only run the cases whose name
+//!   contains the substring.
+//!
+//! A case without an `expected` directory fails; `EC2RUST_BLESS=1` writes it.
+//! Until the snapshots of the cases are committed, the test is ignored: run it
+//! with `cargo test -p ec2rust --test snapshots -- --include-ignored`.
+//!
+//! The same cases also check that translating again gives the same files,
+//! byte for byte, whatever the order of `compile_commands.json` and the
+//! number of jobs.
+
+use std::env;
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+use object::{Object, ObjectSymbol};
+
+/// The placeholder for the directory a case is translated in, which the
+/// translation mentions in `#[path]` attributes and such
+const DIR_PLACEHOLDER: &str = "$DIR";
+
+fn snapshots_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/snapshots")
+}
+
+fn env_flag(name: &str) -> bool {
+    env::var_os(name).map_or(false, |value| value != "0" && !value.is_empty())
+}
+
+/// The cases in `tests/snapshots`, by name, in order.
+fn cases() -> Vec<(String, PathBuf)> {
+    let filter = env::var("EC2RUST_SNAPSHOT_FILTER").unwrap_or_default();
+    let mut cases = fs::read_dir(snapshots_dir())
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.is_dir())
+        .map(|path| {
+            (
+                path.file_name().unwrap().to_string_lossy().into_owned(),
+                path,
+            )
+        })
+        .filter(|(name, _)| name.contains(&filter))
+        .collect::<Vec<_>>();
+    cases.sort();
+    cases
+}
+
+/// The files in `dir`, or those with the extension `ext`, recursively,
+/// relative to `dir` and sorted. Build output in `target` is skipped.
+fn files_in(dir: &Path, ext: Option<&str>) -> io::Result<Vec<PathBuf>> {
+    fn walk(
+        root: &Path,
+        dir: &Path,
+        ext: Option<&str>,
+        files: &mut Vec<PathBuf>,
+    ) -> io::Result<()> {
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                if path.file_name().map_or(false, |name| name != "target") {
+                    walk(root, &path, ext, files)?;
+                }
+            } else if ext.map_or(true, |ext| path.extension().map_or(false, |e| e == ext)) {
+                files.push(path.strip_prefix(root).unwrap().to_owned());
+            }
+        }
+        Ok(())
+    }
+    let mut files = vec![];
+    if dir.is_dir() {
+        walk(dir, dir, ext, &mut files)?;
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// A case, copied into a scratch directory so that the paths in its
+/// translation do not depend on where the repository is.
+struct Case {
+    source_dir: PathBuf,
+    work_dir: PathBuf,
+}
+
+impl Case {
+    fn new(source_dir: &Path, work_dir: PathBuf) -> io::Result<Self> {
+        if work_dir.exists() {
+            fs::remove_dir_all(&work_dir)?;
+        }
+        fs::create_dir_all(&work_dir)?;
+        for ext in ["c", "h"] {
+            for file in files_in(source_dir, Some(ext))? {
+                fs::copy(source_dir.join(&file), work_dir.join(&file))?;
+            }
+        }
+        Ok(Self {
+            source_dir: source_dir.to_owned(),
+            work_dir,
+        })
+    }
+
+    fn is_binary(&self) -> bool {
+        self.work_dir.join("main.c").exists()
+    }
+
+    fn sources(&self) -> io::Result<Vec<PathBuf>> {
+        files_in(&self.work_dir, Some("c"))
+    }
+
+    /// Write a `compile_commands.json` that compiles every `.c` file of the
+    /// case to an object file of the same name, in the order of their names
+    /// or, with `reverse`, the opposite one.
+    fn write_compile_commands(&self, reverse: bool) -> io::Result<PathBuf> {
+        let mut sources = self.sources()?;
+        if reverse {
+            sources.reverse();
+        }
+        let commands = sources
+            .iter()
+            .map(|file| {
+                let object = file.with_extension("o");
+                serde_json::json!({
+                    "directory": self.work_dir,
+                    "file": file,
+                    "arguments": ["cc", "-c", "-o", object, file],
+                    "output": object,
+                })
+            })
+            .collect::<Vec<_>>();
+        let path = self.work_dir.join("compile_commands.json");
+        fs::write(&path, serde_json::to_string_pretty(&commands).unwrap())?;
+        Ok(path)
+    }
+
+    /// Translate the case into the crate `out_dir` of the work directory,
+    /// and return the directory. `reverse` reverses the order of the compile
+    /// commands, and `jobs` files are translated in parallel.
+    fn transpile(&self, out_dir: &str, reverse: bool, jobs: usize) -> Result<PathBuf, String> {
+        let cc_db = self
+            .write_compile_commands(reverse)
+            .map_err(|e| e.to_string())?;
+        let out_dir = self.work_dir.join(out_dir);
+        let mut cmd = Command::new(env!("CARGO_BIN_EXE_ec2rust-transpile"));
+        cmd.current_dir(&self.work_dir)
+            .arg(&cc_db)
+            .arg("--output-dir")
+            .arg(&out_dir)
+            .arg("--dependency-file")
+            .arg(self.work_dir.join("dependencies.json"))
+            .arg("--emit-build-files")
+            .args(["--jobs", &jobs.to_string()]);
+        if self.is_binary() {
+            cmd.args(["--binary", "main"]);
+        }
+        let output = cmd.output().map_err(|e| e.to_string())?;
+        if !output.status.success() {
+            return Err(format!(
+                "ec2rust-transpile failed with {}:\n{}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+        Ok(out_dir)
+    }
+
+    /// The `.rs` files of the crate `out_dir`, by path in the crate, with the
+    /// work directory replaced by a placeholder.
+    fn rust_files(&self, out_dir: &Path) -> io::Result<Vec<(PathBuf, String)>> {
+        let work_dir = self.work_dir.to_str().unwrap();
+        files_in(out_dir, Some("rs"))?
+            .into_iter()
+            .map(|file| {
+                let text = fs::read_to_string(out_dir.join(&file))?;
+                Ok((file, text.replace(work_dir, DIR_PLACEHOLDER)))
+            })
+            .collect()
+    }
+
+    /// The C files of the case without a translation among the `.rs` files
+    /// `rust_files`. The transpiler skips the files it cannot export with a
+    /// warning only.
+    fn untranslated_sources(&self, rust_files: &[&Path]) -> io::Result<Vec<String>> {
+        Ok(self
+            .sources()?
+            .into_iter()
+            .filter(|source| {
+                let stem = source.file_stem();
+                !rust_files.iter().any(|file| file.file_stem() == stem)
+            })
+            .map(|source| source.display().to_string())
+            .collect())
+    }
+
+    fn expected_dir(&self) -> PathBuf {
+        self.source_dir.join("expected")
+    }
+
+    /// Replace the snapshots of the case with `files`.
+    fn bless(&self, files: &[(PathBuf, String)]) -> io::Result<()> {
+        let expected_dir = self.expected_dir();
+        if expected_dir.exists() {
+            fs::remove_dir_all(&expected_dir)?;
+        }
+        for (file, text) in files {
+            let path = expected_dir.join(file);
+            fs::create_dir_all(path.parent().unwrap())?;
+            fs::write(path, text)?;
+        }
+        Ok(())
+    }
+
+    /// Compare `files` with the snapshots, and describe the differences.
+    fn compare(&self, files: &[(PathBuf, String)]) -> io::Result<Vec<String>> {
+        let expected_dir = self.expected_dir();
+        let mut differences = vec![];
+        for (file, text) in files {
+            match fs::read_to_string(expected_dir.join(file)) {
+                Ok(expected) if expected == *text => {}
+                Ok(expected) => differences.push(format!(
+                    "{} differs:\n{}",
+                    file.display(),
+                    first_difference(&expected, text)
+                )),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    differences.push(format!("{} is not in the snapshot", file.display()))
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        for file in files_in(&expected_dir, Some("rs"))? {
+            if !files.iter().any(|(f, _)| *f == file) {
+                differences.push(format!("{} is no longer emitted", file.display()));
+            }
+        }
+        Ok(differences)
+    }
+
+    /// Build the C program and the translated binary, run both, and describe
+    /// how their exit status and output differ.
+    fn compare_programs(&self, out_dir: &Path) -> Result<Vec<String>, String> {
+        let c_binary = self.work_dir.join("c-main");
+        let mut cc = Command::new(env::var("CC").unwrap_or_else(|_| "cc".into()));
+        cc.current_dir(&self.work_dir)
+            .arg("-o")
+            .arg(&c_binary)
+            .args(self.sources().map_err(|e| e.to_string())?);
+        run(&mut cc, "cc")?;
+
+        let rust_binary = build_binary(out_dir)?;
+
+        let c = Command::new(&c_binary)
+            .current_dir(&self.work_dir)
+            .output()
+            .map_err(|e| e.to_string())?;
+        let rust = Command::new(&rust_binary)
+            .current_dir(&self.work_dir)
+            .output()
+            .map_err(|e| e.to_string())?;
+        let mut differences = vec![];
+        if c.status.code() != rust.status.code() {
+            differences.push(format!(
+                "the C program exits with {}, the Rust program with {}",
+                c.status, rust.status
+            ));
+        }
+        if c.stdout != rust.stdout {
+            differences.push(format!(
+                "standard output differs:\n{}",
+                first_difference(
+                    &String::from_utf8_lossy(&c.stdout),
+                    &String::from_utf8_lossy(&rust.stdout)
+                )
+            ));
+        }
+        Ok(differences)
+    }
+}
+
+/// Build the binary `main` of the translated crate `out_dir`, and return its
+/// path.
+fn build_binary(out_dir: &Path) -> Result<PathBuf, String> {
+    let mut cargo = Command::new(env::var("CARGO").unwrap_or_else(|_| "cargo".into()));
+    cargo
+        .current_dir(out_dir)
+        .args(["build", "--quiet", "--bin", "main"])
+        .env_remove("CARGO_TARGET_DIR");
+    run(&mut cargo, "cargo build")?;
+    Ok(out_dir.join("target/debug/main"))
+}
+
+/// Run `cmd`, failing with its standard error unless it succeeds.
+fn run(cmd: &mut Command, what: &str) -> Result<Output, String> {
+    let output = cmd.output().map_err(|e| format!("{}: {}", what, e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "{} failed with {}:\n{}",
+            what,
+            output.status,
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    Ok(output)
+}
+
+/// The first line where `expected` and `actual` differ, with its number.
+fn first_difference(expected: &str, actual: &str) -> String {
+    let mut expected_lines = expected.lines();
+    let mut actual_lines = actual.lines();
+    for line in 1.. {
+        match (expected_lines.next(), actual_lines.next()) {
+            (Some(e), Some(a)) if e == a => continue,
+            (None, None) => break,
+            (e, a) => {
+                return format!(
+                    "  line {}:\n  - {}\n  + {}",
+                    line,
+                    e.unwrap_or("<end of file>"),
+                    a.unwrap_or("<end of file>")
+                )
+            }
+        }
+    }
+    // Only the final newline differs
+    "  at the end of the file".to_owned()
+}
+
+/// A scratch directory for this test run
+fn work_root(test: &str) -> PathBuf {
+    env::temp_dir().join(format!("ec2rust-{}-{}", test, std::process::id()))
+}
+
+#[test]
+#[ignore = "the cases have no snapshots yet; bless them with EC2RUST_BLESS=1"]
+fn snapshots() {
+    let bless = env_flag("EC2RUST_BLESS");
+    let build = env_flag("EC2RUST_SNAPSHOT_BUILD");
+    let work_root = work_root("snapshots");
+
+    let mut failures = String::new();
+    for (name, source_dir) in cases() {
+        let case = Case::new(&source_dir, work_root.join(&name)).unwrap();
+        let out_dir = match case.transpile("rust", false, 1) {
+            Ok(out_dir) => out_dir,
+            Err(e) => {
+                writeln!(failures, "{}: {}", name, e).unwrap();
+                continue;
+            }
+        };
+        let files = case.rust_files(&out_dir).unwrap();
+        let paths = files.iter().map(|(file, _)| &**file).collect::<Vec<_>>();
+        let untranslated = case.untranslated_sources(&paths).unwrap();
+        if !untranslated.is_empty() {
+            let untranslated = untranslated.join(", ");
+            writeln!(failures, "{}: {} not translated", name, untranslated).unwrap();
+            continue;
+        }
+
+        if bless {
+            case.bless(&files).unwrap();
+            eprintln!("{}: wrote the snapshot of {} files", name, files.len());
+        } else if !case.expected_dir().exists() {
+            writeln!(failures, "{}: there is no snapshot", name).unwrap();
+        } else {
+            for difference in case.compare(&files).unwrap() {
+                writeln!(failures, "{}: {}", name, difference).unwrap();
+            }
+        }
+
+        if build && case.is_binary() {
+            match case.compare_programs(&out_dir) {
+                Ok(differences) => {
+                    for difference in differences {
+                        writeln!(failures, "{}: {}", name, difference).unwrap();
+                    }
+                }
+                Err(e) => writeln!(failures, "{}: {}", name, e).unwrap(),
+            }
+        }
+    }
+
+    if failures.is_empty() {
+        fs::remove_dir_all(&work_root).ok();
+    } else {
+        panic!(
+            "snapshot tests failed; the translations are in {}, and \
+             EC2RUST_BLESS=1 updates the snapshots:\n{}",
+            work_root.display(),
+            failures
+        );
+    }
+}
+
+/// Translate each case three times: as usual, with the compile commands in the
+/// opposite order, and with four jobs, and check that every file of the crate
+/// is the same.
+#[test]
+fn deterministic() {
+    let work_root = work_root("deterministic");
+
+    let mut failures = String::new();
+    for (name, source_dir) in cases() {
+        let case = Case::new(&source_dir, work_root.join(&name)).unwrap();
+        let mut runs = vec![];
+        let variants = [
+            ("first", false, 1),
+            ("reversed", true, 1),
+            ("-j 4", false, 4),
+        ];
+        for (run, reverse, jobs) in variants {
+            match case.transpile("rust", reverse, jobs) {
+                Ok(out_dir) => {
+                    let files = files_in(&out_dir, None)
+                        .unwrap()
+                        .into_iter()
+                        .map(|file| {
+                            let contents = fs::read(out_dir.join(&file)).unwrap();
+                            (file, contents)
+                        })
+                        .collect::<Vec<_>>();
+                    // The transpiler does not overwrite the files of an
+                    // earlier run
+                    fs::remove_dir_all(&out_dir).unwrap();
+                    runs.push((run, files));
+                }
+                Err(e) => writeln!(failures, "{} ({}): {}", name, run, e).unwrap(),
+            }
+        }
+        if runs.len() != variants.len() {
+            continue;
+        }
+        let (_, first) = &runs[0];
+        let rust_files = first
+            .iter()
+            .map(|(file, _)| &**file)
+            .filter(|file| file.extension().map_or(false, |ext| ext == "rs"))
+            .collect::<Vec<_>>();
+        let untranslated = case.untranslated_sources(&rust_files).unwrap();
+        if !untranslated.is_empty() {
+            let untranslated = untranslated.join(", ");
+            writeln!(failures, "{}: {} not translated", name, untranslated).unwrap();
+            continue;
+        }
+        for (run, again) in &runs[1..] {
+            for (file, contents) in first {
+                match again.iter().find(|(f, _)| f == file) {
+                    Some((_, other)) if other == contents => {}
+                    Some((_, other)) => writeln!(
+                        failures,
+                        "{} ({}): {} differs:\n{}",
+                        name,
+                        run,
+                        file.display(),
+                        first_difference(
+                            &String::from_utf8_lossy(contents),
+                            &String::from_utf8_lossy(other)
+                        )
+                    )
+                    .unwrap(),
+                    None => {
+                        let file = file.display();
+                        writeln!(failures, "{} ({}): {} is not emitted", name, run, file).unwrap()
+                    }
+                }
+            }
+            for (file, _) in again {
+                if !first.iter().any(|(f, _)| f == file) {
+                    let file = file.display();
+                    writeln!(failures, "{} ({}): {} is only emitted", name, run, file).unwrap();
+                }
+            }
+        }
+    }
+
+    if failures.is_empty() {
+        fs::remove_dir_all(&work_root).ok();
+    } else {
+        panic!(
+            "translating again failed or gave different files:\n{}",
+            failures
+        );
+    }
+}
+
+/// The weak declarations of the `weak` case, which nothing defines, are weak
+/// undefined symbols of the Rust object file, so that the binary links and
+/// sees them as null. The linker drops them from the binary itself.
+#[test]
+fn weak_declarations() {
+    let work_root = work_root("weak");
+    let case = Case::new(&snapshots_dir().join("weak"), work_root.join("weak")).unwrap();
+    let out_dir = case.transpile("rust", false, 1).unwrap();
+    let binary = build_binary(&out_dir).unwrap();
+    let output = run(&mut Command::new(&binary), "main").unwrap();
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "maybe_missing is not defined\n\
+         also_missing is missing\n\
+         missing_counter is missing\n"
+    );
+
+    let object_path = work_root.join("main.o");
+    let mut cargo = Command::new(env::var("CARGO").unwrap_or_else(|_| "cargo".into()));
+    cargo
+        .current_dir(&out_dir)
+        .args(["rustc", "--quiet", "--bin", "main", "--", "--emit"])
+        .arg(format!("obj={}", object_path.display()))
+        .env_remove("CARGO_TARGET_DIR");
+    run(&mut cargo, "cargo rustc").unwrap();
+    let data = fs::read(&object_path).unwrap();
+    let object = object::File::parse(&*data).unwrap();
+    for name in ["maybe_missing", "missing_counter", "also_missing"] {
+        let symbol = object
+            .symbols()
+            .find(|symbol| symbol.name() == Ok(name))
+            .unwrap_or_else(|| panic!("{} is not in the symbol table", name));
+        assert!(
+            symbol.is_undefined() && symbol.is_weak(),
+            "{} is not a weak undefined symbol",
+            name
+        );
+    }
+    fs::remove_dir_all(&work_root).ok();
+}
@@ -0,0 +1,41 @@
+#include <stdio.h>
+
+static const char *classify(int n) {
+    switch (n % 4) {
+    case 0:
+        return "zero";
+    case 1:
+    case 2:
+        return "low";
+    default:
+        return "high";
+    }
+}
+
+static int find(const int *values, int len, int wanted) {
+    int i;
+    for (i = 0; i < len; i++) {
+        if (values[i] == wanted)
+            goto found;
+    }
+    return -1;
+found:
+    return i;
+}
+
+int main(void) {
+    int values[] = {3, 1, 4, 1, 5, 9, 2, 6};
+    int n = 0;
+    do {
+        if (values[n] % 2 == 0)
+            continue;
+        printf("%d is %s\n", values[n], classify(values[n]));
+    } while (++n < 8);
+    while (n > 0) {
+        if (--n == 3)
+            break;
+    }
+    printf("found 9 at %d, 7 at %d, stopped at %d\n", find(values, 8, 9),
+           find(values, 8, 7), n);
+    return values[n] == 1 ? 0 : 1;
+}
@@ -0,0 +1,10 @@
+#include <stdio.h>
+
+int main(void) {
+    int sum = 0;
+    for (int i = 1; i <= 10; i++) {
+        sum += i * i;
+    }
+    printf("Hello, world! %d\n", sum);
+    return 0;
+}
@@ -0,0 +1,42 @@
+enum shape_kind { CIRCLE, RECTANGLE, TRIANGLE };
+
+struct point {
+    double x, y;
+};
+
+struct shape {
+    enum shape_kind kind;
+    union {
+        double radius;
+        struct point size;
+        struct point corners[3];
+    } u;
+};
+
+static const double pi = 3.14159265358979;
+
+double shape_area(const struct shape *shape) {
+    switch (shape->kind) {
+    case CIRCLE:
+        return pi * shape->u.radius * shape->u.radius;
+    case RECTANGLE:
+        return shape->u.size.x * shape->u.size.y;
+    case TRIANGLE: {
+        const struct point *p = shape->u.corners;
+        double area = (p[1].x - p[0].x) * (p[2].y - p[0].y) -
+                      (p[2].x - p[0].x) * (p[1].y - p[0].y);
+        return area < 0 ? -area / 2 : area / 2;
+    }
+    }
+    return 0;
+}
+
+unsigned shapes_total(const struct shape *shapes, unsigned n, double *area) {
+    unsigned count = 0;
+    *area = 0;
+    for (; n; n--, shapes++) {
+        *area += shape_area(shapes);
+        count++;
+    }
+    return count;
+}
@@ -0,0 +1,13 @@
+#include "counter.h"
+
+int counters_created = 0;
+
+struct counter counter_new(const char *name) {
+    struct counter counter = {name, 0};
+    counters_created++;
+    return counter;
+}
+
+void counter_add(struct counter *counter, unsigned long n) {
+    counter->count += n;
+}
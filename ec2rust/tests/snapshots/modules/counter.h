@@ -0,0 +1,14 @@
+#ifndef COUNTER_H
+#define COUNTER_H
+
+struct counter {
+    const char *name;
+    unsigned long count;
+};
+
+extern int counters_created;
+
+struct counter counter_new(const char *name);
+void counter_add(struct counter *counter, unsigned long n);
+
+#endif
@@ -0,0 +1,17 @@
+#include <stdio.h>
+#include <string.h>
+
+#include "counter.h"
+
+int main(int argc, char **argv) {
+    struct counter words = counter_new("words");
+    struct counter letters = counter_new("letters");
+    const char *text[] = {"golden", "snapshot", "tests"};
+    for (unsigned i = 0; i < sizeof(text) / sizeof(text[0]); i++) {
+        counter_add(&words, 1);
+        counter_add(&letters, strlen(text[i]));
+    }
+    printf("%s: %lu, %s: %lu (%d counters, %d arguments)\n", words.name,
+           words.count, letters.name, letters.count, counters_created, argc);
+    return 0;
+}
@@ -0,0 +1,18 @@
+#include <stdio.h>
+
+/* Nothing defines these, so their addresses are null */
+extern int maybe_missing(int x) __attribute__((weak));
+extern int missing_counter __attribute__((weak));
+void also_missing(void);
+#pragma weak also_missing
+
+int main(void) {
+    int *counter = &missing_counter;
+    if (maybe_missing)
+        printf("maybe_missing(1) = %d\n", maybe_missing(1));
+    else
+        printf("maybe_missing is not defined\n");
+    printf("also_missing is %s\n", also_missing ? "defined" : "missing");
+    printf("missing_counter is %s\n", counter ? "defined" : "missing");
+    return 0;
+}